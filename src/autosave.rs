@@ -0,0 +1,409 @@
+use crate::{
+  fs,
+  shared::lock,
+  store::{self, SharedStore},
+  AppConfigError, AppConfigManager, ConfigKey, Result, SharedData,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+  io,
+  path::PathBuf,
+  sync::{
+    mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    Arc, Mutex, Weak,
+  },
+  thread::JoinHandle,
+  time::{Duration, Instant, SystemTime},
+};
+
+/// Called on the background thread when it fails to write the file.
+pub(crate) type BackgroundErrorCallback = Box<dyn FnMut(&AppConfigError) + Send>;
+
+enum Request {
+  Write(Vec<u8>),
+  Flush(Sender<()>),
+}
+
+/// The thread started by `enable_autosave` and the channels to it.
+pub(crate) struct AutoSaver {
+  path: PathBuf,
+  requests: Option<Sender<Request>>,
+  results: Receiver<io::Result<(fs::FileStamp, SystemTime)>>,
+  thread: Option<JoinHandle<()>>,
+  /// The number of completed writes.
+  written: usize,
+}
+
+impl AutoSaver {
+  fn start(
+    key: ConfigKey,
+    interval: Duration,
+    options: fs::WriteOptions,
+    store: Option<SharedStore>,
+    on_error: Arc<Mutex<Option<BackgroundErrorCallback>>>,
+  ) -> Self {
+    let (requests, receiver) = mpsc::channel();
+    let (sender, results) = mpsc::channel();
+    let path = key.path().to_owned();
+    let thread = {
+      let path = path.clone();
+      std::thread::spawn(move || {
+        let mut pending: Option<Vec<u8>> = None;
+        let mut last_write: Option<Instant> = None;
+        loop {
+          let request = match (&pending, last_write) {
+            (None, _) => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            (Some(_), Some(last)) => receiver.recv_timeout(interval.saturating_sub(last.elapsed())),
+            (Some(_), None) => Err(RecvTimeoutError::Timeout),
+          };
+          let (write, done, disconnected) = match request {
+            Ok(Request::Write(s)) => {
+              pending = Some(s);
+              let due = last_write.is_none_or(|last| last.elapsed() >= interval);
+              (due, None, false)
+            }
+            Ok(Request::Flush(done)) => (true, Some(done), false),
+            Err(RecvTimeoutError::Timeout) => (true, None, false),
+            Err(RecvTimeoutError::Disconnected) => (true, None, true),
+          };
+          if let Some(s) = pending.take_if(|_| write) {
+            let result = store::write_to(store.as_ref(), &key, &s, options)
+              .and_then(|_| fs::FileStamp::new(&key, &s, store.as_ref()))
+              .map(|stamp| (stamp, SystemTime::now()));
+            last_write = Some(Instant::now());
+            let result = match result {
+              Err(source) => {
+                let copy = io::Error::new(source.kind(), source.to_string());
+                let err = store::store_error(store.as_ref(), &path)(source);
+                if let Some(f) = lock(&on_error).as_mut() {
+                  f(&err);
+                }
+                Err(copy)
+              }
+              result => result,
+            };
+            if sender.send(result).is_err() {
+              break;
+            }
+          }
+          if let Some(done) = done {
+            done.send(()).ok();
+          }
+          if disconnected {
+            break;
+          }
+        }
+      })
+    };
+    Self {
+      path,
+      requests: Some(requests),
+      results,
+      thread: Some(thread),
+      written: 0,
+    }
+  }
+}
+
+impl Drop for AutoSaver {
+  fn drop(&mut self) {
+    // The thread writes the pending data and exits
+    self.requests.take();
+    if let Some(thread) = self.thread.take() {
+      thread.join().ok();
+    }
+  }
+}
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// Writes the file on a background thread after [`request_save`](Self::request_save),
+  /// at most once per `interval`. Requests in between are coalesced, only the latest data is written.
+  ///
+  /// `save()` and dropping the manager write the pending data first.
+  /// Backups and the conflict policy only apply to `save()`.
+  pub fn enable_autosave(&self, interval: Duration) -> Result {
+    let path = self.user_config_path()?;
+    // The previous thread finishes its writes first
+    lock(&self.autosave).take();
+    let saver = AutoSaver::start(
+      self.config_key(&path),
+      interval,
+      self.write_options(),
+      self.shared_store(),
+      self.on_background_error.clone(),
+    );
+    *lock(&self.autosave) = Some(saver);
+    Ok(())
+  }
+
+  /// Writes the current data on a background thread without waiting for it.
+  ///
+  /// The data is serialized now. Writes happen in order, so the file ends up with the data
+  /// of the last call. Errors are passed to the callback set with `with_on_background_error`
+  /// and returned by the next [`flush`](Self::flush).
+  /// After `enable_autosave`, the write waits for its interval.
+  pub fn save_in_background(&self) -> Result {
+    if lock(&self.autosave).is_none() {
+      self.enable_autosave(Duration::ZERO)?;
+    }
+    self.request_save()
+  }
+
+  /// Saves the data in the background if `enable_autosave` was called, otherwise right away.
+  ///
+  /// The data is serialized now, so later changes are not written until the next request.
+  /// Errors from the background thread are returned by the next [`flush`](Self::flush).
+  pub fn request_save(&self) -> Result {
+    let path = lock(&self.autosave).as_ref().map(|x| x.path.clone());
+    let path = match path {
+      Some(path) => path,
+      None => return self.save(),
+    };
+    let s = self.serialize_data(&path)?;
+    self.mark_clean();
+    #[cfg(feature = "exit-flush")]
+    self.set_exit_flush(Some((&path, s.clone())));
+    if let Some(saver) = &*lock(&self.autosave) {
+      if let Some(requests) = &saver.requests {
+        requests.send(Request::Write(s)).ok();
+      }
+    }
+    Ok(())
+  }
+
+  /// Waits until the data requested with `request_save` is written.
+  /// Returns the first error of the background writes since the last call.
+  pub fn flush(&self) -> Result {
+    let done = match &*lock(&self.autosave) {
+      Some(AutoSaver {
+        requests: Some(requests),
+        ..
+      }) => {
+        let (sender, done) = mpsc::channel();
+        requests.send(Request::Flush(sender)).ok();
+        done
+      }
+      _ => return Ok(()),
+    };
+    done.recv().ok();
+    self.drain_autosave()
+  }
+
+  /// Saves if the data changed and the checkpoint interval passed since the last write.
+  /// Returns whether a save was started.
+  ///
+  /// The data of an `Rc` manager cannot be read from another thread, so the application calls this
+  /// regularly, e.g. from its event loop. A `Send + Sync` manager can call it on a background
+  /// thread with [`start_checkpoints`](Self::start_checkpoints). The file is written on the
+  /// background thread when `enable_autosave` was called.
+  pub fn checkpoint(&self) -> Result<bool> {
+    self.drain_autosave()?;
+    let interval = match self.checkpoint_interval {
+      Some(interval) => interval,
+      None => return Ok(false),
+    };
+    let due = lock(&self.last_saved_at).is_none_or(|last| {
+      SystemTime::now()
+        .duration_since(last)
+        .map_or(true, |elapsed| elapsed >= interval)
+    });
+    if !due || !self.is_dirty() {
+      return Ok(false);
+    }
+    self.request_save()?;
+    Ok(true)
+  }
+
+  /// Calls [`checkpoint`](Self::checkpoint) every checkpoint interval on a background thread,
+  /// so that the changes are saved without the application calling it.
+  ///
+  /// The thread only keeps a weak reference and stops when the manager is dropped.
+  /// The file is written on the thread of `enable_autosave` if it was called, otherwise on this one.
+  /// Errors are passed to the callback set with `with_on_save_error`.
+  pub fn start_checkpoints(self: &Arc<Self>)
+  where
+    Self: Send + Sync + 'static,
+  {
+    let interval = match self.checkpoint_interval {
+      Some(interval) => interval,
+      None => return,
+    };
+    let (stop, stopped) = mpsc::channel::<()>();
+    // The previous thread stops
+    *lock(&self.checkpoint_stop) = Some(stop);
+    let manager = Arc::downgrade(self);
+    std::thread::spawn(move || {
+      while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+        let manager = match Weak::upgrade(&manager) {
+          Some(manager) => manager,
+          None => break,
+        };
+        if let Err(err) = manager.checkpoint() {
+          manager.report_save_error(err);
+        }
+      }
+    });
+  }
+
+  /// When the file was last written by this manager.
+  pub fn last_saved_at(&self) -> Option<SystemTime> {
+    self.drain_autosave().ok();
+    *lock(&self.last_saved_at)
+  }
+
+  /// Records the writes of the background thread so that they are not taken for external changes.
+  pub(crate) fn drain_autosave(&self) -> Result {
+    let mut autosave = lock(&self.autosave);
+    let saver = match autosave.as_mut() {
+      Some(saver) => saver,
+      None => return Ok(()),
+    };
+    let mut first_error = None;
+    for result in saver.results.try_iter() {
+      match result {
+        Ok((stamp, time)) => {
+          saver.written += 1;
+          *lock(&self.file_stamp) = Some((saver.path.clone(), stamp));
+          *lock(&self.last_saved_at) = Some(time);
+        }
+        Err(source) => {
+          first_error.get_or_insert(store::store_error(self.store.as_ref(), &saver.path)(source));
+        }
+      }
+    }
+    first_error.map_or(Ok(()), Err)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{AppConfigManager, AtomicAppConfigManager};
+  use serde::{Deserialize, Serialize};
+  use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+  };
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Window {
+    x: u32,
+  }
+
+  #[test]
+  fn checkpoint() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Window::default()));
+    let manager = AppConfigManager::new(config.clone(), "checkpoint", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_checkpoint_interval(Duration::from_millis(50))
+      .with_auto_saving(false);
+    assert_eq!(manager.last_saved_at(), None);
+    assert!(manager.checkpoint().unwrap());
+    let saved_at = manager.last_saved_at().unwrap();
+    // Not changed
+    std::thread::sleep(Duration::from_millis(60));
+    assert!(!manager.checkpoint().unwrap());
+
+    config.borrow_mut().x = 1;
+    assert!(manager.checkpoint().unwrap());
+    assert!(manager.last_saved_at().unwrap() > saved_at);
+    // Too early
+    config.borrow_mut().x = 2;
+    assert!(!manager.checkpoint().unwrap());
+  }
+
+  #[test]
+  fn background_checkpoints() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Arc::new(RwLock::new(Window::default()));
+    let manager = Arc::new(
+      AtomicAppConfigManager::new(config.clone(), "checkpoints", "sumibi-yakitori")
+        .with_base_dir(dir.path())
+        .with_checkpoint_interval(Duration::from_millis(20))
+        .with_auto_saving(false),
+    );
+    let path = manager.get_user_config_path().unwrap();
+    manager.start_checkpoints();
+    config.write().unwrap().x = 1;
+    let started = Instant::now();
+    while std::fs::read_to_string(&path).ok().as_deref() != Some("x = 1\n") {
+      assert!(started.elapsed() < Duration::from_secs(10));
+      std::thread::sleep(Duration::from_millis(10));
+    }
+
+    // Stops with the manager
+    let weak = Arc::downgrade(&manager);
+    drop(manager);
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(weak.upgrade().is_none());
+    config.write().unwrap().x = 2;
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "x = 1\n");
+  }
+
+  #[test]
+  fn save_in_background() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Window::default()));
+    let manager = AppConfigManager::new(config.clone(), "background", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    for x in 1..=50 {
+      config.borrow_mut().x = x;
+      manager.save_in_background().unwrap();
+    }
+    manager.flush().unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "x = 50\n");
+
+    // A folder where the file should be
+    let errors = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let mut manager = manager;
+    manager.set_on_background_error({
+      let errors = errors.clone();
+      move |err| errors.lock().unwrap().push(err.to_string())
+    });
+    std::fs::remove_file(&path).unwrap();
+    std::fs::create_dir(&path).unwrap();
+    manager.save_in_background().unwrap();
+    assert!(manager.flush().is_err());
+    assert_eq!(errors.lock().unwrap().len(), 1);
+  }
+
+  #[test]
+  fn coalesced_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Window::default()));
+    let manager = AppConfigManager::new(config.clone(), "autosave", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_auto_saving(false);
+    manager.enable_autosave(Duration::from_millis(50)).unwrap();
+    let path = manager.get_user_config_path().unwrap();
+
+    for x in 1..=100 {
+      config.borrow_mut().x = x;
+      manager.request_save().unwrap();
+    }
+    manager.flush().unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "x = 100\n");
+    let written = crate::shared::lock(&manager.autosave)
+      .as_ref()
+      .unwrap()
+      .written;
+    assert!((1..10).contains(&written), "{} writes", written);
+    // The own writes are not external changes
+    assert!(!manager.reload_if_changed().unwrap());
+
+    // Written when the manager is dropped
+    config.borrow_mut().x = 101;
+    manager.request_save().unwrap();
+    drop(manager);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "x = 101\n");
+  }
+}
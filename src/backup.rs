@@ -0,0 +1,208 @@
+use crate::{fs, AppConfigError, AppConfigManager, Result, SharedData};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+  path::{Path, PathBuf},
+  time::SystemTime,
+};
+
+/// The backup restored by [`AppConfigManager::restore_from_backup`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BackupSelector {
+  /// `app_config.toml.1` if it exists, otherwise `app_config.toml.bak`.
+  Latest,
+  /// `app_config.toml.{n}` kept by `with_backup_retention`, `1` being the newest.
+  Index(usize),
+  /// Any file in the configured format.
+  Path(PathBuf),
+}
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// The backups kept by `with_backup_retention` with their modification times, newest first.
+  /// The times are `UNIX_EPOCH` with [`with_store`](Self::with_store).
+  pub fn list_backups(&self) -> Result<Vec<(PathBuf, SystemTime)>> {
+    let path = self.user_config_path()?;
+    let mut backups = Vec::new();
+    for i in 1..=self.backup_retention {
+      let backup = numbered_backup(&path, i);
+      if self.uses_store() {
+        if self.is_stored(&backup) {
+          backups.push((backup, SystemTime::UNIX_EPOCH));
+        }
+        continue;
+      }
+      match std::fs::metadata(&backup).and_then(|x| x.modified()) {
+        Ok(modified) => backups.push((backup, modified)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => {
+          return Err(AppConfigError::Io {
+            path: backup,
+            source: err,
+          })
+        }
+      }
+    }
+    Ok(backups)
+  }
+
+  /// Replaces the file with a backup and loads it. The backup is left in place.
+  ///
+  /// The backup is parsed first, so a broken backup never replaces the file.
+  pub fn restore_from_backup(&self, which: BackupSelector) -> Result {
+    let path = self.user_config_path()?;
+    let backup = match which {
+      BackupSelector::Latest => {
+        let numbered = numbered_backup(&path, 1);
+        if self.is_stored(&numbered) {
+          numbered
+        }
+        else {
+          fs::with_suffix(&path, ".bak")
+        }
+      }
+      BackupSelector::Index(i) => numbered_backup(&path, i),
+      BackupSelector::Path(backup) => backup,
+    };
+    let s = self.read_stored(&backup)?;
+    let (mut value, _) = self.parse_file(&backup, &s, false)?;
+    let fixed = self.fix_up(&mut value);
+    self.validate(&backup, &value)?;
+    self.write_stored(&path, &s, self.write_options())?;
+    self.data.write(|data| *data = value);
+    self.mark_clean();
+    if fixed {
+      self.mark_dirty();
+    }
+    self.notify(&self.on_load);
+    self.record_stamp(&path, &s);
+    *crate::shared::lock(&self.last_loaded_at) = Some(SystemTime::now());
+    Ok(())
+  }
+
+  /// Reads the backup of the file at `path` into the data.
+  /// Returns whether it was upgraded by a migration, or `None` if it is missing or broken too.
+  pub(crate) fn load_backup(&self, path: &Path, detected: bool) -> Option<bool> {
+    let backup = fs::with_suffix(path, ".bak");
+    let s = self.read_stored(&backup).ok()?;
+    let (mut value, migrated) = self.parse_file(&backup, &s, detected).ok()?;
+    self.fix_up(&mut value);
+    self.validate(&backup, &value).ok()?;
+    self.data.write(|data| *data = value);
+    self.notify(&self.on_load);
+    Some(migrated)
+  }
+
+  /// Moves the file at `path` to `app_config.toml.corrupt-{unix time}`,
+  /// or `app_config.toml.corrupt` in a store, replacing the file quarantined before.
+  pub(crate) fn quarantine(&self, path: &Path) -> Result<PathBuf> {
+    // A store cannot be listed, so it keeps the file without the time
+    if self.uses_store() {
+      let quarantined = fs::with_suffix(path, ".corrupt");
+      self.copy_stored(path, &quarantined)?;
+      self.remove_stored(path)?;
+      return Ok(quarantined);
+    }
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let prefix = format!("{}.corrupt-", name);
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    for entry in std::fs::read_dir(dir).map_err(AppConfigError::io(dir))? {
+      let entry = entry.map_err(AppConfigError::io(dir))?;
+      if entry.file_name().to_string_lossy().starts_with(&prefix) {
+        std::fs::remove_file(entry.path()).map_err(AppConfigError::io(entry.path()))?;
+      }
+    }
+    let timestamp = SystemTime::now()
+      .duration_since(SystemTime::UNIX_EPOCH)
+      .map_or(0, |x| x.as_secs());
+    let quarantined = fs::with_suffix(path, &format!(".corrupt-{}", timestamp));
+    std::fs::rename(path, &quarantined).map_err(AppConfigError::io(path))?;
+    Ok(quarantined)
+  }
+}
+
+/// `app_config.toml.{i}`
+fn numbered_backup(path: &Path, i: usize) -> PathBuf {
+  fs::with_suffix(path, &format!(".{}", i))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::BackupSelector;
+  use crate::{AppConfigError, AppConfigManager, LoadOutcome};
+  use serde::{Deserialize, Serialize};
+  use std::{cell::RefCell, rc::Rc};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Volume {
+    level: u32,
+  }
+
+  #[test]
+  fn restore_from_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Volume { level: 1 }));
+    let manager = AppConfigManager::new(config.clone(), "restore", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_backup_retention(3)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    for level in 2..=4 {
+      manager.save().unwrap();
+      config.borrow_mut().level = level;
+    }
+
+    // `.1` has level 2, `.2` has level 1
+    manager.restore_from_backup(BackupSelector::Latest).unwrap();
+    assert_eq!(*config.borrow(), Volume { level: 2 });
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "level = 2\n");
+    manager
+      .restore_from_backup(BackupSelector::Index(2))
+      .unwrap();
+    assert_eq!(*config.borrow(), Volume { level: 1 });
+    assert_eq!(manager.list_backups().unwrap().len(), 2);
+
+    // A broken backup leaves the file alone
+    let broken = dir.path().join("broken.toml");
+    std::fs::write(&broken, "level = ").unwrap();
+    assert!(matches!(
+      manager.restore_from_backup(BackupSelector::Path(broken)),
+      Err(AppConfigError::Deserialize { .. })
+    ));
+    assert!(manager
+      .restore_from_backup(BackupSelector::Index(3))
+      .unwrap_err()
+      .is_not_found());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "level = 1\n");
+    assert_eq!(*config.borrow(), Volume { level: 1 });
+  }
+
+  #[test]
+  fn quarantine_corrupt() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Volume::default()));
+    let manager = AppConfigManager::new(config.clone(), "quarantine", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_quarantine_corrupt(true)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    for content in ["level = ", "level = \"loud\""] {
+      std::fs::write(&path, content).unwrap();
+      let quarantined = match manager.load_with_outcome().unwrap() {
+        LoadOutcome::Quarantined { path, .. } => path,
+        outcome => panic!("{:?}", outcome),
+      };
+      assert!(!path.exists());
+      assert_eq!(std::fs::read_to_string(&quarantined).unwrap(), content);
+    }
+    // Only the latest one is kept
+    let count = std::fs::read_dir(path.parent().unwrap()).unwrap().count();
+    assert_eq!(count, 1);
+
+    manager.save().unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "level = 0\n");
+  }
+}
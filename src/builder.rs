@@ -0,0 +1,287 @@
+use crate::{AppConfigError, AppConfigManager, Location, SharedData};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{cell::RefCell, fmt, rc::Rc, sync::Arc};
+
+type Configure<T, S> = Arc<dyn Fn(AppConfigManager<T, S>) -> AppConfigManager<T, S> + Send + Sync>;
+
+/// Creates managers with the same options, checking them before the first load or save.
+///
+/// Any `with_*` option of [`AppConfigManager`] is set with [`with_options`](Self::with_options).
+/// The builder is `Clone`, so one template can create managers that differ e.g. only in file name.
+///
+/// ```rust
+/// use appconfig::AppConfigManagerBuilder;
+/// use serde::{Deserialize, Serialize};
+/// use std::{cell::RefCell, rc::Rc};
+///
+/// #[derive(Serialize, Deserialize, Default)]
+/// struct Pane {
+///   width: u32,
+/// }
+///
+/// let template = AppConfigManagerBuilder::<Pane>::new("my_app", "sumibi-yakitori")
+///   .with_options(|manager| manager.with_backup(true).with_auto_saving(false));
+/// # let template = template.with_options(|manager| manager.with_store(appconfig::MemoryStore::new()));
+/// let left = Rc::new(RefCell::new(Pane::default()));
+/// let right = Rc::new(RefCell::new(Pane::default()));
+/// let left = template.clone().with_file_name("left").build(left).unwrap();
+/// let right = template.with_file_name("right").build(right).unwrap();
+/// assert_ne!(left.config_path().unwrap(), right.config_path().unwrap());
+/// ```
+pub struct AppConfigManagerBuilder<T, S = Rc<RefCell<T>>>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  app_name: String,
+  organization_name: String,
+  options: Vec<Configure<T, S>>,
+}
+
+impl<T, S> Clone for AppConfigManagerBuilder<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  fn clone(&self) -> Self {
+    Self {
+      app_name: self.app_name.clone(),
+      organization_name: self.organization_name.clone(),
+      options: self.options.clone(),
+    }
+  }
+}
+
+impl<T, S> AppConfigManagerBuilder<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  pub fn new(app_name: impl Into<String>, organization_name: impl Into<String>) -> Self {
+    Self {
+      app_name: app_name.into(),
+      organization_name: organization_name.into(),
+      options: Vec::new(),
+    }
+  }
+
+  pub fn set_app_name(&mut self, value: impl Into<String>) -> &mut Self {
+    self.app_name = value.into();
+    self
+  }
+
+  pub fn with_app_name(mut self, value: impl Into<String>) -> Self {
+    self.set_app_name(value);
+    self
+  }
+
+  pub fn set_organization_name(&mut self, value: impl Into<String>) -> &mut Self {
+    self.organization_name = value.into();
+    self
+  }
+
+  pub fn with_organization_name(mut self, value: impl Into<String>) -> Self {
+    self.set_organization_name(value);
+    self
+  }
+
+  /// See [`AppConfigManager::with_file_name`].
+  pub fn set_file_name(&mut self, value: impl Into<String>) -> &mut Self {
+    let value = value.into();
+    self.add_options(move |manager| manager.with_file_name(value.clone()))
+  }
+
+  pub fn with_file_name(mut self, value: impl Into<String>) -> Self {
+    self.set_file_name(value);
+    self
+  }
+
+  /// Sets options of the manager, e.g. `|manager| manager.with_backup(true)`.
+  /// `f` is called by every `build()`, in the order it was added.
+  pub fn add_options(
+    &mut self,
+    f: impl Fn(AppConfigManager<T, S>) -> AppConfigManager<T, S> + Send + Sync + 'static,
+  ) -> &mut Self {
+    self.options.push(Arc::new(f));
+    self
+  }
+
+  pub fn with_options(
+    mut self,
+    f: impl Fn(AppConfigManager<T, S>) -> AppConfigManager<T, S> + Send + Sync + 'static,
+  ) -> Self {
+    self.add_options(f);
+    self
+  }
+
+  /// Creates the manager for `data`, failing if the names are invalid, options exclude each other,
+  /// or the path of the file cannot be determined. Nothing is created on disk.
+  ///
+  /// The path is resolved again by every load and save, since e.g. the variable of
+  /// `with_env_override` may change in the meantime.
+  pub fn build(&self, data: S) -> Result<AppConfigManager<T, S>, BuildError> {
+    let mut manager = self.build_unchecked(data);
+    let result = check(&manager);
+    if result.is_err() {
+      // An invalid manager must not save on drop
+      manager.set_auto_saving(false);
+    }
+    result.map(|_| manager)
+  }
+
+  /// Like `build()`, without the checks. Used by [`AppConfigManager::new`].
+  pub(crate) fn build_unchecked(&self, data: S) -> AppConfigManager<T, S> {
+    let manager =
+      AppConfigManager::with_defaults(data, self.app_name.clone(), self.organization_name.clone());
+    self.options.iter().fold(manager, |manager, f| f(manager))
+  }
+}
+
+fn check<T, S>(manager: &AppConfigManager<T, S>) -> Result<(), BuildError>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  manager.check_names().map_err(|err| match err {
+    AppConfigError::InvalidName { name, reason } => BuildError::InvalidName { name, reason },
+    err => BuildError::Config(err),
+  })?;
+  if manager.portable_mode && manager.base_dir.is_some() {
+    return Err(BuildError::ConflictingOptions(
+      "with_portable_mode",
+      "with_base_dir",
+    ));
+  }
+  if manager.portable_mode && manager.location != Location::Config {
+    return Err(BuildError::ConflictingOptions(
+      "with_portable_mode",
+      "with_location",
+    ));
+  }
+  if !manager.supports_integrity_policy() {
+    return Err(BuildError::ConflictingOptions(
+      "with_integrity_policy",
+      "with_format",
+    ));
+  }
+  manager.config_path().map_err(BuildError::Config)?;
+  Ok(())
+}
+
+/// The error returned by [`AppConfigManagerBuilder::build`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum BuildError {
+  /// The application or organization name cannot be used in the folder name,
+  /// see [`AppConfigError::InvalidName`].
+  InvalidName { name: String, reason: String },
+  /// The two options cannot be used together, e.g. `with_portable_mode` ignores `with_base_dir`.
+  ConflictingOptions(&'static str, &'static str),
+  /// The path of the file could not be determined, e.g. because of an invalid file name.
+  Config(AppConfigError),
+}
+
+impl fmt::Display for BuildError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      BuildError::InvalidName { name, reason } => {
+        write!(f, "Invalid name {:?}: {}", name, reason)
+      }
+      BuildError::ConflictingOptions(first, second) => {
+        write!(f, "{} cannot be used together with {}", first, second)
+      }
+      BuildError::Config(err) => write!(f, "{}", err),
+    }
+  }
+}
+
+impl std::error::Error for BuildError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      BuildError::Config(err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{AppConfigManagerBuilder, BuildError};
+  use crate::{AppConfigError, Location, TestConfigDir};
+  use serde::{Deserialize, Serialize};
+  use std::{cell::RefCell, rc::Rc};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Panel {
+    visible: bool,
+  }
+
+  #[test]
+  fn build() {
+    let dir = TestConfigDir::new().unwrap();
+    let base = dir.path().to_owned();
+    let template = AppConfigManagerBuilder::<Panel>::new("builder", "sumibi-yakitori")
+      .with_options(move |manager| manager.with_base_dir(&base).with_auto_saving(false));
+    let left = template
+      .clone()
+      .with_file_name("left")
+      .build(Rc::new(RefCell::new(Panel::default())))
+      .unwrap();
+    let right = template
+      .with_file_name("right")
+      .build(Rc::new(RefCell::new(Panel::default())))
+      .unwrap();
+    assert_eq!(
+      left.config_path().unwrap(),
+      dir.path().join("com.sumibi-yakitori.builder/left.toml")
+    );
+    assert_eq!(
+      right.config_path().unwrap(),
+      dir.path().join("com.sumibi-yakitori.builder/right.toml")
+    );
+  }
+
+  #[test]
+  fn build_errors() {
+    let dir = TestConfigDir::new().unwrap();
+    let base = dir.path().to_owned();
+    let builder = AppConfigManagerBuilder::<Panel>::new("builder_errors", "sumibi-yakitori")
+      .with_options(move |manager| manager.with_base_dir(&base));
+    let build = |builder: AppConfigManagerBuilder<Panel>| {
+      builder
+        .build(Rc::new(RefCell::new(Panel::default())))
+        .err()
+        .unwrap()
+    };
+    assert!(matches!(
+      build(builder.clone().with_app_name("")),
+      BuildError::InvalidName { .. }
+    ));
+    assert!(matches!(
+      build(builder.clone().with_file_name(".hidden")),
+      BuildError::Config(AppConfigError::InvalidFileName(_))
+    ));
+    assert!(matches!(
+      build(
+        builder
+          .clone()
+          .with_options(|manager| manager.with_portable_mode(true))
+      ),
+      BuildError::ConflictingOptions("with_portable_mode", "with_base_dir")
+    ));
+    assert!(matches!(
+      build(
+        AppConfigManagerBuilder::<Panel>::new("builder_errors", "sumibi-yakitori").with_options(
+          |manager| {
+            manager
+              .with_portable_mode(true)
+              .with_location(Location::Cache)
+          }
+        )
+      ),
+      BuildError::ConflictingOptions("with_portable_mode", "with_location")
+    ));
+    // The rejected managers did not save on drop
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+  }
+}
@@ -0,0 +1,290 @@
+#[cfg(feature = "compression")]
+use crate::{deflate, shared::Boxed, FormatError, ThreadingBound};
+use crate::{AppConfigError, AppConfigManager, Result, SharedData};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+  borrow::Cow,
+  path::{Path, PathBuf},
+};
+
+/// Starts a compressed file, followed by the name of the [`Compression`] and a newline,
+/// e.g. `appconfig-compressed:zstd\n`.
+const HEADER: &[u8] = b"appconfig-compressed:";
+
+/// Compresses the file written by a manager, see [`AppConfigManager::with_compression`].
+///
+/// [`Deflate`] is built in. Others are implemented with a compression crate, e.g. `zstd`.
+#[cfg(feature = "compression")]
+pub trait Compression {
+  /// Written to the file to recognize it, e.g. `zstd`.
+  fn name(&self) -> &str;
+
+  fn compress(&self, bytes: &[u8]) -> std::result::Result<Vec<u8>, FormatError>;
+
+  fn decompress(&self, bytes: &[u8]) -> std::result::Result<Vec<u8>, FormatError>;
+}
+
+/// Raw DEFLATE from RFC 1951, readable by zlib and `flate2::read::DeflateDecoder`.
+/// Named `deflate` in the file.
+#[cfg(feature = "compression")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Deflate;
+
+#[cfg(feature = "compression")]
+impl Compression for Deflate {
+  fn name(&self) -> &str {
+    "deflate"
+  }
+
+  fn compress(&self, bytes: &[u8]) -> std::result::Result<Vec<u8>, FormatError> {
+    Ok(deflate::compress(bytes))
+  }
+
+  fn decompress(&self, bytes: &[u8]) -> std::result::Result<Vec<u8>, FormatError> {
+    deflate::decompress(bytes)
+  }
+}
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// Compresses the file with `compression` when saving, and decompresses it when loading.
+  ///
+  /// A file that is not compressed is still loaded, and saved compressed right away unless
+  /// [`with_save_migrated`](Self::with_save_migrated) is disabled.
+  /// With [`with_encryption`](Self::with_encryption), the file is compressed before it is encrypted.
+  ///
+  /// ```rust,no_run
+  /// use appconfig::{AppConfigManager, Deflate};
+  /// use std::{cell::RefCell, collections::HashMap, rc::Rc};
+  ///
+  /// let cache = Rc::new(RefCell::new(HashMap::<String, String>::new()));
+  /// let manager = AppConfigManager::new(cache, "my_app", "sumibi-yakitori").with_compression(Deflate);
+  /// ```
+  #[cfg(feature = "compression")]
+  pub fn set_compression(
+    &mut self,
+    compression: impl Compression + ThreadingBound<S::Threading> + 'static,
+  ) -> &mut Self {
+    self.compression = Some(Boxed::new(compression, |x| Box::new(x) as _));
+    self
+  }
+
+  #[cfg(feature = "compression")]
+  pub fn with_compression(
+    mut self,
+    compression: impl Compression + ThreadingBound<S::Threading> + 'static,
+  ) -> Self {
+    self.set_compression(compression);
+    self
+  }
+
+  /// Appends `suffix` to the name of the compressed file, e.g. `.zst` for `app_config.toml.zst`.
+  /// The file keeps its name by default.
+  ///
+  /// A file under the name without the suffix is loaded when the compressed one does not exist,
+  /// and removed after saving with [`with_remove_legacy_file`](Self::with_remove_legacy_file).
+  #[cfg(feature = "compression")]
+  pub fn set_compressed_file_suffix(&mut self, suffix: impl Into<String>) -> &mut Self {
+    self.compressed_file_suffix = suffix.into();
+    self
+  }
+
+  #[cfg(feature = "compression")]
+  pub fn with_compressed_file_suffix(mut self, suffix: impl Into<String>) -> Self {
+    self.set_compressed_file_suffix(suffix);
+    self
+  }
+
+  /// Returns `true` if the file is written compressed.
+  pub(crate) fn compresses(&self) -> bool {
+    #[cfg(feature = "compression")]
+    return self.compression.is_some();
+    #[cfg(not(feature = "compression"))]
+    false
+  }
+
+  /// The suffix appended to the file name, empty without compression.
+  pub(crate) fn compression_suffix(&self) -> &str {
+    #[cfg(feature = "compression")]
+    if self.compresses() {
+      return &self.compressed_file_suffix;
+    }
+    ""
+  }
+
+  /// The contents of a compressed file for `s`, or `s` itself without compression.
+  pub(crate) fn compress(&self, path: &Path, s: Vec<u8>) -> Result<Vec<u8>> {
+    #[cfg(feature = "compression")]
+    if let Some(compression) = &self.compression {
+      let compressed = compression
+        .compress(&s)
+        .map_err(AppConfigError::serialize(path))?;
+      let header = [HEADER, compression.name().as_bytes(), b"\n"].concat();
+      return Ok([header, compressed].concat());
+    }
+    let _ = path;
+    Ok(s)
+  }
+
+  /// Decompresses the file at `path` read as `s`. Also returns `true` if the file
+  /// was not compressed although it should be.
+  pub(crate) fn decompress<'a>(
+    &self,
+    path: &Path,
+    s: Cow<'a, [u8]>,
+  ) -> Result<(Cow<'a, [u8]>, bool)> {
+    let rest = match s.strip_prefix(HEADER) {
+      Some(rest) => rest,
+      None => return Ok((s, self.compresses())),
+    };
+    let end = rest.iter().position(|&x| x == b'\n').unwrap_or(rest.len());
+    let name = String::from_utf8_lossy(&rest[..end]);
+    let compressed = rest.get(end + 1..).unwrap_or_default();
+    #[cfg(feature = "compression")]
+    if let Some(compression) = self.compression.as_ref().filter(|x| x.name() == name) {
+      return compression
+        .decompress(compressed)
+        .map(|s| (Cow::Owned(s), false))
+        .map_err(AppConfigError::deserialize(path));
+    }
+    let _ = compressed;
+    Err(AppConfigError::Deserialize {
+      path: path.to_owned(),
+      source: format!("The file is compressed with {}", name).into(),
+    })
+  }
+
+  /// The file without the compressed file suffix, loaded when the compressed one does not exist.
+  pub(crate) fn find_uncompressed_file(&self) -> Result<Option<PathBuf>> {
+    Ok(
+      self
+        .uncompressed_file()?
+        .filter(|path| self.is_stored(path)),
+    )
+  }
+
+  /// The candidate of [`find_uncompressed_file`](Self::find_uncompressed_file).
+  pub(crate) fn uncompressed_file(&self) -> Result<Option<PathBuf>> {
+    if self.compression_suffix().is_empty() {
+      return Ok(None);
+    }
+    let path = self
+      .user_config_dir()?
+      .join(self.uncompressed_file_name(self.format.extension())?);
+    Ok(Some(path))
+  }
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+  use super::{Compression, Deflate};
+  use crate::{AppConfigManager, FormatError};
+  use serde::{Deserialize, Serialize};
+  use std::{cell::RefCell, rc::Rc};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Cache {
+    entries: Vec<String>,
+  }
+
+  /// Run-length encoding, standing in for a real compression.
+  struct Rle;
+
+  impl Compression for Rle {
+    fn name(&self) -> &str {
+      "rle"
+    }
+
+    fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>, FormatError> {
+      let mut compressed = Vec::new();
+      for &byte in bytes {
+        match compressed.len() {
+          len if len >= 2 && compressed[len - 1] == byte && compressed[len - 2] < u8::MAX => {
+            compressed[len - 2] += 1
+          }
+          _ => compressed.extend([1, byte]),
+        }
+      }
+      Ok(compressed)
+    }
+
+    fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>, FormatError> {
+      let chunks = bytes.chunks_exact(2);
+      if !chunks.remainder().is_empty() {
+        return Err("Truncated".into());
+      }
+      Ok(
+        chunks
+          .flat_map(|x| std::iter::repeat_n(x[1], x[0].into()))
+          .collect(),
+      )
+    }
+  }
+
+  #[test]
+  fn compression() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Cache {
+      entries: vec!["a".repeat(1000)],
+    }));
+    let new_manager = || {
+      AppConfigManager::new(config.clone(), "compression", "sumibi-yakitori")
+        .with_base_dir(dir.path())
+        .with_compression(Rle)
+        .with_auto_saving(false)
+    };
+    let manager = new_manager();
+    let path = manager.get_user_config_path().unwrap();
+    manager.save().unwrap();
+    let s = std::fs::read(&path).unwrap();
+    assert!(s.starts_with(b"appconfig-compressed:rle\n"));
+    assert!(s.len() < 100);
+    *config.borrow_mut() = Cache::default();
+    manager.load().unwrap();
+    assert_eq!(config.borrow().entries, ["a".repeat(1000)]);
+
+    // A plaintext file is compressed when loaded
+    std::fs::write(&path, "entries = [\"b\"]\n").unwrap();
+    manager.load().unwrap();
+    assert_eq!(config.borrow().entries, ["b"]);
+    assert!(std::fs::read(&path)
+      .unwrap()
+      .starts_with(b"appconfig-compressed:rle\n"));
+
+    // Under another name
+    let manager = new_manager()
+      .with_compressed_file_suffix(".rle")
+      .with_remove_legacy_file(true);
+    let compressed = manager.get_user_config_path().unwrap();
+    assert!(compressed.ends_with("app_config.toml.rle"));
+    std::fs::write(&path, "entries = [\"c\"]\n").unwrap();
+    manager.load().unwrap();
+    assert_eq!(config.borrow().entries, ["c"]);
+    assert!(compressed.exists());
+    assert!(!path.exists());
+  }
+
+  #[test]
+  fn deflate() {
+    let dir = tempfile::tempdir().unwrap();
+    let entries: Vec<_> = (0..100).map(|i| format!("/home/alice/{}.txt", i)).collect();
+    let config = Rc::from(RefCell::from(Cache {
+      entries: entries.clone(),
+    }));
+    let manager = AppConfigManager::new(config.clone(), "deflate", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_compression(Deflate)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    manager.save().unwrap();
+    let s = std::fs::read(&path).unwrap();
+    assert!(s.starts_with(b"appconfig-compressed:deflate\n"));
+    assert!(s.len() < 1000);
+    *config.borrow_mut() = Cache::default();
+    manager.load().unwrap();
+    assert_eq!(config.borrow().entries, entries);
+  }
+}
@@ -0,0 +1,123 @@
+use crate::{
+  fs, store::SharedStore, AppConfigError, AppConfigManager, ConfigKey, Result, SharedData,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::Path;
+
+/// What `save()` does when the file was changed by another program since the last load or save.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum ConflictPolicy {
+  /// Writes the file anyway, losing the other changes.
+  #[default]
+  Overwrite,
+  /// Fails with [`AppConfigError::ExternalModification`].
+  Error,
+  /// Leaves the other changes in place and writes nothing.
+  KeepTheirs,
+}
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// The stamp of `path` to check before writing it, `None` if nothing was read from
+  /// or written to this file yet or the policy is `Overwrite`.
+  pub(crate) fn conflict_stamp(&self, path: &Path) -> Option<fs::FileStamp> {
+    if self.conflict_policy == ConflictPolicy::Overwrite {
+      return None;
+    }
+    match &*crate::shared::lock(&self.file_stamp) {
+      Some((stamp_path, stamp)) if stamp_path == path => Some(stamp.clone()),
+      _ => None,
+    }
+  }
+}
+
+/// Applies `policy` before writing `key`, last seen as `stamp`.
+/// Returns `false` if the write must be skipped.
+pub(crate) fn check_conflict(
+  policy: ConflictPolicy,
+  stamp: Option<&fs::FileStamp>,
+  key: &ConfigKey,
+  store: Option<&SharedStore>,
+) -> Result<bool> {
+  let changed = match stamp {
+    Some(stamp) => match stamp.changed(key, store) {
+      Ok(changed) => changed,
+      // Removed by the other program
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => true,
+      Err(err) => return Err(AppConfigError::io(key.path())(err)),
+    },
+    None => false,
+  };
+  match policy {
+    _ if !changed => Ok(true),
+    ConflictPolicy::Error => Err(AppConfigError::ExternalModification {
+      path: key.path().to_owned(),
+    }),
+    _ => Ok(false),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::ConflictPolicy;
+  use crate::{AppConfigError, AppConfigManager};
+  use serde::{Deserialize, Serialize};
+  use std::{cell::RefCell, rc::Rc};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Volume {
+    level: u32,
+  }
+
+  #[test]
+  fn conflict_policies() {
+    let dir = tempfile::tempdir().unwrap();
+    for policy in [
+      ConflictPolicy::Overwrite,
+      ConflictPolicy::Error,
+      ConflictPolicy::KeepTheirs,
+    ] {
+      let config = Rc::from(RefCell::from(Volume { level: 1 }));
+      let manager = AppConfigManager::new(config.clone(), "conflict", "sumibi-yakitori")
+        .with_base_dir(dir.path())
+        .with_conflict_policy(policy)
+        .with_auto_saving(false);
+      let path = manager.get_user_config_path().unwrap();
+      std::fs::write(&path, "level = 1\n").unwrap();
+      manager.load().unwrap();
+      config.borrow_mut().level = 2;
+      // Edited in a text editor meanwhile
+      std::fs::write(&path, "level = 30\n").unwrap();
+
+      let result = manager.save();
+      let saved = std::fs::read_to_string(&path).unwrap();
+      match policy {
+        ConflictPolicy::Overwrite => {
+          result.unwrap();
+          assert_eq!(saved, "level = 2\n");
+        }
+        ConflictPolicy::Error => {
+          assert!(matches!(
+            result,
+            Err(AppConfigError::ExternalModification { .. })
+          ));
+          assert_eq!(saved, "level = 30\n");
+        }
+        _ => {
+          result.unwrap();
+          assert_eq!(saved, "level = 30\n");
+        }
+      }
+
+      // No conflict once the edit was loaded
+      manager.load().unwrap();
+      config.borrow_mut().level += 1;
+      manager.save().unwrap();
+      assert_ne!(std::fs::read_to_string(&path).unwrap(), saved);
+    }
+  }
+}
@@ -0,0 +1,453 @@
+//! ChaCha20-Poly1305 (RFC 8439) and scrypt (RFC 7914) for the `encryption` feature.
+
+/// The size of a ChaCha20-Poly1305 key.
+pub(crate) const KEY_LEN: usize = 32;
+/// The size of a ChaCha20-Poly1305 nonce.
+pub(crate) const NONCE_LEN: usize = 12;
+/// The size of a Poly1305 tag.
+pub(crate) const TAG_LEN: usize = 16;
+
+fn le32(bytes: &[u8]) -> u32 {
+  u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+  x[a] = x[a].wrapping_add(x[b]);
+  x[d] = (x[d] ^ x[a]).rotate_left(16);
+  x[c] = x[c].wrapping_add(x[d]);
+  x[b] = (x[b] ^ x[c]).rotate_left(12);
+  x[a] = x[a].wrapping_add(x[b]);
+  x[d] = (x[d] ^ x[a]).rotate_left(8);
+  x[c] = x[c].wrapping_add(x[d]);
+  x[b] = (x[b] ^ x[c]).rotate_left(7);
+}
+
+fn chacha20_block(key: &[u8; KEY_LEN], counter: u32, nonce: &[u8; NONCE_LEN]) -> [u8; 64] {
+  let mut state = [0u32; 16];
+  state[..4].copy_from_slice(&[0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574]);
+  for (i, word) in key.chunks_exact(4).enumerate() {
+    state[4 + i] = le32(word);
+  }
+  state[12] = counter;
+  for (i, word) in nonce.chunks_exact(4).enumerate() {
+    state[13 + i] = le32(word);
+  }
+  let mut x = state;
+  for _ in 0..10 {
+    quarter_round(&mut x, 0, 4, 8, 12);
+    quarter_round(&mut x, 1, 5, 9, 13);
+    quarter_round(&mut x, 2, 6, 10, 14);
+    quarter_round(&mut x, 3, 7, 11, 15);
+    quarter_round(&mut x, 0, 5, 10, 15);
+    quarter_round(&mut x, 1, 6, 11, 12);
+    quarter_round(&mut x, 2, 7, 8, 13);
+    quarter_round(&mut x, 3, 4, 9, 14);
+  }
+  let mut block = [0; 64];
+  for (i, bytes) in block.chunks_exact_mut(4).enumerate() {
+    bytes.copy_from_slice(&x[i].wrapping_add(state[i]).to_le_bytes());
+  }
+  block
+}
+
+/// XORs `data` with the key stream starting at block `counter`.
+fn chacha20(key: &[u8; KEY_LEN], counter: u32, nonce: &[u8; NONCE_LEN], data: &mut [u8]) {
+  for (i, chunk) in data.chunks_mut(64).enumerate() {
+    let block = chacha20_block(key, counter.wrapping_add(i as u32), nonce);
+    for (x, k) in chunk.iter_mut().zip(block.iter()) {
+      *x ^= k;
+    }
+  }
+}
+
+/// The Poly1305 tag of `message` with a one-time `key`, with 26-bit limbs.
+fn poly1305(key: &[u8; 32], message: &[u8]) -> [u8; TAG_LEN] {
+  const MASK: u32 = 0x3ff_ffff;
+  let r = [
+    le32(&key[0..]) & 0x3ff_ffff,
+    (le32(&key[3..]) >> 2) & 0x3ff_ff03,
+    (le32(&key[6..]) >> 4) & 0x3ff_c0ff,
+    (le32(&key[9..]) >> 6) & 0x3f0_3fff,
+    (le32(&key[12..]) >> 8) & 0x00f_ffff,
+  ];
+  let s = [r[1] * 5, r[2] * 5, r[3] * 5, r[4] * 5];
+  let mut h = [0u32; 5];
+
+  for chunk in message.chunks(16) {
+    let mut block = [0; 17];
+    block[..chunk.len()].copy_from_slice(chunk);
+    // The bit after the message, which is bit 128 for a full block
+    block[chunk.len()] = 1;
+    h[0] += le32(&block[0..]) & MASK;
+    h[1] += (le32(&block[3..]) >> 2) & MASK;
+    h[2] += (le32(&block[6..]) >> 4) & MASK;
+    h[3] += (le32(&block[9..]) >> 6) & MASK;
+    h[4] += (le32(&block[12..]) >> 8) | (u32::from(block[16]) << 24);
+
+    let m = |a: u32, b: u32| u64::from(a) * u64::from(b);
+    let d = [
+      m(h[0], r[0]) + m(h[1], s[3]) + m(h[2], s[2]) + m(h[3], s[1]) + m(h[4], s[0]),
+      m(h[0], r[1]) + m(h[1], r[0]) + m(h[2], s[3]) + m(h[3], s[2]) + m(h[4], s[1]),
+      m(h[0], r[2]) + m(h[1], r[1]) + m(h[2], r[0]) + m(h[3], s[3]) + m(h[4], s[2]),
+      m(h[0], r[3]) + m(h[1], r[2]) + m(h[2], r[1]) + m(h[3], r[0]) + m(h[4], s[3]),
+      m(h[0], r[4]) + m(h[1], r[3]) + m(h[2], r[2]) + m(h[3], r[1]) + m(h[4], r[0]),
+    ];
+    let mut carry = 0;
+    for i in 0..5 {
+      let x = d[i] + carry;
+      h[i] = x as u32 & MASK;
+      carry = x >> 26;
+    }
+    let x = u64::from(h[0]) + carry * 5;
+    h[0] = x as u32 & MASK;
+    h[1] += (x >> 26) as u32;
+  }
+
+  // Fully carries h, then subtracts p = 2^130 - 5 if h >= p
+  let mut carry = 0;
+  for x in h.iter_mut().skip(1) {
+    *x += carry;
+    carry = *x >> 26;
+    *x &= MASK;
+  }
+  h[0] += carry * 5;
+  h[1] += h[0] >> 26;
+  h[0] &= MASK;
+  let mut g = [0u32; 5];
+  let mut carry = 5;
+  for i in 0..5 {
+    g[i] = h[i] + carry;
+    carry = g[i] >> 26;
+    g[i] &= MASK;
+  }
+  // All ones if h + 5 reached 2^130
+  let select = 0u32.wrapping_sub(carry);
+  for i in 0..5 {
+    h[i] = (h[i] & !select) | (g[i] & select);
+  }
+
+  let words = [
+    h[0] | (h[1] << 26),
+    (h[1] >> 6) | (h[2] << 20),
+    (h[2] >> 12) | (h[3] << 14),
+    (h[3] >> 18) | (h[4] << 8),
+  ];
+  let mut tag = [0; TAG_LEN];
+  let mut carry = 0;
+  for (i, bytes) in tag.chunks_exact_mut(4).enumerate() {
+    let x = u64::from(words[i]) + u64::from(le32(&key[16 + i * 4..])) + carry;
+    bytes.copy_from_slice(&(x as u32).to_le_bytes());
+    carry = x >> 32;
+  }
+  tag
+}
+
+fn aead_tag(
+  key: &[u8; KEY_LEN],
+  nonce: &[u8; NONCE_LEN],
+  aad: &[u8],
+  ciphertext: &[u8],
+) -> [u8; TAG_LEN] {
+  let mut one_time_key = [0; 32];
+  one_time_key.copy_from_slice(&chacha20_block(key, 0, nonce)[..32]);
+  let pad = |len: usize| vec![0; (16 - len % 16) % 16];
+  let mut data = Vec::with_capacity(aad.len() + ciphertext.len() + 48);
+  data.extend_from_slice(aad);
+  data.extend(pad(aad.len()));
+  data.extend_from_slice(ciphertext);
+  data.extend(pad(ciphertext.len()));
+  data.extend_from_slice(&(aad.len() as u64).to_le_bytes());
+  data.extend_from_slice(&(ciphertext.len() as u64).to_le_bytes());
+  poly1305(&one_time_key, &data)
+}
+
+/// The ciphertext of `plaintext` followed by the tag.
+pub(crate) fn seal(
+  key: &[u8; KEY_LEN],
+  nonce: &[u8; NONCE_LEN],
+  aad: &[u8],
+  plaintext: &[u8],
+) -> Vec<u8> {
+  let mut sealed = plaintext.to_vec();
+  chacha20(key, 1, nonce, &mut sealed);
+  let tag = aead_tag(key, nonce, aad, &sealed);
+  sealed.extend_from_slice(&tag);
+  sealed
+}
+
+/// The plaintext of `sealed` from [`seal`], or `None` if the tag does not match.
+pub(crate) fn open(
+  key: &[u8; KEY_LEN],
+  nonce: &[u8; NONCE_LEN],
+  aad: &[u8],
+  sealed: &[u8],
+) -> Option<Vec<u8>> {
+  let split = sealed.len().checked_sub(TAG_LEN)?;
+  let (ciphertext, tag) = sealed.split_at(split);
+  let expected = aead_tag(key, nonce, aad, ciphertext);
+  // Compared without an early exit
+  if expected.iter().zip(tag).fold(0, |x, (a, b)| x | (a ^ b)) != 0 {
+    return None;
+  }
+  let mut plaintext = ciphertext.to_vec();
+  chacha20(key, 1, nonce, &mut plaintext);
+  Some(plaintext)
+}
+
+const SHA256_K: [u32; 64] = [
+  0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+  0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+  0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+  0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+  0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+  0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+  0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+  0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+fn sha256(parts: &[&[u8]]) -> [u8; 32] {
+  let mut h: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+  ];
+  let len: usize = parts.iter().map(|x| x.len()).sum();
+  let mut message = Vec::with_capacity(len + 72);
+  for part in parts {
+    message.extend_from_slice(part);
+  }
+  message.push(0x80);
+  message.resize((len + 8) / 64 * 64 + 56, 0);
+  message.extend_from_slice(&(len as u64 * 8).to_be_bytes());
+
+  for chunk in message.chunks_exact(64) {
+    let mut w = [0u32; 64];
+    for (i, word) in chunk.chunks_exact(4).enumerate() {
+      w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+    }
+    for i in 16..64 {
+      let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+      let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+      w[i] = w[i - 16]
+        .wrapping_add(s0)
+        .wrapping_add(w[i - 7])
+        .wrapping_add(s1);
+    }
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+    for i in 0..64 {
+      let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+      let ch = (e & f) ^ (!e & g);
+      let t1 = hh
+        .wrapping_add(s1)
+        .wrapping_add(ch)
+        .wrapping_add(SHA256_K[i])
+        .wrapping_add(w[i]);
+      let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+      let maj = (a & b) ^ (a & c) ^ (b & c);
+      let t2 = s0.wrapping_add(maj);
+      hh = g;
+      g = f;
+      f = e;
+      e = d.wrapping_add(t1);
+      d = c;
+      c = b;
+      b = a;
+      a = t1.wrapping_add(t2);
+    }
+    for (x, y) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+      *x = x.wrapping_add(y);
+    }
+  }
+
+  let mut digest = [0; 32];
+  for (bytes, x) in digest.chunks_exact_mut(4).zip(h) {
+    bytes.copy_from_slice(&x.to_be_bytes());
+  }
+  digest
+}
+
+fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+  let mut block = [0; 64];
+  if key.len() > 64 {
+    block[..32].copy_from_slice(&sha256(&[key]));
+  }
+  else {
+    block[..key.len()].copy_from_slice(key);
+  }
+  let inner_key = block.map(|x| x ^ 0x36);
+  let outer_key = block.map(|x| x ^ 0x5c);
+  let mut inner = vec![&inner_key[..]];
+  inner.extend_from_slice(parts);
+  sha256(&[&outer_key, &sha256(&inner)])
+}
+
+/// PBKDF2-HMAC-SHA256 with one iteration, as used by scrypt.
+fn pbkdf2_sha256(password: &[u8], salt: &[u8], len: usize) -> Vec<u8> {
+  (1..=len.div_ceil(32))
+    .flat_map(|i: usize| hmac_sha256(password, &[salt, &(i as u32).to_be_bytes()]))
+    .take(len)
+    .collect()
+}
+
+fn salsa20_8(block: &mut [u32]) {
+  let mut x = [0u32; 16];
+  x.copy_from_slice(block);
+  let mut step = |a: usize, b: usize, c: usize, r: u32| {
+    x[a] ^= x[b].wrapping_add(x[c]).rotate_left(r);
+  };
+  for _ in 0..4 {
+    for &(i, j, k, l) in &[(0, 4, 8, 12), (5, 9, 13, 1), (10, 14, 2, 6), (15, 3, 7, 11)] {
+      step(j, i, l, 7);
+      step(k, j, i, 9);
+      step(l, k, j, 13);
+      step(i, l, k, 18);
+    }
+    for &(i, j, k, l) in &[(0, 1, 2, 3), (5, 6, 7, 4), (10, 11, 8, 9), (15, 12, 13, 14)] {
+      step(j, i, l, 7);
+      step(k, j, i, 9);
+      step(l, k, j, 13);
+      step(i, l, k, 18);
+    }
+  }
+  for (y, x) in block.iter_mut().zip(x) {
+    *y = y.wrapping_add(x);
+  }
+}
+
+/// BlockMix with Salsa20/8 from `input` to `output`, both of `2 * r` blocks of 16 words.
+fn block_mix(input: &[u32], output: &mut [u32]) {
+  let blocks = input.len() / 16;
+  let mut x = [0u32; 16];
+  x.copy_from_slice(&input[input.len() - 16..]);
+  for (i, block) in input.chunks_exact(16).enumerate() {
+    for (x, y) in x.iter_mut().zip(block) {
+      *x ^= y;
+    }
+    salsa20_8(&mut x);
+    // Even blocks go to the first half, odd blocks to the second
+    let j = i / 2 + (i % 2) * (blocks / 2);
+    output[j * 16..(j + 1) * 16].copy_from_slice(&x);
+  }
+}
+
+fn ro_mix(block: &mut [u32], n: usize) {
+  let len = block.len();
+  let mut v = vec![0u32; len * n];
+  let mut x = block.to_vec();
+  let mut y = vec![0u32; len];
+  for i in 0..n {
+    v[i * len..(i + 1) * len].copy_from_slice(&x);
+    block_mix(&x, &mut y);
+    std::mem::swap(&mut x, &mut y);
+  }
+  for _ in 0..n {
+    let j = x[len - 16] as usize & (n - 1);
+    for (x, v) in x.iter_mut().zip(&v[j * len..(j + 1) * len]) {
+      *x ^= v;
+    }
+    block_mix(&x, &mut y);
+    std::mem::swap(&mut x, &mut y);
+  }
+  block.copy_from_slice(&x);
+}
+
+/// scrypt with the cost `n`, a power of two, the block size `r` and the parallelism `p`.
+pub(crate) fn scrypt(
+  password: &[u8],
+  salt: &[u8],
+  n: usize,
+  r: usize,
+  p: usize,
+  len: usize,
+) -> Vec<u8> {
+  debug_assert!(n.is_power_of_two());
+  let bytes = pbkdf2_sha256(password, salt, p * 128 * r);
+  let mut words: Vec<u32> = bytes.chunks_exact(4).map(le32).collect();
+  for block in words.chunks_exact_mut(32 * r) {
+    ro_mix(block, n);
+  }
+  let bytes: Vec<u8> = words.iter().flat_map(|x| x.to_le_bytes()).collect();
+  pbkdf2_sha256(password, &bytes, len)
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::encryption::unhex;
+  use std::convert::TryInto;
+
+  fn bytes(hex: &str) -> Vec<u8> {
+    unhex(hex.as_bytes()).unwrap()
+  }
+
+  #[test]
+  fn poly1305() {
+    // RFC 8439, 2.5.2
+    let key = bytes("85d6be7857556d337f4452fe42d506a80103808afb0db2fd4abff6af4149f51b");
+    assert_eq!(
+      super::poly1305(
+        key[..].try_into().unwrap(),
+        b"Cryptographic Forum Research Group"
+      )
+      .to_vec(),
+      bytes("a8061dc1305136c6c22b8baf0c0127a9")
+    );
+  }
+
+  #[test]
+  fn chacha20_poly1305() {
+    // RFC 8439, 2.8.2
+    let key: Vec<u8> = (0x80..0xa0).collect();
+    let key = key[..].try_into().unwrap();
+    let nonce = bytes("070000004041424344454647");
+    let nonce = nonce[..].try_into().unwrap();
+    let aad = bytes("50515253c0c1c2c3c4c5c6c7");
+    let plaintext: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+    let sealed = super::seal(key, nonce, &aad, plaintext);
+    assert_eq!(
+      sealed,
+      bytes(concat!(
+        "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d6",
+        "3dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b36",
+        "92ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc",
+        "3ff4def08e4b7a9de576d26586cec64b6116",
+        "1ae10b594f09e26a7e902ecbd0600691"
+      ))
+    );
+    assert_eq!(super::open(key, nonce, &aad, &sealed).unwrap(), plaintext);
+
+    let mut modified = sealed.clone();
+    modified[0] ^= 1;
+    assert_eq!(super::open(key, nonce, &aad, &modified), None);
+    assert_eq!(super::open(key, nonce, b"", &sealed), None);
+    assert_eq!(super::open(key, nonce, &aad, &sealed[..15]), None);
+  }
+
+  #[test]
+  fn sha256() {
+    assert_eq!(
+      super::sha256(&[b"abc"]).to_vec(),
+      bytes("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+    );
+    assert_eq!(
+      super::sha256(&[&[b'a'; 999], b"a"]).to_vec(),
+      bytes("41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3")
+    );
+  }
+
+  #[test]
+  fn scrypt() {
+    // RFC 7914, 11 and 12
+    assert_eq!(
+      super::pbkdf2_sha256(b"passwd", b"salt", 64),
+      bytes(concat!(
+        "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc",
+        "49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783"
+      ))
+    );
+    assert_eq!(
+      super::scrypt(b"", b"", 16, 1, 1, 64),
+      bytes(concat!(
+        "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442",
+        "fcd0069ded0948f8326a753a0fc81f17e8d3e0fb2e0d3628cf35e20c38d18906"
+      ))
+    );
+  }
+}
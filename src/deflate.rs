@@ -0,0 +1,387 @@
+//! Raw DEFLATE (RFC 1951) for the `compression` feature. Compresses with LZ77 and the fixed
+//! Huffman codes, which suit short text files, and decompresses any DEFLATE stream.
+
+use crate::FormatError;
+
+const LENGTH_BASE: [u16; 29] = [
+  3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+  163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+  0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+  1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049,
+  3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+  0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// The number of earlier positions tried for each match.
+const MAX_CHAIN: usize = 64;
+
+struct BitWriter {
+  bytes: Vec<u8>,
+  bits: u32,
+  len: u32,
+}
+
+impl BitWriter {
+  fn write(&mut self, value: u32, len: u32) {
+    self.bits |= value << self.len;
+    self.len += len;
+    while self.len >= 8 {
+      self.bytes.push(self.bits as u8);
+      self.bits >>= 8;
+      self.len -= 8;
+    }
+  }
+
+  /// Huffman codes are written from the most significant bit.
+  fn write_code(&mut self, code: u32, len: u32) {
+    self.write(code.reverse_bits() >> (32 - len), len);
+  }
+
+  fn write_literal(&mut self, symbol: u16) {
+    let symbol = u32::from(symbol);
+    match symbol {
+      0..=143 => self.write_code(0x30 + symbol, 8),
+      144..=255 => self.write_code(0x190 + symbol - 144, 9),
+      256..=279 => self.write_code(symbol - 256, 7),
+      _ => self.write_code(0xc0 + symbol - 280, 8),
+    }
+  }
+
+  fn write_match(&mut self, len: usize, distance: usize) {
+    let i = LENGTH_BASE
+      .iter()
+      .rposition(|&x| usize::from(x) <= len)
+      .unwrap();
+    self.write_literal(257 + i as u16);
+    self.write(
+      (len - usize::from(LENGTH_BASE[i])) as u32,
+      u32::from(LENGTH_EXTRA[i]),
+    );
+    let i = DISTANCE_BASE
+      .iter()
+      .rposition(|&x| usize::from(x) <= distance)
+      .unwrap();
+    self.write_code(i as u32, 5);
+    self.write(
+      (distance - usize::from(DISTANCE_BASE[i])) as u32,
+      u32::from(DISTANCE_EXTRA[i]),
+    );
+  }
+
+  fn finish(mut self) -> Vec<u8> {
+    if self.len > 0 {
+      self.bytes.push(self.bits as u8);
+    }
+    self.bytes
+  }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+  (usize::from(bytes[0]) << 10 ^ usize::from(bytes[1]) << 5 ^ usize::from(bytes[2])) & 0x7fff
+}
+
+/// `bytes` as a single block with the fixed Huffman codes.
+pub(crate) fn compress(bytes: &[u8]) -> Vec<u8> {
+  let mut out = BitWriter {
+    bytes: Vec::with_capacity(bytes.len() / 2 + 8),
+    bits: 0,
+    len: 0,
+  };
+  // The last block, with the fixed Huffman codes
+  out.write(0b011, 3);
+
+  // The last position for each hash, and the previous position with the same hash
+  let mut head = vec![usize::MAX; 0x8000];
+  let mut prev = vec![usize::MAX; bytes.len()];
+
+  let mut i = 0;
+  while i < bytes.len() {
+    let mut best = (0, 0);
+    if i + MIN_MATCH <= bytes.len() {
+      let max = MAX_MATCH.min(bytes.len() - i);
+      let mut candidate = head[hash(&bytes[i..])];
+      for _ in 0..MAX_CHAIN {
+        if candidate == usize::MAX || i - candidate > WINDOW {
+          break;
+        }
+        let len = bytes[candidate..]
+          .iter()
+          .zip(&bytes[i..i + max])
+          .take_while(|(a, b)| a == b)
+          .count();
+        if len > best.0 {
+          best = (len, i - candidate);
+          if len == max {
+            break;
+          }
+        }
+        candidate = prev[candidate];
+      }
+    }
+
+    let (mut len, distance) = best;
+    if len >= MIN_MATCH {
+      out.write_match(len, distance);
+    }
+    else {
+      out.write_literal(u16::from(bytes[i]));
+      len = 1;
+    }
+    // Positions without 3 bytes left are never matched
+    for j in i..(i + len).min((bytes.len() + 1).saturating_sub(MIN_MATCH)) {
+      let h = hash(&bytes[j..]);
+      prev[j] = head[h];
+      head[h] = j;
+    }
+    i += len;
+  }
+  out.write_literal(256);
+  out.finish()
+}
+
+struct BitReader<'a> {
+  bytes: &'a [u8],
+  pos: usize,
+  bit: u32,
+}
+
+impl BitReader<'_> {
+  fn bits(&mut self, len: u32) -> Result<u32, FormatError> {
+    let mut value = 0;
+    for i in 0..len {
+      let byte = self
+        .bytes
+        .get(self.pos)
+        .ok_or("Unexpected end of the compressed data")?;
+      value |= u32::from(byte >> self.bit & 1) << i;
+      self.bit += 1;
+      if self.bit == 8 {
+        self.bit = 0;
+        self.pos += 1;
+      }
+    }
+    Ok(value)
+  }
+
+  fn align(&mut self) {
+    if self.bit > 0 {
+      self.bit = 0;
+      self.pos += 1;
+    }
+  }
+}
+
+/// A canonical Huffman code, as the number of codes of each length
+/// and the symbols ordered by their codes.
+struct Huffman {
+  counts: [u16; 16],
+  symbols: Vec<u16>,
+}
+
+impl Huffman {
+  fn new(lengths: &[u8]) -> Self {
+    let mut counts = [0; 16];
+    for &len in lengths {
+      counts[usize::from(len)] += 1;
+    }
+    counts[0] = 0;
+    let mut symbols: Vec<u16> = (0..lengths.len() as u16)
+      .filter(|&x| lengths[usize::from(x)] > 0)
+      .collect();
+    symbols.sort_by_key(|&x| lengths[usize::from(x)]);
+    Self { counts, symbols }
+  }
+
+  fn decode(&self, reader: &mut BitReader<'_>) -> Result<u16, FormatError> {
+    let (mut code, mut first, mut index) = (0, 0, 0);
+    for &count in &self.counts[1..] {
+      code |= reader.bits(1)? as usize;
+      let count = usize::from(count);
+      if code < first + count {
+        return Ok(self.symbols[index + code - first]);
+      }
+      index += count;
+      first = (first + count) << 1;
+      code <<= 1;
+    }
+    Err("Invalid Huffman code".into())
+  }
+}
+
+/// The code lengths of the dynamic Huffman codes of a block.
+fn dynamic_codes(reader: &mut BitReader<'_>) -> Result<(Huffman, Huffman), FormatError> {
+  const ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+  ];
+  let literals = reader.bits(5)? as usize + 257;
+  let distances = reader.bits(5)? as usize + 1;
+  let code_lengths = reader.bits(4)? as usize + 4;
+  let mut lengths = [0; 19];
+  for &i in &ORDER[..code_lengths] {
+    lengths[i] = reader.bits(3)? as u8;
+  }
+  let code = Huffman::new(&lengths);
+
+  let mut lengths = Vec::with_capacity(literals + distances);
+  while lengths.len() < literals + distances {
+    let symbol = code.decode(reader)?;
+    let (len, repeat) = match symbol {
+      0..=15 => (symbol as u8, 1),
+      16 => (
+        *lengths.last().ok_or("Invalid code lengths")?,
+        3 + reader.bits(2)?,
+      ),
+      17 => (0, 3 + reader.bits(3)?),
+      _ => (0, 11 + reader.bits(7)?),
+    };
+    lengths.extend(std::iter::repeat_n(len, repeat as usize));
+  }
+  if lengths.len() > literals + distances {
+    return Err("Invalid code lengths".into());
+  }
+  Ok((
+    Huffman::new(&lengths[..literals]),
+    Huffman::new(&lengths[literals..]),
+  ))
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+  let mut lengths = [8; 288];
+  lengths[144..256].fill(9);
+  lengths[256..280].fill(7);
+  (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+pub(crate) fn decompress(bytes: &[u8]) -> Result<Vec<u8>, FormatError> {
+  let mut reader = BitReader {
+    bytes,
+    pos: 0,
+    bit: 0,
+  };
+  let mut out = Vec::with_capacity(bytes.len() * 3);
+  loop {
+    let last = reader.bits(1)? == 1;
+    match reader.bits(2)? {
+      0 => {
+        reader.align();
+        let header = bytes
+          .get(reader.pos..reader.pos + 4)
+          .ok_or("Unexpected end of the compressed data")?;
+        let len = usize::from(u16::from_le_bytes([header[0], header[1]]));
+        if u16::from_le_bytes([header[2], header[3]]) != !(len as u16) {
+          return Err("Invalid stored block".into());
+        }
+        reader.pos += 4;
+        let stored = bytes
+          .get(reader.pos..reader.pos + len)
+          .ok_or("Unexpected end of the compressed data")?;
+        out.extend_from_slice(stored);
+        reader.pos += len;
+      }
+      btype @ (1 | 2) => {
+        let (literals, distances) = if btype == 1 {
+          fixed_codes()
+        }
+        else {
+          dynamic_codes(&mut reader)?
+        };
+        loop {
+          let symbol = usize::from(literals.decode(&mut reader)?);
+          if symbol < 256 {
+            out.push(symbol as u8);
+            continue;
+          }
+          if symbol == 256 {
+            break;
+          }
+          let i = symbol - 257;
+          let len = usize::from(*LENGTH_BASE.get(i).ok_or("Invalid length")?)
+            + reader.bits(u32::from(LENGTH_EXTRA[i]))? as usize;
+          let i = usize::from(distances.decode(&mut reader)?);
+          let distance = usize::from(*DISTANCE_BASE.get(i).ok_or("Invalid distance")?)
+            + reader.bits(u32::from(DISTANCE_EXTRA[i]))? as usize;
+          let start = out.len().checked_sub(distance).ok_or("Invalid distance")?;
+          // The copy can overlap the bytes it writes
+          for j in start..start + len {
+            out.push(out[j]);
+          }
+        }
+      }
+      _ => return Err("Invalid block type".into()),
+    }
+    if last {
+      return Ok(out);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::encryption::unhex;
+
+  const TEXT: &str = "[window]\nwidth = 1280\nheight = 720\n\n[recent]\nfiles = [\"/home/alice/a.txt\", \"/home/alice/b.txt\", \"/home/alice/c.txt\"]\n";
+
+  #[test]
+  fn round_trip() {
+    let repeated = TEXT.repeat(400);
+    let long_run = [b'a'; 70_000];
+    for bytes in [
+      &b""[..],
+      b"a",
+      TEXT.as_bytes(),
+      repeated.as_bytes(),
+      &long_run[..],
+    ] {
+      let compressed = super::compress(bytes);
+      assert_eq!(super::decompress(&compressed).unwrap(), bytes);
+    }
+    assert!(super::compress(repeated.as_bytes()).len() < repeated.len() / 20);
+  }
+
+  #[test]
+  fn decompress() {
+    // Generated with Python's `zlib`
+    let dynamic = concat!(
+      "3592b96143310c43fb4ce111c44b47e1595ca5f204d93e0288dfc1e627f148e8fbfbf719aff76bfc7cafb2ab",
+      "8ccaaf4aaab8ea5025aa93b2aef4a29c5746ffbbd0d4dfee2b670f3857ee9e6ab0b22133bab92a30b4ec0e0b",
+      "3af524a3ed6907a3b19c6d92421598fb560fecc335ed90507b812045e020c854852befee711094f7340741ad",
+      "f671104c113808e65301c17a7a40b09e693c817c02044704018223b6e00d86b0834718da287805d3b2513c9d",
+      "2862f2974e14c0b0d0f562f75d35132056ba79328d2994641c5349255996224cb26c659b9d88424f4632c492",
+      "ccc4f44c92a1f8501f583c866682c573b45f0d662996028bcfa706165feaab60d09a5960f1f33c4bb0c4c352",
+      "7c9a26ce5afd26d4c71712daaff844f2eefe0f",
+    );
+    let keys: String = (0..60)
+      .map(|i| format!("key_{} = {}\n", i, i * i))
+      .collect();
+    assert_eq!(
+      super::decompress(&unhex(dynamic.as_bytes()).unwrap()).unwrap(),
+      keys.as_bytes()
+    );
+
+    let fixed = concat!(
+      "8b2ecfcc4bc92f8fe52acf4c29c950b0553034b230e0ca48cd4ccf2801f2cc8d0cb8b8a28b529353f34a62",
+      "b9d23273528b81a2d14afa19f9b9a9fa893999c94052afa4a2444947014530099b60325830960b00",
+    );
+    let stored: String = TEXT.bytes().map(|x| format!("{:02x}", x)).collect();
+    let stored = format!("0175008aff{}", stored);
+    for hex in [fixed, &stored] {
+      assert_eq!(
+        super::decompress(&unhex(hex.as_bytes()).unwrap()).unwrap(),
+        TEXT.as_bytes()
+      );
+    }
+
+    let fixed = unhex(fixed.as_bytes()).unwrap();
+    assert!(super::decompress(&fixed[..fixed.len() - 2]).is_err());
+    assert!(super::decompress(b"\xff").is_err());
+  }
+}
@@ -0,0 +1,87 @@
+use crate::FormatError;
+use toml_edit::{DocumentMut, Item, TableLike};
+
+/// Writes `value` into the TOML document `original`, keeping the comments, key order and
+/// whitespace of the keys that remain. New keys are appended to their table.
+pub(crate) fn patch_document(original: &str, value: &toml::Table) -> Result<String, FormatError> {
+  let mut document: DocumentMut = original.parse()?;
+  patch_table(document.as_table_mut(), value)?;
+  Ok(document.to_string())
+}
+
+fn patch_table(table: &mut dyn TableLike, value: &toml::Table) -> Result<(), FormatError> {
+  let removed: Vec<String> = table
+    .iter()
+    .map(|(key, _)| key.to_owned())
+    .filter(|key| !value.contains_key(key))
+    .collect();
+  for key in removed {
+    table.remove(&key);
+  }
+  for (key, new) in value {
+    match table.get_mut(key) {
+      Some(item) => patch_item(item, new)?,
+      None => {
+        table.insert(key, to_item(new)?);
+      }
+    }
+  }
+  Ok(())
+}
+
+fn patch_item(item: &mut Item, new: &toml::Value) -> Result<(), FormatError> {
+  if let (Some(table), toml::Value::Table(new)) = (item.as_table_like_mut(), new) {
+    return patch_table(table, new);
+  }
+  if let Item::Value(old) = item {
+    // Unchanged values keep their formatting, e.g. `0xff` or a literal string
+    if to_toml(old).as_ref() == Some(new) {
+      return Ok(());
+    }
+    let decor = old.decor().clone();
+    *old = to_value(new)?;
+    *old.decor_mut() = decor;
+    return Ok(());
+  }
+  *item = to_item(new)?;
+  Ok(())
+}
+
+fn to_item(value: &toml::Value) -> Result<Item, FormatError> {
+  match value {
+    toml::Value::Table(table) => {
+      let document: DocumentMut = toml::to_string(table)?.parse()?;
+      Ok(Item::Table(document.as_table().clone()))
+    }
+    value => Ok(Item::Value(to_value(value)?)),
+  }
+}
+
+fn to_value(value: &toml::Value) -> Result<toml_edit::Value, FormatError> {
+  Ok(value.to_string().parse()?)
+}
+
+fn to_toml(value: &toml_edit::Value) -> Option<toml::Value> {
+  let value = value.clone().decorated("", "");
+  toml::from_str::<toml::Table>(&format!("value = {}", value))
+    .ok()?
+    .remove("value")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::patch_document;
+
+  #[test]
+  fn patch() {
+    let original = "# my comment\nfont_size = 14 # points\nmask = 0xff\n\n[window]\n# width in pixels\nwidth = 800\nold = 1\n";
+    let value = toml::from_str(
+      "font_size = 16\nmask = 255\n[window]\nwidth = 800\nheight = 600\n[plugins]\nenabled = true\n",
+    )
+    .unwrap();
+    assert_eq!(
+      patch_document(original, &value).unwrap(),
+      "# my comment\nfont_size = 16 # points\nmask = 0xff\n\n[window]\n# width in pixels\nwidth = 800\nheight = 600\n\n[plugins]\nenabled = true\n"
+    );
+  }
+}
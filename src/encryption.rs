@@ -0,0 +1,553 @@
+#[cfg(feature = "encryption")]
+use crate::{
+  crypto,
+  shared::{lock, Boxed},
+  FormatError, ThreadingBound,
+};
+use crate::{AppConfigError, AppConfigManager, Result, SharedData};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{borrow::Cow, path::Path};
+#[cfg(feature = "encryption")]
+use std::{fmt, sync::Mutex};
+
+/// Starts an encrypted file, followed by the version of the container,
+/// the salt in hex and a newline, e.g. `appconfig-encrypted:1:9f2c…\n`.
+const HEADER: &[u8] = b"appconfig-encrypted:";
+
+/// The version of the container written by `save()`.
+const VERSION: &[u8] = b"1";
+
+/// Encrypts the file written by a manager, see [`AppConfigManager::with_encryption`].
+///
+/// [`ChaCha20Poly1305`] is built in. Others are implemented with an authenticated cipher
+/// from another crate, e.g. `aes-gcm` or `age`. The encrypted bytes must carry what decrypting them needs besides the key, such as the nonce.
+#[cfg(feature = "encryption")]
+pub trait Cipher {
+  fn encrypt(&self, key: &SecretKey, plaintext: &[u8])
+    -> std::result::Result<Vec<u8>, FormatError>;
+
+  /// Fails when the key is wrong or the encrypted bytes were modified.
+  fn decrypt(
+    &self,
+    key: &SecretKey,
+    ciphertext: &[u8],
+  ) -> std::result::Result<Vec<u8>, FormatError>;
+}
+
+/// The key passed to a [`Cipher`]. The bytes are overwritten when it is dropped.
+#[cfg(feature = "encryption")]
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecretKey(Vec<u8>);
+
+#[cfg(feature = "encryption")]
+impl SecretKey {
+  pub fn new(bytes: impl Into<Vec<u8>>) -> Self {
+    Self(bytes.into())
+  }
+
+  pub fn as_bytes(&self) -> &[u8] {
+    &self.0
+  }
+
+  /// A 32-byte key for [`ChaCha20Poly1305`] derived from `passphrase` and the salt of a
+  /// [`KeyRequest`] with scrypt (N = 2^15, r = 8, p = 1), which takes about 0.1 s
+  /// in an optimized build.
+  pub fn from_passphrase(passphrase: &str, salt: &[u8]) -> Self {
+    Self(crypto::scrypt(
+      passphrase.as_bytes(),
+      salt,
+      1 << 15,
+      8,
+      1,
+      crypto::KEY_LEN,
+    ))
+  }
+}
+
+#[cfg(feature = "encryption")]
+impl fmt::Debug for SecretKey {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("SecretKey(***)")
+  }
+}
+
+#[cfg(feature = "encryption")]
+impl Drop for SecretKey {
+  fn drop(&mut self) {
+    for byte in &mut self.0 {
+      // SAFETY: `byte` is a valid reference. The write is not optimized away.
+      unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+  }
+}
+
+/// What a key is requested for.
+#[cfg(feature = "encryption")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum KeyPurpose {
+  /// Writing a file that has not been decrypted before.
+  Encrypt,
+  /// Reading a file.
+  Decrypt,
+}
+
+/// Passed to a [`KeyProvider`].
+#[cfg(feature = "encryption")]
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct KeyRequest<'a> {
+  pub purpose: KeyPurpose,
+  /// The file to be read or written.
+  pub path: &'a Path,
+  /// Stored in the file to derive a key from a passphrase,
+  /// e.g. with [`SecretKey::from_passphrase`].
+  /// A new salt is generated for each key requested for encryption.
+  pub salt: &'a [u8],
+}
+
+/// Supplies the key for [`AppConfigManager::with_encryption`],
+/// e.g. from the OS keychain or by asking the user for a passphrase.
+///
+/// Called on the first load or save that needs the key. The key is then kept until
+/// a file with another salt is read. Implemented for closures and for a fixed [`SecretKey`].
+#[cfg(feature = "encryption")]
+pub trait KeyProvider {
+  fn key(&self, request: &KeyRequest<'_>) -> std::result::Result<SecretKey, FormatError>;
+}
+
+#[cfg(feature = "encryption")]
+impl<F> KeyProvider for F
+where
+  F: Fn(&KeyRequest<'_>) -> std::result::Result<SecretKey, FormatError>,
+{
+  fn key(&self, request: &KeyRequest<'_>) -> std::result::Result<SecretKey, FormatError> {
+    self(request)
+  }
+}
+
+#[cfg(feature = "encryption")]
+impl KeyProvider for SecretKey {
+  fn key(&self, _request: &KeyRequest<'_>) -> std::result::Result<SecretKey, FormatError> {
+    Ok(self.clone())
+  }
+}
+
+/// A fixed passphrase that the key is derived from with [`SecretKey::from_passphrase`].
+/// The passphrase is overwritten when it is dropped.
+#[cfg(feature = "encryption")]
+pub struct Passphrase(SecretKey);
+
+#[cfg(feature = "encryption")]
+impl Passphrase {
+  pub fn new(passphrase: impl Into<String>) -> Self {
+    Self(SecretKey::new(passphrase.into()))
+  }
+}
+
+#[cfg(feature = "encryption")]
+impl fmt::Debug for Passphrase {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("Passphrase(***)")
+  }
+}
+
+#[cfg(feature = "encryption")]
+impl KeyProvider for Passphrase {
+  fn key(&self, request: &KeyRequest<'_>) -> std::result::Result<SecretKey, FormatError> {
+    let passphrase = std::str::from_utf8(self.0.as_bytes())?;
+    Ok(SecretKey::from_passphrase(passphrase, request.salt))
+  }
+}
+
+/// ChaCha20-Poly1305 from RFC 8439 with a 32-byte key, e.g. from [`Passphrase`].
+/// A random nonce is written before the ciphertext.
+#[cfg(feature = "encryption")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChaCha20Poly1305;
+
+#[cfg(feature = "encryption")]
+impl ChaCha20Poly1305 {
+  fn key(key: &SecretKey) -> std::result::Result<&[u8; crypto::KEY_LEN], FormatError> {
+    use std::convert::TryInto;
+
+    key
+      .as_bytes()
+      .try_into()
+      .map_err(|_| format!("The key must be {} bytes", crypto::KEY_LEN).into())
+  }
+}
+
+#[cfg(feature = "encryption")]
+impl Cipher for ChaCha20Poly1305 {
+  fn encrypt(
+    &self,
+    key: &SecretKey,
+    plaintext: &[u8],
+  ) -> std::result::Result<Vec<u8>, FormatError> {
+    let key = Self::key(key)?;
+    let mut nonce = [0; crypto::NONCE_LEN];
+    getrandom::fill(&mut nonce)?;
+    Ok([&nonce[..], &crypto::seal(key, &nonce, b"", plaintext)].concat())
+  }
+
+  fn decrypt(
+    &self,
+    key: &SecretKey,
+    ciphertext: &[u8],
+  ) -> std::result::Result<Vec<u8>, FormatError> {
+    let key = Self::key(key)?;
+    let invalid = || FormatError::from("The key is wrong or the file was modified");
+    if ciphertext.len() < crypto::NONCE_LEN {
+      return Err(invalid());
+    }
+    let (nonce, sealed) = ciphertext.split_at(crypto::NONCE_LEN);
+    let mut nonce_array = [0; crypto::NONCE_LEN];
+    nonce_array.copy_from_slice(nonce);
+    crypto::open(key, &nonce_array, b"", sealed).ok_or_else(invalid)
+  }
+}
+
+#[cfg(feature = "encryption")]
+pub(crate) struct Encryption<M> {
+  cipher: Boxed<M, dyn Cipher>,
+  provider: Boxed<M, dyn KeyProvider>,
+  /// The salt and the last key that encrypted or decrypted a file.
+  key: Mutex<Option<(Vec<u8>, SecretKey)>>,
+}
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// Encrypts the file with `cipher` when saving, and decrypts it when loading.
+  /// `key` is a [`SecretKey`] or a [`KeyProvider`].
+  ///
+  /// A file that cannot be decrypted fails with [`AppConfigError::DecryptionFailed`],
+  /// which is never skipped like a parse error. A file that is not encrypted is still loaded,
+  /// and saved encrypted right away unless [`with_save_migrated`](Self::with_save_migrated)
+  /// is disabled.
+  ///
+  /// ```rust,no_run
+  /// use appconfig::{AppConfigManager, ChaCha20Poly1305, Passphrase};
+  /// use std::{cell::RefCell, collections::HashMap, rc::Rc};
+  ///
+  /// let tokens = Rc::new(RefCell::new(HashMap::<String, String>::new()));
+  /// let manager = AppConfigManager::new(tokens, "my_app", "sumibi-yakitori")
+  ///   .with_encryption(ChaCha20Poly1305, Passphrase::new("correct horse battery staple"));
+  /// ```
+  #[cfg(feature = "encryption")]
+  pub fn set_encryption(
+    &mut self,
+    cipher: impl Cipher + ThreadingBound<S::Threading> + 'static,
+    key: impl KeyProvider + ThreadingBound<S::Threading> + 'static,
+  ) -> &mut Self {
+    self.encryption = Some(Encryption {
+      cipher: Boxed::new(cipher, |cipher| Box::new(cipher) as _),
+      provider: Boxed::new(key, |key| Box::new(key) as _),
+      key: Mutex::new(None),
+    });
+    self
+  }
+
+  #[cfg(feature = "encryption")]
+  pub fn with_encryption(
+    mut self,
+    cipher: impl Cipher + ThreadingBound<S::Threading> + 'static,
+    key: impl KeyProvider + ThreadingBound<S::Threading> + 'static,
+  ) -> Self {
+    self.set_encryption(cipher, key);
+    self
+  }
+
+  /// Returns `true` if the file is written encrypted.
+  pub(crate) fn encrypts(&self) -> bool {
+    #[cfg(feature = "encryption")]
+    return self.encryption.is_some();
+    #[cfg(not(feature = "encryption"))]
+    false
+  }
+
+  /// The contents of an encrypted file for `s`, or `s` itself without encryption.
+  pub(crate) fn encrypt(&self, path: &Path, s: Vec<u8>) -> Result<Vec<u8>> {
+    #[cfg(feature = "encryption")]
+    if let Some(encryption) = &self.encryption {
+      let cached = lock(&encryption.key).clone();
+      let (salt, key) = match cached {
+        Some(cached) => cached,
+        None => {
+          let salt = new_salt().map_err(|e| AppConfigError::serialize(path)(e.into()))?;
+          let key = self.request_key(encryption, KeyPurpose::Encrypt, path, &salt)?;
+          *lock(&encryption.key) = Some((salt.clone(), key.clone()));
+          (salt, key)
+        }
+      };
+      let encrypted = encryption
+        .cipher
+        .encrypt(&key, &s)
+        .map_err(AppConfigError::serialize(path))?;
+      let header = [HEADER, VERSION, b":", hex(&salt).as_bytes(), b"\n"].concat();
+      return Ok([header, encrypted].concat());
+    }
+    let _ = path;
+    Ok(s)
+  }
+
+  /// Decrypts the file at `path` read as `s`. Also returns `true` if the file
+  /// was not encrypted although it should be.
+  pub(crate) fn decrypt<'a>(&self, path: &Path, s: &'a [u8]) -> Result<(Cow<'a, [u8]>, bool)> {
+    let rest = match s.strip_prefix(HEADER) {
+      Some(rest) => rest,
+      None => return Ok((Cow::Borrowed(s), self.encrypts())),
+    };
+    let end = rest.iter().position(|&x| x == b'\n').unwrap_or(rest.len());
+    let encrypted = rest.get(end + 1..).unwrap_or_default();
+    let mut fields = rest[..end].splitn(2, |&x| x == b':');
+    let version = fields.next().unwrap_or_default();
+    let salt = fields.next().and_then(unhex);
+    let failed = |source| AppConfigError::DecryptionFailed {
+      path: path.to_owned(),
+      source,
+    };
+    let salt = match salt {
+      Some(salt) if version == VERSION => salt,
+      _ => {
+        return Err(failed(
+          format!(
+            "Unsupported encryption version {}",
+            String::from_utf8_lossy(version)
+          )
+          .into(),
+        ))
+      }
+    };
+    #[cfg(feature = "encryption")]
+    if let Some(encryption) = &self.encryption {
+      let cached = lock(&encryption.key)
+        .as_ref()
+        .filter(|(cached, _)| *cached == salt)
+        .map(|(_, key)| key.clone());
+      let key = match cached {
+        Some(key) => key,
+        None => self.request_key(encryption, KeyPurpose::Decrypt, path, &salt)?,
+      };
+      let decrypted = encryption.cipher.decrypt(&key, encrypted).map_err(failed)?;
+      *lock(&encryption.key) = Some((salt, key));
+      return Ok((Cow::Owned(decrypted), false));
+    }
+    let _ = (encrypted, salt);
+    Err(failed("The file is encrypted but no cipher is set".into()))
+  }
+
+  #[cfg(feature = "encryption")]
+  fn request_key(
+    &self,
+    encryption: &Encryption<S::Threading>,
+    purpose: KeyPurpose,
+    path: &Path,
+    salt: &[u8],
+  ) -> Result<SecretKey> {
+    let request = KeyRequest {
+      purpose,
+      path,
+      salt,
+    };
+    encryption
+      .provider
+      .key(&request)
+      .map_err(|source| AppConfigError::KeyUnavailable {
+        path: path.to_owned(),
+        source,
+      })
+  }
+}
+
+/// 16 bytes from the random number generator of the operating system.
+#[cfg(feature = "encryption")]
+fn new_salt() -> std::result::Result<Vec<u8>, getrandom::Error> {
+  let mut salt = vec![0; 16];
+  getrandom::fill(&mut salt)?;
+  Ok(salt)
+}
+
+#[cfg(feature = "encryption")]
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+pub(crate) fn unhex(s: &[u8]) -> Option<Vec<u8>> {
+  let chunks = s.chunks_exact(2);
+  if !chunks.remainder().is_empty() {
+    return None;
+  }
+  chunks
+    .map(|x| u8::from_str_radix(std::str::from_utf8(x).ok()?, 16).ok())
+    .collect()
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+  use super::{ChaCha20Poly1305, Cipher, KeyPurpose, KeyRequest, Passphrase, SecretKey};
+  use crate::{AppConfigError, AppConfigManager, FormatError};
+  use serde::{Deserialize, Serialize};
+  use std::{cell::RefCell, rc::Rc};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Tokens {
+    refresh_token: String,
+  }
+
+  /// Stands in for a real cipher. The first byte checks the key.
+  struct Xor;
+
+  impl Cipher for Xor {
+    fn encrypt(&self, key: &SecretKey, plaintext: &[u8]) -> Result<Vec<u8>, FormatError> {
+      let key = key.as_bytes()[0];
+      Ok(
+        std::iter::once(key)
+          .chain(plaintext.iter().map(|x| x ^ key))
+          .collect(),
+      )
+    }
+
+    fn decrypt(&self, key: &SecretKey, ciphertext: &[u8]) -> Result<Vec<u8>, FormatError> {
+      let key = key.as_bytes()[0];
+      match ciphertext.split_first() {
+        Some((&first, rest)) if first == key => Ok(rest.iter().map(|x| x ^ key).collect()),
+        _ => Err("Wrong key".into()),
+      }
+    }
+  }
+
+  #[test]
+  fn encryption() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Tokens {
+      refresh_token: "abc".to_owned(),
+    }));
+    let new_manager = |key: u8| {
+      AppConfigManager::new(config.clone(), "encryption", "sumibi-yakitori")
+        .with_base_dir(dir.path())
+        .with_encryption(Xor, SecretKey::new([key]))
+        .with_auto_saving(false)
+    };
+    let manager = new_manager(7);
+    let path = manager.get_user_config_path().unwrap();
+
+    // A plaintext file is encrypted when loaded
+    std::fs::write(&path, "refresh_token = \"xyz\"\n").unwrap();
+    manager.load().unwrap();
+    assert_eq!(config.borrow().refresh_token, "xyz");
+    let s = std::fs::read(&path).unwrap();
+    assert!(s.starts_with(b"appconfig-encrypted:1:"));
+    assert!(!String::from_utf8_lossy(&s).contains("xyz"));
+
+    config.borrow_mut().refresh_token = "abc".to_owned();
+    manager.save().unwrap();
+    config.borrow_mut().refresh_token.clear();
+    manager.load().unwrap();
+    assert_eq!(config.borrow().refresh_token, "abc");
+
+    // Not skipped like a parse error, and the file is kept
+    let s = std::fs::read(&path).unwrap();
+    let manager = new_manager(8).with_skip_parsing_error_when_loading(true);
+    assert!(matches!(
+      manager.load(),
+      Err(AppConfigError::DecryptionFailed { .. })
+    ));
+    assert_eq!(config.borrow().refresh_token, "abc");
+    drop(manager);
+    assert_eq!(std::fs::read(&path).unwrap(), s);
+  }
+
+  #[test]
+  fn key_provider() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Tokens::default()));
+    let requests = Rc::new(RefCell::new(Vec::new()));
+    let new_manager = |passphrase: Option<u8>| {
+      let requests = requests.clone();
+      AppConfigManager::new(config.clone(), "key_provider", "sumibi-yakitori")
+        .with_base_dir(dir.path())
+        .with_encryption(Xor, move |request: &KeyRequest<'_>| {
+          requests
+            .borrow_mut()
+            .push((request.purpose, request.salt.to_vec()));
+          let passphrase = passphrase.ok_or("Cancelled")?;
+          // Derives the key from the passphrase and the salt
+          Ok(SecretKey::new([passphrase ^ request.salt[0]]))
+        })
+        .with_auto_saving(false)
+    };
+    let manager = new_manager(Some(3));
+    let path = manager.get_user_config_path().unwrap();
+    assert!(requests.borrow().is_empty());
+    manager.save().unwrap();
+    manager.save().unwrap();
+    manager.load().unwrap();
+    // Asked once, the salt is stored in the file
+    let salt = match &requests.borrow()[..] {
+      [(KeyPurpose::Encrypt, salt)] => salt.clone(),
+      requests => panic!("{:?}", requests),
+    };
+    assert!(String::from_utf8_lossy(&std::fs::read(&path).unwrap())
+      .starts_with(&format!("appconfig-encrypted:1:{}\n", super::hex(&salt))));
+
+    let manager = new_manager(Some(3));
+    manager.load().unwrap();
+    assert_eq!(requests.borrow()[1], (KeyPurpose::Decrypt, salt));
+
+    // A failing provider leaves the file alone
+    let manager = new_manager(None);
+    assert!(matches!(
+      manager.load(),
+      Err(AppConfigError::KeyUnavailable { .. })
+    ));
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(
+      manager.save(),
+      Err(AppConfigError::KeyUnavailable { .. })
+    ));
+    assert!(!path.exists());
+  }
+
+  #[test]
+  fn passphrase() {
+    // Generated with Python's `hashlib.scrypt`
+    assert_eq!(
+      SecretKey::from_passphrase("correct horse", &(0..16).collect::<Vec<_>>()).as_bytes(),
+      &super::unhex(b"5c662628ac4eb1068a287e2aa2a202ae248e98db04d61d3327e7342db6ec7097").unwrap()[..]
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Tokens {
+      refresh_token: "abc".to_owned(),
+    }));
+    let new_manager = |passphrase: &str| {
+      AppConfigManager::new(config.clone(), "passphrase", "sumibi-yakitori")
+        .with_base_dir(dir.path())
+        .with_encryption(ChaCha20Poly1305, Passphrase::new(passphrase))
+        .with_auto_saving(false)
+    };
+    let manager = new_manager("correct horse");
+    let path = manager.get_user_config_path().unwrap();
+    manager.save().unwrap();
+    let s = std::fs::read(&path).unwrap();
+    assert!(!String::from_utf8_lossy(&s).contains("abc"));
+    // A new nonce for each save with the same key
+    manager.save().unwrap();
+    assert_ne!(std::fs::read(&path).unwrap(), s);
+
+    config.borrow_mut().refresh_token.clear();
+    new_manager("correct horse").load().unwrap();
+    assert_eq!(config.borrow().refresh_token, "abc");
+    assert!(matches!(
+      new_manager("battery staple").load(),
+      Err(AppConfigError::DecryptionFailed { .. })
+    ));
+
+    let key = SecretKey::new([0; 16]);
+    assert!(ChaCha20Poly1305.encrypt(&key, b"abc").is_err());
+  }
+}
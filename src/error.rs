@@ -0,0 +1,529 @@
+use std::{
+  fmt, io,
+  path::{Path, PathBuf},
+};
+
+/// The error returned by a [`ConfigFormat`](crate::ConfigFormat) implementation.
+pub type FormatError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The error type of this crate.
+///
+/// The `source` of [`AppConfigError::Serialize`] and [`AppConfigError::Deserialize`] is the error
+/// of the format in use, e.g. `toml::de::Error`, and can be downcast to it.
+/// With the `field-path` feature, errors attributed to a field are wrapped in a [`FieldPathError`].
+///
+/// The error of a format may quote the file, e.g. a line with a token on it. `Display` and `Debug`
+/// only keep its line, column and key path, and `Error::source` does not return it for
+/// [`AppConfigError::Deserialize`].
+#[non_exhaustive]
+pub enum AppConfigError {
+  /// Reading, writing or creating `path` failed.
+  Io { path: PathBuf, source: io::Error },
+  /// The configuration to be written to `path` could not be serialized.
+  Serialize { path: PathBuf, source: FormatError },
+  /// The file at `path` could not be parsed.
+  Deserialize { path: PathBuf, source: FormatError },
+  /// The platform configuration directory could not be determined.
+  /// `path` is the location relative to that directory.
+  ConfigDirUnavailable { path: PathBuf },
+  /// A configuration file exists at `path`, but in a format other than the configured one.
+  FormatMismatch { path: PathBuf, expected: String },
+  /// Portable mode is enabled without fallback, and the `config` folder next to the executable
+  /// at `path` could not be created or written to.
+  PortableDirUnavailable { path: PathBuf, source: io::Error },
+  /// The override for `key` does not point to a value in the configuration.
+  InvalidOverride { key: String, reason: String },
+  /// The file at `path` was written by a newer version of the application.
+  /// `found` is its schema version and `supported` the highest registered migration.
+  NewerVersion {
+    path: PathBuf,
+    found: u32,
+    supported: u32,
+  },
+  /// The file at `path` was changed by another program since it was last loaded or saved,
+  /// and [`ConflictPolicy::Error`](crate::ConflictPolicy::Error) is set.
+  ExternalModification { path: PathBuf },
+  /// The configured file name is empty or would escape the configuration directory.
+  InvalidFileName(String),
+  /// The data could not be changed because it is borrowed, e.g. by a `Ref` that is still alive.
+  AlreadyBorrowed,
+  /// The data could not be saved to `path` because it is borrowed mutably, e.g. by a `RefMut`
+  /// that is still alive when the manager is dropped.
+  BorrowedDuringSave { path: PathBuf },
+  /// The lock file at `path` was held by another process for longer than the timeout set with
+  /// [`with_lock_timeout`](crate::AppConfigManager::with_lock_timeout).
+  Locked { path: PathBuf },
+  /// The configuration is owned by another process or manager, which locked `path`.
+  /// `pid` is the process ID of the owner, when it could be read.
+  AlreadyOwned { path: PathBuf, pid: Option<u32> },
+  /// The file at `path` was not saved because
+  /// [`with_require_ownership`](crate::AppConfigManager::with_require_ownership) is set
+  /// and the manager does not own the configuration.
+  NotOwner { path: PathBuf },
+  /// The file at `path` is encrypted and could not be decrypted, e.g. because the key is wrong.
+  /// Unlike a parse error, this is never skipped.
+  DecryptionFailed { path: PathBuf, source: FormatError },
+  /// The [`KeyProvider`](crate::KeyProvider) failed to supply the key
+  /// to decrypt or encrypt the file at `path`. The file was not changed.
+  KeyUnavailable { path: PathBuf, source: FormatError },
+  /// The secret at the field `key` of the file at `path` could not be read from or written to
+  /// the [`SecretStore`](crate::SecretStore). `source` is `None` if it is missing from the store.
+  SecretUnavailable {
+    path: PathBuf,
+    key: String,
+    source: Option<FormatError>,
+  },
+  /// The file at `path` has `size` bytes, more than the `limit` set with
+  /// [`with_max_file_size`](crate::AppConfigManager::with_max_file_size), and was not read.
+  FileTooLarge {
+    path: PathBuf,
+    size: u64,
+    limit: u64,
+  },
+  /// The checksum of the file at `path` is missing or does not match its contents,
+  /// and [`IntegrityPolicy::Reject`](crate::IntegrityPolicy::Reject) is set.
+  IntegrityMismatch { path: PathBuf },
+  /// The format of the file at `path` has no comments, e.g. JSON, so it cannot hold the checksum
+  /// of [`with_integrity_policy`](crate::AppConfigManager::with_integrity_policy).
+  IntegrityUnsupported { path: PathBuf },
+  /// The [`ConfigStore`](crate::ConfigStore) ran out of space while writing `path`,
+  /// e.g. the quota of `localStorage`. The previous file is left in place.
+  QuotaExceeded { path: PathBuf },
+  /// The [`ConfigStore`](crate::ConfigStore) cannot be used at all,
+  /// e.g. `localStorage` in a private browsing window.
+  StorageUnavailable { path: PathBuf, source: io::Error },
+  /// The data read from or to be written to `path` was rejected by a validator
+  /// added with [`with_validator`](crate::AppConfigManager::with_validator).
+  Invalid { path: PathBuf, reason: String },
+  /// [`purge`](crate::AppConfigManager::purge) did not remove `path`
+  /// because it is not the `{qualifier}.{organization_name}.{app_name}` folder of the application.
+  PurgeRefused { path: PathBuf },
+  /// The application or organization `name` cannot be used in the folder name, e.g. because it
+  /// contains a path separator or is reserved by Windows. It can be sanitized instead with
+  /// [`with_lenient_names`](crate::AppConfigManager::with_lenient_names).
+  InvalidName { name: String, reason: String },
+  /// The folder set with [`with_subdirectory`](crate::AppConfigManager::with_subdirectory)
+  /// is absolute or contains `..`, so it would escape the application folder.
+  InvalidSubdirectory(PathBuf),
+}
+
+impl AppConfigError {
+  pub(crate) fn io(path: impl AsRef<Path>) -> impl FnOnce(io::Error) -> Self {
+    let path = path.as_ref().to_owned();
+    move |source| AppConfigError::Io { path, source }
+  }
+
+  pub(crate) fn serialize(path: impl AsRef<Path>) -> impl FnOnce(FormatError) -> Self {
+    let path = path.as_ref().to_owned();
+    move |source| AppConfigError::Serialize { path, source }
+  }
+
+  pub(crate) fn deserialize(path: impl AsRef<Path>) -> impl FnOnce(FormatError) -> Self {
+    let path = path.as_ref().to_owned();
+    move |source| AppConfigError::Deserialize { path, source }
+  }
+
+  /// The dotted path of the field that failed to parse, e.g. `window.size.width`.
+  ///
+  /// Available for the built-in TOML, JSON and YAML formats when the `field-path` feature is enabled.
+  pub fn field_path(&self) -> Option<&str> {
+    match self {
+      AppConfigError::Deserialize { source, .. } => source
+        .downcast_ref::<FieldPathError>()
+        .map(|err| err.path.as_str()),
+      _ => None,
+    }
+  }
+
+  /// Returns `true` if the error was caused by a file that does not exist.
+  pub fn is_not_found(&self) -> bool {
+    matches!(
+      self,
+      AppConfigError::Io { source, .. } if source.kind() == io::ErrorKind::NotFound
+    )
+  }
+}
+
+impl fmt::Display for AppConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AppConfigError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+      AppConfigError::Serialize { path, source } => {
+        write!(f, "Failed to serialize {}: {}", path.display(), source)
+      }
+      AppConfigError::Deserialize { path, source } => write!(
+        f,
+        "Failed to parse {}: {}",
+        path.display(),
+        redact(source.as_ref())
+      ),
+      AppConfigError::ConfigDirUnavailable { path } => write!(
+        f,
+        "The configuration directory for {} is not available",
+        path.display()
+      ),
+      AppConfigError::FormatMismatch { path, expected } => write!(
+        f,
+        "Found {} but the configured format is .{}",
+        path.display(),
+        expected
+      ),
+      AppConfigError::PortableDirUnavailable { path, source } => write!(
+        f,
+        "The portable configuration directory {} is not writable: {}",
+        path.display(),
+        source
+      ),
+      AppConfigError::InvalidOverride { key, reason } => {
+        write!(f, "Invalid override key {:?}: {}", key, reason)
+      }
+      AppConfigError::NewerVersion {
+        path,
+        found,
+        supported,
+      } => write!(
+        f,
+        "{} has schema version {}, but only versions up to {} are supported",
+        path.display(),
+        found,
+        supported
+      ),
+      AppConfigError::ExternalModification { path } => write!(
+        f,
+        "{} was modified by another program since it was loaded",
+        path.display()
+      ),
+      AppConfigError::InvalidFileName(name) => write!(f, "Invalid config file name: {:?}", name),
+      AppConfigError::AlreadyBorrowed => write!(f, "The configuration data is already borrowed"),
+      AppConfigError::BorrowedDuringSave { path } => write!(
+        f,
+        "{} was not saved because the configuration data is borrowed",
+        path.display()
+      ),
+      AppConfigError::Locked { path } => {
+        write!(f, "{} is locked by another process", path.display())
+      }
+      AppConfigError::AlreadyOwned {
+        path,
+        pid: Some(pid),
+      } => write!(
+        f,
+        "The configuration is owned by process {} ({})",
+        pid,
+        path.display()
+      ),
+      AppConfigError::AlreadyOwned { path, pid: None } => write!(
+        f,
+        "The configuration is owned by another process ({})",
+        path.display()
+      ),
+      AppConfigError::NotOwner { path } => write!(
+        f,
+        "{} was not saved because this instance does not own the configuration",
+        path.display()
+      ),
+      AppConfigError::DecryptionFailed { path, source } => {
+        write!(f, "Failed to decrypt {}: {}", path.display(), source)
+      }
+      AppConfigError::KeyUnavailable { path, source } => write!(
+        f,
+        "The encryption key for {} is not available: {}",
+        path.display(),
+        source
+      ),
+      AppConfigError::SecretUnavailable {
+        path,
+        key,
+        source: Some(source),
+      } => write!(
+        f,
+        "The secret {} of {} is not available: {}",
+        key,
+        path.display(),
+        source
+      ),
+      AppConfigError::SecretUnavailable {
+        path,
+        key,
+        source: None,
+      } => write!(
+        f,
+        "The secret {} of {} is missing from the secret store",
+        key,
+        path.display()
+      ),
+      AppConfigError::FileTooLarge { path, size, limit } => write!(
+        f,
+        "{} has {} bytes, more than the limit of {} bytes",
+        path.display(),
+        size,
+        limit
+      ),
+      AppConfigError::IntegrityMismatch { path } => write!(
+        f,
+        "The checksum of {} does not match its contents",
+        path.display()
+      ),
+      AppConfigError::IntegrityUnsupported { path } => write!(
+        f,
+        "The format of {} has no comments to hold a checksum",
+        path.display()
+      ),
+      AppConfigError::QuotaExceeded { path } => {
+        write!(f, "The storage quota was exceeded by {}", path.display())
+      }
+      AppConfigError::StorageUnavailable { path, source } => write!(
+        f,
+        "The storage for {} is not available: {}",
+        path.display(),
+        source
+      ),
+      AppConfigError::Invalid { path, reason } => {
+        write!(f, "Invalid configuration in {}: {}", path.display(), reason)
+      }
+      AppConfigError::PurgeRefused { path } => write!(
+        f,
+        "Refused to remove {}, which is not the folder of the application",
+        path.display()
+      ),
+      AppConfigError::InvalidName { name, reason } => {
+        write!(f, "Invalid name {:?}: {}", name, reason)
+      }
+      AppConfigError::InvalidSubdirectory(path) => {
+        write!(f, "Invalid subdirectory: {}", path.display())
+      }
+    }
+  }
+}
+
+impl std::error::Error for AppConfigError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      AppConfigError::Io { source, .. }
+      | AppConfigError::PortableDirUnavailable { source, .. }
+      | AppConfigError::StorageUnavailable { source, .. } => Some(source),
+      AppConfigError::Serialize { source, .. }
+      | AppConfigError::DecryptionFailed { source, .. }
+      | AppConfigError::KeyUnavailable { source, .. }
+      | AppConfigError::SecretUnavailable {
+        source: Some(source),
+        ..
+      } => Some(source.as_ref()),
+      _ => None,
+    }
+  }
+}
+
+/// Like the derived `Debug`, but with the source of a parse error redacted.
+impl fmt::Debug for AppConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AppConfigError::Io { path, source } => f
+        .debug_struct("Io")
+        .field("path", path)
+        .field("source", source)
+        .finish(),
+      AppConfigError::Serialize { path, source } => f
+        .debug_struct("Serialize")
+        .field("path", path)
+        .field("source", source)
+        .finish(),
+      AppConfigError::Deserialize { path, source } => f
+        .debug_struct("Deserialize")
+        .field("path", path)
+        .field("source", &redact(source.as_ref()))
+        .finish(),
+      AppConfigError::ConfigDirUnavailable { path } => f
+        .debug_struct("ConfigDirUnavailable")
+        .field("path", path)
+        .finish(),
+      AppConfigError::FormatMismatch { path, expected } => f
+        .debug_struct("FormatMismatch")
+        .field("path", path)
+        .field("expected", expected)
+        .finish(),
+      AppConfigError::PortableDirUnavailable { path, source } => f
+        .debug_struct("PortableDirUnavailable")
+        .field("path", path)
+        .field("source", source)
+        .finish(),
+      AppConfigError::InvalidOverride { key, reason } => f
+        .debug_struct("InvalidOverride")
+        .field("key", key)
+        .field("reason", reason)
+        .finish(),
+      AppConfigError::NewerVersion {
+        path,
+        found,
+        supported,
+      } => f
+        .debug_struct("NewerVersion")
+        .field("path", path)
+        .field("found", found)
+        .field("supported", supported)
+        .finish(),
+      AppConfigError::ExternalModification { path } => f
+        .debug_struct("ExternalModification")
+        .field("path", path)
+        .finish(),
+      AppConfigError::InvalidFileName(x) => f.debug_tuple("InvalidFileName").field(x).finish(),
+      AppConfigError::AlreadyBorrowed => f.write_str("AlreadyBorrowed"),
+      AppConfigError::BorrowedDuringSave { path } => f
+        .debug_struct("BorrowedDuringSave")
+        .field("path", path)
+        .finish(),
+      AppConfigError::Locked { path } => f.debug_struct("Locked").field("path", path).finish(),
+      AppConfigError::AlreadyOwned { path, pid } => f
+        .debug_struct("AlreadyOwned")
+        .field("path", path)
+        .field("pid", pid)
+        .finish(),
+      AppConfigError::NotOwner { path } => f.debug_struct("NotOwner").field("path", path).finish(),
+      AppConfigError::DecryptionFailed { path, source } => f
+        .debug_struct("DecryptionFailed")
+        .field("path", path)
+        .field("source", source)
+        .finish(),
+      AppConfigError::KeyUnavailable { path, source } => f
+        .debug_struct("KeyUnavailable")
+        .field("path", path)
+        .field("source", source)
+        .finish(),
+      AppConfigError::SecretUnavailable { path, key, source } => f
+        .debug_struct("SecretUnavailable")
+        .field("path", path)
+        .field("key", key)
+        .field("source", source)
+        .finish(),
+      AppConfigError::FileTooLarge { path, size, limit } => f
+        .debug_struct("FileTooLarge")
+        .field("path", path)
+        .field("size", size)
+        .field("limit", limit)
+        .finish(),
+      AppConfigError::IntegrityMismatch { path } => f
+        .debug_struct("IntegrityMismatch")
+        .field("path", path)
+        .finish(),
+      AppConfigError::IntegrityUnsupported { path } => f
+        .debug_struct("IntegrityUnsupported")
+        .field("path", path)
+        .finish(),
+      AppConfigError::QuotaExceeded { path } => {
+        f.debug_struct("QuotaExceeded").field("path", path).finish()
+      }
+      AppConfigError::StorageUnavailable { path, source } => f
+        .debug_struct("StorageUnavailable")
+        .field("path", path)
+        .field("source", source)
+        .finish(),
+      AppConfigError::Invalid { path, reason } => f
+        .debug_struct("Invalid")
+        .field("path", path)
+        .field("reason", reason)
+        .finish(),
+      AppConfigError::PurgeRefused { path } => {
+        f.debug_struct("PurgeRefused").field("path", path).finish()
+      }
+      AppConfigError::InvalidName { name, reason } => f
+        .debug_struct("InvalidName")
+        .field("name", name)
+        .field("reason", reason)
+        .finish(),
+      AppConfigError::InvalidSubdirectory(path) => {
+        f.debug_tuple("InvalidSubdirectory").field(path).finish()
+      }
+    }
+  }
+}
+
+/// The message of a parse error without the lines of the file it quotes
+/// and without the values quoted by serde, e.g. `invalid type: string "***", expected u32`.
+pub(crate) fn redact(err: &(dyn std::error::Error + Send + Sync)) -> String {
+  let message = err.to_string();
+  let lines: Vec<&str> = message
+    .lines()
+    .map(str::trim_end)
+    // The snippets of `toml`, e.g. `1 | token = "abc`
+    .filter(|line| {
+      !line.is_empty()
+        && !line
+          .trim_start_matches(|c: char| c.is_ascii_digit() || c == ' ')
+          .starts_with('|')
+    })
+    .collect();
+  mask_values(&lines.join(": "))
+}
+
+/// How serde quotes an unexpected value, see `serde::de::Unexpected`.
+const QUOTED_VALUES: &[(&str, char)] = &[
+  ("string \"", '"'),
+  ("character `", '`'),
+  ("integer `", '`'),
+  ("floating point `", '`'),
+  ("boolean `", '`'),
+  ("unknown variant `", '`'),
+];
+
+fn mask_values(s: &str) -> String {
+  let mut masked = String::new();
+  let mut rest = s;
+  while let Some((start, prefix, close)) = QUOTED_VALUES
+    .iter()
+    .filter_map(|&(prefix, close)| Some((rest.find(prefix)?, prefix, close)))
+    .min_by_key(|x| x.0)
+  {
+    let value = &rest[start + prefix.len()..];
+    let mut escaped = false;
+    let end = value
+      .char_indices()
+      .find(|&(_, c)| {
+        let end = !escaped && c == close;
+        escaped = !escaped && c == '\\';
+        end
+      })
+      .map_or(value.len(), |(i, c)| i + c.len_utf8());
+    masked.push_str(&rest[..start + prefix.len()]);
+    masked.push_str("***");
+    masked.push(close);
+    rest = &value[end..];
+  }
+  masked.push_str(rest);
+  masked
+}
+
+/// A deserialization error annotated with the path of the field that caused it.
+///
+/// Produced by the built-in formats when the `field-path` feature is enabled,
+/// see [`AppConfigError::field_path`].
+#[derive(Debug)]
+pub struct FieldPathError {
+  path: String,
+  source: FormatError,
+}
+
+impl FieldPathError {
+  pub fn new(path: impl Into<String>, source: impl Into<FormatError>) -> Self {
+    Self {
+      path: path.into(),
+      source: source.into(),
+    }
+  }
+
+  /// The dotted path of the field, e.g. `window.size.width` or `shortcuts[1].name`.
+  pub fn path(&self) -> &str {
+    &self.path
+  }
+}
+
+impl fmt::Display for FieldPathError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}: {}", self.path, self.source)
+  }
+}
+
+impl std::error::Error for FieldPathError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    Some(self.source.as_ref())
+  }
+}
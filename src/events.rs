@@ -0,0 +1,159 @@
+//! Events describing what the managers do, to find out why a configuration was not loaded
+//! on a user's machine. Enabled by the `events` feature.
+//!
+//! Nothing is emitted until a handler is installed with [`set_handler`].
+//! Successful loads, saves and migrations are [`Level::Info`], the resolved paths [`Level::Debug`],
+//! and skipped parse errors and failed saves on drop [`Level::Warn`].
+//!
+//! The crate does not depend on `log` or `tracing`. To pass the events on to them,
+//! call their macros from the handler:
+//!
+//! ```ignore
+//! appconfig::events::set_handler(|event| match event.level() {
+//!   appconfig::events::Level::Warn => log::warn!(target: "appconfig", "{}", event),
+//!   appconfig::events::Level::Info => log::info!(target: "appconfig", "{}", event),
+//!   _ => log::debug!(target: "appconfig", "{}", event),
+//! });
+//! ```
+
+use std::{
+  fmt,
+  sync::{PoisonError, RwLock},
+};
+
+/// The importance of an [`Event`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Level {
+  Warn,
+  Info,
+  Debug,
+}
+
+impl fmt::Display for Level {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Level::Warn => "WARN",
+      Level::Info => "INFO",
+      Level::Debug => "DEBUG",
+    })
+  }
+}
+
+/// An event passed to the handler.
+#[derive(Debug)]
+pub struct Event<'a> {
+  level: Level,
+  args: fmt::Arguments<'a>,
+}
+
+impl<'a> Event<'a> {
+  pub fn level(&self) -> Level {
+    self.level
+  }
+
+  /// The message, e.g. `Saved 12 bytes to /home/alice/.config/app/app_config.toml`.
+  pub fn args(&self) -> &fmt::Arguments<'a> {
+    &self.args
+  }
+}
+
+impl fmt::Display for Event<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.args.fmt(f)
+  }
+}
+
+type Handler = Box<dyn Fn(&Event<'_>) + Send + Sync>;
+
+static HANDLER: RwLock<Option<Handler>> = RwLock::new(None);
+
+/// Installs the handler receiving the events of every manager, replacing the previous one.
+///
+/// ```
+/// appconfig::events::set_handler(|event| eprintln!("[appconfig {}] {}", event.level(), event));
+/// ```
+pub fn set_handler(handler: impl Fn(&Event<'_>) + Send + Sync + 'static) {
+  *HANDLER.write().unwrap_or_else(PoisonError::into_inner) = Some(Box::new(handler));
+}
+
+pub(crate) fn emit(level: Level, args: fmt::Arguments<'_>) {
+  let handler = HANDLER.read().unwrap_or_else(PoisonError::into_inner);
+  if let Some(handler) = &*handler {
+    handler(&Event { level, args });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Level;
+  use crate::AppConfigManager;
+  use serde::{Deserialize, Serialize};
+  use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{Arc, Mutex},
+  };
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Volume {
+    level: u32,
+  }
+
+  #[test]
+  fn events() {
+    let dir = tempfile::tempdir().unwrap();
+    let records = Arc::new(Mutex::new(Vec::new()));
+    {
+      let records = records.clone();
+      let dir = dir.path().to_owned();
+      // Other tests run at the same time
+      super::set_handler(move |event| {
+        let message = event.to_string();
+        if message.contains(&*dir.to_string_lossy()) {
+          records.lock().unwrap().push((event.level(), message));
+        }
+      });
+    }
+    let config = Rc::from(RefCell::from(Volume { level: 3 }));
+    let manager = AppConfigManager::new(config.clone(), "events", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    let path = path.display();
+
+    manager.load().unwrap_err();
+    manager.save().unwrap();
+    manager.load().unwrap();
+    std::fs::write(manager.get_user_config_path().unwrap(), "level = ").unwrap();
+    manager.load().unwrap();
+
+    let mut recorded = records.lock().unwrap();
+    let (level, parse_error) = recorded.pop().unwrap();
+    assert_eq!(level, Level::Warn);
+    assert!(parse_error.starts_with(&format!("Failed to parse {}: ", path)));
+    assert!(parse_error.ends_with(", skipped"));
+    assert_eq!(
+      *recorded,
+      [
+        (Level::Debug, format!("Loading {}", path)),
+        (Level::Info, format!("{} does not exist", path)),
+        (Level::Info, format!("Saved 10 bytes to {}", path)),
+        (Level::Debug, format!("Loading {}", path)),
+        (Level::Info, format!("Loaded 10 bytes from {}", path)),
+        (Level::Debug, format!("Loading {}", path)),
+      ]
+    );
+    recorded.clear();
+    drop(recorded);
+
+    std::fs::write(manager.get_user_config_path().unwrap(), "level = 3\n").unwrap();
+    let manager = manager.with_migration(2, |_| {});
+    manager.load().unwrap();
+    let records = records.lock().unwrap();
+    assert!(records.contains(&(
+      Level::Info,
+      format!("Migrated {} from version 1 to 2", path)
+    )));
+  }
+}
@@ -0,0 +1,288 @@
+use crate::{
+  fs::WriteOptions, shared::lock, store::SharedStore, AppConfigManager, ConfigKey, Result,
+  SharedData,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+  path::Path,
+  sync::{Arc, Mutex},
+};
+
+/// The contents written when the process receives a signal, and where to.
+pub(crate) struct Snapshot {
+  key: ConfigKey,
+  contents: Vec<u8>,
+  options: WriteOptions,
+  store: Option<SharedStore>,
+}
+
+/// `None` when the file is up to date.
+type SnapshotSlot = Mutex<Option<Snapshot>>;
+
+pub(crate) type Slot = Arc<SnapshotSlot>;
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// Writes the data when the process is interrupted with Ctrl+C (`SIGINT`) or terminated
+  /// with `SIGTERM`, which skip the save on drop. The process then exits as the signal would have
+  /// made it, e.g. with code 130 in a shell after Ctrl+C.
+  ///
+  /// A signal handler must not touch the data, so what is written is a snapshot taken now,
+  /// by [`refresh_exit_flush`](Self::refresh_exit_flush) and by
+  /// [`request_save`](Self::request_save). It is discarded by each save.
+  /// The handler only wakes a thread that writes the snapshots of all managers.
+  ///
+  /// Only implemented on Unix, elsewhere it just takes the snapshot.
+  /// Other handlers of these signals are replaced.
+  pub fn install_exit_flush(&self) -> Result {
+    let slot = lock(&self.exit_flush)
+      .get_or_insert_with(Default::default)
+      .clone();
+    #[cfg(unix)]
+    signal::register(&slot).map_err(crate::AppConfigError::io(self.user_config_path()?))?;
+    #[cfg(not(unix))]
+    drop(slot);
+    self.refresh_exit_flush()
+  }
+
+  /// Takes a new snapshot of the data to be written by
+  /// [`install_exit_flush`](Self::install_exit_flush), e.g. after changing it.
+  pub fn refresh_exit_flush(&self) -> Result {
+    let slot = match &*lock(&self.exit_flush) {
+      Some(slot) => slot.clone(),
+      None => return Ok(()),
+    };
+    let path = self.user_config_path()?;
+    let s = self.serialize_data(&path)?;
+    *lock(&slot) = Some(self.exit_snapshot(&path, s));
+    Ok(())
+  }
+
+  /// Replaces the snapshot with `contents` for the file at `path`, or discards it with `None`.
+  pub(crate) fn set_exit_flush(&self, snapshot: Option<(&Path, Vec<u8>)>) {
+    if let Some(slot) = &*lock(&self.exit_flush) {
+      *lock(slot) = snapshot.map(|(path, s)| self.exit_snapshot(path, s));
+    }
+  }
+
+  fn exit_snapshot(&self, path: &Path, contents: Vec<u8>) -> Snapshot {
+    Snapshot {
+      key: self.config_key(path),
+      contents,
+      options: self.write_options(),
+      store: self.shared_store(),
+    }
+  }
+}
+
+#[cfg(unix)]
+mod signal {
+  use super::{Slot, SnapshotSlot};
+  use crate::{fs::WriteOptions, shared::lock, store};
+  use std::{
+    fs::File,
+    io::{self, Read},
+    os::unix::io::FromRawFd,
+    sync::{
+      atomic::{AtomicI32, Ordering},
+      Arc, Mutex, Weak,
+    },
+  };
+
+  /// The snapshots of all managers, `None` until the handler is installed.
+  static SLOTS: Mutex<Option<Vec<Weak<SnapshotSlot>>>> = Mutex::new(None);
+
+  /// The pipe through which the handler wakes the flushing thread.
+  static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+  pub(super) fn register(slot: &Slot) -> io::Result<()> {
+    let mut slots = lock(&SLOTS);
+    if slots.is_none() {
+      install()?;
+    }
+    let slots = slots.get_or_insert_with(Vec::new);
+    slots.retain(|slot| slot.strong_count() > 0);
+    slots.push(Arc::downgrade(slot));
+    Ok(())
+  }
+
+  fn install() -> io::Result<()> {
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has room for both ends.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+      return Err(io::Error::last_os_error());
+    }
+    // SAFETY: The read end is owned by the thread from now on.
+    let pipe = unsafe { File::from_raw_fd(fds[0]) };
+    PIPE.store(fds[1], Ordering::SeqCst);
+    std::thread::Builder::new()
+      .name("appconfig-exit-flush".to_owned())
+      .spawn(move || flush_on_signal(pipe))?;
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+      // SAFETY: A zeroed `sigaction` is valid, and `handle` only calls async-signal-safe functions.
+      unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+          return Err(io::Error::last_os_error());
+        }
+      }
+    }
+    Ok(())
+  }
+
+  extern "C" fn handle(signal: libc::c_int) {
+    let byte = signal as u8;
+    // SAFETY: `write` is async-signal-safe and `byte` outlives the call.
+    unsafe {
+      libc::write(
+        PIPE.load(Ordering::SeqCst),
+        &byte as *const u8 as *const libc::c_void,
+        1,
+      );
+    }
+  }
+
+  fn flush_on_signal(mut pipe: File) {
+    let mut signal = [0];
+    if pipe.read_exact(&mut signal).is_err() {
+      return;
+    }
+    if let Some(slots) = &*lock(&SLOTS) {
+      for slot in slots.iter().filter_map(Weak::upgrade) {
+        if let Some(snapshot) = lock(&slot).take() {
+          let options = WriteOptions {
+            durable: true,
+            ..snapshot.options
+          };
+          store::write_to(
+            snapshot.store.as_ref(),
+            &snapshot.key,
+            &snapshot.contents,
+            options,
+          )
+          .ok();
+        }
+      }
+    }
+    // SAFETY: Restores the default action, which ends the process, and raises the signal again.
+    unsafe {
+      libc::signal(signal[0].into(), libc::SIG_DFL);
+      libc::raise(signal[0].into());
+    }
+  }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+  use crate::{AppConfigManager, ConfigKey, ConfigStore};
+  use serde::{Deserialize, Serialize};
+  use std::{
+    cell::RefCell,
+    io::{self, BufRead, BufReader},
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    rc::Rc,
+  };
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Volume {
+    level: u32,
+  }
+
+  /// Keeps the files under their key in a separate folder.
+  struct FolderStore(PathBuf);
+
+  impl ConfigStore for FolderStore {
+    fn read(&self, key: &ConfigKey) -> io::Result<Option<Vec<u8>>> {
+      match std::fs::read(self.0.join(key.to_string())) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        result => result.map(Some),
+      }
+    }
+
+    fn write(&self, key: &ConfigKey, contents: &[u8]) -> io::Result<()> {
+      let path = self.0.join(key.to_string());
+      std::fs::create_dir_all(path.parent().unwrap())?;
+      std::fs::write(path, contents)
+    }
+
+    fn remove(&self, key: &ConfigKey) -> io::Result<()> {
+      std::fs::remove_file(self.0.join(key.to_string()))
+    }
+  }
+
+  const CHILD: &str = "APPCONFIG_EXIT_FLUSH_DIR";
+  const CHILD_STORE: &str = "APPCONFIG_EXIT_FLUSH_STORE";
+
+  /// Runs the test again in a child process with `dir` as the base directory,
+  /// and terminates it once the snapshot is taken.
+  fn terminate_child(dir: &Path, store: Option<&Path>) {
+    let mut command = Command::new(std::env::current_exe().unwrap());
+    command
+      .args(["--exact", "exit_flush::tests::exit_flush", "--nocapture"])
+      .env(CHILD, dir)
+      .stdout(Stdio::piped());
+    if let Some(store) = store {
+      command.env(CHILD_STORE, store);
+    }
+    let mut child = command.spawn().unwrap();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    for line in stdout.lines() {
+      if line.unwrap().ends_with("ready") {
+        break;
+      }
+    }
+    // SAFETY: Sends a signal to the child.
+    assert_eq!(unsafe { libc::kill(child.id() as i32, libc::SIGTERM) }, 0);
+    assert_eq!(child.wait().unwrap().signal(), Some(libc::SIGTERM));
+  }
+
+  #[test]
+  fn exit_flush() {
+    if let Ok(dir) = std::env::var(CHILD) {
+      let config = Rc::from(RefCell::from(Volume { level: 1 }));
+      let mut manager =
+        AppConfigManager::new(config.clone(), "exit_flush", "sumibi-yakitori").with_base_dir(dir);
+      if let Ok(store) = std::env::var(CHILD_STORE) {
+        manager.set_store(FolderStore(store.into()));
+      }
+      manager.install_exit_flush().unwrap();
+      config.borrow_mut().level = 2;
+      manager.refresh_exit_flush().unwrap();
+      println!("ready");
+      loop {
+        std::thread::park();
+      }
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    terminate_child(dir.path(), None);
+    let config = Rc::from(RefCell::from(Volume::default()));
+    let manager = AppConfigManager::new(config.clone(), "exit_flush", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_auto_saving(false);
+    manager.load().unwrap();
+    assert_eq!(*config.borrow(), Volume { level: 2 });
+
+    // Written through the store, nothing on the file system
+    let dir = tempfile::tempdir().unwrap();
+    let store = tempfile::tempdir().unwrap();
+    terminate_child(dir.path(), Some(store.path()));
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    assert_eq!(
+      std::fs::read_to_string(
+        store
+          .path()
+          .join("com.sumibi-yakitori.exit_flush/app_config.toml")
+      )
+      .unwrap(),
+      "level = 2\n"
+    );
+  }
+}
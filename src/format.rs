@@ -0,0 +1,420 @@
+use crate::FormatError;
+use serde::{de::DeserializeOwned, Deserializer, Serialize};
+
+/// A file format used to read and write the configuration file.
+///
+/// [`TomlFormat`] is used unless another format is passed to
+/// [`AppConfigManager::with_format`](crate::AppConfigManager::with_format).
+/// Implement this trait to store the configuration in a format not provided by this crate.
+///
+/// ```rust
+/// use appconfig::{ConfigFormat, FormatError};
+/// use serde::{de::DeserializeOwned, Serialize};
+///
+/// struct BincodeFormat;
+///
+/// impl<T: Serialize + DeserializeOwned> ConfigFormat<T> for BincodeFormat {
+///   fn serialize(&self, value: &T) -> Result<Vec<u8>, FormatError> {
+///     Ok(bincode::serialize(value)?)
+///   }
+///
+///   fn deserialize(&self, bytes: &[u8]) -> Result<T, FormatError> {
+///     Ok(bincode::deserialize(bytes)?)
+///   }
+///
+///   fn extension(&self) -> &str {
+///     "bin"
+///   }
+/// }
+/// ```
+pub trait ConfigFormat<T> {
+  fn serialize(&self, value: &T) -> Result<Vec<u8>, FormatError>;
+
+  fn deserialize(&self, bytes: &[u8]) -> Result<T, FormatError>;
+
+  /// Serializes `value` honoring `options`. Formats without such options can keep the default,
+  /// which calls [`serialize`](Self::serialize).
+  fn serialize_with(&self, value: &T, options: &SaveOptions) -> Result<Vec<u8>, FormatError> {
+    let _ = options;
+    self.serialize(value)
+  }
+
+  /// The file extension used when saving, without the leading dot.
+  fn extension(&self) -> &str;
+
+  /// Additional file extensions recognized when loading.
+  fn alternative_extensions(&self) -> &[&str] {
+    &[]
+  }
+
+  /// The string that starts a line comment, e.g. `#`, or `None` if the format has no comments.
+  fn comment_prefix(&self) -> Option<&str> {
+    None
+  }
+}
+
+impl<T, F> ConfigFormat<T> for Box<F>
+where
+  F: ConfigFormat<T> + ?Sized,
+{
+  fn serialize(&self, value: &T) -> Result<Vec<u8>, FormatError> {
+    (**self).serialize(value)
+  }
+
+  fn deserialize(&self, bytes: &[u8]) -> Result<T, FormatError> {
+    (**self).deserialize(bytes)
+  }
+
+  fn serialize_with(&self, value: &T, options: &SaveOptions) -> Result<Vec<u8>, FormatError> {
+    (**self).serialize_with(value, options)
+  }
+
+  fn extension(&self) -> &str {
+    (**self).extension()
+  }
+
+  fn alternative_extensions(&self) -> &[&str] {
+    (**self).alternative_extensions()
+  }
+
+  fn comment_prefix(&self) -> Option<&str> {
+    (**self).comment_prefix()
+  }
+}
+
+/// How the built-in formats write the file, see
+/// [`AppConfigManager::with_save_options`](crate::AppConfigManager::with_save_options).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SaveOptions {
+  /// Writes arrays one element per line (TOML) or indents nested values (JSON, RON). Enabled by default.
+  ///
+  /// Whether nested tables are written as `[table]` sections or inline is decided by the `toml` crate
+  /// and cannot be configured.
+  pub pretty: bool,
+  /// Sorts the keys of all tables alphabetically, including struct fields, so that maps
+  /// such as `HashMap` are written in the same order every time. Supported by TOML and JSON.
+  pub sort_keys: bool,
+}
+
+impl Default for SaveOptions {
+  fn default() -> Self {
+    Self {
+      pretty: true,
+      sort_keys: false,
+    }
+  }
+}
+
+impl SaveOptions {
+  pub fn with_pretty(mut self, value: bool) -> Self {
+    self.pretty = value;
+    self
+  }
+
+  pub fn with_sort_keys(mut self, value: bool) -> Self {
+    self.sort_keys = value;
+    self
+  }
+}
+
+/// File extensions of the formats built into this crate.
+pub(crate) const BUILTIN_EXTENSIONS: &[&str] = &[
+  "toml",
+  #[cfg(feature = "json")]
+  "json",
+  #[cfg(feature = "yaml")]
+  "yaml",
+  #[cfg(feature = "yaml")]
+  "yml",
+  #[cfg(feature = "ron")]
+  "ron",
+];
+
+/// Returns the built-in format that handles files with `extension`.
+pub(crate) fn builtin<T>(extension: &str) -> Option<Box<dyn ConfigFormat<T>>>
+where
+  T: Serialize + DeserializeOwned,
+{
+  match extension {
+    "toml" => Some(Box::new(TomlFormat)),
+    #[cfg(feature = "json")]
+    "json" => Some(Box::new(JsonFormat)),
+    #[cfg(feature = "yaml")]
+    "yaml" | "yml" => Some(Box::new(YamlFormat)),
+    #[cfg(feature = "ron")]
+    "ron" => Some(Box::new(RonFormat)),
+    _ => None,
+  }
+}
+
+/// Deserializes from `de`, recording the path of the failing field when the `field-path` feature is enabled.
+fn deserialize_from<'de, D, T>(de: D) -> Result<T, FormatError>
+where
+  D: Deserializer<'de>,
+  D::Error: Send + Sync + 'static,
+  T: DeserializeOwned,
+{
+  #[cfg(feature = "field-path")]
+  return serde_path_to_error::deserialize(de).map_err(|err| {
+    let path = err.path().to_string();
+    match path.as_str() {
+      // Syntax errors at the top level are not attributed to a field
+      "" | "." => err.into_inner().into(),
+      _ => crate::FieldPathError::new(path, err.into_inner()).into(),
+    }
+  });
+  #[cfg(not(feature = "field-path"))]
+  return Ok(T::deserialize(de)?);
+}
+
+/// `app_config.toml` (default)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TomlFormat;
+
+impl<T: Serialize + DeserializeOwned> ConfigFormat<T> for TomlFormat {
+  fn serialize(&self, value: &T) -> Result<Vec<u8>, FormatError> {
+    self.serialize_with(value, &SaveOptions::default())
+  }
+
+  fn serialize_with(&self, value: &T, options: &SaveOptions) -> Result<Vec<u8>, FormatError> {
+    if options.sort_keys {
+      let sorted = sort_toml(toml::Value::try_from(value)?);
+      let options = SaveOptions {
+        sort_keys: false,
+        ..options.clone()
+      };
+      return self.serialize_with(&sorted, &options);
+    }
+    let s = if options.pretty {
+      toml::to_string_pretty(value)?
+    }
+    else {
+      toml::to_string(value)?
+    };
+    Ok(s.into_bytes())
+  }
+
+  fn deserialize(&self, bytes: &[u8]) -> Result<T, FormatError> {
+    deserialize_from(toml::Deserializer::new(std::str::from_utf8(bytes)?))
+  }
+
+  fn extension(&self) -> &str {
+    "toml"
+  }
+
+  fn comment_prefix(&self) -> Option<&str> {
+    Some("#")
+  }
+}
+
+/// Rebuilds all tables with their keys in alphabetical order.
+/// The `preserve_order` feature of `toml` keeps the insertion order otherwise.
+fn sort_toml(value: toml::Value) -> toml::Value {
+  match value {
+    toml::Value::Table(table) => {
+      let mut entries: Vec<_> = table
+        .into_iter()
+        .map(|(key, value)| (key, sort_toml(value)))
+        .collect();
+      entries.sort_by(|a, b| a.0.cmp(&b.0));
+      toml::Value::Table(entries.into_iter().collect())
+    }
+    toml::Value::Array(array) => toml::Value::Array(array.into_iter().map(sort_toml).collect()),
+    value => value,
+  }
+}
+
+/// `app_config.json`, requires the `json` feature.
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct JsonFormat;
+
+#[cfg(feature = "json")]
+impl<T: Serialize + DeserializeOwned> ConfigFormat<T> for JsonFormat {
+  fn serialize(&self, value: &T) -> Result<Vec<u8>, FormatError> {
+    self.serialize_with(value, &SaveOptions::default())
+  }
+
+  fn serialize_with(&self, value: &T, options: &SaveOptions) -> Result<Vec<u8>, FormatError> {
+    if options.sort_keys {
+      let sorted = sort_json(serde_json::to_value(value)?);
+      let options = SaveOptions {
+        sort_keys: false,
+        ..options.clone()
+      };
+      return self.serialize_with(&sorted, &options);
+    }
+    if options.pretty {
+      Ok(serde_json::to_vec_pretty(value)?)
+    }
+    else {
+      Ok(serde_json::to_vec(value)?)
+    }
+  }
+
+  fn deserialize(&self, bytes: &[u8]) -> Result<T, FormatError> {
+    let mut de = serde_json::Deserializer::from_slice(bytes);
+    let value = deserialize_from(&mut de)?;
+    de.end()?;
+    Ok(value)
+  }
+
+  fn extension(&self) -> &str {
+    "json"
+  }
+}
+
+/// Rebuilds all objects with their keys in alphabetical order, see [`sort_toml`].
+#[cfg(feature = "json")]
+fn sort_json(value: serde_json::Value) -> serde_json::Value {
+  match value {
+    serde_json::Value::Object(object) => {
+      let mut entries: Vec<_> = object
+        .into_iter()
+        .map(|(key, value)| (key, sort_json(value)))
+        .collect();
+      entries.sort_by(|a, b| a.0.cmp(&b.0));
+      serde_json::Value::Object(entries.into_iter().collect())
+    }
+    serde_json::Value::Array(array) => {
+      serde_json::Value::Array(array.into_iter().map(sort_json).collect())
+    }
+    value => value,
+  }
+}
+
+/// `app_config.yaml` (or `app_config.yml` when loading), requires the `yaml` feature.
+#[cfg(feature = "yaml")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct YamlFormat;
+
+#[cfg(feature = "yaml")]
+impl<T: Serialize + DeserializeOwned> ConfigFormat<T> for YamlFormat {
+  fn serialize(&self, value: &T) -> Result<Vec<u8>, FormatError> {
+    Ok(serde_yaml::to_string(value)?.into_bytes())
+  }
+
+  fn deserialize(&self, bytes: &[u8]) -> Result<T, FormatError> {
+    deserialize_from(serde_yaml::Deserializer::from_slice(bytes))
+  }
+
+  fn extension(&self) -> &str {
+    "yaml"
+  }
+
+  fn alternative_extensions(&self) -> &[&str] {
+    &["yml"]
+  }
+
+  fn comment_prefix(&self) -> Option<&str> {
+    Some("#")
+  }
+}
+
+/// `app_config.ron`, requires the `ron` feature.
+#[cfg(feature = "ron")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RonFormat;
+
+#[cfg(feature = "ron")]
+impl<T: Serialize + DeserializeOwned> ConfigFormat<T> for RonFormat {
+  fn serialize(&self, value: &T) -> Result<Vec<u8>, FormatError> {
+    self.serialize_with(value, &SaveOptions::default())
+  }
+
+  fn serialize_with(&self, value: &T, options: &SaveOptions) -> Result<Vec<u8>, FormatError> {
+    let s = if options.pretty {
+      ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())?
+    }
+    else {
+      ron::ser::to_string(value)?
+    };
+    Ok(s.into_bytes())
+  }
+
+  fn deserialize(&self, bytes: &[u8]) -> Result<T, FormatError> {
+    Ok(ron::de::from_bytes(bytes)?)
+  }
+
+  fn extension(&self) -> &str {
+    "ron"
+  }
+
+  fn comment_prefix(&self) -> Option<&str> {
+    Some("//")
+  }
+}
+
+/// Parses JSON5 (comments, trailing commas, unquoted keys) for hand-edited JSON files.
+#[cfg(feature = "json5")]
+pub(crate) fn deserialize_json5<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, FormatError> {
+  Ok(json5::from_str(std::str::from_utf8(bytes)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{ConfigFormat, SaveOptions, TomlFormat};
+  use serde::{Deserialize, Serialize};
+  use std::collections::HashMap;
+
+  #[derive(Serialize, Deserialize)]
+  struct Config {
+    recent_files: Vec<String>,
+    window: Window,
+  }
+
+  #[derive(Serialize, Deserialize)]
+  struct Window {
+    size: (u32, u32),
+  }
+
+  #[test]
+  fn toml_output_styles() {
+    let config = Config {
+      recent_files: vec!["a.txt".into(), "b.txt".into()],
+      window: Window { size: (800, 600) },
+    };
+    let write = |pretty| {
+      let options = SaveOptions::default().with_pretty(pretty);
+      String::from_utf8(TomlFormat.serialize_with(&config, &options).unwrap()).unwrap()
+    };
+    assert_eq!(
+      write(true),
+      "recent_files = [\n    \"a.txt\",\n    \"b.txt\",\n]\n\n[window]\nsize = [\n    800,\n    600,\n]\n"
+    );
+    assert_eq!(
+      write(false),
+      "recent_files = [\"a.txt\", \"b.txt\"]\n\n[window]\nsize = [800, 600]\n"
+    );
+  }
+
+  #[derive(Serialize, Deserialize)]
+  struct Keymap {
+    name: String,
+    bindings: HashMap<String, String>,
+  }
+
+  #[test]
+  fn sorted_keys() {
+    let keymap = |keys: &[&str]| Keymap {
+      name: "default".into(),
+      bindings: keys
+        .iter()
+        .map(|&key| (key.to_owned(), format!("cmd_{}", key)))
+        .collect(),
+    };
+    let keys = ["q", "w", "e", "r", "t", "y", "u", "i", "o", "p"];
+    let mut reversed = keys;
+    reversed.reverse();
+    let options = SaveOptions::default().with_sort_keys(true);
+    let a = TomlFormat.serialize_with(&keymap(&keys), &options).unwrap();
+    let b = TomlFormat
+      .serialize_with(&keymap(&reversed), &options)
+      .unwrap();
+    assert_eq!(a, b);
+    assert!(String::from_utf8(a)
+      .unwrap()
+      .starts_with("name = \"default\"\n\n[bindings]\ne = \"cmd_e\"\ni = \"cmd_i\"\n"));
+  }
+}
@@ -0,0 +1,381 @@
+use crate::{store::SharedStore, ConfigKey};
+use std::{
+  collections::hash_map::DefaultHasher,
+  fs::{File, OpenOptions},
+  hash::{Hash, Hasher},
+  io::{self, Write},
+  path::{Path, PathBuf},
+  time::{Duration, Instant, SystemTime},
+};
+
+/// What a file looked like when it was last read or written, to notice changes made by others.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FileStamp {
+  modified: Option<SystemTime>,
+  len: u64,
+  hash: u64,
+}
+
+impl FileStamp {
+  /// The stamp of the file, which was just read or written with `contents`.
+  pub(crate) fn new(
+    key: &ConfigKey,
+    contents: &[u8],
+    store: Option<&SharedStore>,
+  ) -> io::Result<Self> {
+    if store.is_some() {
+      return Ok(Self {
+        modified: None,
+        len: contents.len() as u64,
+        hash: hash_bytes(contents),
+      });
+    }
+    let metadata = std::fs::metadata(key.path())?;
+    Ok(Self {
+      modified: metadata.modified().ok(),
+      len: metadata.len(),
+      hash: hash_bytes(contents),
+    })
+  }
+
+  /// Returns `true` if the file no longer matches the stamp. The contents are only compared
+  /// when the modification time and size are the same, since the time may be coarse.
+  pub(crate) fn changed(&self, key: &ConfigKey, store: Option<&SharedStore>) -> io::Result<bool> {
+    if let Some(store) = store {
+      let contents = store.read(key)?.ok_or(io::ErrorKind::NotFound)?;
+      return Ok(*self != Self::new(key, &contents, Some(store))?);
+    }
+    let path = key.path();
+    let metadata = std::fs::metadata(path)?;
+    if metadata.modified().ok() != self.modified || metadata.len() != self.len {
+      return Ok(true);
+    }
+    Ok(hash_bytes(&std::fs::read(path)?) != self.hash)
+  }
+}
+
+pub(crate) fn hash_bytes(contents: &[u8]) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  contents.hash(&mut hasher);
+  hasher.finish()
+}
+
+/// Locks the file at `path`, waiting up to `timeout` while another process holds the lock.
+/// Returns `None` when it timed out. The lock is released when the returned file is closed.
+///
+/// An exclusive lock creates the file, a shared lock fails if it does not exist.
+pub(crate) fn lock_file(
+  path: &Path,
+  exclusive: bool,
+  timeout: Duration,
+) -> io::Result<Option<File>> {
+  let file = if exclusive {
+    OpenOptions::new()
+      .create(true)
+      .truncate(false)
+      .write(true)
+      .open(path)?
+  }
+  else {
+    File::open(path)?
+  };
+  let started = Instant::now();
+  loop {
+    match try_lock(&file, exclusive)? {
+      true => return Ok(Some(file)),
+      false if started.elapsed() < timeout => std::thread::sleep(Duration::from_millis(10)),
+      false => return Ok(None),
+    }
+  }
+}
+
+/// Locks `file` without waiting. Returns `false` if another process holds the lock.
+#[cfg(unix)]
+fn try_lock(file: &File, exclusive: bool) -> io::Result<bool> {
+  use std::os::unix::io::AsRawFd;
+
+  let operation = if exclusive {
+    libc::LOCK_EX
+  }
+  else {
+    libc::LOCK_SH
+  };
+  // SAFETY: The descriptor stays open while `file` is borrowed.
+  if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } == 0 {
+    return Ok(true);
+  }
+  let err = io::Error::last_os_error();
+  match err.raw_os_error() {
+    Some(libc::EWOULDBLOCK) => Ok(false),
+    _ => Err(err),
+  }
+}
+
+/// Locks `file` without waiting. Returns `false` if another process holds the lock.
+#[cfg(windows)]
+fn try_lock(file: &File, exclusive: bool) -> io::Result<bool> {
+  use std::os::windows::io::AsRawHandle;
+  use windows_sys::Win32::{
+    Foundation::ERROR_LOCK_VIOLATION,
+    Storage::FileSystem::{LockFileEx, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY},
+    System::IO::OVERLAPPED,
+  };
+
+  let mut flags = LOCKFILE_FAIL_IMMEDIATELY;
+  if exclusive {
+    flags |= LOCKFILE_EXCLUSIVE_LOCK;
+  }
+  // SAFETY: An all-zero `OVERLAPPED` locks from offset 0 on a synchronous handle,
+  // which stays open while `file` is borrowed.
+  let locked = unsafe {
+    let mut overlapped: OVERLAPPED = std::mem::zeroed();
+    LockFileEx(
+      file.as_raw_handle(),
+      flags,
+      0,
+      u32::MAX,
+      u32::MAX,
+      &mut overlapped,
+    )
+  };
+  if locked != 0 {
+    return Ok(true);
+  }
+  let err = io::Error::last_os_error();
+  match err.raw_os_error() {
+    Some(code) if code == ERROR_LOCK_VIOLATION as i32 => Ok(false),
+    _ => Err(err),
+  }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn try_lock(_file: &File, _exclusive: bool) -> io::Result<bool> {
+  Err(io::ErrorKind::Unsupported.into())
+}
+
+/// The sibling file that `write_atomic` writes to before renaming it over `path`.
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+  with_suffix(path, ".tmp")
+}
+
+/// `path` with `suffix` appended to the file name, e.g. `app_config.toml.bak`.
+pub(crate) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+  let mut name = path.file_name().unwrap_or_default().to_owned();
+  name.push(suffix);
+  path.with_file_name(name)
+}
+
+/// How often a write that failed because another process briefly holds the file is attempted again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Retries {
+  pub(crate) count: u32,
+  pub(crate) delay: Duration,
+}
+
+impl Default for Retries {
+  fn default() -> Self {
+    Self {
+      count: 5,
+      delay: Duration::from_millis(20),
+    }
+  }
+}
+
+/// How `write_atomic` writes a file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct WriteOptions {
+  /// Sync the file and (on Unix) the parent directory to disk
+  /// so that the new contents also survive a power loss.
+  pub(crate) durable: bool,
+  pub(crate) retries: Retries,
+  /// The Unix permissions of the file, otherwise the default of the process.
+  pub(crate) mode: Option<u32>,
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it over `path`,
+/// so that a crash or a full disk never leaves a truncated file behind.
+pub(crate) fn write_atomic(path: &Path, contents: &[u8], options: WriteOptions) -> io::Result<()> {
+  let temp = temp_path(path);
+  let result = retry(options.retries, path, false, || {
+    let mut file = create_file(&temp, options.mode)?;
+    file.write_all(contents)?;
+    if options.durable {
+      file.sync_all()?;
+    }
+    Ok(())
+  })
+  .and_then(|_| retry(options.retries, path, true, || std::fs::rename(&temp, path)));
+  if result.is_err() {
+    std::fs::remove_file(&temp).ok();
+    return result;
+  }
+  if options.durable {
+    sync_parent_dir(path)?;
+  }
+  Ok(())
+}
+
+/// Moves `from` to `to`, copying when they are on different file systems.
+pub(crate) fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+  if std::fs::rename(from, to).is_ok() {
+    return Ok(());
+  }
+  let contents = std::fs::read(from)?;
+  let options = WriteOptions {
+    durable: true,
+    ..Default::default()
+  };
+  write_atomic(to, &contents, options)?;
+  std::fs::remove_file(from)
+}
+
+/// Copies `path` to `path.1`, shifting the older copies up to `path.{retention}`
+/// and dropping the oldest one. The copy keeps the modification time of `path`.
+pub(crate) fn rotate_backups(path: &Path, retention: usize) -> io::Result<()> {
+  let numbered = |i: usize| with_suffix(path, &format!(".{}", i));
+  match std::fs::remove_file(numbered(retention)) {
+    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+    _ => {}
+  }
+  for i in (1..retention).rev() {
+    let from = numbered(i);
+    if from.exists() {
+      std::fs::rename(&from, numbered(i + 1))?;
+    }
+  }
+  let modified = std::fs::metadata(path)?.modified()?;
+  let backup = numbered(1);
+  std::fs::copy(path, &backup)?;
+  std::fs::File::options()
+    .write(true)
+    .open(&backup)?
+    .set_modified(modified)
+}
+
+/// Creates or truncates the file at `path`, never readable by others than `mode` allows.
+#[cfg(unix)]
+fn create_file(path: &Path, mode: Option<u32>) -> io::Result<File> {
+  use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+  let mut options = OpenOptions::new();
+  options.write(true).create(true).truncate(true);
+  if let Some(mode) = mode {
+    options.mode(mode);
+  }
+  let file = options.open(path)?;
+  // The mode above is restricted by the umask, and does not apply to an existing file
+  if let Some(mode) = mode {
+    file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+  }
+  Ok(file)
+}
+
+#[cfg(not(unix))]
+fn create_file(path: &Path, _mode: Option<u32>) -> io::Result<File> {
+  File::create(path)
+}
+
+/// Creates the directory `path` and its parents. With a file `mode`, the new directories
+/// can be entered by those who can read the file, e.g. `0o700` for `0o600`.
+pub(crate) fn create_dir_all(path: &Path, mode: Option<u32>) -> io::Result<()> {
+  let mut builder = std::fs::DirBuilder::new();
+  builder.recursive(true);
+  #[cfg(unix)]
+  if let Some(mode) = mode {
+    use std::os::unix::fs::DirBuilderExt;
+    builder.mode(mode | (mode & 0o444) >> 2);
+  }
+  #[cfg(not(unix))]
+  let _ = mode;
+  builder.create(path)
+}
+
+/// Makes the directory entry created by a rename durable.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+  match path.parent() {
+    Some(parent) => std::fs::File::open(parent)?.sync_all(),
+    None => Ok(()),
+  }
+}
+
+/// Directories cannot be opened as files on Windows; syncing the file is the best we can do.
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+  Ok(())
+}
+
+/// Calls `f` again while it fails because an antivirus scanner, a backup tool or an editor
+/// briefly holds the file at `path` open, which happens on Windows.
+/// Replacing the file with a `rename` then also fails with `PermissionDenied`.
+fn retry<R>(
+  retries: Retries,
+  path: &Path,
+  rename: bool,
+  mut f: impl FnMut() -> io::Result<R>,
+) -> io::Result<R> {
+  let mut attempt = 0;
+  loop {
+    match f() {
+      Err(err) if attempt < retries.count && is_transient(&err, rename) => {
+        attempt += 1;
+        event!(
+          Info,
+          "Retrying to write {} ({}/{}): {}",
+          path.display(),
+          attempt,
+          retries.count,
+          err
+        );
+        std::thread::sleep(retries.delay);
+      }
+      result => return result,
+    }
+  }
+}
+
+/// `ERROR_SHARING_VIOLATION` and `ERROR_LOCK_VIOLATION`
+#[cfg(windows)]
+fn is_transient(err: &io::Error, rename: bool) -> bool {
+  matches!(err.raw_os_error(), Some(32 | 33))
+    || (rename && err.kind() == io::ErrorKind::PermissionDenied)
+}
+
+#[cfg(not(windows))]
+fn is_transient(_err: &io::Error, _rename: bool) -> bool {
+  false
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Retries;
+  use std::{io, path::Path, time::Duration};
+
+  #[test]
+  fn retry() {
+    let retries = Retries {
+      count: 2,
+      delay: Duration::ZERO,
+    };
+    let path = Path::new("app_config.toml");
+    let mut attempts = 0;
+    let result: io::Result<()> = super::retry(retries, path, true, || {
+      attempts += 1;
+      Err(io::ErrorKind::NotFound.into())
+    });
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(attempts, 1);
+
+    // A sharing violation
+    #[cfg(windows)]
+    {
+      let mut attempts = 0;
+      let result: io::Result<()> = super::retry(retries, path, false, || {
+        attempts += 1;
+        Err(io::Error::from_raw_os_error(32))
+      });
+      assert!(result.is_err());
+      assert_eq!(attempts, 3);
+    }
+  }
+}
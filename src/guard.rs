@@ -0,0 +1,133 @@
+use crate::{AppConfigError, AppConfigManager, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+  cell::RefMut,
+  ops::{Deref, DerefMut},
+};
+
+/// Borrows the data mutably and saves it when dropped, returned by [`AppConfigManager::edit`].
+///
+/// A failed save on drop is passed to [`on_save_error`](AppConfigManager::set_on_save_error),
+/// call [`commit`](Self::commit) to get the error instead.
+/// Nothing is saved when the guard is dropped while the thread is panicking,
+/// since the changes may be half done.
+pub struct EditGuard<'a, T>
+where
+  T: Sized + Serialize + DeserializeOwned,
+{
+  manager: &'a AppConfigManager<T>,
+  data: Option<RefMut<'a, T>>,
+}
+
+impl<'a, T> EditGuard<'a, T>
+where
+  T: Sized + Serialize + DeserializeOwned,
+{
+  /// Releases the data and saves it.
+  pub fn commit(mut self) -> Result {
+    self.data.take();
+    self.manager.save()
+  }
+}
+
+impl<'a, T> Deref for EditGuard<'a, T>
+where
+  T: Sized + Serialize + DeserializeOwned,
+{
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    self.data.as_ref().expect("released by commit")
+  }
+}
+
+impl<'a, T> DerefMut for EditGuard<'a, T>
+where
+  T: Sized + Serialize + DeserializeOwned,
+{
+  fn deref_mut(&mut self) -> &mut T {
+    self.data.as_mut().expect("released by commit")
+  }
+}
+
+impl<'a, T> Drop for EditGuard<'a, T>
+where
+  T: Sized + Serialize + DeserializeOwned,
+{
+  fn drop(&mut self) {
+    // `None` after `commit`
+    if self.data.take().is_some() && !std::thread::panicking() {
+      if let Err(err) = self.manager.save() {
+        self.manager.report_save_error(err);
+      }
+    }
+  }
+}
+
+impl<T> AppConfigManager<T>
+where
+  T: Sized + Serialize + DeserializeOwned,
+{
+  /// Borrows the data mutably until the returned guard is dropped, then saves it.
+  ///
+  /// Returns [`AppConfigError::AlreadyBorrowed`] instead of panicking when the data is borrowed,
+  /// e.g. by another guard.
+  pub fn edit(&self) -> Result<EditGuard<'_, T>> {
+    let data = self
+      .data
+      .try_borrow_mut()
+      .map_err(|_| AppConfigError::AlreadyBorrowed)?;
+    Ok(EditGuard {
+      manager: self,
+      data: Some(data),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{AppConfigError, AppConfigManager};
+  use serde::{Deserialize, Serialize};
+  use std::{cell::RefCell, panic::AssertUnwindSafe, rc::Rc};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Window {
+    x: u32,
+    y: u32,
+  }
+
+  #[test]
+  fn edit() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Window::default()));
+    let manager = AppConfigManager::new(config.clone(), "edit", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    let read = || std::fs::read_to_string(&path).unwrap();
+
+    let mut guard = manager.edit().unwrap();
+    guard.x = 1;
+    guard.y = 2;
+    assert!(matches!(
+      manager.edit(),
+      Err(AppConfigError::AlreadyBorrowed)
+    ));
+    guard.commit().unwrap();
+    assert_eq!(read(), "x = 1\ny = 2\n");
+
+    // Saved when dropped
+    manager.edit().unwrap().x = 3;
+    assert_eq!(read(), "x = 3\ny = 2\n");
+
+    // Not saved while panicking
+    std::panic::catch_unwind(AssertUnwindSafe(|| {
+      let mut guard = manager.edit().unwrap();
+      guard.x = 4;
+      panic!();
+    }))
+    .unwrap_err();
+    assert_eq!(read(), "x = 3\ny = 2\n");
+    assert_eq!(config.borrow().x, 4);
+  }
+}
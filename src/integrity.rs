@@ -0,0 +1,289 @@
+use crate::{AppConfigError, AppConfigManager, Result, SharedData};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::Path;
+
+/// Starts the value of the checksum on the last line of the file.
+const MARKER: &str = "appconfig-crc32: ";
+
+/// Whether `save()` appends a checksum to the file, and what `load()` does when it is missing
+/// or does not match, e.g. after a bit flip on flash storage or after the file was edited by hand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum IntegrityPolicy {
+  /// No checksum is written or checked.
+  #[default]
+  Disabled,
+  /// Fails with [`AppConfigError::IntegrityMismatch`], which is reported, skipped and replaced
+  /// by the backup like a parse error.
+  Reject,
+  /// Like `Reject`, but loads a file without a checksum, e.g. one written before the policy was set,
+  /// and saves it again with one.
+  AdoptUnmarked,
+  /// Reports the mismatch to the callback of `with_on_parse_error` and loads the file.
+  Warn,
+  /// Loads the file. The next save writes a new checksum.
+  Accept,
+}
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// Appends a CRC-32 checksum of the file as a comment on its last line when saving,
+  /// e.g. `# appconfig-crc32: 1c291ca3`, and checks it when loading. [`IntegrityPolicy::Disabled`] by default.
+  ///
+  /// The format must have comments, so JSON files fail with [`AppConfigError::IntegrityUnsupported`].
+  pub fn set_integrity_policy(&mut self, value: IntegrityPolicy) -> &mut Self {
+    self.integrity_policy = value;
+    self
+  }
+
+  pub fn with_integrity_policy(mut self, value: IntegrityPolicy) -> Self {
+    self.set_integrity_policy(value);
+    self
+  }
+
+  /// Returns `false` if a checksum is required but the format has no comments to hold it.
+  pub(crate) fn supports_integrity_policy(&self) -> bool {
+    self.integrity_policy == IntegrityPolicy::Disabled || self.format.comment_prefix().is_some()
+  }
+
+  /// `s` written to `path` followed by the line with its checksum.
+  pub(crate) fn append_checksum(&self, path: &Path, mut s: Vec<u8>) -> Result<Vec<u8>> {
+    if self.integrity_policy == IntegrityPolicy::Disabled {
+      return Ok(s);
+    }
+    let prefix = self.checksum_prefix(path)?;
+    if !s.is_empty() && !s.ends_with(b"\n") {
+      s.push(b'\n');
+    }
+    let line = format!("{} {}{:08x}\n", prefix, MARKER, crc32(&s));
+    s.extend(line.into_bytes());
+    Ok(s)
+  }
+
+  /// Removes the line with the checksum from the file at `path` read as `s` and checks it.
+  /// Also returns `true` if the file has no checksum and is to be saved again with one.
+  pub(crate) fn verify_checksum<'a>(&self, path: &Path, s: &'a [u8]) -> Result<(&'a [u8], bool)> {
+    if self.integrity_policy == IntegrityPolicy::Disabled {
+      return Ok((s, false));
+    }
+    let prefix = self.checksum_prefix(path)?;
+    let start = s[..s.len().saturating_sub(1)]
+      .iter()
+      .rposition(|&x| x == b'\n')
+      .map_or(0, |i| i + 1);
+    let (body, line) = s.split_at(start);
+    let expected = String::from_utf8_lossy(line)
+      .trim_end()
+      .strip_prefix(prefix)
+      .and_then(|line| line.trim_start().strip_prefix(MARKER))
+      .map(|x| u32::from_str_radix(x, 16).ok());
+    let (body, unchecked) = match expected {
+      Some(Some(expected)) if expected == crc32(body) => return Ok((body, false)),
+      Some(_) => (body, false),
+      None if self.integrity_policy == IntegrityPolicy::AdoptUnmarked => return Ok((s, true)),
+      None => (s, true),
+    };
+    let err = AppConfigError::IntegrityMismatch {
+      path: path.to_owned(),
+    };
+    match self.integrity_policy {
+      IntegrityPolicy::Reject | IntegrityPolicy::AdoptUnmarked => return Err(err),
+      IntegrityPolicy::Warn => {
+        event!(Warn, "{}", err);
+        if let Ok(mut on_parse_error) = self.on_parse_error.try_lock() {
+          if let Some(f) = on_parse_error.as_mut() {
+            f(&err);
+          }
+        }
+      }
+      _ => {}
+    }
+    Ok((body, unchecked))
+  }
+
+  fn checksum_prefix(&self, path: &Path) -> Result<&str> {
+    self
+      .format
+      .comment_prefix()
+      .ok_or_else(|| AppConfigError::IntegrityUnsupported {
+        path: path.to_owned(),
+      })
+  }
+}
+
+/// CRC-32 (IEEE 802.3) of `bytes`.
+fn crc32(bytes: &[u8]) -> u32 {
+  const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+      let mut crc = i as u32;
+      let mut bit = 0;
+      while bit < 8 {
+        crc = if crc & 1 == 1 {
+          (crc >> 1) ^ 0xedb8_8320
+        }
+        else {
+          crc >> 1
+        };
+        bit += 1;
+      }
+      table[i] = crc;
+      i += 1;
+    }
+    table
+  };
+  !bytes.iter().fold(!0, |crc, &byte| {
+    (crc >> 8) ^ TABLE[((crc ^ u32::from(byte)) & 0xff) as usize]
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::IntegrityPolicy;
+  use crate::{AppConfigError, AppConfigManager, LoadOutcome};
+  use serde::{Deserialize, Serialize};
+  use std::{cell::RefCell, rc::Rc};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Calibration {
+    offset: i32,
+    gain: f64,
+  }
+
+  #[test]
+  fn crc32() {
+    assert_eq!(super::crc32(b"123456789"), 0xcbf4_3926);
+  }
+
+  #[test]
+  fn integrity() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Calibration {
+      offset: 42,
+      gain: 1.5,
+    }));
+    let new_manager = |policy| {
+      AppConfigManager::new(config.clone(), "integrity", "sumibi-yakitori")
+        .with_base_dir(dir.path())
+        .with_integrity_policy(policy)
+        .with_skip_parsing_error_when_loading(false)
+        .with_auto_saving(false)
+    };
+    let manager = new_manager(IntegrityPolicy::Reject);
+    let path = manager.get_user_config_path().unwrap();
+    manager.save().unwrap();
+    let s = std::fs::read_to_string(&path).unwrap();
+    assert!(s.starts_with("offset = 42\ngain = 1.5\n# appconfig-crc32: "));
+    manager.load().unwrap();
+
+    // `offset = 43` still parses
+    let mut flipped = s.into_bytes();
+    flipped[10] ^= 1;
+    std::fs::write(&path, &flipped).unwrap();
+    assert!(matches!(
+      manager.load(),
+      Err(AppConfigError::IntegrityMismatch { .. })
+    ));
+    assert_eq!(config.borrow().offset, 42);
+
+    // Falls back to the backup
+    let manager = manager.with_backup(true);
+    manager.save().unwrap();
+    manager.save().unwrap();
+    std::fs::write(&path, &flipped).unwrap();
+    config.borrow_mut().offset = 0;
+    assert!(matches!(
+      manager.load_with_outcome().unwrap(),
+      LoadOutcome::BackupLoaded(AppConfigError::IntegrityMismatch { .. })
+    ));
+    assert_eq!(config.borrow().offset, 42);
+
+    for policy in [IntegrityPolicy::Warn, IntegrityPolicy::Accept] {
+      std::fs::write(&path, &flipped).unwrap();
+      let warnings = Rc::new(RefCell::new(0));
+      let counter = warnings.clone();
+      let manager = new_manager(policy).with_on_parse_error(move |_| *counter.borrow_mut() += 1);
+      manager.load().unwrap();
+      assert_eq!(config.borrow().offset, 43);
+      assert_eq!(*warnings.borrow(), (policy == IntegrityPolicy::Warn) as i32);
+      // Written with a new checksum
+      manager.save().unwrap();
+      new_manager(IntegrityPolicy::Reject).load().unwrap();
+    }
+  }
+
+  #[test]
+  fn unmarked() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Calibration::default()));
+    let new_manager = |policy| {
+      AppConfigManager::new(config.clone(), "integrity_unmarked", "sumibi-yakitori")
+        .with_base_dir(dir.path())
+        .with_integrity_policy(policy)
+        .with_skip_parsing_error_when_loading(false)
+        .with_auto_saving(false)
+    };
+    let path = new_manager(IntegrityPolicy::Reject)
+      .get_user_config_path()
+      .unwrap();
+    let unmarked = "offset = 42\ngain = 1.5\n";
+    for s in [
+      unmarked,
+      "offset = 42\ngain = 1.5\n# appconfig-crc32: nothex\n",
+    ] {
+      std::fs::write(&path, s).unwrap();
+      assert!(matches!(
+        new_manager(IntegrityPolicy::Reject).load(),
+        Err(AppConfigError::IntegrityMismatch { .. })
+      ));
+    }
+    assert_eq!(config.borrow().offset, 0);
+
+    std::fs::write(&path, unmarked).unwrap();
+    new_manager(IntegrityPolicy::AdoptUnmarked).load().unwrap();
+    assert_eq!(config.borrow().offset, 42);
+    // Saved again with a checksum
+    assert!(std::fs::read_to_string(&path)
+      .unwrap()
+      .contains("# appconfig-crc32: "));
+    new_manager(IntegrityPolicy::Reject).load().unwrap();
+  }
+
+  #[cfg(feature = "json")]
+  #[test]
+  fn unsupported() {
+    use crate::{AppConfigManagerBuilder, BuildError, JsonFormat};
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Calibration::default()));
+    let manager = AppConfigManager::new(config.clone(), "integrity_json", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_format(JsonFormat)
+      .with_integrity_policy(IntegrityPolicy::Reject)
+      .with_auto_saving(false);
+    assert!(matches!(
+      manager.save(),
+      Err(AppConfigError::IntegrityUnsupported { .. })
+    ));
+    let base_dir = dir.path().to_owned();
+    assert!(matches!(
+      AppConfigManagerBuilder::<Calibration>::new("integrity_json", "sumibi-yakitori")
+        .with_options(move |manager| {
+          manager
+            .with_base_dir(&base_dir)
+            .with_format(JsonFormat)
+            .with_integrity_policy(IntegrityPolicy::Warn)
+            .with_auto_saving(false)
+        })
+        .build(config),
+      Err(BuildError::ConflictingOptions(
+        "with_integrity_policy",
+        "with_format"
+      ))
+    ));
+  }
+}
@@ -0,0 +1,448 @@
+use crate::{FormatError, SecretStore};
+use std::fmt;
+
+/// A [`SecretStore`] in the keychain of the operating system: the login keychain on macOS,
+/// the Credential Manager on Windows, and the Secret Service (GNOME Keyring, KWallet) through
+/// `secret-tool` on Linux and the BSDs.
+///
+/// Not available elsewhere, or when `secret-tool` is missing or no keyring is running,
+/// e.g. over SSH, so the secrets are written to the file.
+///
+/// ```no_run
+/// # use std::{cell::RefCell, rc::Rc};
+/// # use appconfig::{AppConfigManager, SystemKeyring};
+/// # let config = Rc::new(RefCell::new(0_u32));
+/// let manager = AppConfigManager::new(config, "app", "org").with_secret_store(SystemKeyring::new());
+/// ```
+#[derive(Clone)]
+pub struct SystemKeyring {
+  /// `secret-tool` and the arguments before its command.
+  #[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+  ))]
+  command: Vec<std::ffi::OsString>,
+}
+
+impl SystemKeyring {
+  pub fn new() -> Self {
+    Self {
+      #[cfg(all(
+        unix,
+        not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+      ))]
+      command: vec!["secret-tool".into()],
+    }
+  }
+}
+
+impl Default for SystemKeyring {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl fmt::Debug for SystemKeyring {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("SystemKeyring")
+  }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+  use super::SystemKeyring;
+  use crate::FormatError;
+  use std::{convert::TryFrom, ffi::c_void, io, ptr};
+
+  type OsStatus = i32;
+
+  const ERR_SEC_ITEM_NOT_FOUND: OsStatus = -25300;
+
+  #[link(name = "Security", kind = "framework")]
+  extern "C" {
+    fn SecKeychainFindGenericPassword(
+      keychain: *const c_void,
+      service_length: u32,
+      service: *const u8,
+      account_length: u32,
+      account: *const u8,
+      password_length: *mut u32,
+      password: *mut *mut c_void,
+      item: *mut *mut c_void,
+    ) -> OsStatus;
+    fn SecKeychainAddGenericPassword(
+      keychain: *mut c_void,
+      service_length: u32,
+      service: *const u8,
+      account_length: u32,
+      account: *const u8,
+      password_length: u32,
+      password: *const c_void,
+      item: *mut *mut c_void,
+    ) -> OsStatus;
+    fn SecKeychainItemModifyAttributesAndData(
+      item: *mut c_void,
+      attributes: *const c_void,
+      length: u32,
+      data: *const c_void,
+    ) -> OsStatus;
+    fn SecKeychainItemFreeContent(attributes: *mut c_void, data: *mut c_void) -> OsStatus;
+  }
+
+  #[link(name = "CoreFoundation", kind = "framework")]
+  extern "C" {
+    fn CFRelease(object: *const c_void);
+  }
+
+  fn check(status: OsStatus) -> Result<(), FormatError> {
+    match status {
+      0 => Ok(()),
+      status => Err(format!("Keychain error {}", status).into()),
+    }
+  }
+
+  fn length(s: &[u8]) -> Result<u32, FormatError> {
+    u32::try_from(s.len()).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput).into())
+  }
+
+  impl SystemKeyring {
+    pub(super) fn get_secret(
+      &self,
+      service: &str,
+      key: &str,
+    ) -> Result<Option<String>, FormatError> {
+      let mut password_length = 0;
+      let mut password = ptr::null_mut();
+      // SAFETY: The lengths match the strings, and the password is copied before it is freed.
+      unsafe {
+        let status = SecKeychainFindGenericPassword(
+          ptr::null(),
+          length(service.as_bytes())?,
+          service.as_ptr(),
+          length(key.as_bytes())?,
+          key.as_ptr(),
+          &mut password_length,
+          &mut password,
+          ptr::null_mut(),
+        );
+        if status == ERR_SEC_ITEM_NOT_FOUND {
+          return Ok(None);
+        }
+        check(status)?;
+        let bytes =
+          std::slice::from_raw_parts(password as *const u8, password_length as usize).to_vec();
+        SecKeychainItemFreeContent(ptr::null_mut(), password);
+        Ok(Some(String::from_utf8(bytes)?))
+      }
+    }
+
+    pub(super) fn set_secret(
+      &self,
+      service: &str,
+      key: &str,
+      secret: &str,
+    ) -> Result<(), FormatError> {
+      let mut item = ptr::null_mut();
+      // SAFETY: The lengths match the strings, and the item is released after it is modified.
+      unsafe {
+        let status = SecKeychainFindGenericPassword(
+          ptr::null(),
+          length(service.as_bytes())?,
+          service.as_ptr(),
+          length(key.as_bytes())?,
+          key.as_ptr(),
+          ptr::null_mut(),
+          ptr::null_mut(),
+          &mut item,
+        );
+        if status == ERR_SEC_ITEM_NOT_FOUND {
+          return check(SecKeychainAddGenericPassword(
+            ptr::null_mut(),
+            length(service.as_bytes())?,
+            service.as_ptr(),
+            length(key.as_bytes())?,
+            key.as_ptr(),
+            length(secret.as_bytes())?,
+            secret.as_ptr() as *const c_void,
+            ptr::null_mut(),
+          ));
+        }
+        check(status)?;
+        let status = SecKeychainItemModifyAttributesAndData(
+          item,
+          ptr::null(),
+          length(secret.as_bytes())?,
+          secret.as_ptr() as *const c_void,
+        );
+        CFRelease(item);
+        check(status)
+      }
+    }
+
+    pub(super) fn available(&self) -> bool {
+      true
+    }
+  }
+}
+
+#[cfg(windows)]
+mod platform {
+  use super::SystemKeyring;
+  use crate::FormatError;
+  use std::{convert::TryFrom, io, ptr};
+  use windows_sys::Win32::{
+    Foundation::ERROR_NOT_FOUND,
+    Security::Credentials::{
+      CredFree, CredReadW, CredWriteW, CREDENTIALW, CRED_PERSIST_LOCAL_MACHINE, CRED_TYPE_GENERIC,
+    },
+  };
+
+  /// The credential of `key` of `service`, named like the `keyring` crate does.
+  fn target_name(service: &str, key: &str) -> Vec<u16> {
+    format!("{}.{}", key, service)
+      .encode_utf16()
+      .chain(Some(0))
+      .collect()
+  }
+
+  impl SystemKeyring {
+    pub(super) fn get_secret(
+      &self,
+      service: &str,
+      key: &str,
+    ) -> Result<Option<String>, FormatError> {
+      let target = target_name(service, key);
+      let mut credential: *mut CREDENTIALW = ptr::null_mut();
+      // SAFETY: `target` is null-terminated, and the blob is copied before the credential is freed.
+      unsafe {
+        if CredReadW(target.as_ptr(), CRED_TYPE_GENERIC, 0, &mut credential) == 0 {
+          let err = io::Error::last_os_error();
+          return match err.raw_os_error() {
+            Some(code) if code == ERROR_NOT_FOUND as i32 => Ok(None),
+            _ => Err(err.into()),
+          };
+        }
+        let blob = std::slice::from_raw_parts(
+          (*credential).CredentialBlob,
+          (*credential).CredentialBlobSize as usize,
+        )
+        .to_vec();
+        CredFree(credential as *const _);
+        Ok(Some(String::from_utf8(blob)?))
+      }
+    }
+
+    pub(super) fn set_secret(
+      &self,
+      service: &str,
+      key: &str,
+      secret: &str,
+    ) -> Result<(), FormatError> {
+      let mut target = target_name(service, key);
+      let mut user_name: Vec<u16> = key.encode_utf16().chain(Some(0)).collect();
+      let credential = CREDENTIALW {
+        Type: CRED_TYPE_GENERIC,
+        TargetName: target.as_mut_ptr(),
+        CredentialBlobSize: u32::try_from(secret.len())
+          .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?,
+        CredentialBlob: secret.as_ptr() as *mut u8,
+        Persist: CRED_PERSIST_LOCAL_MACHINE,
+        UserName: user_name.as_mut_ptr(),
+        ..Default::default()
+      };
+      // SAFETY: The strings and the blob outlive the call, which only reads them.
+      if unsafe { CredWriteW(&credential, 0) } == 0 {
+        return Err(io::Error::last_os_error().into());
+      }
+      Ok(())
+    }
+
+    pub(super) fn available(&self) -> bool {
+      true
+    }
+  }
+}
+
+#[cfg(all(
+  unix,
+  not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+))]
+mod platform {
+  use super::SystemKeyring;
+  use crate::FormatError;
+  use std::{
+    io::{self, Write},
+    process::{Command, Output, Stdio},
+  };
+
+  impl SystemKeyring {
+    /// Runs `secret-tool` with `args`, writing `input` to its standard input.
+    fn run(&self, args: &[&str], input: &str) -> io::Result<Output> {
+      let mut child = Command::new(&self.command[0])
+        .args(&self.command[1..])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+      if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes())?;
+      }
+      child.wait_with_output()
+    }
+
+    /// The secret, or `None` if `secret-tool` found none and reported nothing else.
+    fn lookup(&self, service: &str, key: &str) -> Result<Option<String>, FormatError> {
+      let output = self.run(&["lookup", "service", service, "key", key], "")?;
+      if output.status.success() {
+        return Ok(Some(String::from_utf8(output.stdout)?));
+      }
+      match String::from_utf8_lossy(&output.stderr).trim() {
+        "" => Ok(None),
+        message => Err(message.to_owned().into()),
+      }
+    }
+
+    pub(super) fn get_secret(
+      &self,
+      service: &str,
+      key: &str,
+    ) -> Result<Option<String>, FormatError> {
+      self.lookup(service, key)
+    }
+
+    pub(super) fn set_secret(
+      &self,
+      service: &str,
+      key: &str,
+      secret: &str,
+    ) -> Result<(), FormatError> {
+      let label = format!("--label={} {}", service, key);
+      // The secret is passed on the standard input, never in the arguments
+      let output = self.run(&["store", &label, "service", service, "key", key], secret)?;
+      if output.status.success() {
+        return Ok(());
+      }
+      Err(
+        String::from_utf8_lossy(&output.stderr)
+          .trim()
+          .to_owned()
+          .into(),
+      )
+    }
+
+    pub(super) fn available(&self) -> bool {
+      self.lookup("appconfig", "availability").is_ok()
+    }
+  }
+}
+
+#[cfg(not(any(
+  target_os = "macos",
+  windows,
+  all(
+    unix,
+    not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+  )
+)))]
+mod platform {
+  use super::SystemKeyring;
+  use crate::FormatError;
+  use std::io;
+
+  impl SystemKeyring {
+    pub(super) fn get_secret(
+      &self,
+      _service: &str,
+      _key: &str,
+    ) -> Result<Option<String>, FormatError> {
+      Err(io::Error::from(io::ErrorKind::Unsupported).into())
+    }
+
+    pub(super) fn set_secret(
+      &self,
+      _service: &str,
+      _key: &str,
+      _secret: &str,
+    ) -> Result<(), FormatError> {
+      Err(io::Error::from(io::ErrorKind::Unsupported).into())
+    }
+
+    pub(super) fn available(&self) -> bool {
+      false
+    }
+  }
+}
+
+impl SecretStore for SystemKeyring {
+  fn get(&self, service: &str, key: &str) -> Result<Option<String>, FormatError> {
+    self.get_secret(service, key)
+  }
+
+  fn set(&self, service: &str, key: &str, secret: &str) -> Result<(), FormatError> {
+    self.set_secret(service, key, secret)
+  }
+
+  fn is_available(&self) -> bool {
+    self.available()
+  }
+}
+
+#[cfg(test)]
+#[cfg(all(
+  unix,
+  not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+))]
+mod tests {
+  use super::SystemKeyring;
+  use crate::SecretStore;
+
+  /// Stands in for `secret-tool`, keeping each secret in a file.
+  const SECRET_TOOL: &str = r#"
+dir="$(dirname "$0")"
+case "$1" in
+  store) cat > "$dir/$4.$6" ;;
+  lookup) cat "$dir/$3.$5" 2> /dev/null ;;
+  *) echo "Unknown command" >&2; exit 2 ;;
+esac
+"#;
+
+  #[test]
+  fn secret_tool() {
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("secret-tool");
+    std::fs::write(&script, SECRET_TOOL).unwrap();
+    let keyring = SystemKeyring {
+      command: vec!["sh".into(), script.into()],
+    };
+    assert!(keyring.is_available());
+    assert_eq!(keyring.get("com.org.app", "token").unwrap(), None);
+    keyring.set("com.org.app", "token", "s3cr3t").unwrap();
+    assert_eq!(
+      keyring.get("com.org.app", "token").unwrap().as_deref(),
+      Some("s3cr3t")
+    );
+    assert_eq!(
+      std::fs::read_to_string(dir.path().join("com.org.app.token")).unwrap(),
+      "s3cr3t"
+    );
+
+    // Errors of `secret-tool` are reported, and it is not available
+    let keyring = SystemKeyring {
+      command: vec![
+        "sh".into(),
+        "-c".into(),
+        "echo 'Cannot autolaunch D-Bus' >&2; exit 1".into(),
+      ],
+    };
+    assert_eq!(
+      keyring.get("com.org.app", "token").unwrap_err().to_string(),
+      "Cannot autolaunch D-Bus"
+    );
+    assert!(!keyring.is_available());
+    let keyring = SystemKeyring {
+      command: vec!["appconfig-missing-secret-tool".into()],
+    };
+    assert!(!keyring.is_available());
+  }
+}
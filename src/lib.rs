@@ -1,12 +1,15 @@
 //! A simple configuration file manager for desktop applications.
 //!
-//! The configuration file is read from and written to the following locations.
+//! The configuration file is read from and written to the following locations by default.
+//! Other directories can be selected with [`Location`].
 //!
-//! |Platform | Value                                    | Example                                  |
-//! | ------- | ---------------------------------------- | ---------------------------------------- |
-//! | Linux   | `$XDG_DATA_HOME` or `$HOME`/.local/share | /home/alice/.local/share                 |
-//! | macOS   | `$HOME`/Library/Application Support      | /Users/Alice/Library/Application Support |
-//! | Windows | `{FOLDERID_LocalAppData}`                | C:\Users\Alice\AppData\Local             |
+//! |Platform | Value                                 | Example                                  |
+//! | ------- | ------------------------------------- | ---------------------------------------- |
+//! | Linux   | `$XDG_CONFIG_HOME` or `$HOME`/.config | /home/alice/.config                      |
+//! | macOS   | `$HOME`/Library/Application Support   | /Users/Alice/Library/Application Support |
+//! | Windows | `{FOLDERID_RoamingAppData}`           | C:\Users\Alice\AppData\Roaming           |
+//! | iOS     | `$HOME`/Library/Application Support   | (sandbox)/Library/Application Support    |
+//! | Android | `Context.getFilesDir()`               | /data/user/0/com.example.app/files       |
 //!
 //! # Usage
 //!
@@ -39,51 +42,410 @@
 //!     std::env!("CARGO_CRATE_NAME"), // CRATE_BIN_NAME etc..,
 //!     "sumibi-yakitori",
 //!   );
+//!   # let manager = manager.with_store(appconfig::MemoryStore::new());
 //!
 //!   manager.save().unwrap();
 //!   manager.load().unwrap();
 //!   assert_eq!(*config.borrow(), MyAppConfig::default());
 //! }
 //! ```
+//!
+//! # Features
+//!
+//! | Feature               | Description                                            |
+//! | --------------------- | ------------------------------------------------------ |
+//! | `json`                | Adds `JsonFormat` (`app_config.json`)                  |
+//! | `yaml`                | Adds `YamlFormat` (`app_config.yaml`)                  |
+//! | `ron`                 | Adds `RonFormat` (`app_config.ron`)                    |
+//! | `json5`               | Lenient JSON loading, implies `json`                   |
+//! | `field-path`          | Report the path of the field that failed to parse      |
+//! | `preserve-formatting` | Keep comments and formatting of TOML files when saving |
+//! | `watch`               | Watch the file for changes made by other programs      |
+//! | `async`               | Adds `load_async()` and `save_async()`                 |
+//! | `events`              | Emits events through `events::set_handler()`           |
+//! | `exit-flush`          | Adds `install_exit_flush()` to save on Ctrl+C          |
+//! | `encryption`          | Encrypts the file with ChaCha20-Poly1305 and scrypt    |
+//! | `secrets`             | Keeps `Secret` fields in the keychain of the OS        |
+//! | `compression`         | Compresses the file with DEFLATE                       |
+//! | `wasm`                | Adds `WebStore` for a `WebStorage` like `localStorage` |
+//! | `android`             | Finds the files directory of the app on Android        |
+//!
+//! # Minimum supported Rust version
+//!
+//! Rust 1.85, the first release with `io::ErrorKind::QuotaExceeded`, which `ConfigStore` reports.
+
+/// Emits an event through the handler installed with [`events::set_handler`] with the `events` feature.
+macro_rules! event {
+  ($level:ident, $($arg:tt)+) => {
+    #[cfg(feature = "events")]
+    crate::events::emit(crate::events::Level::$level, format_args!($($arg)+));
+    #[cfg(not(feature = "events"))]
+    let _ = format_args!($($arg)+);
+  };
+}
+
+mod autosave;
+mod backup;
+mod builder;
+mod compression;
+mod conflict;
+#[cfg(feature = "encryption")]
+mod crypto;
+#[cfg(feature = "compression")]
+mod deflate;
+#[cfg(feature = "preserve-formatting")]
+mod edit;
+mod encryption;
+mod error;
+#[cfg(feature = "events")]
+pub mod events;
+#[cfg(feature = "exit-flush")]
+mod exit_flush;
+mod format;
+mod fs;
+mod guard;
+mod integrity;
+#[cfg(feature = "secrets")]
+mod keyring;
+mod location;
+mod metadata;
+mod name;
+mod nfc;
+#[cfg(feature = "async")]
+mod nonblocking;
+mod outcome;
+mod owned;
+mod ownership;
+mod path;
+mod reset;
+mod sandbox;
+#[cfg(feature = "secrets")]
+mod secret;
+mod security;
+mod sensitive;
+mod shared;
+mod snapshot;
+mod store;
+mod testing;
+mod transaction;
+mod validation;
+mod value;
+#[cfg(feature = "watch")]
+mod watch;
+#[cfg(feature = "wasm")]
+mod web;
 
 pub use anyhow;
+pub use backup::BackupSelector;
+pub use builder::{AppConfigManagerBuilder, BuildError};
+#[cfg(feature = "compression")]
+pub use compression::{Compression, Deflate};
+pub use conflict::ConflictPolicy;
+#[cfg(feature = "encryption")]
+pub use encryption::{
+  ChaCha20Poly1305, Cipher, KeyProvider, KeyPurpose, KeyRequest, Passphrase, SecretKey,
+};
+pub use error::{AppConfigError, FieldPathError, FormatError};
+#[cfg(feature = "json")]
+pub use format::JsonFormat;
+#[cfg(feature = "ron")]
+pub use format::RonFormat;
+#[cfg(feature = "yaml")]
+pub use format::YamlFormat;
+pub use format::{ConfigFormat, SaveOptions, TomlFormat};
+pub use guard::EditGuard;
+pub use integrity::IntegrityPolicy;
+#[cfg(feature = "secrets")]
+pub use keyring::SystemKeyring;
+pub use location::{Location, MacosStyle, WindowsProfile};
+pub use metadata::ConfigFileMetadata;
+pub use name::Naming;
+pub use outcome::{LoadOutcome, MigrationOutcome};
+pub use owned::{Owned, OwnedAppConfigManager};
+pub use ownership::OwnershipGuard;
+pub use reset::PurgeReport;
+pub use sandbox::{runtime_environment, RuntimeEnvironment};
+#[cfg(feature = "secrets")]
+pub use secret::{Secret, SecretStore};
+pub use security::SecurityWarning;
+pub use sensitive::Sensitive;
 use serde::{de::DeserializeOwned, Serialize};
-use std::{cell::RefCell, ops::Deref, path::PathBuf, rc::Rc};
-pub type Result<T = ()> = anyhow::Result<T>;
+use shared::{lock, Boxed};
+pub use shared::{MultiThreaded, SharedData, SingleThreaded, ThreadingBound};
+pub use snapshot::ConfigSnapshot;
+use std::{
+  cell::RefCell,
+  ops::Deref,
+  path::{Path, PathBuf},
+  rc::Rc,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, RwLock,
+  },
+  time::{Duration, SystemTime},
+};
+pub use store::{ConfigKey, ConfigStore, MemoryStore};
+pub use testing::TestConfigDir;
+pub use transaction::TransactionError;
+pub use validation::ValidationPolicy;
+#[cfg(feature = "watch")]
+pub use watch::ConfigWatcher;
+#[cfg(feature = "wasm")]
+pub use web::{WebStorage, WebStorageError, WebStore};
+pub type Result<T = ()> = std::result::Result<T, AppConfigError>;
+
+type ErrorCallback<M> = Boxed<M, dyn FnMut(&AppConfigError)>;
+type WarningCallback<M> = Boxed<M, dyn FnMut(&SecurityWarning)>;
+type Subscriber<T, M> = Boxed<M, dyn FnMut(&T)>;
+type Migration<M> = Boxed<M, dyn Fn(&mut toml::Value)>;
+type Validator<T, M> = Boxed<M, dyn Fn(&T) -> std::result::Result<(), String>>;
+type Fixup<T, M> = Boxed<M, dyn Fn(&mut T) -> bool>;
+type TypedMigration<T, M> = Boxed<M, dyn Fn(&str, &[u8]) -> std::result::Result<T, FormatError>>;
+
+/// A manager whose data can be shared with other threads, see [`SharedData`].
+pub type AtomicAppConfigManager<T> = AppConfigManager<T, Arc<RwLock<T>>>;
 
 /// A manager that manages a single configuration file.
 ///
-/// By default, the configuration file will be saved automatically when the manager is dropped.
+/// By default, the configuration file will be saved automatically when the manager is dropped,
+/// unless the data is unchanged since the last load or save.
+/// The file is replaced atomically, so an interrupted save never leaves a truncated file behind.
 /// The name of the folder where the configuration file will be saved will be the FQDN consisting of the specified organization name and application name.
 ///
 /// e.g.
 /// `com.{organization_name}.{app_name}/app_config.toml`
-pub struct AppConfigManager<T>
+///
+/// The `com` prefix can be changed with [`with_qualifier`](Self::with_qualifier).
+/// The file name can be changed with [`with_file_name`](Self::with_file_name),
+/// and the file extension follows the selected [`ConfigFormat`].
+///
+/// The data is shared through an `Rc<RefCell<T>>`, an `Arc<RwLock<T>>` for an
+/// [`AtomicAppConfigManager`], which is `Send + Sync` and saves the data while other threads read it,
+/// or any other [`SharedData`]. An [`OwnedAppConfigManager`] keeps the data to itself.
+pub struct AppConfigManager<T, S = Rc<RefCell<T>>>
 where
   T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
 {
-  data: Rc<RefCell<T>>,
+  data: S,
   organization_name: String,
   app_name: String,
+  qualifier: String,
+  naming: Naming,
+  subdirectory: Option<PathBuf>,
   skip_parsing_error_when_loading: bool,
   auto_saving: bool,
+  save_on_panic: bool,
+  format: Boxed<S::Threading, dyn ConfigFormat<T>>,
+  format_autodetect: bool,
+  remove_other_formats: bool,
+  durable_writes: bool,
+  save_retries: fs::Retries,
+  unix_permissions: Option<u32>,
+  permission_check: bool,
+  fix_insecure_permissions: bool,
+  file_lock: bool,
+  lock_timeout: Duration,
+  max_file_size: u64,
+  delete_on_reset: bool,
+  /// Set by `delete_file()` until the next save, so that the file is not saved on drop.
+  deleted: AtomicBool,
+  sandbox_migration: bool,
+  store: Option<store::SharedStore>,
+  require_ownership: bool,
+  owned: Arc<AtomicBool>,
+  tolerate_missing_file: bool,
+  file_name: String,
+  base_dir: Option<PathBuf>,
+  location: Location,
+  windows_profile: WindowsProfile,
+  macos_style: MacosStyle,
+  system_fallback: bool,
+  system_dir: Option<PathBuf>,
+  system_layer: bool,
+  save_diff_from_system: bool,
+  env_prefix: Option<String>,
+  env_separator: String,
+  key_overrides: Vec<(String, String)>,
+  defaults: Option<fn() -> T>,
+  preserve_unknown_keys: bool,
+  save_options: SaveOptions,
+  file_header: Option<String>,
+  migrations: Vec<(u32, Migration<S::Threading>)>,
+  typed_migrations: Vec<TypedMigration<T, S::Threading>>,
+  save_migrated: bool,
+  #[cfg(feature = "preserve-formatting")]
+  preserve_formatting: bool,
+  document: Mutex<Option<value::LoadedDocument>>,
+  session_overrides: Mutex<Vec<value::SessionOverride>>,
+  fqdn_subfolder: bool,
+  lenient_names: bool,
+  portable_mode: bool,
+  portable_fallback: bool,
+  /// Whether the portable folder can be used, checked once.
+  portable_dir: path::PortableDir,
+  /// The folder of the executable, replaced in tests.
+  exe_dir: Option<PathBuf>,
+  env_override: Option<String>,
+  legacy_identities: Vec<name::LegacyIdentity>,
+  remove_legacy_file: bool,
+  legacy_file: Mutex<Option<PathBuf>>,
+  backup: bool,
+  backup_retention: usize,
+  quarantine_corrupt: bool,
+  skip_unchanged: bool,
+  /// The hash of the data as of the last load or save.
+  clean_hash: Mutex<Option<u64>>,
+  dirty: AtomicBool,
+  /// The file read by the last load or written by the last save.
+  file_stamp: Mutex<Option<(PathBuf, fs::FileStamp)>>,
+  #[cfg(feature = "exit-flush")]
+  exit_flush: Mutex<Option<exit_flush::Slot>>,
+  #[cfg(feature = "encryption")]
+  encryption: Option<encryption::Encryption<S::Threading>>,
+  #[cfg(feature = "secrets")]
+  secret_store: Option<Boxed<S::Threading, dyn SecretStore>>,
+  #[cfg(feature = "compression")]
+  compression: Option<Boxed<S::Threading, dyn Compression>>,
+  #[cfg(feature = "compression")]
+  compressed_file_suffix: String,
+  conflict_policy: ConflictPolicy,
+  integrity_policy: IntegrityPolicy,
+  autosave: Mutex<Option<autosave::AutoSaver>>,
+  checkpoint_interval: Option<Duration>,
+  /// Dropped to stop the thread of `start_checkpoints`.
+  checkpoint_stop: Mutex<Option<std::sync::mpsc::Sender<()>>>,
+  last_saved_at: Mutex<Option<SystemTime>>,
+  last_loaded_at: Mutex<Option<SystemTime>>,
+  on_background_error: Arc<Mutex<Option<autosave::BackgroundErrorCallback>>>,
+  /// Set when the backup was loaded, so that the next save does not replace it with the broken file.
+  keep_backup: AtomicBool,
+  #[cfg(feature = "json5")]
+  lenient_json: bool,
+  on_parse_error: Mutex<Option<ErrorCallback<S::Threading>>>,
+  on_save_error: Mutex<Option<ErrorCallback<S::Threading>>>,
+  on_security_warning: Mutex<Option<WarningCallback<S::Threading>>>,
+  last_save_error: Mutex<Option<AppConfigError>>,
+  on_load: Mutex<Vec<Subscriber<T, S::Threading>>>,
+  on_save: Mutex<Vec<Subscriber<T, S::Threading>>>,
+  validators: Vec<Validator<T, S::Threading>>,
+  /// Set by `ValidationPolicy::UseDefaults`.
+  invalid_defaults: Option<fn() -> T>,
+  validate_on_save: bool,
+  fixups: Vec<Fixup<T, S::Threading>>,
 }
 
-impl<T> AppConfigManager<T>
+impl<T, S> AppConfigManager<T, S>
 where
   T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
 {
-  pub fn new(
-    data: Rc<RefCell<T>>,
-    app_name: impl Into<String>,
-    organization_name: impl Into<String>,
-  ) -> Self {
+  /// Creates a manager without checking the names and options.
+  /// [`AppConfigManagerBuilder`] reports invalid ones before the first load or save.
+  pub fn new(data: S, app_name: impl Into<String>, organization_name: impl Into<String>) -> Self {
+    AppConfigManagerBuilder::new(app_name, organization_name).build_unchecked(data)
+  }
+
+  pub(crate) fn with_defaults(data: S, app_name: String, organization_name: String) -> Self {
     Self {
       data,
-      organization_name: organization_name.into(),
-      app_name: app_name.into(),
+      organization_name,
+      app_name,
+      qualifier: "com".to_owned(),
+      naming: Naming::ReverseDns,
+      subdirectory: None,
       auto_saving: true,
+      save_on_panic: false,
       skip_parsing_error_when_loading: true,
+      format: Boxed::sync(TomlFormat, |format| Box::new(format) as _),
+      format_autodetect: false,
+      remove_other_formats: false,
+      durable_writes: false,
+      save_retries: Default::default(),
+      unix_permissions: None,
+      permission_check: false,
+      fix_insecure_permissions: false,
+      file_lock: false,
+      lock_timeout: Duration::from_secs(5),
+      max_file_size: 16 << 20,
+      delete_on_reset: false,
+      deleted: AtomicBool::new(false),
+      sandbox_migration: false,
+      store: None,
+      require_ownership: false,
+      owned: Arc::new(AtomicBool::new(false)),
+      tolerate_missing_file: false,
+      file_name: "app_config".to_owned(),
+      base_dir: None,
+      location: Location::Config,
+      windows_profile: WindowsProfile::Roaming,
+      macos_style: MacosStyle::ApplicationSupport,
+      system_fallback: false,
+      system_dir: None,
+      system_layer: false,
+      save_diff_from_system: false,
+      env_prefix: None,
+      env_separator: "_".to_owned(),
+      key_overrides: Vec::new(),
+      defaults: None,
+      preserve_unknown_keys: false,
+      save_options: SaveOptions::default(),
+      file_header: None,
+      migrations: Vec::new(),
+      typed_migrations: Vec::new(),
+      save_migrated: true,
+      #[cfg(feature = "preserve-formatting")]
+      preserve_formatting: false,
+      document: Mutex::new(None),
+      session_overrides: Mutex::new(Vec::new()),
+      fqdn_subfolder: !cfg!(any(target_os = "android", target_os = "ios")),
+      lenient_names: false,
+      portable_mode: false,
+      portable_fallback: true,
+      portable_dir: Default::default(),
+      exe_dir: None,
+      env_override: None,
+      legacy_identities: Vec::new(),
+      remove_legacy_file: false,
+      legacy_file: Mutex::new(None),
+      backup: false,
+      backup_retention: 0,
+      quarantine_corrupt: false,
+      skip_unchanged: false,
+      clean_hash: Mutex::new(None),
+      dirty: AtomicBool::new(false),
+      file_stamp: Mutex::new(None),
+      #[cfg(feature = "exit-flush")]
+      exit_flush: Mutex::new(None),
+      #[cfg(feature = "encryption")]
+      encryption: None,
+      #[cfg(feature = "secrets")]
+      secret_store: None,
+      #[cfg(feature = "compression")]
+      compression: None,
+      #[cfg(feature = "compression")]
+      compressed_file_suffix: String::new(),
+      conflict_policy: ConflictPolicy::Overwrite,
+      integrity_policy: IntegrityPolicy::Disabled,
+      autosave: Mutex::new(None),
+      checkpoint_interval: None,
+      checkpoint_stop: Mutex::new(None),
+      last_saved_at: Mutex::new(None),
+      last_loaded_at: Mutex::new(None),
+      on_background_error: Arc::new(Mutex::new(None)),
+      keep_backup: AtomicBool::new(false),
+      #[cfg(feature = "json5")]
+      lenient_json: false,
+      on_parse_error: Mutex::new(None),
+      on_save_error: Mutex::new(None),
+      on_security_warning: Mutex::new(None),
+      last_save_error: Mutex::new(None),
+      on_load: Mutex::new(Vec::new()),
+      on_save: Mutex::new(Vec::new()),
+      validators: Vec::new(),
+      invalid_defaults: None,
+      validate_on_save: false,
+      fixups: Vec::new(),
     }
   }
 
@@ -107,6 +469,26 @@ where
     self
   }
 
+  /// Also saves when the manager is dropped while the thread is panicking. Disabled by default,
+  /// since the data may be half changed.
+  ///
+  /// The previous file is then copied to `app_config.toml.bak` even without
+  /// [`with_backup`](Self::with_backup), to be restored with
+  /// [`restore_from_backup`](Self::restore_from_backup).
+  ///
+  /// A manager dropped inside `catch_unwind` counts as panicking, while one that outlives it
+  /// is saved normally afterwards, whatever state the panic left the data in.
+  pub fn set_save_on_panic(&mut self, value: bool) -> &mut Self {
+    self.save_on_panic = value;
+    self
+  }
+
+  pub fn with_save_on_panic(mut self, value: bool) -> Self {
+    self.set_save_on_panic(value);
+    self
+  }
+
+  /// A blank organization name is left out of the folder name, e.g. `com.{app_name}`.
   pub fn set_organization_name(&mut self, value: impl Into<String>) -> &mut Self {
     self.organization_name = value.into();
     self
@@ -127,98 +509,3874 @@ where
     self
   }
 
-  pub fn load(&self) -> Result {
-    let path = self.get_user_config_path()?;
-    let s = std::fs::read_to_string(&path)?;
-    if self.skip_parsing_error_when_loading {
-      if let Ok(value) = toml::from_str(&s) {
-        *self.data.as_ref().borrow_mut() = value;
-      }
-    }
-    else {
-      *self.data.as_ref().borrow_mut() = toml::from_str(&s)?;
-    }
-    Ok(())
+  /// The first part of the folder name `{qualifier}.{organization_name}.{app_name}`, `com` by default,
+  /// e.g. `io.github` or `org` to match the bundle identifier on macOS. A blank qualifier is left out.
+  ///
+  /// Changing it moves the folder. [`with_legacy_qualifier`](Self::with_legacy_qualifier)
+  /// still reads the file from the previous one.
+  pub fn set_qualifier(&mut self, value: impl Into<String>) -> &mut Self {
+    self.qualifier = value.into();
+    self
   }
 
-  pub fn save(&self) -> Result {
-    let path = self.get_user_config_path()?;
-    let s = toml::to_string_pretty(&*self.data.as_ref().borrow())?;
-    std::fs::write(&path, &s.as_bytes())?;
-    Ok(())
+  pub fn with_qualifier(mut self, value: impl Into<String>) -> Self {
+    self.set_qualifier(value);
+    self
   }
 
-  pub fn data(&self) -> &RefCell<T> {
-    &self.data
+  /// Makes `load()` succeed without changing the data when the file does not exist yet.
+  /// Other errors, such as `PermissionDenied`, are still returned.
+  pub fn set_tolerate_missing_file(&mut self, value: bool) -> &mut Self {
+    self.tolerate_missing_file = value;
+    self
   }
 
-  fn get_user_config_path(&self) -> Result<PathBuf> {
-    use std::io;
-    let mut path = dirs_next::config_dir()
-      // TODO:
-      .ok_or(io::Error::new(io::ErrorKind::NotFound, "Config path"))?
-      .join(&format!("com.{}.{}", self.organization_name, self.app_name));
+  pub fn with_tolerate_missing_file(mut self, value: bool) -> Self {
+    self.set_tolerate_missing_file(value);
+    self
+  }
 
-    if !path.exists() {
-      std::fs::create_dir_all(&path)?;
-    }
-    path = path.join("app_config.toml");
-    Ok(path)
+  /// Uses `value` instead of the platform configuration directory.
+  /// A relative path is resolved against the current directory each time the file is accessed.
+  pub fn set_base_dir(&mut self, value: impl Into<PathBuf>) -> &mut Self {
+    self.base_dir = Some(value.into());
+    self
   }
-}
 
-impl<T> Deref for AppConfigManager<T>
-where
-  T: Sized + Serialize + DeserializeOwned,
-{
-  type Target = RefCell<T>;
+  pub fn with_base_dir(mut self, value: impl Into<PathBuf>) -> Self {
+    self.set_base_dir(value);
+    self
+  }
 
-  fn deref(&self) -> &Self::Target {
-    self.data()
+  /// Selects the platform directory the file is stored in, [`Location::Config`] by default.
+  /// Ignored when a base directory is set.
+  pub fn set_location(&mut self, value: Location) -> &mut Self {
+    self.location = value;
+    self
   }
-}
 
-impl<T> Drop for AppConfigManager<T>
-where
-  T: Sized + Serialize + DeserializeOwned,
-{
-  fn drop(&mut self) {
-    if self.auto_saving {
-      self.save().ok();
-    }
+  pub fn with_location(mut self, value: Location) -> Self {
+    self.set_location(value);
+    self
   }
-}
 
-#[cfg(test)]
-mod tests {
-  use std::{cell::RefCell, rc::Rc};
+  /// Selects the roaming or local profile folder on Windows, [`WindowsProfile::Roaming`] by default.
+  /// Has no effect on other platforms.
+  pub fn set_windows_profile(&mut self, value: WindowsProfile) -> &mut Self {
+    self.windows_profile = value;
+    self
+  }
 
-  use crate::AppConfigManager;
-  use serde::{Deserialize, Serialize};
+  pub fn with_windows_profile(mut self, value: WindowsProfile) -> Self {
+    self.set_windows_profile(value);
+    self
+  }
 
-  #[derive(Debug, Serialize, Deserialize, PartialEq)]
-  struct MyAppConfig {
-    window_pos: (u32, u32),
+  /// Selects where the file is stored on macOS, [`MacosStyle::ApplicationSupport`] by default.
+  /// Has no effect on other platforms, or when a base directory or another location is set.
+  ///
+  /// With [`MacosStyle::Preferences`], `load()` still reads the file in `Application Support`
+  /// as long as none exists in `Preferences`. The next `save()` writes to `Preferences`.
+  pub fn set_macos_style(&mut self, value: MacosStyle) -> &mut Self {
+    self.macos_style = value;
+    self
   }
 
-  impl Default for MyAppConfig {
-    fn default() -> Self {
-      Self {
-        window_pos: (320, 280),
-      }
+  pub fn with_macos_style(mut self, value: MacosStyle) -> Self {
+    self.set_macos_style(value);
+    self
+  }
+
+  /// Makes `load()` read the machine-wide file at [`system_config_path`](Self::system_config_path)
+  /// when the user has no file yet, so that administrators can provide defaults.
+  /// The system file is never written, `save()` always creates the user file.
+  pub fn set_system_fallback(&mut self, value: bool) -> &mut Self {
+    self.system_fallback = value;
+    self
+  }
+
+  pub fn with_system_fallback(mut self, value: bool) -> Self {
+    self.set_system_fallback(value);
+    self
+  }
+
+  /// Overrides values from the file with environment variables named `{prefix}_{KEY}`,
+  /// e.g. `MYAPP_WINDOW_POS=100,200` or `MYAPP_PROXY_PORT=8080` for `[proxy] port`.
+  ///
+  /// Names are matched against the keys of the configuration, so `WINDOW_POS` becomes
+  /// `window_pos` or `window.pos`, whichever exists. Values are parsed as TOML where possible,
+  /// otherwise they are strings. A value of the wrong type makes `load()` fail to parse the file.
+  ///
+  /// Overridden values are never saved: `save()` writes the value from the file instead,
+  /// unless the application changed it. Requires one of the built-in formats.
+  pub fn set_env_prefix(&mut self, prefix: impl Into<String>) -> &mut Self {
+    self.env_prefix = Some(prefix.into());
+    self
+  }
+
+  pub fn with_env_prefix(mut self, prefix: impl Into<String>) -> Self {
+    self.set_env_prefix(prefix);
+    self
+  }
+
+  /// Sets the separator between the prefix and nested keys in environment variable names, `_` by default.
+  pub fn set_env_separator(&mut self, separator: impl Into<String>) -> &mut Self {
+    self.env_separator = separator.into();
+    self
+  }
+
+  pub fn with_env_separator(mut self, separator: impl Into<String>) -> Self {
+    self.set_env_separator(separator);
+    self
+  }
+
+  /// Overrides values from the file for this session, e.g. from `--set window_pos.0=500 --set theme=dark`.
+  ///
+  /// Keys are dotted paths into the configuration, where numbers index into arrays.
+  /// Values are parsed like those of [`set_env_prefix`](Self::set_env_prefix) and take precedence over them.
+  /// Like those, overridden values are not saved unless the application changes them.
+  /// An invalid key makes `load()` fail with [`AppConfigError::InvalidOverride`].
+  pub fn set_overrides<K, V>(&mut self, overrides: impl IntoIterator<Item = (K, V)>) -> &mut Self
+  where
+    K: Into<String>,
+    V: Into<String>,
+  {
+    self.key_overrides = overrides
+      .into_iter()
+      .map(|(key, value)| (key.into(), value.into()))
+      .collect();
+    self
+  }
+
+  pub fn with_overrides<K, V>(mut self, overrides: impl IntoIterator<Item = (K, V)>) -> Self
+  where
+    K: Into<String>,
+    V: Into<String>,
+  {
+    self.set_overrides(overrides);
+    self
+  }
+
+  /// Fills the fields missing from the file with the values of `T::default()` instead of failing to parse it,
+  /// so that an old file keeps working after a field is added to the configuration.
+  /// Requires one of the built-in formats.
+  pub fn set_merge_defaults(&mut self, value: bool) -> &mut Self
+  where
+    T: Default,
+  {
+    self.defaults = if value { Some(T::default) } else { None };
+    self
+  }
+
+  pub fn with_merge_defaults(mut self, value: bool) -> Self
+  where
+    T: Default,
+  {
+    self.set_merge_defaults(value);
+    self
+  }
+
+  /// Keeps the keys that `T` does not know about, such as tables added by plugins, when saving a loaded file.
+  ///
+  /// Only the keys that belong to `T` are updated, or removed when `T` no longer writes them.
+  /// Requires one of the built-in formats.
+  pub fn set_preserve_unknown_keys(&mut self, value: bool) -> &mut Self {
+    self.preserve_unknown_keys = value;
+    if !value {
+      *lock(&self.document) = None;
     }
+    self
   }
 
-  #[test]
-  fn it_works() {
-    let config = Rc::from(RefCell::from(MyAppConfig::default()));
-    let manager = AppConfigManager::new(
-      config.clone(),
-      std::env!("CARGO_CRATE_NAME"), // CRATE_BIN_NAME etc..,
-      "sumibi-yakitori",
-    );
-    manager.save().unwrap();
-    manager.load().unwrap();
-    assert_eq!(*config.borrow(), MyAppConfig::default());
+  pub fn with_preserve_unknown_keys(mut self, value: bool) -> Self {
+    self.set_preserve_unknown_keys(value);
+    self
+  }
+
+  /// Updates the existing TOML file key by key when saving, instead of writing it from scratch,
+  /// so that comments, key order and whitespace added by the user are kept.
+  /// New keys are appended to their table.
+  #[cfg(feature = "preserve-formatting")]
+  pub fn set_preserve_formatting(&mut self, value: bool) -> &mut Self {
+    self.preserve_formatting = value;
+    self
+  }
+
+  #[cfg(feature = "preserve-formatting")]
+  pub fn with_preserve_formatting(mut self, value: bool) -> Self {
+    self.set_preserve_formatting(value);
+    self
+  }
+
+  /// Uses the machine-wide file as defaults that the user file overrides key by key.
+  ///
+  /// Both files are merged before the result is deserialized, so the user file only needs
+  /// to contain the keys it changes. When both have a value at the same key, the user value wins,
+  /// even if the system file has a table there. Requires one of the built-in formats.
+  pub fn set_system_layer(&mut self, value: bool) -> &mut Self {
+    self.system_layer = value;
+    self
+  }
+
+  pub fn with_system_layer(mut self, value: bool) -> Self {
+    self.set_system_layer(value);
+    self
+  }
+
+  /// With the system layer enabled, makes `save()` write only the values that differ from the system file,
+  /// so that later changes to the system defaults still reach the user.
+  pub fn set_save_diff_from_system(&mut self, value: bool) -> &mut Self {
+    self.save_diff_from_system = value;
+    self
+  }
+
+  pub fn with_save_diff_from_system(mut self, value: bool) -> Self {
+    self.set_save_diff_from_system(value);
+    self
+  }
+
+  /// Uses `value` instead of the platform directory for the system fallback, e.g. `/usr/share`.
+  pub fn set_system_dir(&mut self, value: impl Into<PathBuf>) -> &mut Self {
+    self.system_dir = Some(value.into());
+    self
+  }
+
+  pub fn with_system_dir(mut self, value: impl Into<PathBuf>) -> Self {
+    self.set_system_dir(value);
+    self
+  }
+
+  /// Whether the file is placed in a `com.{organization_name}.{app_name}` folder.
+  /// Enabled by default, except on Android and iOS where the directory already belongs to the application.
+  pub fn set_fqdn_subfolder(&mut self, value: bool) -> &mut Self {
+    self.fqdn_subfolder = value;
+    self
+  }
+
+  pub fn with_fqdn_subfolder(mut self, value: bool) -> Self {
+    self.set_fqdn_subfolder(value);
+    self
+  }
+
+  /// Stores the file in a `config` folder next to the executable, for builds distributed as a portable zip.
+  /// The folder is used as is, without the `com.{organization_name}.{app_name}` subfolder.
+  ///
+  /// When that folder cannot be written to (e.g. under `Program Files` or `/usr/bin`),
+  /// the platform configuration directory is used instead, see
+  /// [`uses_portable_dir`](Self::uses_portable_dir) and [`set_portable_fallback`](Self::set_portable_fallback).
+  /// `load_with_outcome` then returns [`LoadOutcome::PortableFallback`].
+  ///
+  /// Whether the folder can be written to is checked once, when the path is first needed.
+  /// A folder that already holds the file is used even if it is read-only.
+  pub fn set_portable_mode(&mut self, value: bool) -> &mut Self {
+    self.portable_mode = value;
+    self.portable_dir = Default::default();
+    self
+  }
+
+  pub fn with_portable_mode(mut self, value: bool) -> Self {
+    self.set_portable_mode(value);
+    self
+  }
+
+  /// Whether portable mode falls back to the platform configuration directory. Enabled by default.
+  /// When disabled, accessing the file fails with [`AppConfigError::PortableDirUnavailable`] instead.
+  pub fn set_portable_fallback(&mut self, value: bool) -> &mut Self {
+    self.portable_fallback = value;
+    self
+  }
+
+  pub fn with_portable_fallback(mut self, value: bool) -> Self {
+    self.set_portable_fallback(value);
+    self
+  }
+
+  /// Reads the directory from the environment variable `name` (e.g. `MYAPP_CONFIG_DIR`) when it is set,
+  /// so that the location can be changed without rebuilding the application.
+  ///
+  /// The directory is used verbatim, without the `com.{organization_name}.{app_name}` subfolder,
+  /// and takes precedence over every other location setting. An empty or blank value is ignored.
+  pub fn set_env_override(&mut self, name: impl Into<String>) -> &mut Self {
+    self.env_override = Some(name.into());
+    self
+  }
+
+  pub fn with_env_override(mut self, name: impl Into<String>) -> Self {
+    self.set_env_override(name);
+    self
+  }
+
+  /// Reads the file saved under a previous application or organization name,
+  /// i.e. `{qualifier}.{organization_name}.{app_name}`, while no file exists under the current one.
+  ///
+  /// Can be called several times, the identities are checked in order. The next `save()` writes
+  /// the file under the current name. Nothing is created by `load()`.
+  pub fn add_legacy_identity(
+    &mut self,
+    app_name: impl Into<String>,
+    organization_name: impl Into<String>,
+  ) -> &mut Self {
+    self.legacy_identities.push(name::LegacyIdentity {
+      naming: None,
+      qualifier: None,
+      app_name: Some(app_name.into()),
+      organization_name: Some(organization_name.into()),
+    });
+    self
+  }
+
+  pub fn with_legacy_identity(
+    mut self,
+    app_name: impl Into<String>,
+    organization_name: impl Into<String>,
+  ) -> Self {
+    self.add_legacy_identity(app_name, organization_name);
+    self
+  }
+
+  /// Like `add_legacy_identity`, for the file saved under a previous qualifier with the current names,
+  /// e.g. `com` after switching to `with_qualifier("io.github")`.
+  pub fn add_legacy_qualifier(&mut self, qualifier: impl Into<String>) -> &mut Self {
+    self.legacy_identities.push(name::LegacyIdentity {
+      naming: None,
+      qualifier: Some(qualifier.into()),
+      app_name: None,
+      organization_name: None,
+    });
+    self
+  }
+
+  pub fn with_legacy_qualifier(mut self, qualifier: impl Into<String>) -> Self {
+    self.add_legacy_qualifier(qualifier);
+    self
+  }
+
+  /// Removes the file loaded from a legacy identity once it was saved under the current name.
+  pub fn set_remove_legacy_file(&mut self, value: bool) -> &mut Self {
+    self.remove_legacy_file = value;
+    self
+  }
+
+  pub fn with_remove_legacy_file(mut self, value: bool) -> Self {
+    self.set_remove_legacy_file(value);
+    self
+  }
+
+  /// Sets the name of the configuration file, `app_config` by default.
+  ///
+  /// The extension of the configured format is appended if missing, so that several managers
+  /// can keep separate files such as `ui.toml` and `keybindings.toml` in the same folder.
+  /// Names containing path separators or `..` are rejected when the file is accessed.
+  pub fn set_file_name(&mut self, value: impl Into<String>) -> &mut Self {
+    self.file_name = value.into();
+    self
+  }
+
+  pub fn with_file_name(mut self, value: impl Into<String>) -> Self {
+    self.set_file_name(value);
+    self
+  }
+
+  /// Places the file in a folder inside the application folder,
+  /// e.g. `com.org.app/profiles/default/settings.toml` for `profiles/default`.
+  /// Backups and quarantined files are kept next to it, and the folders are created on save.
+  ///
+  /// Absolute paths and `..` are rejected with [`AppConfigError::InvalidSubdirectory`]
+  /// when the file is accessed.
+  pub fn set_subdirectory(&mut self, value: impl AsRef<Path>) -> &mut Self {
+    let value: PathBuf = value
+      .as_ref()
+      .components()
+      .filter(|x| *x != std::path::Component::CurDir)
+      .collect();
+    self.subdirectory = Some(value).filter(|x| !x.as_os_str().is_empty());
+    self
+  }
+
+  pub fn with_subdirectory(mut self, value: impl AsRef<Path>) -> Self {
+    self.set_subdirectory(value);
+    self
+  }
+
+  /// Names the file after the configuration type, e.g. `MyAppConfig` is saved as `my_app_config.toml`.
+  ///
+  /// Module paths and generic parameters are not part of the name.
+  pub fn set_file_name_from_type(&mut self) -> &mut Self {
+    self.set_file_name(path::type_file_name::<T>())
+  }
+
+  pub fn with_file_name_from_type(mut self) -> Self {
+    self.set_file_name_from_type();
+    self
+  }
+
+  /// Sets how the built-in formats write the file.
+  pub fn set_save_options(&mut self, value: SaveOptions) -> &mut Self {
+    self.save_options = value;
+    self
+  }
+
+  pub fn with_save_options(mut self, value: SaveOptions) -> Self {
+    self.set_save_options(value);
+    self
+  }
+
+  /// Writes arrays one element per line so that the file is easy to edit by hand. Enabled by default.
+  pub fn set_pretty_output(&mut self, value: bool) -> &mut Self {
+    self.save_options.pretty = value;
+    self
+  }
+
+  pub fn with_pretty_output(mut self, value: bool) -> Self {
+    self.set_pretty_output(value);
+    self
+  }
+
+  /// Writes keys in alphabetical order so that the file does not change between saves
+  /// when the configuration contains a `HashMap`. Supported by TOML and JSON.
+  pub fn set_sorted_keys(&mut self, value: bool) -> &mut Self {
+    self.save_options.sort_keys = value;
+    self
+  }
+
+  pub fn with_sorted_keys(mut self, value: bool) -> Self {
+    self.set_sorted_keys(value);
+    self
+  }
+
+  /// Writes `value` as comment lines at the top of the file on every save,
+  /// e.g. `Generated by MyApp 2.3.1 - do not edit while the app is running`.
+  ///
+  /// Formats without comments, such as JSON, are written without the header.
+  pub fn set_file_header(&mut self, value: impl Into<String>) -> &mut Self {
+    self.file_header = Some(value.into());
+    self
+  }
+
+  pub fn with_file_header(mut self, value: impl Into<String>) -> Self {
+    self.set_file_header(value);
+    self
+  }
+
+  /// Registers a migration that upgrades a file from version `version - 1` to `version`.
+  ///
+  /// The file stores its version in the `__appconfig_version` key, written on every save with
+  /// the highest registered version. Files without the key are version 1.
+  /// When loading, the migrations newer than the file run in order on the parsed document
+  /// before it is deserialized into `T`. A file newer than every migration fails to load with
+  /// [`AppConfigError::NewerVersion`]. See [`with_save_migrated`](Self::with_save_migrated).
+  pub fn add_migration(
+    &mut self,
+    version: u32,
+    migration: impl Fn(&mut toml::Value) + ThreadingBound<S::Threading> + 'static,
+  ) -> &mut Self {
+    let migration = Boxed::new(migration, |migration| Box::new(migration) as _);
+    self.migrations.push((version, migration));
+    self.migrations.sort_by_key(|(version, _)| *version);
+    self
+  }
+
+  pub fn with_migration(
+    mut self,
+    version: u32,
+    migration: impl Fn(&mut toml::Value) + ThreadingBound<S::Threading> + 'static,
+  ) -> Self {
+    self.add_migration(version, migration);
+    self
+  }
+
+  /// Registers an older version of the configuration type with a conversion to `T`.
+  ///
+  /// When the file does not parse as `T`, the older types are tried in the order they were
+  /// registered and the first one that parses is converted with `migration`.
+  /// Register the newest version first, and chain the conversions for older ones,
+  /// e.g. `|v1: ConfigV1| ConfigV3::from(ConfigV2::from(v1))`.
+  /// Layers and overrides are not applied to a file in an older version.
+  /// Only the built-in formats are supported.
+  pub fn add_typed_migration<Old>(
+    &mut self,
+    migration: impl Fn(Old) -> T + ThreadingBound<S::Threading> + 'static,
+  ) -> &mut Self
+  where
+    Old: Serialize + DeserializeOwned + 'static,
+  {
+    let migration = Boxed::new(migration, |migration| {
+      Box::new(move |extension: &str, s: &[u8]| parse_older(&migration, extension, s)) as _
+    });
+    self.typed_migrations.push(migration);
+    self
+  }
+
+  pub fn with_typed_migration<Old>(
+    mut self,
+    migration: impl Fn(Old) -> T + ThreadingBound<S::Threading> + 'static,
+  ) -> Self
+  where
+    Old: Serialize + DeserializeOwned + 'static,
+  {
+    self.add_typed_migration(migration);
+    self
+  }
+
+  /// Registers `Old` as an older version of the configuration type that is converted with `From`.
+  /// See [`with_typed_migration`](Self::with_typed_migration).
+  pub fn add_previous_version<Old>(&mut self) -> &mut Self
+  where
+    Old: Serialize + DeserializeOwned + 'static,
+    T: From<Old> + 'static,
+  {
+    let migration = Boxed::sync(T::from, |migration| {
+      Box::new(move |extension: &str, s: &[u8]| parse_older(migration, extension, s)) as _
+    });
+    self.typed_migrations.push(migration);
+    self
+  }
+
+  pub fn with_previous_version<Old>(mut self) -> Self
+  where
+    Old: Serialize + DeserializeOwned + 'static,
+    T: From<Old> + 'static,
+  {
+    self.add_previous_version::<Old>();
+    self
+  }
+
+  /// Saves the file right after `load()` upgraded it with a migration, so that the upgrade runs once.
+  /// When disabled, the upgraded file is written by the next `save()`. Enabled by default.
+  pub fn set_save_migrated(&mut self, value: bool) -> &mut Self {
+    self.save_migrated = value;
+    self
+  }
+
+  pub fn with_save_migrated(mut self, value: bool) -> Self {
+    self.set_save_migrated(value);
+    self
+  }
+
+  /// Copies the previous file to `app_config.toml.bak` before each save.
+  ///
+  /// When the file fails to parse, `load()` reads the backup instead and reports
+  /// [`LoadOutcome::BackupLoaded`].
+  pub fn set_backup(&mut self, value: bool) -> &mut Self {
+    self.backup = value;
+    self
+  }
+
+  pub fn with_backup(mut self, value: bool) -> Self {
+    self.set_backup(value);
+    self
+  }
+
+  /// Keeps the last `count` versions of the file as `app_config.toml.1` (the newest)
+  /// to `app_config.toml.{count}`, rotated when a save changes the contents. `0` disables it.
+  pub fn set_backup_retention(&mut self, count: usize) -> &mut Self {
+    self.backup_retention = count;
+    self
+  }
+
+  pub fn with_backup_retention(mut self, count: usize) -> Self {
+    self.set_backup_retention(count);
+    self
+  }
+
+  /// Moves a file that fails to parse to `app_config.toml.corrupt-{unix time}` when the error is skipped,
+  /// so that the next save does not overwrite it. Only the latest quarantined file is kept.
+  ///
+  /// Reported as [`LoadOutcome::Quarantined`].
+  pub fn set_quarantine_corrupt(&mut self, value: bool) -> &mut Self {
+    self.quarantine_corrupt = value;
+    self
+  }
+
+  pub fn with_quarantine_corrupt(mut self, value: bool) -> Self {
+    self.set_quarantine_corrupt(value);
+    self
+  }
+
+  pub fn set_format(
+    &mut self,
+    value: impl ConfigFormat<T> + ThreadingBound<S::Threading> + 'static,
+  ) -> &mut Self {
+    self.format = Boxed::new(value, |value| Box::new(value) as _);
+    self
+  }
+
+  pub fn with_format(
+    mut self,
+    value: impl ConfigFormat<T> + ThreadingBound<S::Threading> + 'static,
+  ) -> Self {
+    self.set_format(value);
+    self
+  }
+
+  /// When no file exists in the configured format, `load()` reads a file left behind in another
+  /// built-in format instead of failing. `save()` always writes the configured format.
+  ///
+  /// The format is detected by the file extension, falling back to trying every built-in format
+  /// when the content does not match the extension.
+  pub fn set_format_autodetect(&mut self, value: bool) -> &mut Self {
+    self.format_autodetect = value;
+    self
+  }
+
+  pub fn with_format_autodetect(mut self, value: bool) -> Self {
+    self.set_format_autodetect(value);
+    self
+  }
+
+  /// Removes files in other built-in formats after a successful `save()`.
+  pub fn set_remove_other_formats(&mut self, value: bool) -> &mut Self {
+    self.remove_other_formats = value;
+    self
+  }
+
+  pub fn with_remove_other_formats(mut self, value: bool) -> Self {
+    self.set_remove_other_formats(value);
+    self
+  }
+
+  /// Syncs the file and its directory to disk after saving, so that the configuration
+  /// survives a power loss right after `save()` returns.
+  ///
+  /// This is off by default because it makes every save, including the one on drop, noticeably slower.
+  pub fn set_durable_writes(&mut self, value: bool) -> &mut Self {
+    self.durable_writes = value;
+    self
+  }
+
+  pub fn with_durable_writes(mut self, value: bool) -> Self {
+    self.set_durable_writes(value);
+    self
+  }
+
+  /// How often writing the file is attempted again, `delay` apart, when it fails because another
+  /// process such as an antivirus scanner briefly holds the file open. Other errors are returned
+  /// right away. Only happens on Windows, 5 times 20 milliseconds by default.
+  pub fn set_save_retries(&mut self, count: u32, delay: Duration) -> &mut Self {
+    self.save_retries = fs::Retries { count, delay };
+    self
+  }
+
+  pub fn with_save_retries(mut self, count: u32, delay: Duration) -> Self {
+    self.set_save_retries(count, delay);
+    self
+  }
+
+  /// Sets the mode of the file on every save, e.g. `0o600` for a file storing a token,
+  /// instead of the default of the process. The folders created for it get the matching
+  /// `0o700`. Does nothing on Windows.
+  ///
+  /// The file never has other permissions, even for a moment, since it is written
+  /// to a new file that replaces it.
+  pub fn set_unix_permissions(&mut self, mode: u32) -> &mut Self {
+    self.unix_permissions = Some(mode);
+    self
+  }
+
+  pub fn with_unix_permissions(mut self, mode: u32) -> Self {
+    self.set_unix_permissions(mode);
+    self
+  }
+
+  /// Checks on Unix that other users cannot access the loaded file, like ssh does for keys.
+  /// A [`SecurityWarning`] is passed to the callback set with
+  /// [`with_on_security_warning`](Self::with_on_security_warning), or written to stderr.
+  /// The machine-wide file is not checked.
+  pub fn set_check_permissions(&mut self, value: bool) -> &mut Self {
+    self.permission_check = value;
+    self
+  }
+
+  pub fn with_check_permissions(mut self, value: bool) -> Self {
+    self.set_check_permissions(value);
+    self
+  }
+
+  /// Also removes the group and other permissions of a loaded file
+  /// that fails the check of [`with_check_permissions`](Self::with_check_permissions).
+  /// Enables the check.
+  pub fn set_fix_insecure_permissions(&mut self, value: bool) -> &mut Self {
+    self.fix_insecure_permissions = value;
+    self
+  }
+
+  pub fn with_fix_insecure_permissions(mut self, value: bool) -> Self {
+    self.set_fix_insecure_permissions(value);
+    self
+  }
+
+  /// Fails to load a file larger than `bytes` with [`AppConfigError::FileTooLarge`] instead of
+  /// reading it, e.g. a corrupted file or a symlink to something huge. The size of the target of
+  /// a symlink is checked. `0` means no limit. 16 MiB by default.
+  pub fn set_max_file_size(&mut self, bytes: u64) -> &mut Self {
+    self.max_file_size = bytes;
+    self
+  }
+
+  pub fn with_max_file_size(mut self, bytes: u64) -> Self {
+    self.set_max_file_size(bytes);
+    self
+  }
+
+  /// Locks `app_config.toml.lock` next to the file while saving, and while reading it when loading,
+  /// so that several instances of the application do not write at the same time.
+  ///
+  /// The lock is an advisory lock of the operating system, so it is released when a process
+  /// crashes, and it does not keep other programs from writing the file.
+  pub fn set_file_lock(&mut self, value: bool) -> &mut Self {
+    self.file_lock = value;
+    self
+  }
+
+  pub fn with_file_lock(mut self, value: bool) -> Self {
+    self.set_file_lock(value);
+    self
+  }
+
+  /// How long to wait for the lock of another process before failing with
+  /// [`AppConfigError::Locked`]. 5 seconds by default.
+  pub fn set_lock_timeout(&mut self, value: Duration) -> &mut Self {
+    self.lock_timeout = value;
+    self
+  }
+
+  pub fn with_lock_timeout(mut self, value: Duration) -> Self {
+    self.set_lock_timeout(value);
+    self
+  }
+
+  /// Makes `save()`, including the one on drop, fail with [`AppConfigError::NotOwner`] unless
+  /// the configuration is owned through [`try_acquire_ownership`](Self::try_acquire_ownership).
+  pub fn set_require_ownership(&mut self, value: bool) -> &mut Self {
+    self.require_ownership = value;
+    self
+  }
+
+  pub fn with_require_ownership(mut self, value: bool) -> Self {
+    self.set_require_ownership(value);
+    self
+  }
+
+  /// Sets what `save()`, including the one on drop, does when the file was changed by another program
+  /// since the last load or save. [`ConflictPolicy::Overwrite`] by default.
+  pub fn set_conflict_policy(&mut self, value: ConflictPolicy) -> &mut Self {
+    self.conflict_policy = value;
+    self
+  }
+
+  pub fn with_conflict_policy(mut self, value: ConflictPolicy) -> Self {
+    self.set_conflict_policy(value);
+    self
+  }
+
+  /// Registers a check that runs on the data parsed by `load()`, returning the reason it is invalid.
+  /// What happens then is selected with [`with_validation_policy`](Self::with_validation_policy).
+  pub fn add_validator(
+    &mut self,
+    f: impl Fn(&T) -> std::result::Result<(), String> + ThreadingBound<S::Threading> + 'static,
+  ) -> &mut Self {
+    self.validators.push(Boxed::new(f, |f| Box::new(f) as _));
+    self
+  }
+
+  pub fn with_validator(
+    mut self,
+    f: impl Fn(&T) -> std::result::Result<(), String> + ThreadingBound<S::Threading> + 'static,
+  ) -> Self {
+    self.add_validator(f);
+    self
+  }
+
+  pub fn set_validation_policy(&mut self, value: ValidationPolicy) -> &mut Self
+  where
+    T: Default,
+  {
+    self.invalid_defaults = match value {
+      ValidationPolicy::Reject => None,
+      ValidationPolicy::UseDefaults => Some(T::default),
+    };
+    self
+  }
+
+  pub fn with_validation_policy(mut self, value: ValidationPolicy) -> Self
+  where
+    T: Default,
+  {
+    self.set_validation_policy(value);
+    self
+  }
+
+  /// Runs the validators before `save()` too, which fails with [`AppConfigError::Invalid`]
+  /// instead of writing invalid data.
+  pub fn set_validate_on_save(&mut self, value: bool) -> &mut Self {
+    self.validate_on_save = value;
+    self
+  }
+
+  pub fn with_validate_on_save(mut self, value: bool) -> Self {
+    self.set_validate_on_save(value);
+    self
+  }
+
+  /// Registers a repair that runs on every loaded value before it is stored into the data
+  /// and before the validators, e.g. to clamp a window position onto the screen.
+  /// `f` returns whether it changed anything.
+  ///
+  /// A repaired file is reported as [`LoadOutcome::FixedUp`] and the data stays dirty,
+  /// so that `save_if_dirty()` writes the corrected file.
+  pub fn add_fixup(
+    &mut self,
+    f: impl Fn(&mut T) -> bool + ThreadingBound<S::Threading> + 'static,
+  ) -> &mut Self {
+    self.fixups.push(Boxed::new(f, |f| Box::new(f) as _));
+    self
+  }
+
+  pub fn with_fixup(
+    mut self,
+    f: impl Fn(&mut T) -> bool + ThreadingBound<S::Threading> + 'static,
+  ) -> Self {
+    self.add_fixup(f);
+    self
+  }
+
+  /// Makes [`checkpoint`](Self::checkpoint) save the changes at most every `interval`,
+  /// so that a crash loses at most that much.
+  ///
+  /// The data of an `Rc` manager can only be read on its own thread, so the application calls
+  /// `checkpoint()` from its event loop. A manager that is `Send + Sync`, e.g. an
+  /// [`AtomicAppConfigManager`] in an `Arc`, checks on a background thread instead after
+  /// [`start_checkpoints`](Self::start_checkpoints).
+  pub fn set_checkpoint_interval(&mut self, interval: Duration) -> &mut Self {
+    self.checkpoint_interval = Some(interval);
+    self
+  }
+
+  pub fn with_checkpoint_interval(mut self, interval: Duration) -> Self {
+    self.set_checkpoint_interval(interval);
+    self
+  }
+
+  /// Leaves the file untouched when it already has the contents `save()` would write,
+  /// so that saving without changes does not update its modification time.
+  pub fn set_skip_unchanged(&mut self, value: bool) -> &mut Self {
+    self.skip_unchanged = value;
+    self
+  }
+
+  pub fn with_skip_unchanged(mut self, value: bool) -> Self {
+    self.set_skip_unchanged(value);
+    self
+  }
+
+  /// Accepts JSON5 syntax (comments, trailing commas, unquoted keys) when loading a JSON file.
+  /// The file is still saved as strict JSON.
+  #[cfg(feature = "json5")]
+  pub fn set_lenient_json(&mut self, value: bool) -> &mut Self {
+    self.lenient_json = value;
+    self
+  }
+
+  #[cfg(feature = "json5")]
+  pub fn with_lenient_json(mut self, value: bool) -> Self {
+    self.set_lenient_json(value);
+    self
+  }
+
+  /// Registers a callback invoked once whenever `load()` fails to parse the file,
+  /// including when the error is skipped by `skip_parsing_error_when_loading`.
+  /// The error is [`AppConfigError::Deserialize`], or [`AppConfigError::IntegrityMismatch`]
+  /// with [`with_integrity_policy`](Self::with_integrity_policy).
+  pub fn set_on_parse_error(
+    &mut self,
+    f: impl FnMut(&AppConfigError) + ThreadingBound<S::Threading> + 'static,
+  ) -> &mut Self {
+    *lock(&self.on_parse_error) = Some(Boxed::new(f, |f| Box::new(f) as _));
+    self
+  }
+
+  pub fn with_on_parse_error(
+    mut self,
+    f: impl FnMut(&AppConfigError) + ThreadingBound<S::Threading> + 'static,
+  ) -> Self {
+    self.set_on_parse_error(f);
+    self
+  }
+
+  /// Registers a callback invoked when a save that cannot return its error fails,
+  /// e.g. the auto-save when the manager or an [`EditGuard`] is dropped.
+  ///
+  /// Without a callback, the error is written to stderr.
+  /// Either way it is kept for [`take_last_save_error`](Self::take_last_save_error).
+  pub fn set_on_save_error(
+    &mut self,
+    f: impl FnMut(&AppConfigError) + ThreadingBound<S::Threading> + 'static,
+  ) -> &mut Self {
+    *lock(&self.on_save_error) = Some(Boxed::new(f, |f| Box::new(f) as _));
+    self
+  }
+
+  pub fn with_on_save_error(
+    mut self,
+    f: impl FnMut(&AppConfigError) + ThreadingBound<S::Threading> + 'static,
+  ) -> Self {
+    self.set_on_save_error(f);
+    self
+  }
+
+  /// Registers a callback invoked when a loaded file can be accessed by other users,
+  /// see [`with_check_permissions`](Self::with_check_permissions).
+  pub fn set_on_security_warning(
+    &mut self,
+    f: impl FnMut(&SecurityWarning) + ThreadingBound<S::Threading> + 'static,
+  ) -> &mut Self {
+    *lock(&self.on_security_warning) = Some(Boxed::new(f, |f| Box::new(f) as _));
+    self
+  }
+
+  pub fn with_on_security_warning(
+    mut self,
+    f: impl FnMut(&SecurityWarning) + ThreadingBound<S::Threading> + 'static,
+  ) -> Self {
+    self.set_on_security_warning(f);
+    self
+  }
+
+  /// Registers a callback invoked on the background thread when it fails to write the file,
+  /// see [`save_in_background`](Self::save_in_background).
+  pub fn set_on_background_error(
+    &mut self,
+    f: impl FnMut(&AppConfigError) + Send + 'static,
+  ) -> &mut Self {
+    *lock(&self.on_background_error) = Some(Box::new(f));
+    self
+  }
+
+  pub fn with_on_background_error(
+    mut self,
+    f: impl FnMut(&AppConfigError) + Send + 'static,
+  ) -> Self {
+    self.set_on_background_error(f);
+    self
+  }
+
+  /// Registers a callback invoked with the data after it was loaded from a file or a string.
+  /// Callbacks are called in the order they were added.
+  ///
+  /// The data stays borrowed while the callbacks run: they can read it through the `RefCell`,
+  /// but must not modify it, so `load()` cannot be called from a callback.
+  pub fn add_on_load(
+    &mut self,
+    f: impl FnMut(&T) + ThreadingBound<S::Threading> + 'static,
+  ) -> &mut Self {
+    lock(&self.on_load).push(Boxed::new(f, |f| Box::new(f) as _));
+    self
+  }
+
+  pub fn with_on_load(
+    mut self,
+    f: impl FnMut(&T) + ThreadingBound<S::Threading> + 'static,
+  ) -> Self {
+    self.add_on_load(f);
+    self
+  }
+
+  /// Registers a callback invoked with the data after each successful `save()`,
+  /// including the one on drop. The same rules as for [`add_on_load`](Self::add_on_load) apply.
+  pub fn add_on_save(
+    &mut self,
+    f: impl FnMut(&T) + ThreadingBound<S::Threading> + 'static,
+  ) -> &mut Self {
+    lock(&self.on_save).push(Boxed::new(f, |f| Box::new(f) as _));
+    self
+  }
+
+  pub fn with_on_save(
+    mut self,
+    f: impl FnMut(&T) + ThreadingBound<S::Threading> + 'static,
+  ) -> Self {
+    self.add_on_save(f);
+    self
+  }
+
+  fn notify(&self, subscribers: &Mutex<Vec<Subscriber<T, S::Threading>>>) {
+    // Skipped when a callback itself loads or saves, or another thread notifies
+    if let Ok(mut subscribers) = subscribers.try_lock() {
+      self.data.read(|data| {
+        for f in subscribers.iter_mut() {
+          f(data);
+        }
+      });
+    }
+  }
+
+  pub fn load(&self) -> Result {
+    self.load_returning_path().map(|_| ())
+  }
+
+  /// Like `load()`, but returns the file that was read, e.g. for logging or a "Reveal in Finder" button.
+  /// It differs from [`config_path`](Self::config_path) when a file of a legacy identity, in another
+  /// format or the machine-wide file was read. With `tolerate_missing_file`, a missing file returns
+  /// `config_path()`.
+  pub fn load_returning_path(&self) -> Result<PathBuf> {
+    match self.load_file() {
+      Err(err) if self.tolerate_missing_file && err.is_not_found() => self.user_config_path(),
+      result => result.map(|(_, path)| path),
+    }
+  }
+
+  /// Like `load()`, but reports whether the file was loaded, missing, or skipped because of a parse error.
+  /// A missing file is not an error here.
+  pub fn load_with_outcome(&self) -> Result<LoadOutcome> {
+    let outcome = match self.load_file() {
+      Err(err) if err.is_not_found() && self.is_first_run()? => LoadOutcome::FirstRun,
+      Err(err) if err.is_not_found() => LoadOutcome::FileMissing,
+      result => result?.0,
+    };
+    Ok(match self.portable_fallback_dir() {
+      Some(dir) => LoadOutcome::PortableFallback {
+        dir,
+        outcome: Box::new(outcome),
+      },
+      None => outcome,
+    })
+  }
+
+  /// Loads the file if it exists, otherwise writes the current in-memory data to create it.
+  ///
+  /// Returns `true` if the file was created. An existing file is never overwritten here,
+  /// even if it fails to parse.
+  pub fn load_or_init(&self) -> Result<bool> {
+    let outcome = match self.load_with_outcome()? {
+      LoadOutcome::PortableFallback { outcome, .. } => *outcome,
+      outcome => outcome,
+    };
+    match outcome {
+      LoadOutcome::FileMissing | LoadOutcome::FirstRun => {
+        self.save()?;
+        Ok(true)
+      }
+      _ => Ok(false),
+    }
+  }
+
+  /// Loads the file found by `locate_file` and returns its path.
+  fn load_file(&self) -> Result<(LoadOutcome, PathBuf)> {
+    let migrated_from = self.migrate_from_host()?;
+    let (path, detected, legacy) = self.locate_file()?;
+    event!(Debug, "Loading {}", path.display());
+    let s = {
+      let _lock = self.lock_file(&path, false)?;
+      self.read_stored(&path)?
+    };
+    self.check_permissions(&path)?;
+    let outcome = self.load_located(&path, &s, detected, legacy)?;
+    Ok((sandbox_outcome(outcome, migrated_from), path))
+  }
+
+  /// The file to load, whether it was found by format autodetection,
+  /// and the same path again if it belongs to a legacy identity.
+  fn locate_file(&self) -> Result<(PathBuf, bool, Option<PathBuf>)> {
+    let (key, detected, legacy) = self.locate_job()?.run()?;
+    Ok((key.path().to_owned(), detected, legacy))
+  }
+
+  /// The files `locate_file` looks for.
+  fn locate_job(&self) -> Result<LocateJob> {
+    let keys = |paths: Vec<PathBuf>| -> Vec<ConfigKey> {
+      paths.iter().map(|path| self.config_key(path)).collect()
+    };
+    let mut current = self.config_files(&self.extensions())?;
+    current.extend(self.legacy_macos_files()?);
+    let mut legacy: Vec<PathBuf> = self.uncompressed_file()?.into_iter().collect();
+    legacy.extend(self.legacy_identity_files()?);
+    Ok(LocateJob {
+      store: self.shared_store(),
+      current: keys(current),
+      legacy: keys(legacy),
+      other_formats: keys(self.other_format_files()?),
+      format_autodetect: self.format_autodetect,
+      extension: self.format.extension().to_owned(),
+      // Only reported when no other file exists
+      system: self
+        .system_file()
+        .map(|path| path.map(|path| self.config_key(&path))),
+      user: self.config_key(&self.user_config_path()?),
+    })
+  }
+
+  /// Loads `s` read from the file found by `locate_file`.
+  fn load_located(
+    &self,
+    path: &Path,
+    s: &[u8],
+    detected: bool,
+    legacy: Option<PathBuf>,
+  ) -> Result<LoadOutcome> {
+    self.record_stamp(path, s);
+    let (outcome, migrated) = match self.load_bytes(path, s, detected) {
+      Ok((LoadOutcome::ParseErrorSkipped(err), _))
+      | Err(err @ AppConfigError::Deserialize { .. })
+      | Err(err @ AppConfigError::IntegrityMismatch { .. })
+      | Err(err @ AppConfigError::Invalid { .. })
+        if self.backup =>
+      {
+        match self.load_backup(path, detected) {
+          Some(migrated) => {
+            self.keep_backup.store(true, Ordering::Relaxed);
+            (LoadOutcome::BackupLoaded(err), migrated)
+          }
+          None if self.skip_parsing_error_when_loading => {
+            (LoadOutcome::ParseErrorSkipped(err), false)
+          }
+          None => return Err(err),
+        }
+      }
+      result => result?,
+    };
+    let outcome = match outcome {
+      // The machine-wide file is not ours to move
+      LoadOutcome::ParseErrorSkipped(error)
+        if self.quarantine_corrupt && self.system_config_path().ok().as_deref() != Some(path) =>
+      {
+        LoadOutcome::Quarantined {
+          error,
+          path: self.quarantine(path)?,
+        }
+      }
+      outcome => outcome,
+    };
+    if let LoadOutcome::Loaded = outcome {
+      self.mark_clean();
+    }
+    if let LoadOutcome::Loaded | LoadOutcome::FixedUp | LoadOutcome::BackupLoaded(_) = outcome {
+      event!(Info, "Loaded {} bytes from {}", s.len(), path.display());
+      *lock(&self.last_loaded_at) = Some(SystemTime::now());
+      // A file that failed to parse is never removed
+      *lock(&self.legacy_file) = legacy;
+      if migrated && self.save_migrated {
+        self.save()?;
+      }
+    }
+    Ok(outcome)
+  }
+
+  /// Parses `s` read from `path` into the data, reporting and skipping parse errors as configured.
+  /// Also returns whether the contents were upgraded by a migration.
+  fn load_bytes(&self, path: &Path, s: &[u8], detected: bool) -> Result<(LoadOutcome, bool)> {
+    let parsed = match self.parse_file(path, s, detected) {
+      Err(err @ AppConfigError::Deserialize { .. })
+      | Err(err @ AppConfigError::IntegrityMismatch { .. }) => Err(err),
+      // Only parse errors are reported and skipped
+      Err(err) => return Err(err),
+      Ok(value) => Ok(value),
+    };
+    if let Err(err) = &parsed {
+      // Ignored when the callback itself calls `load()`
+      let skipped = if self.skip_parsing_error_when_loading {
+        ", skipped"
+      }
+      else {
+        ""
+      };
+      event!(Warn, "{}{}", err, skipped);
+      if let Ok(mut on_parse_error) = self.on_parse_error.try_lock() {
+        if let Some(f) = on_parse_error.as_mut() {
+          f(err);
+        }
+      }
+    }
+    let parsed = match parsed {
+      Ok((mut value, migrated)) => {
+        let fixed = self.fix_up(&mut value);
+        match self.validate(path, &value) {
+          Ok(()) => Ok((value, migrated, fixed)),
+          Err(err) => match self.invalid_defaults {
+            Some(defaults) => {
+              self.data.write(|data| *data = defaults());
+              self.notify(&self.on_load);
+              return Ok((LoadOutcome::DefaultsUsed(err), false));
+            }
+            None => Err(err),
+          },
+        }
+      }
+      Err(err) => Err(err),
+    };
+    match parsed {
+      Ok((value, migrated, fixed)) => {
+        self.data.write(|data| *data = value);
+        self.notify(&self.on_load);
+        let outcome = if fixed {
+          LoadOutcome::FixedUp
+        }
+        else {
+          LoadOutcome::Loaded
+        };
+        Ok((outcome, migrated))
+      }
+      Err(err) if self.skip_parsing_error_when_loading => {
+        Ok((LoadOutcome::ParseErrorSkipped(err), false))
+      }
+      Err(err) => Err(err),
+    }
+  }
+
+  pub fn save(&self) -> Result {
+    self.save_returning_path().map(|_| ())
+  }
+
+  /// Like `save()`, but returns the file that was written, which is always
+  /// [`config_path`](Self::config_path) even if the data was loaded from elsewhere.
+  pub fn save_returning_path(&self) -> Result<PathBuf> {
+    let pending = self.prepare_save()?;
+    let path = pending.path.clone();
+    if let Some(written) = pending.job.run(&pending.contents)? {
+      self.finish_save(pending, written)?;
+    }
+    Ok(path)
+  }
+
+  /// How `save()` writes the file.
+  pub(crate) fn write_options(&self) -> fs::WriteOptions {
+    fs::WriteOptions {
+      durable: self.durable_writes,
+      retries: self.save_retries,
+      mode: self.unix_permissions,
+    }
+  }
+
+  /// Locks the lock file of `path` if `with_file_lock` is set, exclusively for writing.
+  /// The lock is released when the returned file is dropped.
+  fn lock_file(&self, path: &Path, exclusive: bool) -> Result<Option<std::fs::File>> {
+    lock_file(path, exclusive, self.file_lock_timeout())
+  }
+
+  /// The timeout of `with_file_lock`, `None` if the file is not locked.
+  fn file_lock_timeout(&self) -> Option<Duration> {
+    Some(self.lock_timeout).filter(|_| self.file_lock && !self.uses_store())
+  }
+
+  /// Everything `save()` does before touching the file: the checks and the serialization.
+  fn prepare_save(&self) -> Result<PendingSave> {
+    let path = self.user_config_path()?;
+    if self.require_ownership && !self.is_owner() {
+      return Err(AppConfigError::NotOwner { path });
+    }
+    // Nothing else borrows the data until it is serialized
+    if self.data.try_read(|_| ()).is_none() {
+      return Err(AppConfigError::BorrowedDuringSave { path });
+    }
+    self.flush()?;
+    if self.validate_on_save {
+      self.data.read(|data| self.validate(&path, data))?;
+    }
+    let contents = self.serialize_data(&path)?;
+    let hash = self.data_hash();
+    let backup = self.backup && !self.keep_backup.load(Ordering::Relaxed);
+    let job = SaveJob {
+      store: self.shared_store(),
+      key: self.config_key(&path),
+      lock_timeout: self.file_lock_timeout(),
+      conflict_policy: self.conflict_policy,
+      conflict_stamp: self.conflict_stamp(&path),
+      skip_unchanged: self.skip_unchanged,
+      backup: Some(self.config_key(&fs::with_suffix(&path, ".bak"))).filter(|_| backup),
+      numbered_backups: self.numbered_backup_keys(&path),
+      max_file_size: self.max_file_size,
+      options: self.write_options(),
+    };
+    Ok(PendingSave {
+      path,
+      contents,
+      hash,
+      job,
+    })
+  }
+
+  /// Everything `save()` does after writing the file.
+  fn finish_save(&self, pending: PendingSave, written: Written) -> Result {
+    let path = &pending.path;
+    self.deleted.store(false, Ordering::Relaxed);
+    if written.write {
+      self.keep_backup.store(false, Ordering::Relaxed);
+      *lock(&self.last_saved_at) = Some(SystemTime::now());
+      event!(
+        Info,
+        "Saved {} bytes to {}",
+        pending.contents.len(),
+        path.display()
+      );
+    }
+    // The data may have changed since it was serialized
+    *lock(&self.clean_hash) = pending.hash;
+    self.dirty.store(false, Ordering::Relaxed);
+    *lock(&self.file_stamp) = written.stamp.map(|stamp| (path.clone(), stamp));
+    #[cfg(feature = "exit-flush")]
+    self.set_exit_flush(None);
+    if self.remove_other_formats {
+      while let Some(other) = self.find_config_in_other_format()? {
+        self.remove_stored(&other)?;
+      }
+    }
+    if self.remove_legacy_file {
+      let legacy = lock(&self.legacy_file).take();
+      if let Some(legacy) = legacy {
+        self.remove_stored(&legacy)?;
+        // Only succeeds if the folder is now empty
+        if let Some(dir) = legacy.parent().filter(|_| !self.uses_store()) {
+          let _ = std::fs::remove_dir(dir);
+        }
+      }
+    }
+    self.notify(&self.on_save);
+    Ok(())
+  }
+
+  /// Loads the file again if it was changed by another program, e.g. a text editor,
+  /// since the last load or save. Returns whether it was loaded.
+  ///
+  /// The modification time and size are compared first, then the contents,
+  /// so that edits are noticed even when the file system has a coarse time resolution.
+  pub fn reload_if_changed(&self) -> Result<bool> {
+    self.drain_autosave().ok();
+    let changed = match &*lock(&self.file_stamp) {
+      Some((path, stamp)) => match stamp.changed(&self.config_key(path), self.store.as_ref()) {
+        Ok(changed) => changed,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => false,
+        Err(err) => return Err(AppConfigError::io(path)(err)),
+      },
+      None => self.find_config_file(&self.extensions())?.is_some(),
+    };
+    if changed {
+      self.load()?;
+    }
+    Ok(changed)
+  }
+
+  fn record_stamp(&self, path: &Path, contents: &[u8]) {
+    let stamp = fs::FileStamp::new(&self.config_key(path), contents, self.store.as_ref())
+      .ok()
+      .map(|stamp| (path.to_owned(), stamp));
+    *lock(&self.file_stamp) = stamp;
+  }
+
+  /// Returns `true` if the data changed since the last load or save, or `mark_dirty()` was called.
+  ///
+  /// The manager cannot observe changes through the `RefCell`, so the data is serialized
+  /// and compared with a hash taken at the last load or save. Each call costs a serialization.
+  pub fn is_dirty(&self) -> bool {
+    let clean_hash = *lock(&self.clean_hash);
+    self.dirty.load(Ordering::Relaxed) || clean_hash.is_none() || self.data_hash() != clean_hash
+  }
+
+  /// Makes the next `save_if_dirty()` write the file even if the data did not change.
+  pub fn mark_dirty(&self) {
+    self.dirty.store(true, Ordering::Relaxed);
+  }
+
+  /// Saves only if [`is_dirty`](Self::is_dirty). Returns whether the file was written.
+  pub fn save_if_dirty(&self) -> Result<bool> {
+    if !self.is_dirty() {
+      return Ok(false);
+    }
+    self.save()?;
+    Ok(true)
+  }
+
+  /// Returns the error of the last save that could not return it, see
+  /// [`set_on_save_error`](Self::set_on_save_error).
+  pub fn take_last_save_error(&self) -> Option<AppConfigError> {
+    lock(&self.last_save_error).take()
+  }
+
+  /// Reports an error of a save that cannot return it.
+  pub(crate) fn report_save_error(&self, err: AppConfigError) {
+    event!(Warn, "Failed to save: {}", err);
+    match lock(&self.on_save_error).as_mut() {
+      Some(f) => f(&err),
+      None => eprintln!("Failed to save the configuration: {}", err),
+    }
+    *lock(&self.last_save_error) = Some(err);
+  }
+
+  /// Changes the data with `f` and saves it.
+  ///
+  /// ```no_run
+  /// # use std::{cell::RefCell, rc::Rc};
+  /// # use appconfig::AppConfigManager;
+  /// # #[derive(serde::Serialize, serde::Deserialize)]
+  /// # struct MyAppConfig { window_pos: (u32, u32) }
+  /// # let config = Rc::new(RefCell::new(MyAppConfig { window_pos: (0, 0) }));
+  /// # let manager = AppConfigManager::new(config.clone(), "app", "org");
+  /// manager.update(|config| config.window_pos = (320, 280))?;
+  /// # Ok::<(), appconfig::AppConfigError>(())
+  /// ```
+  ///
+  /// Returns [`AppConfigError::AlreadyBorrowed`] instead of panicking when the data is borrowed.
+  pub fn update(&self, f: impl FnOnce(&mut T)) -> Result {
+    self.update_no_save(f)?;
+    self.save()
+  }
+
+  /// Like [`update`](Self::update), without saving.
+  pub fn update_no_save(&self, f: impl FnOnce(&mut T)) -> Result {
+    self
+      .data
+      .try_write(f)
+      .ok_or(AppConfigError::AlreadyBorrowed)
+  }
+
+  fn mark_clean(&self) {
+    *lock(&self.clean_hash) = self.data_hash();
+    self.dirty.store(false, Ordering::Relaxed);
+  }
+
+  fn data_hash(&self) -> Option<u64> {
+    let s = self
+      .data
+      .try_read(|data| self.format.serialize_with(data, &self.save_options))?
+      .ok()?;
+    Some(fs::hash_bytes(&s))
+  }
+
+  /// The contents that `save()` would write, without writing them.
+  ///
+  /// The contents are never encrypted.
+  pub fn to_config_string(&self) -> Result<String> {
+    let path = self.user_config_path()?;
+    let s = self.serialize_plaintext(&path)?;
+    String::from_utf8(s).map_err(|err| AppConfigError::Serialize {
+      path,
+      source: err.into(),
+    })
+  }
+
+  /// Replaces the data with `s` in the configured format, as if it had been read from the file.
+  ///
+  /// Parse errors are reported and skipped like in `load()`. Nothing is written,
+  /// even when a migration upgraded the contents.
+  pub fn apply_config_str(&self, s: &str) -> Result {
+    let path = self.user_config_path()?;
+    self.load_bytes(&path, s.as_bytes(), false).map(|_| ())
+  }
+
+  /// Writes the current data to `path`, e.g. for an "Export settings" command.
+  ///
+  /// The file is written atomically in the configured format, whatever its extension.
+  /// The configuration file itself is not touched.
+  pub fn save_to(&self, path: impl AsRef<Path>) -> Result {
+    let path = path.as_ref();
+    let s = self.serialize_data(path)?;
+    fs::write_atomic(path, &s, self.write_options()).map_err(AppConfigError::io(path))
+  }
+
+  /// Replaces the data with the contents of the file at `path`, e.g. for an "Import settings" command.
+  ///
+  /// Unlike `load()`, parse errors are always returned, since the user picked the file.
+  /// The configuration file is only updated by the next `save()`.
+  pub fn load_from(&self, path: impl AsRef<Path>) -> Result {
+    let path = path.as_ref();
+    let s = read_file(path, self.max_file_size)?;
+    let (mut value, _) = self.parse_file(path, &s, false)?;
+    self.fix_up(&mut value);
+    self.validate(path, &value)?;
+    self.data.write(|data| *data = value);
+    self.notify(&self.on_load);
+    Ok(())
+  }
+
+  /// The contents of the file at `path` for the current data.
+  fn serialize_data(&self, path: &Path) -> Result<Vec<u8>> {
+    let s = self.serialize_plaintext(path)?;
+    let s = self.compress(path, s)?;
+    let s = self.encrypt(path, s)?;
+    self.append_checksum(path, s)
+  }
+
+  /// The contents of the file at `path` before encryption.
+  fn serialize_plaintext(&self, path: &Path) -> Result<Vec<u8>> {
+    let s = self
+      .data
+      .read(|data| match self.serialize_layered(path, data)? {
+        Some(s) => Ok(s),
+        None => self
+          .format
+          .serialize_with(data, &self.save_options)
+          .map_err(AppConfigError::serialize(path)),
+      })?;
+    Ok(match self.rendered_header() {
+      Some(header) => [header.into_bytes(), s].concat(),
+      None => s,
+    })
+  }
+
+  /// The file header as comment lines followed by an empty line.
+  fn rendered_header(&self) -> Option<String> {
+    let prefix = self.format.comment_prefix()?;
+    let header = self.file_header.as_ref()?;
+    let mut rendered = String::new();
+    for line in header.lines() {
+      rendered.push_str(prefix);
+      if !line.is_empty() {
+        rendered.push(' ');
+        rendered.push_str(line);
+      }
+      rendered.push('\n');
+    }
+    rendered.push('\n');
+    Some(rendered)
+  }
+
+  /// Converts an existing configuration file from one format to another.
+  ///
+  /// The file in the `from` format is parsed, written in the `to` format and then removed.
+  /// The old file is only removed after the new one has been synced to disk.
+  /// The in-memory data is not changed, call `load()` afterwards to read the converted file.
+  ///
+  /// Returns `false` if there was no file to convert.
+  pub fn convert_format(
+    &self,
+    from: impl ConfigFormat<T>,
+    to: impl ConfigFormat<T>,
+  ) -> Result<bool> {
+    let extensions: Vec<&str> = std::iter::once(from.extension())
+      .chain(from.alternative_extensions().iter().copied())
+      .collect();
+    let old_path = match self.find_config_file(&extensions)? {
+      Some(path) => path,
+      None => return Ok(false),
+    };
+    let new_path = self
+      .get_user_config_dir()?
+      .join(self.file_name(to.extension())?);
+    if old_path == new_path {
+      return Ok(false);
+    }
+
+    let s = self.read_stored(&old_path)?;
+    let value = from
+      .deserialize(&s)
+      .map_err(AppConfigError::deserialize(&old_path))?;
+    let s = to
+      .serialize(&value)
+      .map_err(AppConfigError::serialize(&new_path))?;
+    let options = fs::WriteOptions {
+      durable: true,
+      ..self.write_options()
+    };
+    self.write_stored(&new_path, &s, options)?;
+    self.remove_stored(&old_path)?;
+    Ok(true)
+  }
+
+  /// Moves the file from the folder of this application in the `from` directory to the one in `to`,
+  /// e.g. for files written to [`Location::Data`] by an older release.
+  ///
+  /// An existing file at the destination is never overwritten.
+  /// The base directory, portable mode and environment override settings are not used here.
+  pub fn migrate_location(&self, from: Location, to: Location) -> Result<MigrationOutcome> {
+    let from = self.in_subdirectory(self.location_dir(from)?);
+    let to = self.in_subdirectory(self.location_dir(to)?);
+    self.migrate_dir(&from, &to)
+  }
+
+  fn migrate_dir(&self, from: &Path, to: &Path) -> Result<MigrationOutcome> {
+    // Some locations share a directory, e.g. `Config` and `Data` on macOS
+    if from == to {
+      return Ok(MigrationOutcome::NothingToMigrate);
+    }
+    let old_path = match self.find_config_file_in(from, &self.extensions())? {
+      Some(path) => path,
+      None => return Ok(MigrationOutcome::NothingToMigrate),
+    };
+    if self.find_config_file_in(to, &self.extensions())?.is_some() {
+      return Ok(MigrationOutcome::DestinationExists);
+    }
+    let new_path = to.join(old_path.file_name().unwrap_or_default());
+    if self.uses_store() {
+      self.copy_stored(&old_path, &new_path)?;
+      self.remove_stored(&old_path)?;
+      return Ok(MigrationOutcome::Migrated);
+    }
+    std::fs::create_dir_all(to).map_err(AppConfigError::io(to))?;
+    fs::move_file(&old_path, &new_path).map_err(AppConfigError::io(&old_path))?;
+    Ok(MigrationOutcome::Migrated)
+  }
+
+  /// The pointer to the data passed to [`new`](Self::new).
+  pub fn data(&self) -> &S {
+    &self.data
+  }
+
+  /// The machine-wide file read when no user file exists, if enabled.
+  fn system_file(&self) -> Result<Option<PathBuf>> {
+    if !(self.system_fallback || self.system_layer) {
+      return Ok(None);
+    }
+    self.system_config_path().map(Some)
+  }
+
+  /// Parses the contents of the file at `path`. Also returns whether the file was upgraded by a migration.
+  fn parse_file(&self, path: &Path, s: &[u8], detected: bool) -> Result<(T, bool)> {
+    let (s, unchecked) = self.verify_checksum(path, s)?;
+    let (s, plaintext) = self.decrypt(path, s)?;
+    let (s, uncompressed) = self.decompress(path, s)?;
+    let s = &*s;
+    let parsed = if self.loads_layers() {
+      self.parse_layered(path, s)
+    }
+    else if detected {
+      self
+        .parse_detected(path, s)
+        .map(|value| (value, false))
+        .map_err(AppConfigError::deserialize(path))
+    }
+    else {
+      self
+        .parse(&*self.format, s)
+        .map(|value| (value, false))
+        .map_err(AppConfigError::deserialize(path))
+    };
+    match parsed {
+      Err(err @ AppConfigError::Deserialize { .. }) => match self.parse_older_version(path, s) {
+        Some(value) => {
+          event!(Info, "Migrated {} from an older type", path.display());
+          Ok((value, true))
+        }
+        None => Err(err),
+      },
+      // Saved again to add a checksum, encrypt or compress it
+      result => {
+        result.map(|(value, migrated)| (value, migrated || unchecked || plaintext || uncompressed))
+      }
+    }
+  }
+
+  /// Parses the file as one of the types registered with `with_typed_migration`.
+  fn parse_older_version(&self, path: &Path, s: &[u8]) -> Option<T> {
+    let extension = self.format_extension(path);
+    self
+      .typed_migrations
+      .iter()
+      .find_map(|migration| migration(extension, s).ok())
+  }
+
+  fn parse<V: DeserializeOwned>(
+    &self,
+    format: &dyn ConfigFormat<V>,
+    s: &[u8],
+  ) -> std::result::Result<V, FormatError> {
+    let result = format.deserialize(s);
+    #[cfg(feature = "json5")]
+    if result.is_err() && self.lenient_json && format.extension() == "json" {
+      return format::deserialize_json5(s);
+    }
+    result
+  }
+
+  /// Parses a file found by format autodetection.
+  fn parse_detected(&self, path: &Path, s: &[u8]) -> std::result::Result<T, FormatError> {
+    let extension = path
+      .extension()
+      .and_then(|x| x.to_str())
+      .unwrap_or_default();
+    let result = match format::builtin::<T>(extension) {
+      Some(format) => self.parse(&*format, s),
+      None => return Err("Unknown config file format".into()),
+    };
+    if result.is_ok() {
+      return result;
+    }
+    // The content does not match the extension
+    format::BUILTIN_EXTENSIONS
+      .iter()
+      .filter_map(|&other| format::builtin::<T>(other))
+      .find_map(|format| self.parse(&*format, s).ok())
+      .map_or(result, Ok)
+  }
+}
+
+/// Parses a file in the format with the given extension as `Old` and converts it with `migration`.
+fn parse_older<Old, T>(
+  migration: impl Fn(Old) -> T,
+  extension: &str,
+  s: &[u8],
+) -> std::result::Result<T, FormatError>
+where
+  Old: Serialize + DeserializeOwned,
+{
+  let format = format::builtin::<Old>(extension).ok_or("Unknown config file format")?;
+  Ok(migration(format.deserialize(s)?))
+}
+
+/// Reports a file copied by `migrate_from_host` that was loaded.
+fn sandbox_outcome(outcome: LoadOutcome, migrated_from: Option<PathBuf>) -> LoadOutcome {
+  match (outcome, migrated_from) {
+    (LoadOutcome::Loaded | LoadOutcome::FixedUp, Some(from)) => {
+      LoadOutcome::SandboxMigrated { from }
+    }
+    (outcome, _) => outcome,
+  }
+}
+
+/// Reads the file at `path`, failing if it is larger than `limit` bytes unless `limit` is `0`.
+fn read_file(path: &Path, limit: u64) -> Result<Vec<u8>> {
+  use std::io::Read;
+
+  let file = match std::fs::File::open(path) {
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+      event!(Info, "{} does not exist", path.display());
+      return Err(AppConfigError::io(path)(err));
+    }
+    file => file.map_err(AppConfigError::io(path))?,
+  };
+  let limit = Some(limit).filter(|&x| x > 0);
+  let too_large = |size, limit| AppConfigError::FileTooLarge {
+    path: path.to_owned(),
+    size,
+    limit,
+  };
+  let size = file.metadata().map_err(AppConfigError::io(path))?.len();
+  if let Some(limit) = limit.filter(|&x| size > x) {
+    return Err(too_large(size, limit));
+  }
+  // The size of a device or a file that is still growing is not known in advance
+  let mut s = Vec::with_capacity(size as usize);
+  file
+    .take(limit.map_or(u64::MAX, |x| x + 1))
+    .read_to_end(&mut s)
+    .map_err(AppConfigError::io(path))?;
+  match limit {
+    Some(limit) if s.len() as u64 > limit => Err(too_large(s.len() as u64, limit)),
+    _ => Ok(s),
+  }
+}
+
+/// The contents to be written by `save()`.
+struct PendingSave {
+  path: PathBuf,
+  contents: Vec<u8>,
+  /// The hash of the data that was serialized.
+  hash: Option<u64>,
+  job: SaveJob,
+}
+
+/// Everything `save()` does with the file and its backups, without the manager,
+/// so that `save_async` can run it on another thread.
+struct SaveJob {
+  store: Option<store::SharedStore>,
+  key: ConfigKey,
+  lock_timeout: Option<Duration>,
+  conflict_policy: ConflictPolicy,
+  conflict_stamp: Option<fs::FileStamp>,
+  skip_unchanged: bool,
+  /// The `.bak` file of `with_backup`, unless it must be kept.
+  backup: Option<ConfigKey>,
+  numbered_backups: Vec<ConfigKey>,
+  max_file_size: u64,
+  options: fs::WriteOptions,
+}
+
+/// Everything `locate_file` does with the files, without the manager,
+/// so that `load_async` can run it on another thread.
+struct LocateJob {
+  store: Option<store::SharedStore>,
+  /// The file in the configured format, then the one written before `MacosStyle::Preferences`.
+  current: Vec<ConfigKey>,
+  /// The file without the compressed file suffix, then the files of the legacy identities.
+  legacy: Vec<ConfigKey>,
+  /// The files in the other built-in formats.
+  other_formats: Vec<ConfigKey>,
+  format_autodetect: bool,
+  extension: String,
+  system: Result<Option<ConfigKey>>,
+  user: ConfigKey,
+}
+
+impl LocateJob {
+  /// Like `locate_file`, but returns the key of the file.
+  fn run(self) -> Result<(ConfigKey, bool, Option<PathBuf>)> {
+    let store = self.store.as_ref();
+    let find = |keys: Vec<ConfigKey>| keys.into_iter().find(|key| store::exists_in(store, key));
+    let found = find(self.current);
+    let legacy = match found {
+      Some(_) => None,
+      None => find(self.legacy),
+    };
+    let legacy_path = legacy.as_ref().map(|key| key.path().to_owned());
+    let (key, detected) = match found.or(legacy) {
+      Some(key) => (key, false),
+      None => match find(self.other_formats) {
+        Some(other) if self.format_autodetect => (other, true),
+        // A file left behind in another format is not a parsing error and must not be skipped
+        Some(other) => {
+          return Err(AppConfigError::FormatMismatch {
+            path: other.path().to_owned(),
+            expected: self.extension,
+          })
+        }
+        None => match self.system? {
+          Some(system) if store::exists_in(store, &system) => (system, false),
+          _ => (self.user, false),
+        },
+      },
+    };
+    Ok((key, detected, legacy_path))
+  }
+}
+
+/// What [`SaveJob::run`] did.
+struct Written {
+  /// `false` if the file already had the contents.
+  write: bool,
+  stamp: Option<fs::FileStamp>,
+}
+
+impl SaveJob {
+  /// Writes `contents` to the file, creating its folder. Returns `None` when the conflict policy
+  /// keeps the file as it is.
+  fn run(&self, contents: &[u8]) -> Result<Option<Written>> {
+    let store = self.store.as_ref();
+    let path = self.key.path();
+    match path.parent() {
+      Some(dir) if store.is_none() && !dir.as_os_str().is_empty() && !dir.exists() => {
+        fs::create_dir_all(dir, self.options.mode).map_err(AppConfigError::io(dir))?
+      }
+      _ => {}
+    }
+    let _lock = lock_file(path, true, self.lock_timeout)?;
+    if !conflict::check_conflict(
+      self.conflict_policy,
+      self.conflict_stamp.as_ref(),
+      &self.key,
+      store,
+    )? {
+      return Ok(None);
+    }
+    let current = if self.skip_unchanged || !self.numbered_backups.is_empty() {
+      match store::read_from(store, &self.key, self.max_file_size) {
+        Ok(current) => Some(current),
+        Err(err) if err.is_not_found() => None,
+        Err(err) => return Err(err),
+      }
+    }
+    else {
+      None
+    };
+    let write = !(self.skip_unchanged && current.as_deref() == Some(contents));
+    if write {
+      if let Some(backup) = &self.backup {
+        if store::exists_in(store, &self.key) {
+          store::copy_within(store, &self.key, backup, self.max_file_size)?;
+        }
+      }
+      if current.is_some_and(|current| current != contents) {
+        store::rotate_within(store, &self.key, &self.numbered_backups, self.max_file_size)?;
+      }
+      store::write_to(store, &self.key, contents, self.options)
+        .map_err(store::store_error(store, path))?;
+    }
+    let stamp = fs::FileStamp::new(&self.key, contents, store).ok();
+    Ok(Some(Written { write, stamp }))
+  }
+}
+
+/// Locks the lock file of `path` with `timeout`, exclusively for writing. Does nothing without a timeout.
+/// The lock is released when the returned file is dropped.
+fn lock_file(
+  path: &Path,
+  exclusive: bool,
+  timeout: Option<Duration>,
+) -> Result<Option<std::fs::File>> {
+  let timeout = match timeout {
+    Some(timeout) => timeout,
+    None => return Ok(None),
+  };
+  let lock = fs::with_suffix(path, ".lock");
+  match fs::lock_file(&lock, exclusive, timeout) {
+    Ok(Some(file)) => Ok(Some(file)),
+    Ok(None) => Err(AppConfigError::Locked { path: lock }),
+    // Never saved with locking
+    Err(err) if !exclusive && err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+    Err(err) => Err(AppConfigError::Io {
+      path: lock,
+      source: err,
+    }),
+  }
+}
+
+impl<T, S> Deref for AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  type Target = S;
+
+  fn deref(&self) -> &Self::Target {
+    self.data()
+  }
+}
+
+impl<T, S> Drop for AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  fn drop(&mut self) {
+    // Pending background writes must not replace the final save
+    lock(&self.autosave).take();
+    let panicking = std::thread::panicking();
+    if panicking && self.save_on_panic {
+      // Keeps the last file saved without panicking
+      self.backup = true;
+    }
+    let deleted = self.deleted.load(Ordering::Relaxed);
+    if self.auto_saving && !deleted && (!panicking || self.save_on_panic) {
+      match self.save_if_dirty() {
+        Ok(saved) => {
+          event!(Debug, "Saved on drop: {}", saved);
+        }
+        Err(err) => self.report_save_error(err),
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{cell::RefCell, rc::Rc, time::Duration};
+
+  use crate::{
+    AppConfigError, AppConfigManager, BackupSelector, ConfigFormat, FormatError, LoadOutcome,
+    MacosStyle, MigrationOutcome, TestConfigDir,
+  };
+  use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq)]
+  struct MyAppConfig {
+    window_pos: (u32, u32),
+  }
+
+  impl Default for MyAppConfig {
+    fn default() -> Self {
+      Self {
+        window_pos: (320, 280),
+      }
+    }
+  }
+
+  #[test]
+  fn it_works() {
+    let dir = TestConfigDir::new().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = dir.manager(
+      config.clone(),
+      std::env!("CARGO_CRATE_NAME"), // CRATE_BIN_NAME etc..,
+      "sumibi-yakitori",
+    );
+    manager.save().unwrap();
+    manager.load().unwrap();
+    assert_eq!(*config.borrow(), MyAppConfig::default());
+    assert!(manager
+      .get_user_config_path()
+      .unwrap()
+      .starts_with(dir.path()));
+
+    let path = dir.path().to_owned();
+    drop(manager);
+    drop(dir);
+    assert!(!path.exists());
+  }
+
+  #[test]
+  fn error_variants() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = AppConfigManager::new(config.clone(), "error_variants", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+
+    let err = manager.load().unwrap_err();
+    assert!(err.is_not_found());
+    assert!(matches!(&err, AppConfigError::Io { path: p, .. } if *p == path));
+
+    std::fs::write(&path, "window_pos = [").unwrap();
+    let err = manager.load().unwrap_err();
+    match &err {
+      AppConfigError::Deserialize { path: p, source } => {
+        assert_eq!(*p, path);
+        assert!(source.downcast_ref::<toml::de::Error>().is_some());
+      }
+      _ => panic!("unexpected error: {:?}", err),
+    }
+  }
+
+  #[test]
+  fn errors_contain_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = AppConfigManager::new(config.clone(), "errors_contain_path", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    let displayed = path.display().to_string();
+
+    assert!(manager.load().unwrap_err().to_string().contains(&displayed));
+
+    std::fs::write(&path, "window_pos = [").unwrap();
+    assert!(manager.load().unwrap_err().to_string().contains(&displayed));
+
+    let temp = crate::fs::temp_path(&path);
+    std::fs::create_dir_all(&temp).unwrap();
+    assert!(manager.save().unwrap_err().to_string().contains(&displayed));
+    std::fs::remove_dir(&temp).unwrap();
+
+    let err = AppConfigError::ConfigDirUnavailable {
+      path: "com.sumibi-yakitori.errors_contain_path".into(),
+    };
+    assert!(err
+      .to_string()
+      .contains("com.sumibi-yakitori.errors_contain_path"));
+  }
+
+  #[test]
+  fn on_parse_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let errors = Rc::new(RefCell::new(Vec::new()));
+    let config = Rc::from(RefCell::from(MyAppConfig { window_pos: (5, 5) }));
+    let manager = AppConfigManager::new(config.clone(), "on_parse_error", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_on_parse_error({
+        let errors = errors.clone();
+        move |err| errors.borrow_mut().push(err.to_string())
+      })
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+
+    // Missing file
+    assert!(manager.load().is_err());
+    assert!(errors.borrow().is_empty());
+
+    // Clean parse
+    manager.save().unwrap();
+    manager.load().unwrap();
+    assert!(errors.borrow().is_empty());
+
+    // Skipped parse error
+    std::fs::write(&path, "window_pos = [").unwrap();
+    manager.load().unwrap();
+    assert_eq!(*config.borrow(), MyAppConfig { window_pos: (5, 5) });
+    assert_eq!(errors.borrow().len(), 1);
+    assert!(errors.borrow()[0].contains(&path.display().to_string()));
+  }
+
+  #[test]
+  fn on_save_error() {
+    let dir = tempfile::tempdir().unwrap();
+    // The configuration folder cannot be created inside a file
+    let base = dir.path().join("file");
+    std::fs::write(&base, "").unwrap();
+    let errors = Rc::new(RefCell::new(Vec::new()));
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = AppConfigManager::new(config.clone(), "on_save_error", "sumibi-yakitori")
+      .with_base_dir(&base)
+      .with_on_save_error({
+        let errors = errors.clone();
+        move |err| errors.borrow_mut().push(err.to_string())
+      });
+
+    *manager.edit().unwrap() = MyAppConfig { window_pos: (1, 1) };
+    assert_eq!(errors.borrow().len(), 1);
+    assert!(matches!(
+      manager.take_last_save_error(),
+      Some(AppConfigError::Io { .. })
+    ));
+    assert!(manager.take_last_save_error().is_none());
+
+    manager.mark_dirty();
+    drop(manager);
+    assert_eq!(errors.borrow().len(), 2);
+    assert!(errors.borrow()[1].contains(&base.display().to_string()));
+  }
+
+  #[test]
+  fn borrowed_during_save() {
+    let dir = tempfile::tempdir().unwrap();
+    let errors = Rc::new(RefCell::new(Vec::new()));
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = AppConfigManager::new(config.clone(), "borrowed", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_on_save_error({
+        let errors = errors.clone();
+        move |err| errors.borrow_mut().push(err.to_string())
+      });
+    let path = manager.get_user_config_path().unwrap();
+    manager.save().unwrap();
+
+    // Still borrowed when the manager is dropped
+    std::mem::forget(config.borrow_mut());
+    assert!(manager.is_dirty());
+    assert!(matches!(
+      manager.save(),
+      Err(AppConfigError::BorrowedDuringSave { .. })
+    ));
+    drop(manager);
+    assert_eq!(errors.borrow().len(), 1);
+    assert!(errors.borrow()[0].contains("borrowed"));
+    assert!(path.exists());
+  }
+
+  #[test]
+  fn save_on_panic() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig { window_pos: (1, 1) }));
+    let new_manager = |save_on_panic| {
+      AppConfigManager::new(config.clone(), "save_on_panic", "sumibi-yakitori")
+        .with_base_dir(dir.path())
+        .with_save_on_panic(save_on_panic)
+    };
+    let path = new_manager(false).get_user_config_path().unwrap();
+    new_manager(false).save().unwrap();
+    let panic_while_editing = |manager: AppConfigManager<MyAppConfig>| {
+      let config = config.clone();
+      std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+        let _manager = manager;
+        config.borrow_mut().window_pos.0 = 2;
+        panic!("half changed");
+      }))
+      .unwrap_err();
+    };
+
+    panic_while_editing(new_manager(false));
+    assert_eq!(
+      std::fs::read_to_string(&path).unwrap(),
+      "window_pos = [\n    1,\n    1,\n]\n"
+    );
+
+    panic_while_editing(new_manager(true));
+    assert_eq!(
+      std::fs::read_to_string(&path).unwrap(),
+      "window_pos = [\n    2,\n    1,\n]\n"
+    );
+    assert_eq!(
+      std::fs::read_to_string(crate::fs::with_suffix(&path, ".bak")).unwrap(),
+      "window_pos = [\n    1,\n    1,\n]\n"
+    );
+  }
+
+  #[test]
+  fn load_with_outcome() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig { window_pos: (6, 6) }));
+    let manager = AppConfigManager::new(config.clone(), "load_with_outcome", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+
+    assert!(matches!(
+      manager.load_with_outcome().unwrap(),
+      LoadOutcome::FileMissing
+    ));
+
+    manager.save().unwrap();
+    assert!(matches!(
+      manager.load_with_outcome().unwrap(),
+      LoadOutcome::Loaded
+    ));
+
+    std::fs::write(&path, "window_pos = [").unwrap();
+    assert!(matches!(
+      manager.load_with_outcome().unwrap(),
+      LoadOutcome::ParseErrorSkipped(AppConfigError::Deserialize { .. })
+    ));
+    assert_eq!(*config.borrow(), MyAppConfig { window_pos: (6, 6) });
+  }
+
+  #[test]
+  fn load_or_init() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = AppConfigManager::new(config.clone(), "load_or_init", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+
+    assert!(manager.load_or_init().unwrap());
+    let s = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(
+      toml::from_str::<MyAppConfig>(&s).unwrap(),
+      MyAppConfig::default()
+    );
+    assert!(!manager.load_or_init().unwrap());
+
+    std::fs::write(&path, "window_pos = [").unwrap();
+    config.borrow_mut().window_pos = (1, 1);
+    assert!(!manager.load_or_init().unwrap());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "window_pos = [");
+    assert_eq!(config.borrow().window_pos, (1, 1));
+  }
+
+  #[test]
+  fn load_creates_no_dirs() {
+    let dir = TestConfigDir::new().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = dir
+      .manager(config, "no_dirs", "sumibi-yakitori")
+      .with_file_lock(true)
+      .with_backup(true)
+      .with_system_fallback(true)
+      .with_system_dir(dir.path().join("system"))
+      .with_format_autodetect(true)
+      .with_legacy_identity("old_app", "sumibi-yakitori")
+      .with_auto_saving(false);
+    assert!(manager.load().unwrap_err().is_not_found());
+    assert!(matches!(
+      manager.load_with_outcome().unwrap(),
+      LoadOutcome::FirstRun
+    ));
+    assert!(!manager.config_file_exists().unwrap());
+    assert!(!manager.reload_if_changed().unwrap());
+    assert!(manager.list_backups().unwrap().is_empty());
+    assert!(manager
+      .restore_from_backup(BackupSelector::Latest)
+      .unwrap_err()
+      .is_not_found());
+    manager.enable_autosave(Duration::from_secs(60)).unwrap();
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+    // The background thread creates the folder
+    manager.request_save().unwrap();
+    manager.flush().unwrap();
+    assert!(manager.config_file_exists().unwrap());
+  }
+
+  #[test]
+  fn tolerate_missing_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig { window_pos: (2, 3) }));
+    let mut manager = AppConfigManager::new(config.clone(), "tolerate_missing", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_tolerate_missing_file(true)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+
+    for skip in [true, false] {
+      manager.set_skip_parsing_error_when_loading(skip);
+      manager.load().unwrap();
+      assert_eq!(*config.borrow(), MyAppConfig { window_pos: (2, 3) });
+    }
+
+    // A directory in place of the file is a genuine error
+    std::fs::create_dir(&path).unwrap();
+    let err = manager.load().unwrap_err();
+    assert!(matches!(err, AppConfigError::Io { .. }));
+    assert!(!err.is_not_found());
+    std::fs::remove_dir(&path).unwrap();
+  }
+
+  #[test]
+  fn file_name() {
+    let dir = tempfile::tempdir().unwrap();
+    let ui = Rc::from(RefCell::from(MyAppConfig { window_pos: (1, 1) }));
+    let ui_manager = AppConfigManager::new(ui.clone(), "file_name", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_file_name("ui")
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let keys = Rc::from(RefCell::from(MyAppConfig { window_pos: (2, 2) }));
+    let keys_manager = AppConfigManager::new(keys.clone(), "file_name", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_file_name("keybindings.toml")
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let ui_path = ui_manager.get_user_config_path().unwrap();
+    let keys_path = keys_manager.get_user_config_path().unwrap();
+    assert_eq!(ui_path.file_name().unwrap(), "ui.toml");
+    assert_eq!(keys_path.file_name().unwrap(), "keybindings.toml");
+
+    ui_manager.save().unwrap();
+    keys_manager.save().unwrap();
+    ui_manager.load().unwrap();
+    keys_manager.load().unwrap();
+    assert_eq!(ui.borrow().window_pos, (1, 1));
+    assert_eq!(keys.borrow().window_pos, (2, 2));
+
+    for name in ["../evil", "a/b", "a\\b", "..", ""] {
+      let manager = AppConfigManager::new(ui.clone(), "file_name", "sumibi-yakitori")
+        .with_base_dir(dir.path())
+        .with_file_name(name)
+        .with_auto_saving(false);
+      assert!(matches!(
+        manager.save().unwrap_err(),
+        AppConfigError::InvalidFileName(_)
+      ));
+    }
+  }
+
+  #[test]
+  fn file_name_from_type() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = AppConfigManager::new(config, "file_name_from_type", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_file_name_from_type()
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    assert_eq!(path.file_name().unwrap(), "my_app_config.toml");
+  }
+
+  #[test]
+  fn base_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let mut manager = AppConfigManager::new(config, "base_dir", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_auto_saving(false);
+    assert_eq!(
+      manager.get_user_config_path().unwrap(),
+      dir
+        .path()
+        .join("com.sumibi-yakitori.base_dir/app_config.toml")
+    );
+
+    manager.set_fqdn_subfolder(false);
+    manager.save().unwrap();
+    assert!(dir.path().join("app_config.toml").exists());
+  }
+
+  #[test]
+  fn portable_mode() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let mut manager = AppConfigManager::new(config, "portable_mode", "sumibi-yakitori")
+      .with_base_dir(dir.path().join("base"))
+      .with_portable_mode(true)
+      .with_portable_fallback(false)
+      .with_auto_saving(false);
+    // Instead of the folder of the test executable
+    manager.exe_dir = Some(dir.path().to_owned());
+    assert!(manager.uses_portable_dir());
+
+    let path = manager.config_path().unwrap();
+    assert_eq!(path, dir.path().join("config/app_config.toml"));
+    manager.save().unwrap();
+    assert!(path.exists());
+    assert!(!dir.path().join("base").exists());
+  }
+
+  #[test]
+  fn env_override() {
+    let dir = tempfile::tempdir().unwrap();
+    let overridden = dir.path().join("from_env");
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = AppConfigManager::new(config, "env_override", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_env_override("APPCONFIG_TEST_ENV_OVERRIDE_DIR")
+      .with_auto_saving(false);
+    let default_path = dir
+      .path()
+      .join("com.sumibi-yakitori.env_override/app_config.toml");
+    assert_eq!(manager.get_user_config_path().unwrap(), default_path);
+
+    std::env::set_var("APPCONFIG_TEST_ENV_OVERRIDE_DIR", &overridden);
+    assert_eq!(
+      manager.get_user_config_path().unwrap(),
+      overridden.join("app_config.toml")
+    );
+    manager.save().unwrap();
+    assert!(overridden.join("app_config.toml").exists());
+
+    std::env::set_var("APPCONFIG_TEST_ENV_OVERRIDE_DIR", "  ");
+    assert_eq!(manager.get_user_config_path().unwrap(), default_path);
+    std::env::remove_var("APPCONFIG_TEST_ENV_OVERRIDE_DIR");
+  }
+
+  #[test]
+  fn migrate_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let (old_dir, new_dir) = (dir.path().join("old"), dir.path().join("new/nested"));
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = AppConfigManager::new(config, "migrate_dir", "sumibi-yakitori")
+      .with_base_dir(&old_dir)
+      .with_fqdn_subfolder(false)
+      .with_auto_saving(false);
+    assert_eq!(
+      manager.migrate_dir(&old_dir, &new_dir).unwrap(),
+      MigrationOutcome::NothingToMigrate
+    );
+
+    manager.save().unwrap();
+    assert_eq!(
+      manager.migrate_dir(&old_dir, &new_dir).unwrap(),
+      MigrationOutcome::Migrated
+    );
+    assert!(!old_dir.join("app_config.toml").exists());
+    assert!(new_dir.join("app_config.toml").exists());
+
+    std::fs::write(old_dir.join("app_config.toml"), "window_pos = [1, 1]").unwrap();
+    std::fs::write(new_dir.join("app_config.toml"), "window_pos = [2, 2]").unwrap();
+    assert_eq!(
+      manager.migrate_dir(&old_dir, &new_dir).unwrap(),
+      MigrationOutcome::DestinationExists
+    );
+    assert_eq!(
+      std::fs::read_to_string(new_dir.join("app_config.toml")).unwrap(),
+      "window_pos = [2, 2]"
+    );
+    assert!(old_dir.join("app_config.toml").exists());
+  }
+
+  #[test]
+  fn macos_style() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = AppConfigManager::new(config, "macos_style", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_macos_style(MacosStyle::Preferences)
+      .with_auto_saving(false);
+    // A base directory always takes precedence
+    assert_eq!(
+      manager.get_user_config_path().unwrap(),
+      dir
+        .path()
+        .join("com.sumibi-yakitori.macos_style/app_config.toml")
+    );
+    assert_eq!(manager.find_legacy_macos_file().unwrap(), None);
+
+    let mut manager = manager;
+    manager.base_dir = None;
+    let path = manager
+      .location_dir(crate::Location::Config)
+      .unwrap()
+      .join(manager.file_name("toml").unwrap());
+    if cfg!(target_os = "macos") {
+      assert!(path.ends_with("Library/Preferences/com.sumibi-yakitori.macos_style.toml"));
+    }
+    else {
+      assert!(path.ends_with("com.sumibi-yakitori.macos_style/app_config.toml"));
+    }
+  }
+
+  #[test]
+  fn legacy_identity() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = AppConfigManager::new(config.clone(), "newapp", "newcorp")
+      .with_base_dir(dir.path())
+      .with_legacy_identity("oldapp", "newcorp")
+      .with_legacy_identity("oldapp", "oldcorp")
+      .with_remove_legacy_file(true)
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let new_dir = dir.path().join("com.newcorp.newapp");
+    let legacy_dir = dir.path().join("com.oldcorp.oldapp");
+    assert!(manager.load().unwrap_err().is_not_found());
+    assert!(!new_dir.exists());
+
+    std::fs::create_dir_all(&legacy_dir).unwrap();
+    std::fs::write(legacy_dir.join("app_config.toml"), "window_pos = [1, 2]").unwrap();
+    manager.load().unwrap();
+    assert_eq!(config.borrow().window_pos, (1, 2));
+    assert!(!new_dir.exists());
+
+    manager.save().unwrap();
+    assert!(new_dir.join("app_config.toml").exists());
+    assert!(!legacy_dir.exists());
+    config.borrow_mut().window_pos = (0, 0);
+    manager.load().unwrap();
+    assert_eq!(config.borrow().window_pos, (1, 2));
+  }
+
+  #[test]
+  fn qualifier() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig { window_pos: (1, 2) }));
+    let manager = AppConfigManager::new(config.clone(), "qualifier", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_auto_saving(false);
+    let legacy_dir = dir.path().join("com.sumibi-yakitori.qualifier");
+    assert_eq!(manager.config_dir().unwrap(), legacy_dir);
+    manager.save().unwrap();
+
+    let manager = manager
+      .with_qualifier("io.github")
+      .with_legacy_qualifier("com")
+      .with_remove_legacy_file(true);
+    let new_dir = dir.path().join("io.github.sumibi-yakitori.qualifier");
+    assert_eq!(manager.config_dir().unwrap(), new_dir);
+    *config.borrow_mut() = MyAppConfig::default();
+    manager.load().unwrap();
+    assert_eq!(config.borrow().window_pos, (1, 2));
+    manager.save().unwrap();
+    assert!(new_dir.join("app_config.toml").exists());
+    assert!(!legacy_dir.exists());
+
+    let manager = manager.with_qualifier("");
+    assert_eq!(
+      manager.config_dir().unwrap(),
+      dir.path().join("sumibi-yakitori.qualifier")
+    );
+    assert!(matches!(
+      manager.with_qualifier("../x").config_dir(),
+      Err(AppConfigError::InvalidName { .. })
+    ));
+  }
+
+  #[test]
+  fn system_fallback() {
+    let dir = tempfile::tempdir().unwrap();
+    let system_dir = dir.path().join("etc");
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let mut manager = AppConfigManager::new(config.clone(), "system_fallback", "sumibi-yakitori")
+      .with_base_dir(dir.path().join("home"))
+      .with_system_fallback(true)
+      .with_system_dir(&system_dir)
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let system_path = manager.system_config_path().unwrap();
+    assert_eq!(
+      system_path,
+      system_dir.join("com.sumibi-yakitori.system_fallback/app_config.toml")
+    );
+    assert!(manager.load().unwrap_err().is_not_found());
+
+    std::fs::create_dir_all(system_path.parent().unwrap()).unwrap();
+    std::fs::write(&system_path, "window_pos = [1, 2]").unwrap();
+    assert_eq!(manager.load_returning_path().unwrap(), system_path);
+    assert_eq!(config.borrow().window_pos, (1, 2));
+
+    // The user file wins and the system file is left alone
+    let path = manager.config_path().unwrap();
+    config.borrow_mut().window_pos = (3, 4);
+    assert_eq!(manager.save_returning_path().unwrap(), path);
+    config.borrow_mut().window_pos = (0, 0);
+    assert_eq!(manager.load_returning_path().unwrap(), path);
+    assert_eq!(config.borrow().window_pos, (3, 4));
+    assert_eq!(
+      std::fs::read_to_string(&system_path).unwrap(),
+      "window_pos = [1, 2]"
+    );
+
+    // Parse errors in the system file follow the same policy
+    std::fs::remove_file(manager.get_user_config_path().unwrap()).unwrap();
+    std::fs::write(&system_path, "window_pos = [").unwrap();
+    assert!(matches!(
+      manager.load().unwrap_err(),
+      AppConfigError::Deserialize { path, .. } if path == system_path
+    ));
+    manager.set_skip_parsing_error_when_loading(true);
+    manager.load().unwrap();
+    assert_eq!(config.borrow().window_pos, (3, 4));
+  }
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq)]
+  struct LabConfig {
+    theme: String,
+    proxy: Proxy,
+  }
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq)]
+  struct Proxy {
+    host: String,
+    port: u16,
+  }
+
+  #[test]
+  fn system_layer() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(LabConfig {
+      theme: String::new(),
+      proxy: Proxy {
+        host: String::new(),
+        port: 0,
+      },
+    }));
+    let mut manager = AppConfigManager::new(config.clone(), "system_layer", "sumibi-yakitori")
+      .with_base_dir(dir.path().join("home"))
+      .with_system_dir(dir.path().join("etc"))
+      .with_system_layer(true)
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let system_path = manager.system_config_path().unwrap();
+    let user_path = manager.get_user_config_path().unwrap();
+    std::fs::create_dir_all(system_path.parent().unwrap()).unwrap();
+    std::fs::write(
+      &system_path,
+      "theme = \"light\"\n[proxy]\nhost = \"proxy.lab\"\nport = 8080\n",
+    )
+    .unwrap();
+
+    // Only the system file
+    manager.load().unwrap();
+    assert_eq!(config.borrow().proxy.host, "proxy.lab");
+
+    // The user file overrides single keys
+    std::fs::write(&user_path, "theme = \"dark\"\n[proxy]\nport = 3128\n").unwrap();
+    manager.load().unwrap();
+    assert_eq!(
+      *config.borrow(),
+      LabConfig {
+        theme: "dark".into(),
+        proxy: Proxy {
+          host: "proxy.lab".into(),
+          port: 3128,
+        },
+      }
+    );
+
+    // Everything is written by default
+    manager.save().unwrap();
+    let saved: toml::Table = toml::from_str(&std::fs::read_to_string(&user_path).unwrap()).unwrap();
+    assert_eq!(saved["proxy"]["host"].as_str(), Some("proxy.lab"));
+
+    manager.set_save_diff_from_system(true);
+    manager.save().unwrap();
+    let saved: toml::Table = toml::from_str(&std::fs::read_to_string(&user_path).unwrap()).unwrap();
+    assert_eq!(
+      saved,
+      toml::from_str("theme = \"dark\"\n[proxy]\nport = 3128\n").unwrap()
+    );
+
+    // A scalar in the user file replaces a table in the system file
+    std::fs::write(&user_path, "proxy = \"none\"\n").unwrap();
+    assert!(matches!(
+      manager.load().unwrap_err(),
+      AppConfigError::Deserialize { path, .. } if path == user_path
+    ));
+  }
+
+  #[test]
+  fn env_prefix() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(LabConfig {
+      theme: "light".into(),
+      proxy: Proxy {
+        host: "proxy.lab".into(),
+        port: 8080,
+      },
+    }));
+    let manager = AppConfigManager::new(config.clone(), "env_prefix", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_env_prefix("APPCONFIG_TEST_PREFIX")
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    manager.save().unwrap();
+    let path = manager.get_user_config_path().unwrap();
+    let saved = std::fs::read_to_string(&path).unwrap();
+
+    std::env::set_var("APPCONFIG_TEST_PREFIX_PROXY_PORT", "3128");
+    std::env::set_var("APPCONFIG_TEST_PREFIX_THEME", "dark");
+    manager.load().unwrap();
+    assert_eq!(config.borrow().proxy.port, 3128);
+    assert_eq!(config.borrow().theme, "dark");
+
+    // Overrides are not saved, but changes made by the application are
+    config.borrow_mut().proxy.host = "proxy2.lab".into();
+    manager.save().unwrap();
+    assert_eq!(
+      std::fs::read_to_string(&path).unwrap(),
+      saved.replace("proxy.lab", "proxy2.lab")
+    );
+
+    // A value of the wrong type is a parse error
+    std::env::set_var("APPCONFIG_TEST_PREFIX_PROXY_PORT", "many");
+    assert!(matches!(
+      manager.load().unwrap_err(),
+      AppConfigError::Deserialize { .. }
+    ));
+    let mut manager = manager;
+    manager.set_skip_parsing_error_when_loading(true);
+    assert!(matches!(
+      manager.load_with_outcome().unwrap(),
+      LoadOutcome::ParseErrorSkipped(_)
+    ));
+    assert_eq!(config.borrow().proxy.port, 3128);
+
+    std::env::remove_var("APPCONFIG_TEST_PREFIX_PROXY_PORT");
+    std::env::remove_var("APPCONFIG_TEST_PREFIX_THEME");
+  }
+
+  #[test]
+  fn env_prefix_tuple() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = AppConfigManager::new(config.clone(), "env_prefix_tuple", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_env_prefix("APPCONFIG_TEST_TUPLE")
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    manager.save().unwrap();
+
+    std::env::set_var("APPCONFIG_TEST_TUPLE_WINDOW_POS", "100,200");
+    manager.load().unwrap();
+    std::env::remove_var("APPCONFIG_TEST_TUPLE_WINDOW_POS");
+    assert_eq!(config.borrow().window_pos, (100, 200));
+  }
+
+  #[test]
+  fn key_overrides() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let mut manager = AppConfigManager::new(config.clone(), "key_overrides", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_overrides([("window_pos.0", "500")])
+      .with_auto_saving(false);
+    manager.save().unwrap();
+    let path = manager.get_user_config_path().unwrap();
+    let saved = std::fs::read_to_string(&path).unwrap();
+
+    manager.load().unwrap();
+    assert_eq!(config.borrow().window_pos, (500, 280));
+    manager.save().unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), saved);
+
+    for (key, reason) in [
+      ("window_pos.2", "out of range"),
+      ("window_pos.0.x", "not a table"),
+      ("window_pos.first", "not a table"),
+      ("", "empty"),
+    ] {
+      manager.set_overrides([(key, "1")]);
+      // Not a parse error, so it is never skipped
+      match manager.load().unwrap_err() {
+        AppConfigError::InvalidOverride { key: k, reason: r } => {
+          assert_eq!(k, key);
+          assert!(r.contains(reason), "{}", r);
+        }
+        err => panic!("unexpected error: {:?}", err),
+      }
+    }
+  }
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq)]
+  struct EditorConfig {
+    font_size: u32,
+    tab_width: u32,
+    minimap: Minimap,
+  }
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq)]
+  struct Minimap {
+    enabled: bool,
+    scale: u32,
+  }
+
+  impl Default for EditorConfig {
+    fn default() -> Self {
+      Self {
+        font_size: 14,
+        tab_width: 4,
+        minimap: Minimap {
+          enabled: true,
+          scale: 1,
+        },
+      }
+    }
+  }
+
+  #[test]
+  fn merge_defaults() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(EditorConfig::default()));
+    let mut manager = AppConfigManager::new(config.clone(), "merge_defaults", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+
+    // Written before `tab_width` and `minimap` existed, and with a field removed since
+    std::fs::write(&path, "font_size = 18\nline_numbers = true\n").unwrap();
+    assert!(manager.load().is_err());
+
+    manager.set_merge_defaults(true);
+    manager.load().unwrap();
+    assert_eq!(
+      *config.borrow(),
+      EditorConfig {
+        font_size: 18,
+        ..Default::default()
+      }
+    );
+
+    std::fs::write(&path, "font_size = 18\n[minimap]\nscale = 2\n").unwrap();
+    manager.load().unwrap();
+    assert_eq!(
+      config.borrow().minimap,
+      Minimap {
+        enabled: true,
+        scale: 2
+      }
+    );
+  }
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct HostConfig {
+    theme: String,
+    note: Option<String>,
+  }
+
+  #[test]
+  fn preserve_unknown_keys() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(HostConfig::default()));
+    let manager = AppConfigManager::new(config.clone(), "unknown_keys", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_preserve_unknown_keys(true)
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    std::fs::write(
+      &path,
+      "theme = \"light\"\nnote = \"hi\"\n\n[plugin.foo]\nenabled = true\n",
+    )
+    .unwrap();
+
+    manager.load().unwrap();
+    config.borrow_mut().theme = "dark".into();
+    config.borrow_mut().note = None;
+    manager.save().unwrap();
+
+    let saved: toml::Table = toml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(
+      saved,
+      toml::from_str("theme = \"dark\"\n[plugin.foo]\nenabled = true\n").unwrap()
+    );
+  }
+
+  #[cfg(feature = "preserve-formatting")]
+  #[test]
+  fn preserve_formatting() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(EditorConfig::default()));
+    let manager = AppConfigManager::new(config.clone(), "preserve_formatting", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_preserve_formatting(true)
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    std::fs::write(
+      &path,
+      "# my comment\nfont_size = 14 # points\ntab_width = 4\n\n[minimap]\nenabled = true\nscale = 1\n",
+    )
+    .unwrap();
+
+    manager.load().unwrap();
+    config.borrow_mut().font_size = 16;
+    manager.save().unwrap();
+    assert_eq!(
+      std::fs::read_to_string(&path).unwrap(),
+      "# my comment\nfont_size = 16 # points\ntab_width = 4\n\n[minimap]\nenabled = true\nscale = 1\n"
+    );
+
+    // The header is written once
+    let mut manager = manager;
+    manager.set_file_header("Generated by MyApp");
+    manager.save().unwrap();
+    manager.save().unwrap();
+    assert!(std::fs::read_to_string(&path)
+      .unwrap()
+      .starts_with("# Generated by MyApp\n\n# my comment\nfont_size = 16"));
+  }
+
+  #[test]
+  fn file_header() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig { window_pos: (1, 2) }));
+    let manager = AppConfigManager::new(config.clone(), "file_header", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_file_header("Generated by MyApp 2.3.1\n\nDo not edit while the app is running")
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    manager.save().unwrap();
+    let s = std::fs::read_to_string(manager.get_user_config_path().unwrap()).unwrap();
+    assert!(s.starts_with(
+      "# Generated by MyApp 2.3.1\n#\n# Do not edit while the app is running\n\nwindow_pos = ["
+    ));
+
+    *config.borrow_mut() = MyAppConfig::default();
+    manager.load().unwrap();
+    assert_eq!(*config.borrow(), MyAppConfig { window_pos: (1, 2) });
+  }
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct ProfileConfig {
+    display_name: String,
+    colors: Colors,
+  }
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Colors {
+    accent: String,
+  }
+
+  #[test]
+  fn migrations() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(ProfileConfig::default()));
+    let manager = AppConfigManager::new(config.clone(), "migrations", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      // `name` was renamed in version 2
+      .with_migration(2, |doc| {
+        let table = doc.as_table_mut().unwrap();
+        if let Some(name) = table.remove("name") {
+          table.insert("display_name".into(), name);
+        }
+      })
+      // `accent` was moved into `[colors]` in version 3
+      .with_migration(3, |doc| {
+        let table = doc.as_table_mut().unwrap();
+        let accent = table.remove("accent").unwrap_or("blue".into());
+        let mut colors = toml::Table::new();
+        colors.insert("accent".into(), accent);
+        table.insert("colors".into(), colors.into());
+      })
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    std::fs::write(&path, "name = \"sumibi\"\naccent = \"red\"\n").unwrap();
+
+    manager.load().unwrap();
+    assert_eq!(
+      *config.borrow(),
+      ProfileConfig {
+        display_name: "sumibi".into(),
+        colors: Colors {
+          accent: "red".into()
+        },
+      }
+    );
+
+    manager.save().unwrap();
+    let saved: toml::Table = toml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(saved["__appconfig_version"].as_integer(), Some(3));
+    assert_eq!(saved["display_name"].as_str(), Some("sumibi"));
+    manager.load().unwrap();
+    assert_eq!(config.borrow().colors.accent, "red");
+
+    // Written by a newer version of the application
+    std::fs::write(
+      &path,
+      "__appconfig_version = 4\ndisplay_name = \"sumibi\"\n[colors]\naccent = \"red\"\n",
+    )
+    .unwrap();
+    let err = manager.load().unwrap_err();
+    assert!(matches!(
+      err,
+      AppConfigError::NewerVersion {
+        found: 4,
+        supported: 3,
+        ..
+      }
+    ));
+  }
+
+  #[derive(Debug, Serialize, Deserialize)]
+  struct ProfileConfigV1 {
+    name: String,
+  }
+
+  #[derive(Debug, Serialize, Deserialize)]
+  struct ProfileConfigV2 {
+    display_name: String,
+  }
+
+  impl From<ProfileConfigV1> for ProfileConfigV2 {
+    fn from(old: ProfileConfigV1) -> Self {
+      Self {
+        display_name: old.name,
+      }
+    }
+  }
+
+  impl From<ProfileConfigV2> for ProfileConfig {
+    fn from(old: ProfileConfigV2) -> Self {
+      Self {
+        display_name: old.display_name,
+        colors: Colors {
+          accent: "blue".into(),
+        },
+      }
+    }
+  }
+
+  #[test]
+  fn typed_migrations() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(ProfileConfig::default()));
+    let manager = AppConfigManager::new(config.clone(), "typed_migrations", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_previous_version::<ProfileConfigV2>()
+      .with_typed_migration(|v1: ProfileConfigV1| ProfileConfig::from(ProfileConfigV2::from(v1)))
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    std::fs::write(&path, "name = \"sumibi\"\n").unwrap();
+
+    manager.load().unwrap();
+    let expected = ProfileConfig {
+      display_name: "sumibi".into(),
+      colors: Colors {
+        accent: "blue".into(),
+      },
+    };
+    assert_eq!(*config.borrow(), expected);
+    // Written back in the current version
+    let saved: ProfileConfig = toml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(saved, expected);
+
+    // Left for the next save
+    std::fs::write(&path, "name = \"sumibi\"\n").unwrap();
+    let mut manager = manager;
+    manager.set_save_migrated(false);
+    manager.load().unwrap();
+    assert_eq!(*config.borrow(), expected);
+    assert_eq!(
+      std::fs::read_to_string(&path).unwrap(),
+      "name = \"sumibi\"\n"
+    );
+
+    // Not any known version
+    std::fs::write(&path, "nickname = \"sumibi\"\n").unwrap();
+    assert!(matches!(
+      manager.load().unwrap_err(),
+      AppConfigError::Deserialize { .. }
+    ));
+  }
+
+  #[test]
+  fn config_string() {
+    let config = Rc::from(RefCell::from(MyAppConfig { window_pos: (1, 2) }));
+    let mut manager = AppConfigManager::new(config.clone(), "config_string", "sumibi-yakitori")
+      .with_file_header("Preview")
+      .with_pretty_output(false)
+      .with_auto_saving(false);
+    let s = manager.to_config_string().unwrap();
+    assert_eq!(s, "# Preview\n\nwindow_pos = [1, 2]\n");
+
+    manager.apply_config_str("window_pos = [3, 4]").unwrap();
+    assert_eq!(*config.borrow(), MyAppConfig { window_pos: (3, 4) });
+    manager.apply_config_str("window_pos = [").unwrap();
+    assert_eq!(*config.borrow(), MyAppConfig { window_pos: (3, 4) });
+
+    manager.set_skip_parsing_error_when_loading(false);
+    assert!(matches!(
+      manager.apply_config_str("window_pos = [").unwrap_err(),
+      AppConfigError::Deserialize { .. }
+    ));
+  }
+
+  #[test]
+  fn save_to_and_load_from() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig { window_pos: (1, 2) }));
+    let manager = AppConfigManager::new(config.clone(), "export", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_auto_saving(false);
+    let exported = dir.path().join("exported.toml");
+    manager.save_to(&exported).unwrap();
+    assert!(!manager.get_user_config_path().unwrap().exists());
+
+    *config.borrow_mut() = MyAppConfig::default();
+    manager.load_from(&exported).unwrap();
+    assert_eq!(*config.borrow(), MyAppConfig { window_pos: (1, 2) });
+
+    // Strict even though parse errors are skipped by `load()`
+    std::fs::write(&exported, "window_pos = [").unwrap();
+    assert!(matches!(
+      manager.load_from(&exported).unwrap_err(),
+      AppConfigError::Deserialize { .. }
+    ));
+    assert!(manager
+      .load_from(dir.path().join("missing.toml"))
+      .unwrap_err()
+      .is_not_found());
+    assert_eq!(*config.borrow(), MyAppConfig { window_pos: (1, 2) });
+  }
+
+  #[test]
+  fn backup() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig { window_pos: (1, 1) }));
+    let manager = AppConfigManager::new(config.clone(), "backup", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_backup(true)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    let backup = path.with_file_name("app_config.toml.bak");
+    // Nothing to back up yet
+    manager.save().unwrap();
+    assert!(!backup.exists());
+
+    config.borrow_mut().window_pos = (2, 2);
+    manager.save().unwrap();
+    assert_eq!(
+      std::fs::read_to_string(&backup).unwrap(),
+      "window_pos = [\n    1,\n    1,\n]\n"
+    );
+
+    std::fs::write(&path, "window_pos = [").unwrap();
+    assert!(matches!(
+      manager.load_with_outcome().unwrap(),
+      LoadOutcome::BackupLoaded(AppConfigError::Deserialize { .. })
+    ));
+    assert_eq!(*config.borrow(), MyAppConfig { window_pos: (1, 1) });
+
+    // The broken file does not replace the backup
+    manager.save().unwrap();
+    assert!(std::fs::read_to_string(&backup).unwrap().contains("1,"));
+    manager.load().unwrap();
+    assert_eq!(*config.borrow(), MyAppConfig { window_pos: (1, 1) });
+  }
+
+  #[test]
+  fn backup_retention() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = AppConfigManager::new(config.clone(), "backup_retention", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_backup_retention(2)
+      .with_pretty_output(false)
+      .with_auto_saving(false);
+    for i in 1..=4 {
+      config.borrow_mut().window_pos = (i, i);
+      manager.save().unwrap();
+      // Unchanged contents are not rotated
+      manager.save().unwrap();
+    }
+
+    let backups = manager.list_backups().unwrap();
+    let contents: Vec<String> = backups
+      .iter()
+      .map(|(path, _)| std::fs::read_to_string(path).unwrap())
+      .collect();
+    assert_eq!(contents, ["window_pos = [3, 3]\n", "window_pos = [2, 2]\n"]);
+    assert!(backups[0].0.ends_with("app_config.toml.1"));
+    assert!(backups[0].1 >= backups[1].1);
+    assert!(!dir
+      .path()
+      .join("com.sumibi-yakitori.backup_retention/app_config.toml.3")
+      .exists());
+  }
+
+  #[test]
+  fn skip_unchanged() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig { window_pos: (1, 1) }));
+    let manager = AppConfigManager::new(config.clone(), "skip_unchanged", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_file_header("Generated by MyApp")
+      .with_skip_unchanged(true)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    manager.save().unwrap();
+    let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+    std::fs::File::options()
+      .write(true)
+      .open(&path)
+      .unwrap()
+      .set_modified(old)
+      .unwrap();
+    let modified = || std::fs::metadata(&path).unwrap().modified().unwrap();
+
+    manager.save().unwrap();
+    assert_eq!(modified(), old);
+
+    config.borrow_mut().window_pos = (2, 2);
+    manager.save().unwrap();
+    assert_ne!(modified(), old);
+  }
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct MixerConfig {
+    gain: f64,
+    channels: std::collections::HashMap<String, f32>,
+  }
+
+  #[test]
+  fn dirty_tracking() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MixerConfig::default()));
+    config.borrow_mut().channels.insert("left".into(), 0.5);
+    config.borrow_mut().channels.insert("right".into(), 0.25);
+    let manager = AppConfigManager::new(config.clone(), "dirty", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_auto_saving(false);
+    // Never saved
+    assert!(manager.is_dirty());
+    assert!(manager.save_if_dirty().unwrap());
+    assert!(!manager.is_dirty());
+    assert!(!manager.save_if_dirty().unwrap());
+
+    config.borrow_mut().gain = 0.1 + 0.2;
+    assert!(manager.is_dirty());
+    manager.save().unwrap();
+    manager.load().unwrap();
+    assert!(!manager.is_dirty());
+    config.borrow_mut().channels.insert("left".into(), 0.75);
+    assert!(manager.is_dirty());
+    config.borrow_mut().channels.insert("left".into(), 0.5);
+    assert!(!manager.is_dirty());
+
+    manager.mark_dirty();
+    assert!(manager.save_if_dirty().unwrap());
+    assert!(!manager.is_dirty());
+
+    // Dropping without changes does not write
+    let path = manager.get_user_config_path().unwrap();
+    let mut manager = manager;
+    manager.set_auto_saving(true);
+    std::fs::remove_file(&path).unwrap();
+    drop(manager);
+    assert!(!path.exists());
+  }
+
+  #[test]
+  fn reload_if_changed() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig { window_pos: (1, 1) }));
+    let manager = AppConfigManager::new(config.clone(), "reload", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_pretty_output(false)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    assert!(!manager.reload_if_changed().unwrap());
+
+    // The own writes are not external changes
+    manager.save().unwrap();
+    assert!(!manager.reload_if_changed().unwrap());
+    manager.load().unwrap();
+    assert!(!manager.reload_if_changed().unwrap());
+
+    // Same size and modification time
+    let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+    std::fs::write(&path, "window_pos = [2, 2]\n").unwrap();
+    std::fs::File::options()
+      .write(true)
+      .open(&path)
+      .unwrap()
+      .set_modified(modified)
+      .unwrap();
+    assert!(manager.reload_if_changed().unwrap());
+    assert_eq!(*config.borrow(), MyAppConfig { window_pos: (2, 2) });
+    assert!(!manager.reload_if_changed().unwrap());
+  }
+
+  #[test]
+  fn update() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig { window_pos: (1, 2) }));
+    let manager = AppConfigManager::new(config.clone(), "update", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_pretty_output(false)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    manager.update(|config| config.window_pos = (3, 4)).unwrap();
+    assert_eq!(
+      std::fs::read_to_string(&path).unwrap(),
+      "window_pos = [3, 4]\n"
+    );
+
+    manager
+      .update_no_save(|config| config.window_pos.0 = 5)
+      .unwrap();
+    assert!(manager.is_dirty());
+    assert_eq!(
+      std::fs::read_to_string(&path).unwrap(),
+      "window_pos = [3, 4]\n"
+    );
+
+    let borrowed = config.borrow();
+    assert!(matches!(
+      manager.update(|config| config.window_pos.0 = 6),
+      Err(AppConfigError::AlreadyBorrowed)
+    ));
+    drop(borrowed);
+    assert_eq!(*config.borrow(), MyAppConfig { window_pos: (5, 4) });
+  }
+
+  #[test]
+  fn subscribers() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig { window_pos: (1, 2) }));
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let manager = AppConfigManager::new(config.clone(), "subscribers", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_on_load({
+        let events = events.clone();
+        move |data: &MyAppConfig| {
+          events
+            .borrow_mut()
+            .push(format!("load {:?}", data.window_pos))
+        }
+      })
+      .with_on_load({
+        let events = events.clone();
+        // Reading the data again is fine
+        let config = config.clone();
+        move |_: &MyAppConfig| {
+          events
+            .borrow_mut()
+            .push(format!("load again {:?}", config.borrow().window_pos))
+        }
+      })
+      .with_on_save({
+        let events = events.clone();
+        move |data: &MyAppConfig| {
+          events
+            .borrow_mut()
+            .push(format!("save {:?}", data.window_pos))
+        }
+      });
+    manager.save().unwrap();
+    manager.load().unwrap();
+    config.borrow_mut().window_pos = (3, 4);
+    drop(manager);
+    assert_eq!(
+      *events.borrow(),
+      [
+        "save (1, 2)",
+        "load (1, 2)",
+        "load again (1, 2)",
+        "save (3, 4)"
+      ]
+    );
+  }
+
+  #[test]
+  fn failed_save_keeps_original() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig { window_pos: (1, 1) }));
+    let manager = AppConfigManager::new(config.clone(), "failed_save", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_auto_saving(false);
+    manager.save().unwrap();
+    let path = manager.get_user_config_path().unwrap();
+    let original = std::fs::read(&path).unwrap();
+
+    // A directory in place of the temporary file makes the write fail
+    let temp = crate::fs::temp_path(&path);
+    std::fs::create_dir_all(&temp).unwrap();
+    config.borrow_mut().window_pos = (2, 2);
+    assert!(manager.save().is_err());
+    assert_eq!(std::fs::read(&path).unwrap(), original);
+
+    std::fs::remove_dir(&temp).unwrap();
+    manager.save().unwrap();
+    assert!(!temp.exists());
+    assert_ne!(std::fs::read(&path).unwrap(), original);
+  }
+
+  #[test]
+  fn max_file_size() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = AppConfigManager::new(config.clone(), "max_file_size", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_max_file_size(16)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    std::fs::write(&path, "window_pos = [1, 2]\n").unwrap();
+    assert!(matches!(
+      manager.load(),
+      Err(AppConfigError::FileTooLarge {
+        size: 20,
+        limit: 16,
+        ..
+      })
+    ));
+    assert_eq!(*config.borrow(), MyAppConfig::default());
+
+    // The target of a symlink
+    #[cfg(unix)]
+    {
+      let target = dir.path().join("large.toml");
+      std::fs::rename(&path, &target).unwrap();
+      std::os::unix::fs::symlink(&target, &path).unwrap();
+      assert!(matches!(
+        manager.load(),
+        Err(AppConfigError::FileTooLarge { .. })
+      ));
+    }
+
+    let manager = manager.with_max_file_size(0);
+    manager.load().unwrap();
+    assert_eq!(config.borrow().window_pos, (1, 2));
+  }
+
+  #[test]
+  fn file_lock() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = AppConfigManager::new(config.clone(), "file_lock", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_file_lock(true)
+      .with_lock_timeout(Duration::from_millis(50))
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    manager.load().unwrap_err();
+    manager.save().unwrap();
+
+    // Held by another instance
+    let lock = std::fs::File::open(crate::fs::with_suffix(&path, ".lock")).unwrap();
+    lock.lock().unwrap();
+    assert!(matches!(manager.save(), Err(AppConfigError::Locked { .. })));
+    assert!(matches!(manager.load(), Err(AppConfigError::Locked { .. })));
+
+    // Released while waiting
+    let mut manager = manager;
+    manager.set_lock_timeout(Duration::from_secs(10));
+    let holder = std::thread::spawn(move || {
+      std::thread::sleep(Duration::from_millis(100));
+      drop(lock);
+    });
+    manager.save().unwrap();
+    holder.join().unwrap();
+    manager.load().unwrap();
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn unix_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode =
+      |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = AppConfigManager::new(config.clone(), "unix_permissions", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_unix_permissions(0o600)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    assert_eq!(mode(path.parent().unwrap()), 0o700);
+    manager.save().unwrap();
+    assert_eq!(mode(&path), 0o600);
+
+    // Loosened by another program
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    manager.save().unwrap();
+    assert_eq!(mode(&path), 0o600);
+  }
+
+  #[test]
+  fn durable_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig { window_pos: (4, 4) }));
+    let manager = AppConfigManager::new(config.clone(), "durable_writes", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_durable_writes(true)
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    manager.save().unwrap();
+    *config.borrow_mut() = MyAppConfig::default();
+    manager.load().unwrap();
+    assert_eq!(*config.borrow(), MyAppConfig { window_pos: (4, 4) });
+  }
+
+  #[cfg(feature = "field-path")]
+  #[derive(Debug, Serialize, Deserialize, PartialEq)]
+  struct WindowConfig {
+    window: Window,
+    recent_files: Vec<RecentFile>,
+  }
+
+  #[cfg(feature = "field-path")]
+  #[derive(Debug, Serialize, Deserialize, PartialEq)]
+  struct Window {
+    size: Size,
+  }
+
+  #[cfg(feature = "field-path")]
+  #[derive(Debug, Serialize, Deserialize, PartialEq)]
+  struct Size {
+    width: u32,
+    height: u32,
+  }
+
+  #[cfg(feature = "field-path")]
+  #[derive(Debug, Serialize, Deserialize, PartialEq)]
+  struct RecentFile {
+    path: String,
+    pinned: bool,
+  }
+
+  #[cfg(feature = "field-path")]
+  #[test]
+  fn field_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(WindowConfig {
+      window: Window {
+        size: Size {
+          width: 800,
+          height: 600,
+        },
+      },
+      recent_files: vec![],
+    }));
+    let manager = AppConfigManager::new(config.clone(), "field_path", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+
+    std::fs::write(
+      &path,
+      "recent_files = []\n[window.size]\nwidth = \"wide\"\nheight = 600\n",
+    )
+    .unwrap();
+    let err = manager.load().unwrap_err();
+    assert_eq!(err.field_path(), Some("window.size.width"));
+
+    std::fs::write(
+      &path,
+      "[window.size]\nwidth = 1\nheight = 1\n\n[[recent_files]]\npath = \"a\"\npinned = true\n\n[[recent_files]]\npath = \"b\"\npinned = \"yes\"\n",
+    )
+    .unwrap();
+    let err = manager.load().unwrap_err();
+    assert_eq!(err.field_path(), Some("recent_files[1].pinned"));
+  }
+
+  struct BincodeFormat;
+
+  impl<T: Serialize + DeserializeOwned> ConfigFormat<T> for BincodeFormat {
+    fn serialize(&self, value: &T) -> Result<Vec<u8>, FormatError> {
+      Ok(bincode::serialize(value)?)
+    }
+
+    fn deserialize(&self, bytes: &[u8]) -> Result<T, FormatError> {
+      Ok(bincode::deserialize(bytes)?)
+    }
+
+    fn extension(&self) -> &str {
+      "bin"
+    }
+  }
+
+  #[test]
+  fn custom_format() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig { window_pos: (7, 8) }));
+    let format: Box<dyn ConfigFormat<MyAppConfig>> = Box::new(BincodeFormat);
+    let manager = AppConfigManager::new(config.clone(), "custom_format", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_format(format)
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    assert_eq!(path.file_name().unwrap(), "app_config.bin");
+
+    manager.save().unwrap();
+    assert_eq!(
+      std::fs::read(&path).unwrap(),
+      bincode::serialize(&MyAppConfig { window_pos: (7, 8) }).unwrap()
+    );
+    *config.borrow_mut() = MyAppConfig::default();
+    manager.load().unwrap();
+    assert_eq!(*config.borrow(), MyAppConfig { window_pos: (7, 8) });
+  }
+
+  #[cfg(feature = "json")]
+  #[test]
+  fn json_format() {
+    let dir = tempfile::tempdir().unwrap();
+    use crate::JsonFormat;
+
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = AppConfigManager::new(config.clone(), "json_format", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_format(JsonFormat)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    assert_eq!(path.file_name().unwrap(), "app_config.json");
+
+    config.borrow_mut().window_pos = (1, 2);
+    manager.save().unwrap();
+    let s = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(
+      serde_json::from_str::<MyAppConfig>(&s).unwrap(),
+      MyAppConfig { window_pos: (1, 2) }
+    );
+    *config.borrow_mut() = MyAppConfig::default();
+    manager.load().unwrap();
+    assert_eq!(*config.borrow(), MyAppConfig { window_pos: (1, 2) });
+  }
+
+  #[cfg(feature = "json")]
+  #[test]
+  fn json_format_rejects_leftover_toml() {
+    let dir = tempfile::tempdir().unwrap();
+    use crate::JsonFormat;
+
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let toml_manager = AppConfigManager::new(config.clone(), "json_leftover", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_auto_saving(false);
+    toml_manager.save().unwrap();
+
+    let json_manager = AppConfigManager::new(config.clone(), "json_leftover", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_format(JsonFormat)
+      .with_auto_saving(false);
+    let err = json_manager.load().unwrap_err();
+    assert!(matches!(err, AppConfigError::FormatMismatch { .. }));
+    assert!(err.to_string().contains("app_config.toml"));
+  }
+
+  #[cfg(feature = "json")]
+  #[test]
+  fn format_autodetect() {
+    let dir = tempfile::tempdir().unwrap();
+    use crate::JsonFormat;
+
+    let config = Rc::from(RefCell::from(MyAppConfig { window_pos: (3, 4) }));
+    let json_manager =
+      AppConfigManager::new(config.clone(), "format_autodetect", "sumibi-yakitori")
+        .with_base_dir(dir.path())
+        .with_format(JsonFormat)
+        .with_auto_saving(false);
+    json_manager.save().unwrap();
+    let json_path = json_manager.get_user_config_path().unwrap();
+
+    let mut manager = AppConfigManager::new(config.clone(), "format_autodetect", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_format_autodetect(true)
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    *config.borrow_mut() = MyAppConfig::default();
+    manager.load().unwrap();
+    assert_eq!(*config.borrow(), MyAppConfig { window_pos: (3, 4) });
+
+    // Both exist: the configured format wins
+    config.borrow_mut().window_pos = (5, 6);
+    manager.save().unwrap();
+    config.borrow_mut().window_pos = (0, 0);
+    manager.load().unwrap();
+    assert_eq!(*config.borrow(), MyAppConfig { window_pos: (5, 6) });
+    assert!(json_path.exists());
+
+    manager.set_remove_other_formats(true);
+    manager.save().unwrap();
+    assert!(!json_path.exists());
+  }
+
+  #[cfg(feature = "json")]
+  #[test]
+  fn format_autodetect_sniffs_content() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = AppConfigManager::new(config.clone(), "format_sniffing", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_format_autodetect(true)
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let json_path = manager
+      .get_user_config_path()
+      .unwrap()
+      .with_extension("json");
+    std::fs::write(&json_path, "window_pos = [9, 9]\n").unwrap();
+
+    manager.load().unwrap();
+    assert_eq!(*config.borrow(), MyAppConfig { window_pos: (9, 9) });
+  }
+
+  #[cfg(feature = "json")]
+  #[test]
+  fn convert_format() {
+    let dir = tempfile::tempdir().unwrap();
+    use crate::{JsonFormat, TomlFormat};
+
+    let config = Rc::from(RefCell::from(MyAppConfig {
+      window_pos: (10, 20),
+    }));
+    let manager = AppConfigManager::new(config.clone(), "convert_format", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    assert!(!manager.convert_format(JsonFormat, TomlFormat).unwrap());
+
+    let json_manager = AppConfigManager::new(config.clone(), "convert_format", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_format(JsonFormat)
+      .with_auto_saving(false);
+    json_manager.save().unwrap();
+    let json_path = json_manager.get_user_config_path().unwrap();
+
+    assert!(manager.convert_format(JsonFormat, TomlFormat).unwrap());
+    assert!(!json_path.exists());
+    *config.borrow_mut() = MyAppConfig::default();
+    manager.load().unwrap();
+    assert_eq!(
+      *config.borrow(),
+      MyAppConfig {
+        window_pos: (10, 20)
+      }
+    );
+  }
+
+  #[cfg(feature = "json5")]
+  #[test]
+  fn lenient_json() {
+    let dir = tempfile::tempdir().unwrap();
+    use crate::JsonFormat;
+
+    let config = Rc::from(RefCell::from(MyAppConfig::default()));
+    let manager = AppConfigManager::new(config.clone(), "lenient_json", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_format(JsonFormat)
+      .with_lenient_json(true)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    std::fs::write(
+      &path,
+      "{\n  // moved to the second monitor\n  window_pos: [1920, 280,],\n}\n",
+    )
+    .unwrap();
+
+    manager.load().unwrap();
+    assert_eq!(
+      *config.borrow(),
+      MyAppConfig {
+        window_pos: (1920, 280)
+      }
+    );
+
+    manager.save().unwrap();
+    let s = std::fs::read_to_string(&path).unwrap();
+    assert!(serde_json::from_str::<MyAppConfig>(&s).is_ok());
+
+    // Still broken after the lenient attempt: skipped as before
+    std::fs::write(&path, "{ window_pos: [1, ").unwrap();
+    manager.load().unwrap();
+    assert_eq!(config.borrow().window_pos, (1920, 280));
+  }
+
+  #[cfg(feature = "yaml")]
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+  struct Shortcut {
+    name: String,
+    keys: Vec<String>,
+    modifiers: Modifiers,
+  }
+
+  #[cfg(feature = "yaml")]
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+  struct Modifiers {
+    ctrl: bool,
+    shift: bool,
+  }
+
+  #[cfg(feature = "yaml")]
+  fn shortcuts() -> Vec<Shortcut> {
+    vec![
+      Shortcut {
+        name: "save".into(),
+        keys: vec!["S".into()],
+        modifiers: Modifiers {
+          ctrl: true,
+          shift: false,
+        },
+      },
+      Shortcut {
+        name: "redo".into(),
+        keys: vec!["Z".into(), "Y".into()],
+        modifiers: Modifiers {
+          ctrl: true,
+          shift: true,
+        },
+      },
+    ]
+  }
+
+  #[cfg(feature = "yaml")]
+  #[test]
+  fn yaml_format_top_level_sequence() {
+    let dir = tempfile::tempdir().unwrap();
+    use crate::YamlFormat;
+
+    let config = Rc::from(RefCell::from(shortcuts()));
+    let manager = AppConfigManager::new(config.clone(), "yaml_sequence", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_format(YamlFormat)
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    assert_eq!(path.file_name().unwrap(), "app_config.yaml");
+
+    manager.save().unwrap();
+    config.borrow_mut().clear();
+    manager.load().unwrap();
+    assert_eq!(*config.borrow(), shortcuts());
+  }
+
+  #[cfg(feature = "yaml")]
+  #[test]
+  fn yaml_format_loads_yml() {
+    let dir = tempfile::tempdir().unwrap();
+    use crate::YamlFormat;
+
+    let config = Rc::from(RefCell::from(Vec::<Shortcut>::new()));
+    let manager = AppConfigManager::new(config.clone(), "yaml_yml", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_format(YamlFormat)
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let path = manager
+      .get_user_config_path()
+      .unwrap()
+      .with_extension("yml");
+    std::fs::write(&path, serde_yaml::to_string(&shortcuts()).unwrap()).unwrap();
+
+    manager.load().unwrap();
+    assert_eq!(*config.borrow(), shortcuts());
+  }
+
+  #[cfg(feature = "ron")]
+  #[derive(Debug, Serialize, Deserialize, PartialEq)]
+  enum Theme {
+    System,
+    Custom { accent: (u8, u8, u8) },
+  }
+
+  #[cfg(feature = "ron")]
+  #[test]
+  fn ron_format_enum() {
+    let dir = tempfile::tempdir().unwrap();
+    use crate::RonFormat;
+
+    // TOML has no representation for a bare unit variant
+    assert!(toml::to_string(&Theme::System).is_err());
+
+    let config = Rc::from(RefCell::from(Theme::System));
+    let manager = AppConfigManager::new(config.clone(), "ron_enum", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_format(RonFormat)
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    assert_eq!(path.file_name().unwrap(), "app_config.ron");
+
+    manager.save().unwrap();
+    *config.borrow_mut() = Theme::Custom { accent: (0, 0, 0) };
+    manager.load().unwrap();
+    assert_eq!(*config.borrow(), Theme::System);
+
+    *config.borrow_mut() = Theme::Custom {
+      accent: (255, 128, 0),
+    };
+    manager.save().unwrap();
+    // Pretty-printed so that it can be edited by hand
+    assert!(std::fs::read_to_string(&path).unwrap().contains('\n'));
+    *config.borrow_mut() = Theme::System;
+    manager.load().unwrap();
+    assert_eq!(
+      *config.borrow(),
+      Theme::Custom {
+        accent: (255, 128, 0)
+      }
+    );
   }
 }
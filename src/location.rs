@@ -0,0 +1,262 @@
+use std::path::PathBuf;
+
+/// The platform directory the configuration file is stored in, see
+/// [`AppConfigManager::with_location`](crate::AppConfigManager::with_location).
+///
+/// | Location | Linux                                     | macOS                               | Windows                     |
+/// | -------- | ----------------------------------------- | ----------------------------------- | --------------------------- |
+/// | Config   | `$XDG_CONFIG_HOME` or `$HOME`/.config     | `$HOME`/Library/Application Support | `{FOLDERID_RoamingAppData}` |
+/// | Data     | `$XDG_DATA_HOME` or `$HOME`/.local/share  | `$HOME`/Library/Application Support | `{FOLDERID_RoamingAppData}` |
+/// | Cache    | `$XDG_CACHE_HOME` or `$HOME`/.cache       | `$HOME`/Library/Caches              | `{FOLDERID_LocalAppData}`   |
+/// | State    | `$XDG_STATE_HOME` or `$HOME`/.local/state | `$HOME`/Library/Application Support | `{FOLDERID_LocalAppData}`   |
+///
+/// On iOS, `Cache` is `Library/Caches` and the others are `Library/Application Support`
+/// in the sandbox of the application. On Android, `Cache` is the cache directory and the others
+/// are the files directory of the application, found with the `android` feature; without it,
+/// pass the directory from `Context.getFilesDir()` to
+/// [`with_base_dir`](crate::AppConfigManager::with_base_dir).
+///
+/// Managers with different locations share the `com.{organization_name}.{app_name}` folder name,
+/// so preferences and volatile state can be kept apart:
+///
+/// ```rust,no_run
+/// use appconfig::{AppConfigManager, Location};
+/// use std::{cell::RefCell, collections::HashMap, rc::Rc};
+///
+/// let preferences = Rc::new(RefCell::new(HashMap::<String, String>::new()));
+/// let state = Rc::new(RefCell::new(HashMap::<String, u32>::new()));
+/// // ~/.config/com.sumibi-yakitori.my_app/app_config.toml
+/// let preferences = AppConfigManager::new(preferences, "my_app", "sumibi-yakitori");
+/// // ~/.local/state/com.sumibi-yakitori.my_app/app_config.toml
+/// let state = AppConfigManager::new(state, "my_app", "sumibi-yakitori").with_location(Location::State);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Location {
+  /// User preferences (default).
+  #[default]
+  Config,
+  /// Data created by the application that the user would want to keep.
+  Data,
+  /// Data that can be deleted at any time, such as thumbnails.
+  Cache,
+  /// Data that should survive a restart but is not worth backing up,
+  /// such as window geometry or the list of recent files.
+  State,
+}
+
+/// The Windows profile folder used for [`Location::Config`] and [`Location::Data`], see
+/// [`AppConfigManager::with_windows_profile`](crate::AppConfigManager::with_windows_profile).
+///
+/// | Profile | Windows                     | Example                        |
+/// | ------- | --------------------------- | ------------------------------ |
+/// | Roaming | `{FOLDERID_RoamingAppData}` | C:\Users\Alice\AppData\Roaming |
+/// | Local   | `{FOLDERID_LocalAppData}`   | C:\Users\Alice\AppData\Local   |
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum WindowsProfile {
+  /// Follows the user to other machines in a domain with roaming profiles (default).
+  #[default]
+  Roaming,
+  /// Stays on this machine.
+  Local,
+}
+
+/// Where the file for [`Location::Config`] is stored on macOS, see
+/// [`AppConfigManager::with_macos_style`](crate::AppConfigManager::with_macos_style).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MacosStyle {
+  /// `~/Library/Application Support/com.{organization_name}.{app_name}/app_config.toml` (default)
+  #[default]
+  ApplicationSupport,
+  /// `~/Library/Preferences/com.{organization_name}.{app_name}.toml`, the location expected by
+  /// MDM tooling. A file name other than `app_config` is appended, e.g. `com.example.app.ui.toml`.
+  Preferences,
+}
+
+/// The machine-wide directory read by the system fallback.
+pub(crate) fn system_dir() -> Option<PathBuf> {
+  if cfg!(windows) {
+    Some(
+      std::env::var_os("ProgramData")
+        .map_or_else(|| PathBuf::from("C:\\ProgramData"), PathBuf::from),
+    )
+  }
+  else if cfg!(target_os = "macos") {
+    Some(PathBuf::from("/Library/Application Support"))
+  }
+  // The sandbox has no machine-wide directory
+  else if cfg!(any(target_os = "android", target_os = "ios")) {
+    None
+  }
+  else if cfg!(unix) {
+    Some(PathBuf::from("/etc"))
+  }
+  else {
+    None
+  }
+}
+
+/// `~/Library/Preferences`
+pub(crate) fn macos_preferences_dir() -> Option<PathBuf> {
+  dirs_next::home_dir().map(|home| home.join("Library/Preferences"))
+}
+
+impl Location {
+  /// The platform directory for this location.
+  #[cfg(not(any(target_os = "android", target_os = "ios")))]
+  pub(crate) fn dir(self, windows_profile: WindowsProfile) -> Option<PathBuf> {
+    let roaming = matches!(self, Location::Config | Location::Data);
+    if cfg!(windows) && roaming && windows_profile == WindowsProfile::Local {
+      return dirs_next::data_local_dir();
+    }
+    match self {
+      Location::Config => dirs_next::config_dir(),
+      Location::Data => dirs_next::data_dir(),
+      Location::Cache => dirs_next::cache_dir(),
+      Location::State => state_dir(),
+    }
+  }
+
+  /// `$HOME` is the sandbox of the application on iOS.
+  #[cfg(target_os = "ios")]
+  pub(crate) fn dir(self, _windows_profile: WindowsProfile) -> Option<PathBuf> {
+    let library = PathBuf::from(std::env::var_os("HOME")?).join("Library");
+    Some(match self {
+      Location::Cache => library.join("Caches"),
+      _ => library.join("Application Support"),
+    })
+  }
+
+  #[cfg(target_os = "android")]
+  pub(crate) fn dir(self, _windows_profile: WindowsProfile) -> Option<PathBuf> {
+    #[cfg(feature = "android")]
+    {
+      let cmdline = std::fs::read("/proc/self/cmdline").ok()?;
+      let status = std::fs::read_to_string("/proc/self/status").ok()?;
+      let dir = android_app_dir(&cmdline, &status)?;
+      Some(match self {
+        Location::Cache => dir.join("cache"),
+        _ => dir.join("files"),
+      })
+    }
+    #[cfg(not(feature = "android"))]
+    None
+  }
+}
+
+/// The data directory of the Android application running as the process with `cmdline`
+/// and `status`, e.g. `/data/user/0/com.example.app`.
+/// The package name is the process name, and the Android user ID is the Unix user ID / 100000.
+#[cfg(any(
+  all(test, target_os = "linux"),
+  all(target_os = "android", feature = "android")
+))]
+fn android_app_dir(cmdline: &[u8], status: &str) -> Option<PathBuf> {
+  let name = cmdline.split(|&x| x == 0).next()?;
+  // `com.example.app:service` for a service in a separate process
+  let package = std::str::from_utf8(name).ok()?.split(':').next()?;
+  if package.is_empty() || package.contains('/') {
+    return None;
+  }
+  let uid: u32 = status
+    .lines()
+    .find_map(|x| x.strip_prefix("Uid:"))?
+    .split_whitespace()
+    .next()?
+    .parse()
+    .ok()?;
+  Some(PathBuf::from(format!(
+    "/data/user/{}/{}",
+    uid / 100_000,
+    package
+  )))
+}
+
+/// `$XDG_STATE_HOME`, or `$HOME/.local/state` when it is unset, empty or not an absolute path.
+#[cfg(all(
+  unix,
+  not(any(target_os = "macos", target_os = "android", target_os = "ios"))
+))]
+fn state_dir() -> Option<PathBuf> {
+  xdg_state_dir(std::env::var_os("XDG_STATE_HOME"), dirs_next::home_dir())
+}
+
+/// `state_home` if it is an absolute path, otherwise `home/.local/state`.
+#[cfg(all(
+  unix,
+  not(any(target_os = "macos", target_os = "android", target_os = "ios"))
+))]
+fn xdg_state_dir(state_home: Option<std::ffi::OsString>, home: Option<PathBuf>) -> Option<PathBuf> {
+  state_home
+    .map(PathBuf::from)
+    .filter(|path| path.is_absolute())
+    .or_else(|| home.map(|home| home.join(".local/state")))
+}
+
+#[cfg(any(target_os = "macos", not(unix)))]
+fn state_dir() -> Option<PathBuf> {
+  dirs_next::data_local_dir()
+}
+
+#[cfg(all(test, windows))]
+mod windows_tests {
+  use super::{Location, WindowsProfile};
+
+  #[test]
+  fn windows_profiles() {
+    let roaming = Location::Config.dir(WindowsProfile::Roaming).unwrap();
+    let local = Location::Config.dir(WindowsProfile::Local).unwrap();
+    assert!(roaming.ends_with("AppData\\Roaming"));
+    assert!(local.ends_with("AppData\\Local"));
+    assert_eq!(Location::Data.dir(WindowsProfile::Local).unwrap(), local);
+    assert_eq!(Location::Cache.dir(WindowsProfile::Roaming).unwrap(), local);
+  }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+  use super::{Location, WindowsProfile};
+  use std::path::PathBuf;
+
+  #[test]
+  fn android_app_dir() {
+    let status = "Name:\tcom.example.app\nUid:\t1010123\t1010123\t1010123\t1010123\n";
+    assert_eq!(
+      super::android_app_dir(b"com.example.app:sync\0", status),
+      Some(PathBuf::from("/data/user/10/com.example.app"))
+    );
+    assert_eq!(super::android_app_dir(b"/system/bin/sh\0", status), None);
+    assert_eq!(
+      super::android_app_dir(b"com.example.app\0", "Name:\tx\n"),
+      None
+    );
+  }
+
+  #[test]
+  fn xdg_locations() {
+    // The environment is read as is, without changing it for the other tests
+    for (location, dir) in [
+      (Location::Config, dirs_next::config_dir()),
+      (Location::Data, dirs_next::data_dir()),
+      (Location::Cache, dirs_next::cache_dir()),
+      (Location::State, super::state_dir()),
+    ] {
+      assert_eq!(location.dir(WindowsProfile::Roaming), dir);
+      // No effect on other platforms
+      assert_eq!(location.dir(WindowsProfile::Local), dir);
+    }
+
+    let state = PathBuf::from("/var/tmp/state");
+    assert_eq!(
+      super::xdg_state_dir(Some(state.clone().into()), None),
+      Some(state)
+    );
+    // Unset, empty or relative values fall back to `$HOME/.local/state`, as the XDG spec requires
+    let home = PathBuf::from("/home/alice");
+    for value in [None, Some(""), Some("relative/state")] {
+      assert_eq!(
+        super::xdg_state_dir(value.map(Into::into), Some(home.clone())),
+        Some(home.join(".local/state"))
+      );
+    }
+  }
+}
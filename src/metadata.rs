@@ -0,0 +1,96 @@
+use crate::{shared::lock, AppConfigError, AppConfigManager, Result, SharedData};
+use serde::{de::DeserializeOwned, Serialize};
+use std::time::SystemTime;
+
+/// The configuration file as found on disk, returned by [`AppConfigManager::file_metadata`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ConfigFileMetadata {
+  /// When the file was last written by anyone. `None` with [`with_store`](AppConfigManager::with_store)
+  /// or when the platform does not record it.
+  pub modified: Option<SystemTime>,
+  /// The size in bytes.
+  pub len: u64,
+  /// Whether the file is read-only, which makes `save()` fail on Windows.
+  pub read_only: bool,
+}
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// The metadata of the file at [`config_path`](Self::config_path), or `None` if it does not exist yet.
+  ///
+  /// Unlike [`last_saved_at`](Self::last_saved_at), this includes changes made by other programs,
+  /// e.g. to find out which of two synced copies is newer.
+  pub fn file_metadata(&self) -> Result<Option<ConfigFileMetadata>> {
+    self.drain_autosave().ok();
+    let path = self.config_path()?;
+    if self.uses_store() {
+      return match self.read_stored(&path) {
+        Ok(s) => Ok(Some(ConfigFileMetadata {
+          modified: None,
+          len: s.len() as u64,
+          read_only: false,
+        })),
+        Err(err) if err.is_not_found() => Ok(None),
+        Err(err) => Err(err),
+      };
+    }
+    match std::fs::metadata(&path) {
+      Ok(metadata) => Ok(Some(ConfigFileMetadata {
+        modified: metadata.modified().ok(),
+        len: metadata.len(),
+        read_only: metadata.permissions().readonly(),
+      })),
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+      Err(err) => Err(AppConfigError::io(path)(err)),
+    }
+  }
+
+  /// When the file was last loaded into the data by this manager.
+  pub fn last_loaded_at(&self) -> Option<SystemTime> {
+    *lock(&self.last_loaded_at)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::TestConfigDir;
+  use serde::{Deserialize, Serialize};
+  use std::{cell::RefCell, rc::Rc};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Theme {
+    dark: bool,
+  }
+
+  #[test]
+  fn file_metadata() {
+    let dir = TestConfigDir::new().unwrap();
+    let config = Rc::from(RefCell::from(Theme::default()));
+    let manager = dir
+      .manager(config, "metadata", "sumibi-yakitori")
+      .with_auto_saving(false);
+    assert_eq!(manager.file_metadata().unwrap(), None);
+    assert_eq!(manager.last_loaded_at(), None);
+
+    manager.save().unwrap();
+    let metadata = manager.file_metadata().unwrap().unwrap();
+    assert_eq!(metadata.len, "dark = false\n".len() as u64);
+    assert!(!metadata.read_only);
+    assert!(metadata.modified.is_some());
+    assert!(manager.last_saved_at().is_some());
+    assert_eq!(manager.last_loaded_at(), None);
+
+    manager.load().unwrap();
+    assert!(manager.last_loaded_at().unwrap() >= manager.last_saved_at().unwrap());
+
+    let path = manager.config_path().unwrap();
+    let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+    permissions.set_readonly(true);
+    std::fs::set_permissions(&path, permissions).unwrap();
+    assert!(manager.file_metadata().unwrap().unwrap().read_only);
+  }
+}
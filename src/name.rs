@@ -0,0 +1,373 @@
+use crate::{nfc::nfc, AppConfigError, AppConfigManager, Result, SharedData};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Characters that Windows does not allow in file names, besides control characters.
+const RESERVED_CHARS: &[char] = &['/', '\\', '<', '>', ':', '"', '|', '?', '*'];
+
+/// Names that Windows reserves for devices, also with an extension, e.g. `nul.txt`.
+const RESERVED_NAMES: &[&str] = &[
+  "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+  "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// How the folder of the application is named, see [`AppConfigManager::with_naming`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Naming {
+  /// `{qualifier}.{organization_name}.{app_name}`, e.g. `com.example.my_app` (default)
+  #[default]
+  ReverseDns,
+  /// `{app_name}`, e.g. `~/.config/my_app` like most Unix tools.
+  /// The qualifier and organization name are not used.
+  AppNameOnly,
+}
+
+/// A previous identity added with `with_legacy_identity`, `with_legacy_qualifier` or `with_legacy_naming`.
+/// `None` stands for the current value.
+pub(crate) struct LegacyIdentity {
+  pub(crate) naming: Option<Naming>,
+  pub(crate) qualifier: Option<String>,
+  pub(crate) app_name: Option<String>,
+  pub(crate) organization_name: Option<String>,
+}
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// Replaces what cannot be used in the application and organization names instead of failing
+  /// with [`AppConfigError::InvalidName`]:
+  ///
+  /// - `/`, `\`, `<`, `>`, `:`, `"`, `|`, `?`, `*` and control characters become `_`
+  /// - `..` becomes `.`
+  /// - Leading and trailing dots and spaces are removed
+  /// - `_` is appended to names reserved by Windows, e.g. `CON` becomes `CON_` and `nul.txt` `nul_.txt`
+  /// - An empty name becomes `_`
+  pub fn set_lenient_names(&mut self, value: bool) -> &mut Self {
+    self.lenient_names = value;
+    self
+  }
+
+  pub fn with_lenient_names(mut self, value: bool) -> Self {
+    self.set_lenient_names(value);
+    self
+  }
+
+  /// How the folder of the application is named, [`Naming::ReverseDns`] by default.
+  ///
+  /// With [`Naming::AppNameOnly`], managers with the same application name share the folder,
+  /// e.g. `~/.config/my_app/prefs.toml` and `~/.config/my_app/state.toml` with `with_file_name`.
+  /// [`with_legacy_naming`](Self::with_legacy_naming) reads the file from the folder of the other mode.
+  pub fn set_naming(&mut self, value: Naming) -> &mut Self {
+    self.naming = value;
+    self
+  }
+
+  pub fn with_naming(mut self, value: Naming) -> Self {
+    self.set_naming(value);
+    self
+  }
+
+  /// Like `add_legacy_identity`, for the file saved with the current names in the folder of another
+  /// [`Naming`], e.g. `com.example.my_app` after switching to [`Naming::AppNameOnly`], or the other way around.
+  ///
+  /// Legacy identities, qualifiers and namings only bridge between folders next to each other,
+  /// so they are not used without the application folder (`with_fqdn_subfolder(false)`),
+  /// in portable mode, or with `with_env_override`.
+  pub fn add_legacy_naming(&mut self, naming: Naming) -> &mut Self {
+    self.legacy_identities.push(LegacyIdentity {
+      naming: Some(naming),
+      qualifier: None,
+      app_name: None,
+      organization_name: None,
+    });
+    self
+  }
+
+  pub fn with_legacy_naming(mut self, naming: Naming) -> Self {
+    self.add_legacy_naming(naming);
+    self
+  }
+
+  /// Fails if a name used in the folder name cannot be used there, unless `with_lenient_names` is set.
+  pub(crate) fn check_names(&self) -> Result {
+    if self.lenient_names {
+      return Ok(());
+    }
+    let mut names = vec![&self.app_name];
+    if self.naming == Naming::ReverseDns {
+      // A blank organization name or qualifier is left out of the folder name
+      let optional = [&self.organization_name, &self.qualifier];
+      names.extend(optional.iter().filter(|name| !is_blank(name)));
+    }
+    for name in names {
+      let normalized = nfc(name);
+      if let Err(reason) = check_name(&normalized) {
+        return Err(AppConfigError::InvalidName {
+          name: name.clone(),
+          reason: reason.to_owned(),
+        });
+      }
+    }
+    Ok(())
+  }
+
+  /// The folder name `{qualifier}.{organization_name}.{app_name}`, without the qualifier and
+  /// organization name when they are blank, or `{app_name}` with `Naming::AppNameOnly`.
+  pub(crate) fn folder_name(
+    &self,
+    naming: Naming,
+    qualifier: &str,
+    organization_name: &str,
+    app_name: &str,
+  ) -> String {
+    if naming == Naming::AppNameOnly {
+      return self.folder_name_part(app_name);
+    }
+    [qualifier, organization_name]
+      .iter()
+      .filter(|name| !is_blank(name))
+      .chain(&[app_name])
+      .map(|name| self.folder_name_part(name))
+      .collect::<Vec<_>>()
+      .join(".")
+  }
+
+  /// The folder names of the legacy identities in order, with their qualifier, organization and application names.
+  pub(crate) fn legacy_folder_names(
+    &self,
+  ) -> impl Iterator<Item = (String, &String, &String, &String)> + '_ {
+    self.legacy_identities.iter().map(move |identity| {
+      let naming = identity.naming.unwrap_or(self.naming);
+      let qualifier = identity.qualifier.as_ref().unwrap_or(&self.qualifier);
+      let organization_name = identity
+        .organization_name
+        .as_ref()
+        .unwrap_or(&self.organization_name);
+      let app_name = identity.app_name.as_ref().unwrap_or(&self.app_name);
+      (
+        self.folder_name(naming, qualifier, organization_name, app_name),
+        qualifier,
+        organization_name,
+        app_name,
+      )
+    })
+  }
+
+  /// `name` as it appears in the folder name: NFC normalized and sanitized with `with_lenient_names`.
+  pub(crate) fn folder_name_part(&self, name: &str) -> String {
+    let name = nfc(name);
+    if self.lenient_names {
+      sanitize_name(&name)
+    }
+    else {
+      name.into_owned()
+    }
+  }
+}
+
+/// Why `name` cannot be used in a folder name on every platform.
+pub(crate) fn check_name(name: &str) -> std::result::Result<(), &'static str> {
+  if name.is_empty() {
+    return Err("the name is empty");
+  }
+  if name.contains(RESERVED_CHARS) || name.contains(char::is_control) {
+    return Err("the name contains a path separator or a character reserved by Windows");
+  }
+  if name.contains("..") {
+    return Err("the name contains `..`");
+  }
+  if name.starts_with(['.', ' ']) || name.ends_with(['.', ' ']) {
+    return Err("the name starts or ends with a dot or a space");
+  }
+  if is_reserved(name) {
+    return Err("the name is reserved by Windows");
+  }
+  Ok(())
+}
+
+/// `name` changed as documented for `with_lenient_names`, so that `check_name` accepts it.
+pub(crate) fn sanitize_name(name: &str) -> String {
+  let mut name: String = name
+    .chars()
+    .map(|c| {
+      if RESERVED_CHARS.contains(&c) || c.is_control() {
+        '_'
+      }
+      else {
+        c
+      }
+    })
+    .collect();
+  while name.contains("..") {
+    name = name.replace("..", ".");
+  }
+  let mut name = name.trim_matches(['.', ' ']).to_owned();
+  if name.is_empty() {
+    name.push('_');
+  }
+  if is_reserved(&name) {
+    name.insert(name.find('.').unwrap_or(name.len()), '_');
+  }
+  name
+}
+
+fn is_blank(name: &str) -> bool {
+  name.trim().is_empty()
+}
+
+fn is_reserved(name: &str) -> bool {
+  let stem = name.split('.').next().unwrap_or_default().trim_end();
+  RESERVED_NAMES
+    .iter()
+    .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{check_name, sanitize_name};
+  use crate::{AppConfigError, AppConfigManager, Naming, TestConfigDir};
+  use std::{cell::RefCell, rc::Rc};
+
+  #[test]
+  fn check_names() {
+    for name in ["my_app", "My App", "設定", "v1.2"] {
+      assert_eq!(check_name(name), Ok(()), "{}", name);
+    }
+    for name in [
+      "",
+      "../../evil",
+      "..",
+      "a/b",
+      "a\\b",
+      "my app?",
+      "a:b",
+      ".hidden",
+      "app.",
+      " app",
+      "CON",
+      "nul.txt",
+      "Com1",
+      "tab\t",
+    ] {
+      assert!(check_name(name).is_err(), "{:?}", name);
+      assert_eq!(check_name(&sanitize_name(name)), Ok(()), "{:?}", name);
+    }
+    assert_eq!(sanitize_name("../../evil"), "_._evil");
+    assert_eq!(sanitize_name("my app?"), "my app_");
+    assert_eq!(sanitize_name("CON"), "CON_");
+    assert_eq!(sanitize_name("nul.txt"), "nul_.txt");
+    assert_eq!(sanitize_name(" . "), "_");
+  }
+
+  #[test]
+  fn path_traversal() {
+    let dir = TestConfigDir::new().unwrap();
+    let config = Rc::new(RefCell::new(vec![1u8]));
+    let manager = AppConfigManager::new(config.clone(), "../../evil", "sumibi-yakitori")
+      .with_base_dir(dir.path().join("base"))
+      .with_auto_saving(false);
+    assert!(matches!(
+      manager.config_path(),
+      Err(AppConfigError::InvalidName { name, .. }) if name == "../../evil"
+    ));
+    assert!(matches!(
+      manager.save(),
+      Err(AppConfigError::InvalidName { .. })
+    ));
+
+    let manager = manager.with_lenient_names(true);
+    let path = manager.config_path().unwrap();
+    assert_eq!(
+      path,
+      dir
+        .path()
+        .join("base/com.sumibi-yakitori._._evil/app_config.toml")
+    );
+
+    // Decomposed names are the same folder as composed ones
+    let manager = AppConfigManager::new(config, "Cafe\u{301}", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_auto_saving(false);
+    assert_eq!(
+      manager.config_dir().unwrap(),
+      dir.path().join("com.sumibi-yakitori.Caf\u{e9}")
+    );
+  }
+
+  #[test]
+  fn naming() {
+    #[derive(Default, serde::Serialize, serde::Deserialize)]
+    struct Tool {
+      verbose: bool,
+    }
+
+    let dir = TestConfigDir::new().unwrap();
+    let legacy = dir.manager(
+      Rc::new(RefCell::new(Tool { verbose: true })),
+      "mytool",
+      "sumibi-yakitori",
+    );
+    legacy.save().unwrap();
+
+    let prefs = Rc::new(RefCell::new(Tool::default()));
+    let manager = |data: Rc<RefCell<Tool>>, file_name| {
+      AppConfigManager::new(data, "mytool", "")
+        .with_base_dir(dir.path())
+        .with_naming(Naming::AppNameOnly)
+        .with_file_name(file_name)
+        .with_auto_saving(false)
+    };
+    let prefs_manager = manager(prefs.clone(), "app_config")
+      .with_organization_name("sumibi-yakitori")
+      .with_legacy_naming(Naming::ReverseDns);
+    let state_manager = manager(Rc::new(RefCell::new(Tool::default())), "state");
+    assert_eq!(
+      prefs_manager.config_path().unwrap(),
+      dir.path().join("mytool/app_config.toml")
+    );
+    assert_eq!(
+      state_manager.config_path().unwrap(),
+      dir.path().join("mytool/state.toml")
+    );
+
+    prefs_manager.load().unwrap();
+    assert!(prefs.borrow().verbose);
+    prefs_manager.save().unwrap();
+    state_manager.save().unwrap();
+    assert_eq!(
+      std::fs::read_dir(dir.path().join("mytool"))
+        .unwrap()
+        .count(),
+      2
+    );
+  }
+
+  #[test]
+  fn blank_names() {
+    let dir = TestConfigDir::new().unwrap();
+    let folder = |qualifier: &str, organization_name: &str, naming| {
+      let manager = AppConfigManager::new(Rc::new(RefCell::new(())), "myapp", organization_name)
+        .with_base_dir(dir.path())
+        .with_qualifier(qualifier)
+        .with_naming(naming)
+        .with_auto_saving(false);
+      let path = manager.config_dir().unwrap();
+      path
+        .strip_prefix(dir.path())
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_owned()
+    };
+    let reverse_dns = Naming::ReverseDns;
+    assert_eq!(folder("com", "org", reverse_dns), "com.org.myapp");
+    assert_eq!(folder("com", "", reverse_dns), "com.myapp");
+    assert_eq!(folder("com", "  ", reverse_dns), "com.myapp");
+    assert_eq!(folder("", "org", reverse_dns), "org.myapp");
+    assert_eq!(folder(" ", "org", reverse_dns), "org.myapp");
+    assert_eq!(folder("", "", reverse_dns), "myapp");
+    assert_eq!(folder("com", "", Naming::AppNameOnly), "myapp");
+    assert_eq!(folder("", "", Naming::AppNameOnly), "myapp");
+  }
+}
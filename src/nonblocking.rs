@@ -0,0 +1,247 @@
+use crate::{store, AppConfigError, AppConfigManager, Result, SharedData};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+  future::Future,
+  pin::Pin,
+  sync::{Arc, Mutex},
+  task::{Context, Poll, Waker},
+};
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// Like `load()`, but looks for the file, copies it into the sandbox with
+  /// `with_sandbox_migration`, locks, reads it and checks its permissions on another thread.
+  ///
+  /// The file is parsed on the calling thread once it was read.
+  /// Works with any async runtime, e.g. tokio. Each call starts a new thread.
+  pub async fn load_async(&self) -> Result {
+    let result = async {
+      let host_copy = self.host_copy()?;
+      let locate = self.locate_job()?;
+      let permission_check = self.permission_check();
+      let lock_timeout = self.file_lock_timeout();
+      let limit = self.max_file_size;
+      let store = self.shared_store();
+      let (path, s, detected, legacy, warning) = spawn_blocking(move || {
+        if let Some(copy) = host_copy {
+          copy.run()?;
+        }
+        let (key, detected, legacy) = locate.run()?;
+        event!(Debug, "Loading {}", key.path().display());
+        let s = {
+          let _lock = crate::lock_file(key.path(), false, lock_timeout)?;
+          store::read_from(store.as_ref(), &key, limit)?
+        };
+        let warning = match permission_check {
+          Some(check) => check.run(key.path())?,
+          None => None,
+        };
+        Ok::<_, AppConfigError>((key.path().to_owned(), s, detected, legacy, warning))
+      })
+      .await?;
+      if let Some(warning) = &warning {
+        self.report_security_warning(warning);
+      }
+      self.load_located(&path, &s, detected, legacy)
+    }
+    .await;
+    match result {
+      Err(err) if self.tolerate_missing_file && err.is_not_found() => Ok(()),
+      result => result.map(|_| ()),
+    }
+  }
+
+  /// Like `save()`, but creates the folder, locks the file, copies the backups and writes the file
+  /// on another thread. Each call starts a new thread.
+  ///
+  /// The data is validated and serialized on the calling thread before the first `await`,
+  /// so later changes are not written. Files removed by `with_remove_other_formats`
+  /// and `with_remove_legacy_file` are removed on the calling thread afterwards.
+  pub async fn save_async(&self) -> Result {
+    let pending = self.prepare_save()?;
+    let (pending, written) = spawn_blocking(move || {
+      let written = pending.job.run(&pending.contents);
+      (pending, written)
+    })
+    .await;
+    match written? {
+      Some(written) => self.finish_save(pending, written),
+      None => Ok(()),
+    }
+  }
+}
+
+type Shared<R> = Arc<Mutex<(Option<std::thread::Result<R>>, Option<Waker>)>>;
+
+/// Runs `f` on a new OS thread, started for every call rather than taken from a pool,
+/// since the runtime is unknown. The returned future completes when `f` returns.
+fn spawn_blocking<R, F>(f: F) -> Blocking<R>
+where
+  R: Send + 'static,
+  F: FnOnce() -> R + Send + 'static,
+{
+  let shared: Shared<R> = Arc::new(Mutex::new((None, None)));
+  {
+    let shared = shared.clone();
+    std::thread::spawn(move || {
+      let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+      let mut shared = shared.lock().unwrap_or_else(|err| err.into_inner());
+      shared.0 = Some(result);
+      if let Some(waker) = shared.1.take() {
+        waker.wake();
+      }
+    });
+  }
+  Blocking { shared }
+}
+
+struct Blocking<R> {
+  shared: Shared<R>,
+}
+
+impl<R> Future for Blocking<R> {
+  type Output = R;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<R> {
+    let mut shared = self.shared.lock().unwrap_or_else(|err| err.into_inner());
+    match shared.0.take() {
+      Some(Ok(value)) => Poll::Ready(value),
+      Some(Err(panic)) => std::panic::resume_unwind(panic),
+      None => {
+        shared.1 = Some(cx.waker().clone());
+        Poll::Pending
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{shared::lock, AppConfigManager, ConfigKey, ConfigStore, MemoryStore};
+  use serde::{Deserialize, Serialize};
+  use std::{
+    cell::RefCell,
+    future::Future,
+    io,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Wake, Waker},
+    thread::ThreadId,
+  };
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Window {
+    x: u32,
+  }
+
+  /// A minimal executor, standing in for an async runtime.
+  fn block_on<F: Future>(future: F) -> F::Output {
+    struct ThreadWaker(std::thread::Thread);
+    impl Wake for ThreadWaker {
+      fn wake(self: Arc<Self>) {
+        self.0.unpark();
+      }
+    }
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+      match future.as_mut().poll(&mut cx) {
+        Poll::Ready(output) => return output,
+        Poll::Pending => std::thread::park(),
+      }
+    }
+  }
+
+  #[test]
+  fn save_and_load_async() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Window { x: 3 }));
+    let manager = AppConfigManager::new(config.clone(), "async", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_auto_saving(false);
+    block_on(manager.save_async()).unwrap();
+    assert_eq!(
+      std::fs::read_to_string(manager.get_user_config_path().unwrap()).unwrap(),
+      "x = 3\n"
+    );
+    assert!(!manager.is_dirty());
+
+    *config.borrow_mut() = Window::default();
+    block_on(manager.load_async()).unwrap();
+    assert_eq!(*config.borrow(), Window { x: 3 });
+  }
+
+  #[test]
+  fn save_async_with_backups() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Window { x: 1 }));
+    let manager = AppConfigManager::new(config.clone(), "async_backups", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_file_lock(true)
+      .with_backup(true)
+      .with_backup_retention(2)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    std::fs::remove_dir(path.parent().unwrap()).unwrap();
+    let future = manager.save_async();
+    // Nothing happens before the future is polled
+    assert!(!path.parent().unwrap().exists());
+    block_on(future).unwrap();
+    for x in 2..=3 {
+      config.borrow_mut().x = x;
+      block_on(manager.save_async()).unwrap();
+    }
+    let read = |suffix: &str| std::fs::read_to_string(path.with_extension(suffix)).unwrap();
+    assert_eq!(read("toml"), "x = 3\n");
+    assert_eq!(read("toml.bak"), "x = 2\n");
+    assert_eq!(read("toml.1"), "x = 2\n");
+    assert_eq!(read("toml.2"), "x = 1\n");
+
+    *config.borrow_mut() = Window::default();
+    block_on(manager.load_async()).unwrap();
+    assert_eq!(*config.borrow(), Window { x: 3 });
+  }
+
+  /// Records the threads that read from it.
+  #[derive(Clone, Default)]
+  struct Recording {
+    files: MemoryStore,
+    readers: Arc<Mutex<Vec<ThreadId>>>,
+  }
+
+  impl ConfigStore for Recording {
+    fn read(&self, key: &ConfigKey) -> io::Result<Option<Vec<u8>>> {
+      lock(&self.readers).push(std::thread::current().id());
+      self.files.read(key)
+    }
+
+    fn write(&self, key: &ConfigKey, contents: &[u8]) -> io::Result<()> {
+      self.files.write(key, contents)
+    }
+
+    fn remove(&self, key: &ConfigKey) -> io::Result<()> {
+      self.files.remove(key)
+    }
+  }
+
+  #[test]
+  fn load_async_off_the_calling_thread() {
+    let store = Recording::default();
+    let config = Rc::from(RefCell::from(Window::default()));
+    let manager = AppConfigManager::new(config.clone(), "async_locate", "sumibi-yakitori")
+      .with_store(store.clone())
+      .with_auto_saving(false);
+    let path = manager.config_path().unwrap();
+    store.files.insert(path, "x = 5\n");
+    block_on(manager.load_async()).unwrap();
+    assert_eq!(*config.borrow(), Window { x: 5 });
+    // The file was looked for before it was read
+    let readers = lock(&store.readers);
+    assert_eq!(readers.len(), 2);
+    assert!(!readers.contains(&std::thread::current().id()));
+  }
+}
@@ -0,0 +1,58 @@
+use crate::AppConfigError;
+use std::path::PathBuf;
+
+/// What happened during [`AppConfigManager::load_with_outcome`](crate::AppConfigManager::load_with_outcome).
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum LoadOutcome {
+  /// The file was parsed and stored into the data.
+  Loaded,
+  /// The file was parsed, repaired by a fixup added with
+  /// [`with_fixup`](crate::AppConfigManager::with_fixup) and stored into the data.
+  /// The data is dirty until it is saved.
+  FixedUp,
+  /// There is no configuration file, but one was saved before, see
+  /// [`is_first_run`](crate::AppConfigManager::is_first_run). The data is unchanged.
+  FileMissing,
+  /// Nothing was ever saved, see [`is_first_run`](crate::AppConfigManager::is_first_run).
+  /// The data is unchanged.
+  FirstRun,
+  /// The file could not be parsed and `skip_parsing_error_when_loading` is enabled.
+  /// The data is unchanged.
+  ParseErrorSkipped(AppConfigError),
+  /// The file could not be parsed and the backup written by `with_backup` was loaded instead.
+  BackupLoaded(AppConfigError),
+  /// The file could not be parsed and was moved to `path` by `with_quarantine_corrupt`.
+  /// The data is unchanged.
+  Quarantined {
+    error: AppConfigError,
+    path: PathBuf,
+  },
+  /// The file was rejected by a validator and
+  /// [`ValidationPolicy::UseDefaults`](crate::ValidationPolicy::UseDefaults) replaced the data with the defaults.
+  DefaultsUsed(AppConfigError),
+  /// The file was copied from `from` outside the Flatpak or Snap sandbox by
+  /// [`with_sandbox_migration`](crate::AppConfigManager::with_sandbox_migration), then loaded.
+  /// The data is dirty if a fixup repaired it.
+  SandboxMigrated { from: PathBuf },
+  /// Portable mode could not use the folder `dir` next to the executable and fell back to the
+  /// platform configuration directory, see
+  /// [`set_portable_fallback`](crate::AppConfigManager::set_portable_fallback).
+  /// `outcome` is what happened there.
+  PortableFallback {
+    dir: PathBuf,
+    outcome: Box<LoadOutcome>,
+  },
+}
+
+/// What happened during [`AppConfigManager::migrate_location`](crate::AppConfigManager::migrate_location).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MigrationOutcome {
+  /// There is no file at the old location.
+  NothingToMigrate,
+  /// The file was moved to the new location.
+  Migrated,
+  /// A file already exists at the new location. Both files were left untouched.
+  DestinationExists,
+}
@@ -0,0 +1,205 @@
+use crate::{AppConfigManager, LoadOutcome, Result, SharedData, SingleThreaded};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{cell::RefCell, path::PathBuf};
+
+/// The data of the manager inside an [`OwnedAppConfigManager`], which only holds the data
+/// while the owner lends it, see [`OwnedAppConfigManager::manage`].
+pub struct Owned<T>(RefCell<T>);
+
+impl<T> SharedData<T> for Owned<T> {
+  type Threading = SingleThreaded;
+
+  fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+    f(&self.0.borrow())
+  }
+
+  fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+    f(&mut self.0.borrow_mut())
+  }
+
+  fn try_read<R>(&self, f: impl FnOnce(&T) -> R) -> Option<R> {
+    Some(f(&*self.0.try_borrow().ok()?))
+  }
+
+  fn try_write<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+    Some(f(&mut *self.0.try_borrow_mut().ok()?))
+  }
+}
+
+/// A manager that owns its data instead of sharing it through an `Rc<RefCell<T>>`,
+/// created with [`AppConfigManager::owned`].
+///
+/// The data is a plain field: `get` borrows it like any other field, and everything that
+/// replaces it, such as `load`, takes `&mut self`, so borrows are checked at compile time.
+/// Other methods of the manager are called through [`manage`](Self::manage).
+/// Dropping it saves the data like dropping an [`AppConfigManager`].
+pub struct OwnedAppConfigManager<T>
+where
+  T: Sized + Serialize + DeserializeOwned + Default,
+{
+  /// Holds a default value except during `manage`.
+  manager: AppConfigManager<T, Owned<T>>,
+  value: T,
+}
+
+impl<T> AppConfigManager<T, Owned<T>>
+where
+  T: Sized + Serialize + DeserializeOwned + Default,
+{
+  /// Creates a manager that owns the data instead of sharing it through an `Rc<RefCell<T>>`.
+  pub fn owned(
+    value: T,
+    app_name: impl Into<String>,
+    organization_name: impl Into<String>,
+  ) -> OwnedAppConfigManager<T> {
+    OwnedAppConfigManager {
+      manager: Self::new(Owned(RefCell::default()), app_name, organization_name),
+      value,
+    }
+  }
+}
+
+impl<T> OwnedAppConfigManager<T>
+where
+  T: Sized + Serialize + DeserializeOwned + Default,
+{
+  /// Configures the manager with its `set_` methods, e.g. `|manager| { manager.set_base_dir(dir); }`.
+  pub fn configure(mut self, f: impl FnOnce(&mut AppConfigManager<T, Owned<T>>)) -> Self {
+    f(Lent::new(&mut self.manager, &mut self.value).manager);
+    self
+  }
+
+  pub fn get(&self) -> &T {
+    &self.value
+  }
+
+  pub fn get_mut(&mut self) -> &mut T {
+    &mut self.value
+  }
+
+  /// Calls `f` with the manager holding the data, e.g. for `restore_from_backup`.
+  /// The data replaced by `f` is kept.
+  pub fn manage<R>(&mut self, f: impl FnOnce(&AppConfigManager<T, Owned<T>>) -> R) -> R {
+    f(Lent::new(&mut self.manager, &mut self.value).manager)
+  }
+
+  pub fn load(&mut self) -> Result {
+    self.manage(AppConfigManager::load)
+  }
+
+  pub fn load_with_outcome(&mut self) -> Result<LoadOutcome> {
+    self.manage(AppConfigManager::load_with_outcome)
+  }
+
+  pub fn save(&mut self) -> Result {
+    self.manage(AppConfigManager::save)
+  }
+
+  pub fn save_if_dirty(&mut self) -> Result<bool> {
+    self.manage(AppConfigManager::save_if_dirty)
+  }
+
+  /// See [`AppConfigManager::config_path`].
+  pub fn config_path(&self) -> Result<PathBuf> {
+    self.manager.config_path()
+  }
+
+  /// Returns the data, after saving it like dropping the manager would.
+  pub fn into_inner(mut self) -> T {
+    if self.manager.auto_saving {
+      self.manage(|manager| {
+        if let Err(err) = manager.save_if_dirty() {
+          manager.report_save_error(err);
+        }
+      });
+      self.manager.auto_saving = false;
+    }
+    std::mem::take(&mut self.value)
+  }
+}
+
+impl<T> Drop for OwnedAppConfigManager<T>
+where
+  T: Sized + Serialize + DeserializeOwned + Default,
+{
+  fn drop(&mut self) {
+    // The manager is dropped next and saves the data
+    std::mem::swap(self.manager.data.0.get_mut(), &mut self.value);
+  }
+}
+
+/// Moves the data into the manager, and back out when dropped, even if `f` panics.
+struct Lent<'a, T>
+where
+  T: Sized + Serialize + DeserializeOwned,
+{
+  manager: &'a mut AppConfigManager<T, Owned<T>>,
+  value: &'a mut T,
+}
+
+impl<'a, T> Lent<'a, T>
+where
+  T: Sized + Serialize + DeserializeOwned,
+{
+  fn new(manager: &'a mut AppConfigManager<T, Owned<T>>, value: &'a mut T) -> Self {
+    std::mem::swap(manager.data.0.get_mut(), value);
+    Self { manager, value }
+  }
+}
+
+impl<T> Drop for Lent<'_, T>
+where
+  T: Sized + Serialize + DeserializeOwned,
+{
+  fn drop(&mut self) {
+    std::mem::swap(self.manager.data.0.get_mut(), self.value);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::AppConfigManager;
+  use serde::{Deserialize, Serialize};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Volume {
+    level: u32,
+  }
+
+  #[test]
+  fn owned() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut manager = AppConfigManager::owned(Volume { level: 1 }, "owned", "sumibi-yakitori")
+      .configure(|manager| {
+        manager.set_base_dir(dir.path());
+      });
+    let path = manager.config_path().unwrap();
+    manager.save().unwrap();
+    manager.get_mut().level = 2;
+    let volume = manager.get();
+    assert_eq!(volume, &Volume { level: 2 });
+    manager.load().unwrap();
+    assert_eq!(manager.get(), &Volume { level: 1 });
+
+    // Saved before the data is returned
+    manager.get_mut().level = 3;
+    assert_eq!(manager.into_inner(), Volume { level: 3 });
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "level = 3\n");
+
+    let mut manager = AppConfigManager::owned(Volume::default(), "owned", "sumibi-yakitori")
+      .configure(|manager| {
+        manager.set_base_dir(dir.path()).set_auto_saving(false);
+      });
+    manager.load().unwrap();
+    assert_eq!(manager.into_inner(), Volume { level: 3 });
+
+    // Saved on drop
+    let mut manager = AppConfigManager::owned(Volume::default(), "owned", "sumibi-yakitori")
+      .configure(|manager| {
+        manager.set_base_dir(dir.path());
+      });
+    manager.get_mut().level = 4;
+    drop(manager);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "level = 4\n");
+  }
+}
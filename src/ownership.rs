@@ -0,0 +1,124 @@
+use crate::{fs, AppConfigError, AppConfigManager, Result, SharedData};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+  fs::File,
+  io::Write,
+  path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+
+/// Keeps the configuration owned by this process until dropped,
+/// returned by [`AppConfigManager::try_acquire_ownership`].
+#[derive(Debug)]
+pub struct OwnershipGuard {
+  path: PathBuf,
+  /// Holds the lock while open.
+  _file: File,
+  owned: Arc<AtomicBool>,
+}
+
+impl OwnershipGuard {
+  /// The locked file, containing the process ID of the owner.
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+}
+
+impl Drop for OwnershipGuard {
+  fn drop(&mut self) {
+    self.owned.store(false, Ordering::Relaxed);
+  }
+}
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// Locks `app_config.toml.owner` next to the file until the returned guard is dropped,
+  /// e.g. to allow a single instance of the application, or to make the others read-only.
+  ///
+  /// Fails with [`AppConfigError::AlreadyOwned`] without waiting when another process
+  /// or manager owns the configuration. The lock is released when the owner crashes.
+  pub fn try_acquire_ownership(&self) -> Result<OwnershipGuard> {
+    let path = fs::with_suffix(&self.get_user_config_path()?, ".owner");
+    let mut file = match fs::lock_file(&path, true, Duration::ZERO) {
+      Ok(Some(file)) => file,
+      Ok(None) => {
+        // Cannot be read on Windows while it is locked
+        let pid = std::fs::read_to_string(&path)
+          .ok()
+          .and_then(|s| s.trim().parse().ok());
+        return Err(AppConfigError::AlreadyOwned { path, pid });
+      }
+      Err(err) => return Err(AppConfigError::Io { path, source: err }),
+    };
+    file
+      .set_len(0)
+      .and_then(|_| write!(file, "{}", std::process::id()))
+      .map_err(AppConfigError::io(&path))?;
+    self.owned.store(true, Ordering::Relaxed);
+    Ok(OwnershipGuard {
+      path,
+      _file: file,
+      owned: self.owned.clone(),
+    })
+  }
+
+  /// Returns `true` while a guard returned by `try_acquire_ownership` is alive.
+  pub fn is_owner(&self) -> bool {
+    self.owned.load(Ordering::Relaxed)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{AppConfigError, AppConfigManager};
+  use serde::{Deserialize, Serialize};
+  use std::{cell::RefCell, rc::Rc};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Volume {
+    level: u32,
+  }
+
+  #[test]
+  fn ownership() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Volume::default()));
+    let new_manager = || {
+      AppConfigManager::new(config.clone(), "ownership", "sumibi-yakitori")
+        .with_base_dir(dir.path())
+        .with_require_ownership(true)
+        .with_auto_saving(false)
+    };
+    let manager = new_manager();
+    let other = new_manager();
+    assert!(matches!(
+      manager.save(),
+      Err(AppConfigError::NotOwner { .. })
+    ));
+
+    let guard = manager.try_acquire_ownership().unwrap();
+    assert!(manager.is_owner());
+    manager.save().unwrap();
+    match other.try_acquire_ownership() {
+      Err(AppConfigError::AlreadyOwned { pid, .. }) => {
+        if cfg!(unix) {
+          assert_eq!(pid, Some(std::process::id()));
+        }
+      }
+      result => panic!("{:?}", result),
+    }
+    assert!(matches!(other.save(), Err(AppConfigError::NotOwner { .. })));
+
+    drop(guard);
+    assert!(!manager.is_owner());
+    let _guard = other.try_acquire_ownership().unwrap();
+    other.save().unwrap();
+  }
+}
@@ -0,0 +1,666 @@
+use crate::{
+  format, fs, location, AppConfigError, AppConfigManager, Location, MacosStyle, Result, SharedData,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+  io,
+  path::{Component, Path, PathBuf},
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    OnceLock,
+  },
+};
+
+/// The `config` folder next to the executable, or why it cannot be used.
+pub(crate) type PortableDir =
+  OnceLock<std::result::Result<PathBuf, (PathBuf, io::ErrorKind, String)>>;
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// The folder of the configuration file, created if missing unless a store is used.
+  /// Only for writing: loading and path queries never create folders.
+  pub(crate) fn get_user_config_dir(&self) -> Result<PathBuf> {
+    let path = self.user_config_dir()?;
+    if !self.uses_store() && !path.exists() {
+      fs::create_dir_all(&path, self.unix_permissions).map_err(AppConfigError::io(&path))?;
+    }
+    Ok(path)
+  }
+
+  /// The folder of the configuration file, inside the subdirectory of `with_subdirectory`. It may not exist.
+  pub(crate) fn user_config_dir(&self) -> Result<PathBuf> {
+    self.check_subdirectory()?;
+    Ok(self.in_subdirectory(self.app_folder()?))
+  }
+
+  /// The folder of the application, without the subdirectory. It may not exist.
+  pub(crate) fn app_folder(&self) -> Result<PathBuf> {
+    self.check_names()?;
+    if let Some(path) = self.env_override_dir() {
+      return Ok(path);
+    }
+    if self.portable_mode {
+      match self.portable_dir() {
+        Ok(path) => return Ok(path),
+        Err(err) if !self.portable_fallback => return Err(err),
+        Err(_) => {}
+      }
+    }
+
+    let path = match &self.base_dir {
+      Some(dir) if dir.is_relative() => self.app_dir(
+        std::env::current_dir()
+          .map_err(AppConfigError::io(dir))?
+          .join(dir),
+      ),
+      Some(dir) => self.app_dir(dir.clone()),
+      None => self.location_dir(self.location)?,
+    };
+    Ok(path)
+  }
+
+  /// The folder of this application in the platform directory for `location`. It may not exist.
+  /// Relative when the platform has no such directory but a store is used.
+  pub(crate) fn location_dir(&self, location: Location) -> Result<PathBuf> {
+    self.check_names()?;
+    if location == Location::Config && self.macos_preferences_style() {
+      return location::macos_preferences_dir().ok_or_else(|| {
+        AppConfigError::ConfigDirUnavailable {
+          path: PathBuf::from(self.reverse_dns_name()),
+        }
+      });
+    }
+    match location.dir(self.windows_profile) {
+      Some(base) => Ok(self.app_dir(base)),
+      // e.g. in a browser, where the store decides where the file goes
+      None if self.uses_store() => Ok(self.app_dir(PathBuf::new())),
+      None => Err(AppConfigError::ConfigDirUnavailable {
+        path: self.app_dir(PathBuf::new()),
+      }),
+    }
+  }
+
+  /// `dir` joined with the subdirectory of `with_subdirectory`, if any.
+  pub(crate) fn in_subdirectory(&self, dir: PathBuf) -> PathBuf {
+    match &self.subdirectory {
+      Some(subdirectory) => dir.join(subdirectory),
+      None => dir,
+    }
+  }
+
+  /// Fails if the subdirectory of `with_subdirectory` would escape the application folder.
+  fn check_subdirectory(&self) -> Result {
+    let subdirectory = match &self.subdirectory {
+      Some(subdirectory) => subdirectory,
+      None => return Ok(()),
+    };
+    let escapes = subdirectory
+      .components()
+      .any(|x| !matches!(x, Component::Normal(_)));
+    if escapes {
+      return Err(AppConfigError::InvalidSubdirectory(subdirectory.clone()));
+    }
+    Ok(())
+  }
+
+  pub(crate) fn app_dir(&self, base: PathBuf) -> PathBuf {
+    if self.fqdn_subfolder {
+      base.join(self.reverse_dns_name())
+    }
+    else {
+      base
+    }
+  }
+
+  /// The machine-wide file read by `load()` when system fallback is enabled and no user file exists,
+  /// e.g. `/etc/com.{organization_name}.{app_name}/app_config.toml`.
+  /// `%ProgramData%` is used on Windows and `/Library/Application Support` on macOS.
+  pub fn system_config_path(&self) -> Result<PathBuf> {
+    self.check_names()?;
+    self.check_subdirectory()?;
+    let base = match &self.system_dir {
+      Some(dir) => dir.clone(),
+      None => location::system_dir().ok_or_else(|| AppConfigError::ConfigDirUnavailable {
+        path: self.app_dir(PathBuf::new()),
+      })?,
+    };
+    Ok(
+      self
+        .in_subdirectory(self.app_dir(base))
+        .join(self.plain_file_name(self.format.extension())?),
+    )
+  }
+
+  /// `{qualifier}.{organization_name}.{app_name}`, e.g. `com.{organization_name}.{app_name}`
+  pub(crate) fn reverse_dns_name(&self) -> String {
+    self.folder_name(
+      self.naming,
+      &self.qualifier,
+      &self.organization_name,
+      &self.app_name,
+    )
+  }
+
+  /// Whether the file is stored directly in `~/Library/Preferences`.
+  /// Only the platform configuration directory on macOS is affected.
+  fn macos_preferences_style(&self) -> bool {
+    cfg!(target_os = "macos")
+      && self.macos_style == MacosStyle::Preferences
+      && self.location == Location::Config
+      && self.base_dir.is_none()
+      && self.env_override_dir().is_none()
+      && !self.uses_portable_dir()
+  }
+
+  /// The file written before switching to [`MacosStyle::Preferences`], which is still read
+  /// while no file exists in `~/Library/Preferences`.
+  pub(crate) fn find_legacy_macos_file(&self) -> Result<Option<PathBuf>> {
+    Ok(self.first_stored(self.legacy_macos_files()?))
+  }
+
+  /// The candidates of [`find_legacy_macos_file`](Self::find_legacy_macos_file), in order.
+  pub(crate) fn legacy_macos_files(&self) -> Result<Vec<PathBuf>> {
+    if !self.macos_preferences_style() {
+      return Ok(Vec::new());
+    }
+    let dir = match dirs_next::config_dir() {
+      Some(base) => self.in_subdirectory(self.app_dir(base)),
+      None => return Ok(Vec::new()),
+    };
+    self
+      .extensions()
+      .into_iter()
+      .map(|extension| Ok(dir.join(self.plain_file_name(extension)?)))
+      .collect()
+  }
+
+  /// The file saved under the first of the identities set with `with_legacy_identity` that has one,
+  /// e.g. `com.oldcorp.oldapp/app_config.toml` next to `com.newcorp.newapp`.
+  pub(crate) fn find_legacy_identity_file(&self) -> Result<Option<PathBuf>> {
+    Ok(self.first_stored(self.legacy_identity_files()?))
+  }
+
+  /// The candidates of [`find_legacy_identity_file`](Self::find_legacy_identity_file), in order.
+  pub(crate) fn legacy_identity_files(&self) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    if self.legacy_identities.is_empty() {
+      return Ok(paths);
+    }
+    let dir = self.app_folder()?;
+    let current = self.reverse_dns_name();
+    let preferences = self.macos_preferences_style();
+    // Without the FQDN folder, the file of every identity is at the same place
+    if !preferences && dir.file_name() != Some(current.as_ref()) {
+      return Ok(paths);
+    }
+    for (legacy, ..) in self.legacy_folder_names() {
+      for extension in self.extensions() {
+        let path = if preferences {
+          self
+            .in_subdirectory(dir.clone())
+            .join(self.file_name(extension)?.replacen(&current, &legacy, 1))
+        }
+        else {
+          self
+            .in_subdirectory(dir.with_file_name(&legacy))
+            .join(self.plain_file_name(extension)?)
+        };
+        paths.push(path);
+      }
+    }
+    Ok(paths)
+  }
+
+  /// The directory named by the environment variable set with `with_env_override`, if it is set and not blank.
+  fn env_override_dir(&self) -> Option<PathBuf> {
+    let value = std::env::var_os(self.env_override.as_ref()?)?;
+    if value.to_str().is_some_and(|x| x.trim().is_empty()) {
+      return None;
+    }
+    Some(PathBuf::from(value))
+  }
+
+  /// Returns `true` if the file is stored next to the executable, i.e. portable mode is enabled
+  /// and did not fall back to the platform configuration directory.
+  pub fn uses_portable_dir(&self) -> bool {
+    self.portable_mode && self.env_override_dir().is_none() && self.portable_dir().is_ok()
+  }
+
+  /// The folder next to the executable that portable mode could not use
+  /// before falling back to the platform configuration directory.
+  pub(crate) fn portable_fallback_dir(&self) -> Option<PathBuf> {
+    if !self.portable_mode || self.env_override_dir().is_some() {
+      return None;
+    }
+    match self.portable_dir() {
+      Err(AppConfigError::PortableDirUnavailable { path, .. }) => Some(path),
+      _ => None,
+    }
+  }
+
+  /// The `config` folder next to the executable. It may not exist.
+  pub(crate) fn portable_dir(&self) -> Result<PathBuf> {
+    let checked = self.portable_dir.get_or_init(|| {
+      self
+        .check_portable_dir()
+        .map_err(|(path, err)| (path, err.kind(), err.to_string()))
+    });
+    match checked {
+      Ok(path) => Ok(path.clone()),
+      Err((path, kind, message)) => Err(AppConfigError::PortableDirUnavailable {
+        path: path.clone(),
+        source: io::Error::new(*kind, message.clone()),
+      }),
+    }
+  }
+
+  fn check_portable_dir(&self) -> std::result::Result<PathBuf, (PathBuf, io::Error)> {
+    let exe_dir = match &self.exe_dir {
+      Some(dir) => dir.clone(),
+      None => {
+        let exe = std::env::current_exe().map_err(|err| (PathBuf::from("config"), err))?;
+        exe.parent().unwrap_or(&exe).to_owned()
+      }
+    };
+    let dir = exe_dir.join("config");
+    // e.g. shipped with the application in a read-only folder
+    let config_dir = self.in_subdirectory(dir.clone());
+    let exists = self.extensions().iter().any(|extension| {
+      self
+        .file_name(extension)
+        .is_ok_and(|name| config_dir.join(name).is_file())
+    });
+    if exists {
+      return Ok(dir);
+    }
+    writable_dir(&dir).map_err(|err| (dir.clone(), err))?;
+    Ok(dir)
+  }
+
+  /// The path of the configuration file, e.g. to show it in an "About" dialog.
+  /// Nothing is created, the folder may not exist yet.
+  ///
+  /// Reflects the file name, format, location, base directory, portable mode and environment override.
+  pub fn config_path(&self) -> Result<PathBuf> {
+    self.user_config_path()
+  }
+
+  /// The folder of [`config_path`](Self::config_path). It may not exist yet.
+  pub fn config_dir(&self) -> Result<PathBuf> {
+    self.user_config_dir()
+  }
+
+  /// Returns `true` if the file at [`config_path`](Self::config_path) exists, e.g. to detect a first run.
+  pub fn config_file_exists(&self) -> Result<bool> {
+    Ok(self.is_stored(&self.config_path()?))
+  }
+
+  /// Returns `true` if nothing was ever saved, e.g. to show an onboarding wizard.
+  /// Never creates anything.
+  ///
+  /// A file of a legacy identity, in another format or uncompressed counts as saved.
+  /// So does an existing `com.{organization_name}.{app_name}` folder without a file,
+  /// e.g. after [`delete_file`](Self::delete_file).
+  pub fn is_first_run(&self) -> Result<bool> {
+    let found = self.find_config_file(&self.extensions())?.is_some()
+      || self.find_legacy_macos_file()?.is_some()
+      || self.find_uncompressed_file()?.is_some()
+      || self.find_legacy_identity_file()?.is_some()
+      || self.find_config_in_other_format()?.is_some();
+    if found || self.uses_store() {
+      return Ok(!found);
+    }
+    // Without the FQDN folder, the folder is shared with other files
+    let dir = self.app_folder()?;
+    Ok(dir.file_name() != Some(self.reverse_dns_name().as_ref()) || !dir.exists())
+  }
+
+  pub(crate) fn get_user_config_path(&self) -> Result<PathBuf> {
+    Ok(
+      self
+        .get_user_config_dir()?
+        .join(self.file_name(self.format.extension())?),
+    )
+  }
+
+  /// The path of the configuration file without creating its folder.
+  pub(crate) fn user_config_path(&self) -> Result<PathBuf> {
+    Ok(
+      self
+        .user_config_dir()?
+        .join(self.file_name(self.format.extension())?),
+    )
+  }
+
+  /// The file name for `extension`, e.g. `app_config.toml`.
+  pub(crate) fn file_name(&self, extension: &str) -> Result<String> {
+    let name = self.uncompressed_file_name(extension)?;
+    Ok(name + self.compression_suffix())
+  }
+
+  /// The file name for `extension` without the compressed file suffix.
+  pub(crate) fn uncompressed_file_name(&self, extension: &str) -> Result<String> {
+    let name = self.plain_file_name(extension)?;
+    if !self.macos_preferences_style() {
+      return Ok(name);
+    }
+    Ok(match name.strip_prefix("app_config.") {
+      Some(extension) => format!("{}.{}", self.reverse_dns_name(), extension),
+      None => format!("{}.{}", self.reverse_dns_name(), name),
+    })
+  }
+
+  /// The file name for `extension` without the reverse-DNS prefix of [`MacosStyle::Preferences`].
+  fn plain_file_name(&self, extension: &str) -> Result<String> {
+    let name = self.file_name.as_str();
+    let invalid = name.is_empty()
+      || name.contains(['/', '\\', '\0'])
+      || name.contains("..")
+      || name.starts_with('.');
+    if invalid {
+      return Err(AppConfigError::InvalidFileName(name.to_owned()));
+    }
+
+    // `ui.toml` and `ui` both end up as `ui.toml`
+    let stem = Path::new(name)
+      .extension()
+      .and_then(|x| x.to_str())
+      .filter(|x| format::BUILTIN_EXTENSIONS.contains(x) || self.extensions().contains(x))
+      .map_or(name, |x| &name[..name.len() - x.len() - 1]);
+    Ok(format!("{}.{}", stem, extension))
+  }
+
+  /// All file extensions recognized for the configured format. The first one is used when saving.
+  pub(crate) fn extensions(&self) -> Vec<&str> {
+    std::iter::once(self.format.extension())
+      .chain(self.format.alternative_extensions().iter().copied())
+      .collect()
+  }
+
+  /// The extension of `path` if it is one of the built-in formats, otherwise the extension
+  /// of the configured format, e.g. for `app_config.toml.bak`.
+  pub(crate) fn format_extension<'a>(&'a self, path: &'a Path) -> &'a str {
+    path
+      .extension()
+      .and_then(|x| x.to_str())
+      .filter(|x| format::BUILTIN_EXTENSIONS.contains(x))
+      .unwrap_or_else(|| self.format.extension())
+  }
+
+  /// Returns the first existing file among `extensions`.
+  pub(crate) fn find_config_file(&self, extensions: &[&str]) -> Result<Option<PathBuf>> {
+    self.find_config_file_in(&self.user_config_dir()?, extensions)
+  }
+
+  /// The candidates of [`find_config_file`](Self::find_config_file), in order.
+  pub(crate) fn config_files(&self, extensions: &[&str]) -> Result<Vec<PathBuf>> {
+    let dir = self.user_config_dir()?;
+    extensions
+      .iter()
+      .map(|extension| Ok(dir.join(self.file_name(extension)?)))
+      .collect()
+  }
+
+  /// The first of `paths` that exists.
+  pub(crate) fn first_stored(&self, paths: Vec<PathBuf>) -> Option<PathBuf> {
+    paths.into_iter().find(|path| self.is_stored(path))
+  }
+
+  pub(crate) fn find_config_file_in(
+    &self,
+    dir: &Path,
+    extensions: &[&str],
+  ) -> Result<Option<PathBuf>> {
+    for extension in extensions {
+      let path = dir.join(self.file_name(extension)?);
+      if self.is_stored(&path) {
+        return Ok(Some(path));
+      }
+    }
+    Ok(None)
+  }
+
+  pub(crate) fn find_config_in_other_format(&self) -> Result<Option<PathBuf>> {
+    Ok(self.first_stored(self.other_format_files()?))
+  }
+
+  /// The candidates of [`find_config_in_other_format`](Self::find_config_in_other_format), in order.
+  pub(crate) fn other_format_files(&self) -> Result<Vec<PathBuf>> {
+    let extensions = self.extensions();
+    let others: Vec<&str> = format::BUILTIN_EXTENSIONS
+      .iter()
+      .copied()
+      .filter(|extension| !extensions.contains(extension))
+      .collect();
+    self.config_files(&others)
+  }
+}
+
+/// Checks that files can be created in `path`, or in its parent if it does not exist yet.
+/// Nothing is left behind. The probe has its own name, so that instances starting together
+/// do not remove each other's.
+fn writable_dir(path: &Path) -> io::Result<()> {
+  static COUNTER: AtomicUsize = AtomicUsize::new(0);
+  let dir = match path.parent() {
+    Some(parent) if !path.exists() => parent,
+    _ => path,
+  };
+  let probe = dir.join(format!(
+    ".appconfig_write_test.{}.{}",
+    std::process::id(),
+    COUNTER.fetch_add(1, Ordering::Relaxed)
+  ));
+  std::fs::File::options()
+    .write(true)
+    .create_new(true)
+    .open(&probe)?;
+  std::fs::remove_file(&probe)
+}
+
+/// Derives a file name from the name of `T`, e.g. `my_crate::MyAppConfig<u32>` becomes `my_app_config`.
+///
+/// The module path and generic parameters are removed, so `Settings<A>` and `Settings<B>` share a name.
+pub(crate) fn type_file_name<T: ?Sized>() -> String {
+  let name = std::any::type_name::<T>();
+  let name = name.split('<').next().unwrap_or(name);
+  let name = name.rsplit("::").next().unwrap_or(name);
+
+  let chars: Vec<char> = name.chars().collect();
+  let mut snake = String::with_capacity(name.len() + 4);
+  for (i, &c) in chars.iter().enumerate() {
+    if c.is_uppercase() && i > 0 {
+      let prev = chars[i - 1];
+      let next_is_lower = chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+      // `MyApp` -> `my_app`, `HTTPConfig` -> `http_config`
+      if prev.is_lowercase() || prev.is_ascii_digit() || (prev.is_uppercase() && next_is_lower) {
+        snake.push('_');
+      }
+    }
+    snake.extend(c.to_lowercase());
+  }
+  snake
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{type_file_name, writable_dir};
+  use crate::{AppConfigError, AppConfigManager, LoadOutcome, Location, TestConfigDir};
+  use std::{cell::RefCell, rc::Rc};
+
+  #[allow(dead_code)]
+  mod nested {
+    pub struct MyAppConfig;
+    pub struct HTTPSettings<T>(T);
+  }
+
+  #[test]
+  fn type_file_names() {
+    assert_eq!(type_file_name::<nested::MyAppConfig>(), "my_app_config");
+    assert_eq!(
+      type_file_name::<nested::HTTPSettings<nested::MyAppConfig>>(),
+      "http_settings"
+    );
+    assert_eq!(type_file_name::<Vec<u32>>(), "vec");
+    assert_eq!(type_file_name::<u32>(), "u32");
+  }
+
+  #[test]
+  fn config_path() {
+    let dir = TestConfigDir::new().unwrap();
+    let config = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let manager = dir
+      .manager(config, "config_path", "sumibi-yakitori")
+      .with_file_name("settings")
+      .with_location(Location::Data)
+      .with_auto_saving(false);
+    let path = manager.config_path().unwrap();
+    assert_eq!(
+      path,
+      dir
+        .path()
+        .join("com.sumibi-yakitori.config_path/settings.toml")
+    );
+    assert_eq!(manager.config_dir().unwrap(), path.parent().unwrap());
+    assert!(!manager.config_file_exists().unwrap());
+    assert!(!path.parent().unwrap().exists());
+  }
+
+  #[test]
+  fn is_first_run() {
+    #[derive(Default, serde::Serialize, serde::Deserialize)]
+    struct Onboarding {
+      done: bool,
+    }
+
+    let dir = TestConfigDir::new().unwrap();
+    let config = Rc::new(RefCell::new(Onboarding::default()));
+    let manager = dir
+      .manager(config.clone(), "first_run", "sumibi-yakitori")
+      .with_auto_saving(false);
+    assert!(manager.is_first_run().unwrap());
+    assert!(matches!(
+      manager.load_with_outcome().unwrap(),
+      LoadOutcome::FirstRun
+    ));
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+    manager.save().unwrap();
+    assert!(!manager.is_first_run().unwrap());
+    manager.delete_file().unwrap();
+    assert!(!manager.is_first_run().unwrap());
+    assert!(matches!(
+      manager.load_with_outcome().unwrap(),
+      LoadOutcome::FileMissing
+    ));
+
+    // Saved under the old name
+    let renamed = dir
+      .manager(config, "first_run_renamed", "sumibi-yakitori")
+      .with_legacy_identity("first_run", "sumibi-yakitori")
+      .with_auto_saving(false);
+    assert!(renamed.is_first_run().unwrap());
+    manager.save().unwrap();
+    assert!(!renamed.is_first_run().unwrap());
+  }
+
+  #[test]
+  fn subdirectory() {
+    #[derive(Default, serde::Serialize, serde::Deserialize)]
+    struct Profile {
+      name: String,
+    }
+
+    let dir = TestConfigDir::new().unwrap();
+    let config = Rc::new(RefCell::new(Profile::default()));
+    let manager = dir
+      .manager(config.clone(), "subdirectory", "sumibi-yakitori")
+      .with_subdirectory("./profiles/default")
+      .with_file_name("settings")
+      .with_backup_retention(2)
+      .with_auto_saving(false);
+    let path = manager.config_path().unwrap();
+    let app_dir = dir.path().join("com.sumibi-yakitori.subdirectory");
+    assert_eq!(path, app_dir.join("profiles/default/settings.toml"));
+    assert_eq!(
+      manager.config_key(&path).to_string(),
+      "com.sumibi-yakitori.subdirectory/profiles/default/settings.toml"
+    );
+
+    manager.save().unwrap();
+    config.borrow_mut().name = "default".to_owned();
+    manager.save().unwrap();
+    assert!(app_dir.join("profiles/default/settings.toml.1").exists());
+    manager.load().unwrap();
+    assert_eq!(config.borrow().name, "default");
+    assert_eq!(manager.purge().unwrap().files, 2);
+    assert!(!app_dir.exists());
+
+    for subdirectory in ["/etc", "../other", "profiles/../../other"] {
+      let manager = dir
+        .manager(
+          Rc::new(RefCell::new(Profile::default())),
+          "subdirectory",
+          "sumibi-yakitori",
+        )
+        .with_subdirectory(subdirectory)
+        .with_auto_saving(false);
+      assert!(matches!(
+        manager.save(),
+        Err(AppConfigError::InvalidSubdirectory(_))
+      ));
+    }
+  }
+
+  #[test]
+  fn unwritable_portable_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("app.exe");
+    std::fs::write(&file, "").unwrap();
+
+    let path = dir.path().join("config");
+    writable_dir(&path).unwrap();
+    assert!(!path.exists());
+    std::fs::create_dir(&path).unwrap();
+    writable_dir(&path).unwrap();
+    assert_eq!(std::fs::read_dir(&path).unwrap().count(), 0);
+
+    // A file where the folder should be
+    writable_dir(&file.join("config")).unwrap_err();
+
+    let config = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut manager = AppConfigManager::new(config, "portable", "sumibi-yakitori")
+      .with_portable_mode(true)
+      .with_portable_fallback(false)
+      .with_auto_saving(false);
+    manager.exe_dir = Some(file.clone());
+    assert!(matches!(
+      manager.config_path(),
+      Err(AppConfigError::PortableDirUnavailable { .. })
+    ));
+    // Checked once
+    manager.exe_dir = Some(dir.path().to_owned());
+    assert!(!manager.uses_portable_dir());
+    manager.set_portable_mode(true);
+    assert_eq!(manager.config_path().unwrap(), path.join("app_config.toml"));
+
+    // Reported when loading
+    let fallback = dir.path().join("fallback");
+    manager.exe_dir = Some(file.clone());
+    manager.set_portable_fallback(true).set_base_dir(&fallback);
+    manager.set_portable_mode(true);
+    assert_eq!(
+      manager.config_path().unwrap(),
+      fallback.join("com.sumibi-yakitori.portable/app_config.toml")
+    );
+    match manager.load_with_outcome().unwrap() {
+      LoadOutcome::PortableFallback { dir, outcome } => {
+        assert_eq!(dir, file.join("config"));
+        assert!(matches!(*outcome, LoadOutcome::FirstRun));
+      }
+      outcome => panic!("{:?}", outcome),
+    }
+  }
+}
@@ -0,0 +1,250 @@
+use crate::{fs, shared::lock, AppConfigError, AppConfigManager, Result, SharedData};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+  io,
+  path::{Path, PathBuf},
+  sync::atomic::Ordering,
+};
+
+/// What [`AppConfigManager::purge`] removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PurgeReport {
+  /// The number of files, including backups, lock files and quarantined files.
+  pub files: u64,
+  /// Their total size in bytes.
+  pub bytes: u64,
+}
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// Makes `reset_to_default()` delete the file instead of saving the defaults.
+  pub fn set_delete_on_reset(&mut self, value: bool) -> &mut Self {
+    self.delete_on_reset = value;
+    self
+  }
+
+  pub fn with_delete_on_reset(mut self, value: bool) -> Self {
+    self.set_delete_on_reset(value);
+    self
+  }
+
+  /// Removes the file at [`config_path`](Self::config_path), e.g. when uninstalling.
+  /// Returns whether it existed. Backups and files in other formats are left in place.
+  ///
+  /// The file is not saved on drop afterwards, otherwise it would reappear right away.
+  /// Call `save()` to write it again.
+  pub fn delete_file(&self) -> Result<bool> {
+    // Pending background writes land before the file is removed
+    self.flush()?;
+    let path = self.config_path()?;
+    let existed = self.is_stored(&path);
+    let existed = match self.remove_stored(&path) {
+      Ok(()) => existed,
+      Err(err) if err.is_not_found() => false,
+      Err(err) => return Err(err),
+    };
+    self.disarm_save();
+    if existed {
+      event!(Info, "Deleted {}", path.display());
+    }
+    Ok(existed)
+  }
+
+  /// Removes the whole `com.{organization_name}.{app_name}` folder with everything in it,
+  /// including every subdirectory, not only the one set with `with_subdirectory`,
+  /// e.g. for a "Delete my data" button. Like `delete_file()`, the file is not saved on drop afterwards.
+  ///
+  /// Fails with [`AppConfigError::PurgeRefused`] if the folder is not named after the application,
+  /// e.g. with `with_fqdn_subfolder(false)`, in portable mode or in `~/Library/Preferences`,
+  /// so that a misconfigured base directory never takes other files with it.
+  /// With [`with_store`](Self::with_store), only the files this crate writes are removed.
+  pub fn purge(&self) -> Result<PurgeReport> {
+    self.flush()?;
+    let dir = self.app_folder()?;
+    if dir.file_name() != Some(self.reverse_dns_name().as_ref()) {
+      return Err(AppConfigError::PurgeRefused { path: dir });
+    }
+    let report = if self.uses_store() {
+      self.purge_stored()?
+    }
+    else {
+      match measure(&dir) {
+        Ok(report) => {
+          std::fs::remove_dir_all(&dir).map_err(AppConfigError::io(&dir))?;
+          report
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => PurgeReport::default(),
+        Err(err) => return Err(AppConfigError::io(&dir)(err)),
+      }
+    };
+    self.disarm_save();
+    event!(
+      Info,
+      "Purged {} ({} files, {} bytes)",
+      dir.display(),
+      report.files,
+      report.bytes
+    );
+    Ok(report)
+  }
+
+  /// A store cannot be listed, so this removes the files and backups in every known format.
+  fn purge_stored(&self) -> Result<PurgeReport> {
+    let dir = self.user_config_dir()?;
+    let mut report = PurgeReport::default();
+    for extension in self.extensions() {
+      let path = dir.join(self.file_name(extension)?);
+      let suffixes = [String::new(), ".bak".into(), ".corrupt".into()];
+      let numbered = (1..=self.backup_retention).map(|i| format!(".{}", i));
+      for suffix in suffixes.iter().cloned().chain(numbered) {
+        let path = fs::with_suffix(&path, &suffix);
+        match self.read_stored(&path) {
+          Ok(s) => {
+            self.remove_stored(&path)?;
+            report.files += 1;
+            report.bytes += s.len() as u64;
+          }
+          Err(err) if err.is_not_found() => {}
+          Err(err) => return Err(err),
+        }
+      }
+    }
+    Ok(report)
+  }
+
+  /// Keeps the file from being saved on drop or exit until the next `save()`.
+  fn disarm_save(&self) {
+    self.deleted.store(true, Ordering::Relaxed);
+    *lock(&self.file_stamp) = None;
+    #[cfg(feature = "exit-flush")]
+    self.set_exit_flush(None);
+  }
+
+  /// Replaces the data with `T::default()` and saves it, e.g. for a "Reset to defaults" button.
+  /// Deletes the file instead with [`with_delete_on_reset`](Self::with_delete_on_reset).
+  pub fn reset_to_default(&self) -> Result
+  where
+    T: Default,
+  {
+    self
+      .data
+      .try_write(|data| *data = T::default())
+      .ok_or(AppConfigError::AlreadyBorrowed)?;
+    if self.delete_on_reset {
+      self.delete_file().map(|_| ())
+    }
+    else {
+      self.save()
+    }
+  }
+}
+
+/// Counts the files below `dir`, without following symbolic links.
+fn measure(dir: &Path) -> io::Result<PurgeReport> {
+  let mut report = PurgeReport::default();
+  let mut dirs: Vec<PathBuf> = vec![dir.to_owned()];
+  while let Some(dir) = dirs.pop() {
+    for entry in std::fs::read_dir(&dir)? {
+      let entry = entry?;
+      let metadata = entry.metadata()?;
+      if metadata.is_dir() {
+        dirs.push(entry.path());
+      }
+      else {
+        report.files += 1;
+        report.bytes += metadata.len();
+      }
+    }
+  }
+  Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{AppConfigError, AppConfigManager, PurgeReport, TestConfigDir};
+  use serde::{Deserialize, Serialize};
+  use std::{cell::RefCell, rc::Rc};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Grid {
+    columns: u32,
+  }
+
+  #[test]
+  fn delete_file() {
+    let dir = TestConfigDir::new().unwrap();
+    let config = Rc::from(RefCell::from(Grid { columns: 3 }));
+    let manager = dir.manager(config.clone(), "delete", "sumibi-yakitori");
+    let path = manager.config_path().unwrap();
+    manager.save().unwrap();
+    assert!(manager.delete_file().unwrap());
+    assert!(!manager.delete_file().unwrap());
+
+    // Not saved again on drop, even when dirty
+    config.borrow_mut().columns = 4;
+    drop(manager);
+    assert!(!path.exists());
+  }
+
+  #[test]
+  fn reset_to_default() {
+    let dir = TestConfigDir::new().unwrap();
+    let config = Rc::from(RefCell::from(Grid { columns: 3 }));
+    let manager = dir
+      .manager(config.clone(), "reset", "sumibi-yakitori")
+      .with_auto_saving(false);
+    let path = manager.config_path().unwrap();
+    manager.save().unwrap();
+    manager.reset_to_default().unwrap();
+    assert_eq!(*config.borrow(), Grid::default());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "columns = 0\n");
+
+    config.borrow_mut().columns = 5;
+    let manager = manager.with_delete_on_reset(true);
+    manager.reset_to_default().unwrap();
+    assert_eq!(*config.borrow(), Grid::default());
+    assert!(!path.exists());
+  }
+
+  #[test]
+  fn purge() {
+    let dir = TestConfigDir::new().unwrap();
+    let config = Rc::from(RefCell::from(Grid { columns: 3 }));
+    let manager = dir
+      .manager(config.clone(), "purge", "sumibi-yakitori")
+      .with_backup_retention(2);
+    let path = manager.config_path().unwrap();
+    manager.save().unwrap();
+    config.borrow_mut().columns = 4;
+    manager.save().unwrap();
+    let report = manager.purge().unwrap();
+    assert_eq!(report.files, 2);
+    assert_eq!(report.bytes, 2 * "columns = 4\n".len() as u64);
+    assert!(!path.parent().unwrap().exists());
+    assert_eq!(manager.purge().unwrap(), PurgeReport::default());
+    drop(manager);
+    assert!(!path.exists());
+  }
+
+  #[test]
+  fn purge_refused() {
+    let dir = TestConfigDir::new().unwrap();
+    let file = dir.path().join("keep.txt");
+    std::fs::write(&file, "").unwrap();
+    let config = Rc::from(RefCell::from(Grid::default()));
+    let manager = AppConfigManager::new(config, "purge", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_fqdn_subfolder(false);
+    manager.save().unwrap();
+    assert!(matches!(
+      manager.purge(),
+      Err(AppConfigError::PurgeRefused { path }) if path == dir.path()
+    ));
+    assert!(file.exists());
+    assert!(manager.config_file_exists().unwrap());
+  }
+}
@@ -0,0 +1,220 @@
+use crate::{
+  fs,
+  store::{self, ConfigKey, SharedStore},
+  AppConfigManager, Location, Result, SharedData,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+  ffi::OsString,
+  path::{Path, PathBuf},
+};
+
+/// The package format the application runs in, see [`runtime_environment`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum RuntimeEnvironment {
+  /// Not in a sandbox that redirects the configuration directory.
+  Native,
+  /// A Flatpak, where `$XDG_CONFIG_HOME` is `~/.var/app/{app id}/config`.
+  Flatpak,
+  /// A Snap, where `$HOME` is `~/snap/{name}/{revision}`.
+  Snap,
+}
+
+/// Detects a Flatpak by `/.flatpak-info` and a Snap by the `SNAP` environment variable.
+/// Always [`RuntimeEnvironment::Native`] on platforms other than Linux.
+pub fn runtime_environment() -> RuntimeEnvironment {
+  if !cfg!(target_os = "linux") {
+    return RuntimeEnvironment::Native;
+  }
+  detect(
+    Path::new("/.flatpak-info").exists(),
+    std::env::var_os("SNAP"),
+  )
+}
+
+fn detect(flatpak_info: bool, snap: Option<OsString>) -> RuntimeEnvironment {
+  if flatpak_info {
+    RuntimeEnvironment::Flatpak
+  }
+  else if snap.is_some_and(|x| !x.is_empty()) {
+    RuntimeEnvironment::Snap
+  }
+  else {
+    RuntimeEnvironment::Native
+  }
+}
+
+/// The home directory outside the sandbox. Flatpak keeps `$HOME`, Snap sets `$SNAP_REAL_HOME`.
+fn host_home(environment: RuntimeEnvironment) -> Option<PathBuf> {
+  let var = match environment {
+    RuntimeEnvironment::Native => return None,
+    RuntimeEnvironment::Flatpak => "HOME",
+    RuntimeEnvironment::Snap => "SNAP_REAL_HOME",
+  };
+  std::env::var_os(var)
+    .map(PathBuf::from)
+    .filter(|x| x.is_absolute())
+}
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// Copies the file written by a build outside the sandbox, e.g. to `~/.config`, into the sandbox
+  /// on the first load inside a Flatpak or a Snap, when no file exists there yet.
+  /// `load_with_outcome()` then returns [`LoadOutcome::SandboxMigrated`](crate::LoadOutcome::SandboxMigrated).
+  ///
+  /// The file outside is left as it is. It is only readable with a permission such as
+  /// `--filesystem=xdg-config/com.{organization_name}.{app_name}:ro`, and skipped otherwise.
+  pub fn set_sandbox_migration(&mut self, value: bool) -> &mut Self {
+    self.sandbox_migration = value;
+    self
+  }
+
+  pub fn with_sandbox_migration(mut self, value: bool) -> Self {
+    self.set_sandbox_migration(value);
+    self
+  }
+
+  /// Copies the file from outside the sandbox if enabled. Returns the path it was copied from.
+  pub(crate) fn migrate_from_host(&self) -> Result<Option<PathBuf>> {
+    match self.host_copy()? {
+      Some(copy) => copy.run(),
+      None => Ok(None),
+    }
+  }
+
+  /// The copy of `migrate_from_host`, `None` if disabled or not in a sandbox.
+  pub(crate) fn host_copy(&self) -> Result<Option<HostCopy>> {
+    if !self.sandbox_migration {
+      return Ok(None);
+    }
+    match host_home(runtime_environment()) {
+      Some(home) => self.host_copy_from(&home),
+      None => Ok(None),
+    }
+  }
+
+  fn host_copy_from(&self, home: &Path) -> Result<Option<HostCopy>> {
+    let dir = match self.location {
+      Location::Config => ".config",
+      Location::Data => ".local/share",
+      Location::Cache => ".cache",
+      Location::State => ".local/state",
+    };
+    let host = self
+      .in_subdirectory(self.app_dir(home.join(dir)))
+      .join(self.file_name(self.format.extension())?);
+    let path = self.user_config_path()?;
+    if host == path {
+      return Ok(None);
+    }
+    let existing = self.config_files(&self.extensions())?;
+    Ok(Some(HostCopy {
+      store: self.shared_store(),
+      existing: existing.iter().map(|path| self.config_key(path)).collect(),
+      host,
+      key: self.config_key(&path),
+      max_file_size: self.max_file_size,
+      options: self.write_options(),
+    }))
+  }
+}
+
+/// Everything `migrate_from_host` does with the files, without the manager,
+/// so that `load_async` can run it on another thread.
+pub(crate) struct HostCopy {
+  store: Option<SharedStore>,
+  /// The files in the sandbox, which are never replaced.
+  existing: Vec<ConfigKey>,
+  host: PathBuf,
+  key: ConfigKey,
+  max_file_size: u64,
+  options: fs::WriteOptions,
+}
+
+impl HostCopy {
+  /// Copies the file when no file exists in the sandbox yet. Returns the path it was copied from.
+  pub(crate) fn run(self) -> Result<Option<PathBuf>> {
+    let store = self.store.as_ref();
+    if self.existing.iter().any(|key| store::exists_in(store, key)) {
+      return Ok(None);
+    }
+    let s = match crate::read_file(&self.host, self.max_file_size) {
+      Ok(s) => s,
+      Err(err) => {
+        event!(Debug, "Nothing to copy into the sandbox: {}", err);
+        return Ok(None);
+      }
+    };
+    let path = self.key.path();
+    // Creates the folder
+    store::write_to(store, &self.key, &s, self.options).map_err(store::store_error(store, path))?;
+    event!(
+      Info,
+      "Copied {} into the sandbox as {}",
+      self.host.display(),
+      path.display()
+    );
+    Ok(Some(self.host))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::RuntimeEnvironment;
+  use crate::AppConfigManager;
+  use serde::{Deserialize, Serialize};
+  use std::{cell::RefCell, rc::Rc};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Font {
+    size: u32,
+  }
+
+  #[test]
+  fn detect() {
+    assert_eq!(super::detect(false, None), RuntimeEnvironment::Native);
+    assert_eq!(
+      super::detect(false, Some("".into())),
+      RuntimeEnvironment::Native
+    );
+    assert_eq!(
+      super::detect(false, Some("/snap/app/1".into())),
+      RuntimeEnvironment::Snap
+    );
+    assert_eq!(super::detect(true, None), RuntimeEnvironment::Flatpak);
+    assert_eq!(super::host_home(RuntimeEnvironment::Native), None);
+  }
+
+  #[test]
+  fn copy_from_host() {
+    let dir = tempfile::tempdir().unwrap();
+    let home = dir.path().join("home");
+    let config = Rc::from(RefCell::from(Font::default()));
+    let manager = AppConfigManager::new(config.clone(), "sandbox", "sumibi-yakitori")
+      .with_base_dir(dir.path().join("sandbox"))
+      .with_auto_saving(false);
+    let copy_from_host = || {
+      let copy = manager.host_copy_from(&home).unwrap().unwrap();
+      copy.run().unwrap()
+    };
+    // Not readable from the sandbox, nothing is created
+    assert_eq!(copy_from_host(), None);
+    assert!(!dir.path().join("sandbox").exists());
+
+    let host = home.join(".config/com.sumibi-yakitori.sandbox/app_config.toml");
+    std::fs::create_dir_all(host.parent().unwrap()).unwrap();
+    std::fs::write(&host, "size = 14\n").unwrap();
+    assert_eq!(copy_from_host(), Some(host.clone()));
+    manager.load().unwrap();
+    assert_eq!(*config.borrow(), Font { size: 14 });
+
+    // Only once
+    std::fs::write(&host, "size = 20\n").unwrap();
+    assert_eq!(copy_from_host(), None);
+    assert!(host.exists());
+  }
+}
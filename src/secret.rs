@@ -0,0 +1,305 @@
+use crate::{
+  shared::Boxed, AppConfigError, AppConfigManager, FormatError, Result, SharedData, ThreadingBound,
+};
+use serde::{
+  de::DeserializeOwned, ser::SerializeMap, Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{cell::Cell, fmt, path::Path};
+use toml::Value;
+
+/// Written to the file in place of a secret kept in the [`SecretStore`].
+const PLACEHOLDER: &str = "<secret store>";
+
+/// The key of the table a [`Secret`] is serialized to while the manager collects the secrets.
+const MARKER: &str = "__appconfig_secret";
+
+thread_local! {
+  static COLLECTING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// A field kept in the [`SecretStore`] set with
+/// [`with_secret_store`](AppConfigManager::with_secret_store) instead of the file.
+///
+/// Without a store, or when it is not available, the value is written to the file as is.
+/// `Debug` never prints the value.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+  pub fn new(value: T) -> Self {
+    Self(value)
+  }
+
+  pub fn expose(&self) -> &T {
+    &self.0
+  }
+
+  pub fn expose_mut(&mut self) -> &mut T {
+    &mut self.0
+  }
+
+  pub fn into_inner(self) -> T {
+    self.0
+  }
+}
+
+impl<T> From<T> for Secret<T> {
+  fn from(value: T) -> Self {
+    Self(value)
+  }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("Secret(***)")
+  }
+}
+
+impl<T: Serialize> Serialize for Secret<T> {
+  fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    if !COLLECTING.with(Cell::get) {
+      return self.0.serialize(serializer);
+    }
+    let mut map = serializer.serialize_map(Some(1))?;
+    map.serialize_entry(MARKER, &self.0)?;
+    map.end()
+  }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Secret<T> {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+    T::deserialize(deserializer).map(Self)
+  }
+}
+
+/// Keeps the [`Secret`] fields of the configuration, e.g. in the keychain of the OS with
+/// [`SystemKeyring`](crate::SystemKeyring).
+///
+/// A secret is stored under the service `com.{organization_name}.{app_name}`
+/// with the dotted path of the field as the key, e.g. `accounts.0.token`.
+pub trait SecretStore {
+  /// The secret stored under `key`, or `None` if there is none.
+  fn get(&self, service: &str, key: &str) -> std::result::Result<Option<String>, FormatError>;
+
+  fn set(&self, service: &str, key: &str, secret: &str) -> std::result::Result<(), FormatError>;
+
+  /// Returns `false` when the store cannot be used, e.g. on a headless machine without a keyring.
+  /// The secrets are then written to the file.
+  fn is_available(&self) -> bool {
+    true
+  }
+}
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// Keeps the [`Secret`] fields in `store`, writing only a placeholder to the file.
+  ///
+  /// A placeholder whose secret is missing from the store fails to load with
+  /// [`AppConfigError::SecretUnavailable`]. Requires one of the built-in formats.
+  pub fn set_secret_store(
+    &mut self,
+    store: impl SecretStore + ThreadingBound<S::Threading> + 'static,
+  ) -> &mut Self {
+    self.secret_store = Some(Boxed::new(store, |store| Box::new(store) as _));
+    self
+  }
+
+  pub fn with_secret_store(
+    mut self,
+    store: impl SecretStore + ThreadingBound<S::Threading> + 'static,
+  ) -> Self {
+    self.set_secret_store(store);
+    self
+  }
+
+  /// Returns `true` if the secrets are kept out of the file.
+  pub(crate) fn collects_secrets(&self) -> bool {
+    self.secret_store.as_ref().is_some_and(|x| x.is_available())
+  }
+
+  /// Converts `data` to a [`Value`] in which the secrets can be found by `store_secrets`.
+  pub(crate) fn secret_value(&self, data: &T) -> std::result::Result<Value, toml::ser::Error> {
+    let collecting = self.collects_secrets();
+    COLLECTING.with(|x| x.set(collecting));
+    let result = Value::try_from(data);
+    COLLECTING.with(|x| x.set(false));
+    result
+  }
+
+  /// Moves the secrets in `value` to the store, replacing them with placeholders.
+  pub(crate) fn store_secrets(&self, path: &Path, value: &mut Value) -> Result {
+    let store = match &self.secret_store {
+      Some(store) => store,
+      None => return Ok(()),
+    };
+    let service = self.reverse_dns_name();
+    visit(value, &mut Vec::new(), &mut |key, value| {
+      let secret = match value.as_table() {
+        Some(table) if table.len() == 1 => match table.get(MARKER) {
+          Some(secret) => secret,
+          None => return Ok(()),
+        },
+        _ => return Ok(()),
+      };
+      let secret = secret.as_str().ok_or_else(|| AppConfigError::Serialize {
+        path: path.to_owned(),
+        source: format!("The secret {} is not a string", key).into(),
+      })?;
+      store
+        .set(&service, key, secret)
+        .map_err(|source| AppConfigError::SecretUnavailable {
+          path: path.to_owned(),
+          key: key.to_owned(),
+          source: Some(source),
+        })?;
+      *value = Value::String(PLACEHOLDER.to_owned());
+      Ok(())
+    })
+  }
+
+  /// Replaces the placeholders in `value` read from `path` with the secrets from the store.
+  pub(crate) fn load_secrets(&self, path: &Path, mut value: Value) -> Result<Value> {
+    let store = match &self.secret_store {
+      Some(store) => store,
+      None => return Ok(value),
+    };
+    let service = self.reverse_dns_name();
+    visit(&mut value, &mut Vec::new(), &mut |key, value| {
+      if value.as_str() != Some(PLACEHOLDER) {
+        return Ok(());
+      }
+      let unavailable = |source| AppConfigError::SecretUnavailable {
+        path: path.to_owned(),
+        key: key.to_owned(),
+        source,
+      };
+      if !store.is_available() {
+        return Err(unavailable(Some(
+          "The secret store is not available".into(),
+        )));
+      }
+      match store.get(&service, key) {
+        Ok(Some(secret)) => {
+          *value = Value::String(secret);
+          Ok(())
+        }
+        Ok(None) => Err(unavailable(None)),
+        Err(err) => Err(unavailable(Some(err))),
+      }
+    })?;
+    Ok(value)
+  }
+}
+
+/// Calls `f` with each value in `value` and its dotted path, parents first.
+fn visit(
+  value: &mut Value,
+  path: &mut Vec<String>,
+  f: &mut dyn FnMut(&str, &mut Value) -> Result,
+) -> Result {
+  f(&path.join("."), value)?;
+  match value {
+    Value::Table(table) => {
+      for (key, value) in table.iter_mut() {
+        path.push(key.clone());
+        visit(value, path, f)?;
+        path.pop();
+      }
+    }
+    Value::Array(array) => {
+      for (i, value) in array.iter_mut().enumerate() {
+        path.push(i.to_string());
+        visit(value, path, f)?;
+        path.pop();
+      }
+    }
+    _ => {}
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Secret, SecretStore};
+  use crate::{AppConfigError, AppConfigManager, FormatError};
+  use serde::{Deserialize, Serialize};
+  use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Account {
+    user: String,
+    token: Secret<String>,
+  }
+
+  #[derive(Clone, Default)]
+  struct MemoryKeyring {
+    entries: Rc<RefCell<HashMap<(String, String), String>>>,
+    unavailable: bool,
+  }
+
+  impl SecretStore for MemoryKeyring {
+    fn get(&self, service: &str, key: &str) -> Result<Option<String>, FormatError> {
+      let entry = (service.to_owned(), key.to_owned());
+      Ok(self.entries.borrow().get(&entry).cloned())
+    }
+
+    fn set(&self, service: &str, key: &str, secret: &str) -> Result<(), FormatError> {
+      let entry = (service.to_owned(), key.to_owned());
+      self.entries.borrow_mut().insert(entry, secret.to_owned());
+      Ok(())
+    }
+
+    fn is_available(&self) -> bool {
+      !self.unavailable
+    }
+  }
+
+  #[test]
+  fn secret_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Account {
+      user: "alice".to_owned(),
+      token: Secret::new("s3cr3t".to_owned()),
+    }));
+    let keyring = MemoryKeyring::default();
+    let new_manager = |keyring: MemoryKeyring| {
+      AppConfigManager::new(config.clone(), "secrets", "sumibi-yakitori")
+        .with_base_dir(dir.path())
+        .with_secret_store(keyring)
+        .with_auto_saving(false)
+    };
+    let manager = new_manager(keyring.clone());
+    let path = manager.get_user_config_path().unwrap();
+    manager.save().unwrap();
+    assert_eq!(
+      std::fs::read_to_string(&path).unwrap(),
+      "token = \"<secret store>\"\nuser = \"alice\"\n"
+    );
+    let entry = ("com.sumibi-yakitori.secrets".to_owned(), "token".to_owned());
+    assert_eq!(keyring.entries.borrow()[&entry], "s3cr3t");
+
+    *config.borrow_mut() = Account::default();
+    manager.load().unwrap();
+    assert_eq!(config.borrow().token.expose(), "s3cr3t");
+    assert_eq!(format!("{:?}", config.borrow().token), "Secret(***)");
+
+    // Not a parse error
+    keyring.entries.borrow_mut().clear();
+    let manager = manager.with_skip_parsing_error_when_loading(true);
+    assert!(matches!(
+      manager.load(),
+      Err(AppConfigError::SecretUnavailable { .. })
+    ));
+
+    // Written to the file without a keyring
+    let manager = new_manager(MemoryKeyring {
+      unavailable: true,
+      ..keyring
+    });
+    manager.save().unwrap();
+    assert!(std::fs::read_to_string(&path).unwrap().contains("s3cr3t"));
+  }
+}
@@ -0,0 +1,181 @@
+use crate::{AppConfigManager, Result, SharedData};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+  fmt,
+  path::{Path, PathBuf},
+};
+
+/// A loaded file that other users can read or write,
+/// passed to the callback set with [`AppConfigManager::with_on_security_warning`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SecurityWarning {
+  /// The file, resolved when the configuration file is a symbolic link.
+  pub path: PathBuf,
+  /// The Unix permissions it was loaded with.
+  pub mode: u32,
+  /// The group and other permissions were removed
+  /// by [`with_fix_insecure_permissions`](AppConfigManager::with_fix_insecure_permissions).
+  pub fixed: bool,
+}
+
+impl fmt::Display for SecurityWarning {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} is accessible by other users (mode {:o})",
+      self.path.display(),
+      self.mode
+    )?;
+    if self.fixed {
+      write!(f, ", changed to {:o}", self.mode & !0o077)?;
+    }
+    Ok(())
+  }
+}
+
+/// The check of `with_check_permissions` without the manager, so that `load_async`
+/// can run it on another thread.
+pub(crate) struct PermissionCheck {
+  fix: bool,
+  /// The machine-wide file, which is not checked.
+  system: Option<PathBuf>,
+}
+
+impl PermissionCheck {
+  /// Returns the warning for the file at `path` if other users can access it.
+  #[cfg(unix)]
+  pub(crate) fn run(&self, path: &Path) -> Result<Option<SecurityWarning>> {
+    use crate::AppConfigError;
+    use std::{fs::Permissions, os::unix::fs::PermissionsExt};
+
+    if self.system.as_deref() == Some(path) {
+      return Ok(None);
+    }
+    let path = match std::fs::canonicalize(path) {
+      Ok(path) => path,
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+      Err(err) => return Err(AppConfigError::io(path)(err)),
+    };
+    let mode = std::fs::metadata(&path)
+      .map_err(AppConfigError::io(&path))?
+      .permissions()
+      .mode()
+      & 0o7777;
+    if mode & 0o077 == 0 {
+      return Ok(None);
+    }
+    if self.fix {
+      std::fs::set_permissions(&path, Permissions::from_mode(mode & !0o077))
+        .map_err(AppConfigError::io(&path))?;
+    }
+    Ok(Some(SecurityWarning {
+      path,
+      mode,
+      fixed: self.fix,
+    }))
+  }
+
+  #[cfg(not(unix))]
+  pub(crate) fn run(&self, _path: &Path) -> Result<Option<SecurityWarning>> {
+    let _ = (self.fix, &self.system);
+    Ok(None)
+  }
+}
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// Reports the file at `path` if other users can access it, when enabled.
+  pub(crate) fn check_permissions(&self, path: &Path) -> Result {
+    if let Some(check) = self.permission_check() {
+      if let Some(warning) = check.run(path)? {
+        self.report_security_warning(&warning);
+      }
+    }
+    Ok(())
+  }
+
+  /// The check of the loaded file, `None` when disabled.
+  pub(crate) fn permission_check(&self) -> Option<PermissionCheck> {
+    if !cfg!(unix) || !(self.permission_check || self.fix_insecure_permissions) || self.uses_store()
+    {
+      return None;
+    }
+    Some(PermissionCheck {
+      fix: self.fix_insecure_permissions,
+      system: self.system_config_path().ok(),
+    })
+  }
+
+  /// Passes `warning` to the callback of `with_on_security_warning`, or prints it.
+  pub(crate) fn report_security_warning(&self, warning: &SecurityWarning) {
+    use crate::shared::lock;
+
+    event!(Warn, "{}", warning);
+    match lock(&self.on_security_warning).as_mut() {
+      Some(f) => f(warning),
+      None => eprintln!("{}", warning),
+    }
+  }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+  use crate::AppConfigManager;
+  use serde::{Deserialize, Serialize};
+  use std::{cell::RefCell, fs::Permissions, os::unix::fs::PermissionsExt, path::Path, rc::Rc};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Token {
+    token: String,
+  }
+
+  fn mode(path: &Path) -> u32 {
+    std::fs::metadata(path).unwrap().permissions().mode() & 0o777
+  }
+
+  #[test]
+  fn insecure_permissions() {
+    let dir = tempfile::tempdir().unwrap();
+    let warnings = Rc::new(RefCell::new(Vec::new()));
+    let config = Rc::from(RefCell::from(Token::default()));
+    let mut manager = AppConfigManager::new(config.clone(), "insecure", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_check_permissions(true)
+      .with_on_security_warning({
+        let warnings = warnings.clone();
+        move |warning| warnings.borrow_mut().push(warning.clone())
+      })
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    std::fs::write(&path, "token = \"secret\"\n").unwrap();
+    std::fs::set_permissions(&path, Permissions::from_mode(0o644)).unwrap();
+
+    manager.load().unwrap();
+    assert_eq!(warnings.borrow().len(), 1);
+    assert_eq!(warnings.borrow()[0].mode, 0o644);
+    assert!(!warnings.borrow()[0].fixed);
+    assert_eq!(mode(&path), 0o644);
+
+    // The target of a link is checked and fixed
+    let target = dir.path().join("token.toml");
+    std::fs::rename(&path, &target).unwrap();
+    std::os::unix::fs::symlink(&target, &path).unwrap();
+    manager.set_fix_insecure_permissions(true);
+    manager.load().unwrap();
+    assert_eq!(warnings.borrow().len(), 2);
+    assert_eq!(
+      warnings.borrow()[1].path,
+      std::fs::canonicalize(&target).unwrap()
+    );
+    assert_eq!(mode(&target), 0o600);
+    assert!(std::fs::symlink_metadata(&path).unwrap().is_symlink());
+
+    manager.load().unwrap();
+    assert_eq!(warnings.borrow().len(), 2);
+    assert_eq!(config.borrow().token, "secret");
+  }
+}
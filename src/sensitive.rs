@@ -0,0 +1,142 @@
+use crate::{AppConfigManager, SharedData};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+  fmt,
+  ops::{Deref, DerefMut},
+};
+
+/// A field that `Debug` prints as `***`, e.g. for an API token in a configuration
+/// that ends up in a crash report. It is saved and loaded like `T`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Sensitive<T>(pub T);
+
+impl<T> Sensitive<T> {
+  pub fn into_inner(self) -> T {
+    self.0
+  }
+}
+
+impl<T> From<T> for Sensitive<T> {
+  fn from(value: T) -> Self {
+    Self(value)
+  }
+}
+
+impl<T> Deref for Sensitive<T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    &self.0
+  }
+}
+
+impl<T> DerefMut for Sensitive<T> {
+  fn deref_mut(&mut self) -> &mut T {
+    &mut self.0
+  }
+}
+
+impl<T> fmt::Debug for Sensitive<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("***")
+  }
+}
+
+impl<T: Serialize> Serialize for Sensitive<T> {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    self.0.serialize(serializer)
+  }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Sensitive<T> {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    T::deserialize(deserializer).map(Self)
+  }
+}
+
+/// Prints the location and the options, never the data.
+impl<T, S> fmt::Debug for AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("AppConfigManager")
+      .field("app_name", &self.app_name)
+      .field("organization_name", &self.organization_name)
+      .field("path", &self.user_config_path().ok())
+      .field("format", &self.format.extension())
+      .field("location", &self.location)
+      .field("base_dir", &self.base_dir)
+      .field("auto_saving", &self.auto_saving)
+      .field("backup", &self.backup)
+      .field("file_lock", &self.file_lock)
+      .field("encrypted", &self.encrypts())
+      .finish_non_exhaustive()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Sensitive;
+  use crate::AppConfigManager;
+  use serde::{Deserialize, Serialize};
+  use std::{cell::RefCell, rc::Rc};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  enum Mode {
+    #[default]
+    Fast,
+    Safe,
+  }
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Api {
+    api_token: Sensitive<String>,
+    retries: u32,
+    mode: Mode,
+  }
+
+  const TOKEN: &str = "sk-live-0123456789";
+
+  #[test]
+  fn redaction() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Api {
+      api_token: Sensitive(TOKEN.to_owned()),
+      retries: 3,
+      mode: Mode::Safe,
+    }));
+    let manager = AppConfigManager::new(config.clone(), "redaction", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    assert!(!format!("{:?}", config.borrow()).contains(TOKEN));
+    assert!(!format!("{:?}", manager).contains(TOKEN));
+    manager.save().unwrap();
+    assert!(std::fs::read_to_string(&path).unwrap().contains(TOKEN));
+
+    for (content, location) in [
+      // An unterminated string
+      (format!("api_token = \"{}\nretries = 3\n", TOKEN), "line 1"),
+      // The wrong type
+      (
+        format!("api_token = \"a\"\nretries = \"{}\"\n", TOKEN),
+        "line 2",
+      ),
+      // Not one of the variants
+      (
+        format!("api_token = \"a\"\nretries = 3\nmode = \"{}\"\n", TOKEN),
+        "line 3",
+      ),
+    ] {
+      std::fs::write(&path, content).unwrap();
+      let err = manager.load().unwrap_err();
+      for message in [err.to_string(), format!("{:?}", err)] {
+        assert!(!message.contains(TOKEN), "{}", message);
+        assert!(message.contains(location), "{}", message);
+      }
+    }
+  }
+}
@@ -0,0 +1,257 @@
+use std::{
+  cell::RefCell,
+  marker::PhantomData,
+  ops::{Deref, DerefMut},
+  rc::Rc,
+  sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock},
+};
+
+/// The pointer through which the application and an [`AppConfigManager`](crate::AppConfigManager)
+/// share the data, e.g. `Rc<RefCell<T>>` or `Arc<RwLock<T>>`.
+///
+/// Implement it to keep the data in another cell, such as a `parking_lot::Mutex`
+/// or a resource of an ECS. The manager never keeps the data borrowed between calls.
+/// While the trait is in scope, the methods of an `RwLock` are called as `RwLock::read(&lock)`.
+///
+/// ```
+/// use appconfig::{AppConfigManager, SharedData, SingleThreaded};
+/// use std::{cell::RefCell, collections::HashMap, rc::Rc};
+///
+/// /// The settings of every window.
+/// #[derive(Clone, Default)]
+/// struct Windows(Rc<RefCell<HashMap<String, (u32, u32)>>>);
+///
+/// impl SharedData<HashMap<String, (u32, u32)>> for Windows {
+///   type Threading = SingleThreaded;
+///
+///   fn read<R>(&self, f: impl FnOnce(&HashMap<String, (u32, u32)>) -> R) -> R {
+///     f(&self.0.borrow())
+///   }
+///
+///   fn write<R>(&self, f: impl FnOnce(&mut HashMap<String, (u32, u32)>) -> R) -> R {
+///     f(&mut self.0.borrow_mut())
+///   }
+/// }
+///
+/// let windows = Windows::default();
+/// let manager = AppConfigManager::new(windows.clone(), "app", "org").with_auto_saving(false);
+/// ```
+pub trait SharedData<T> {
+  /// [`SingleThreaded`], or [`MultiThreaded`] if the pointer is `Send + Sync`.
+  type Threading;
+
+  /// Calls `f` with the data borrowed.
+  fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R;
+
+  /// Like `read`, but returns `None` instead of panicking when the data is borrowed mutably.
+  /// Calls `read` by default.
+  fn try_read<R>(&self, f: impl FnOnce(&T) -> R) -> Option<R> {
+    Some(self.read(f))
+  }
+
+  /// Calls `f` with the data borrowed mutably.
+  fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R;
+
+  /// Like `write`, but returns `None` instead of panicking when the data is already borrowed.
+  /// Calls `write` by default.
+  fn try_write<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+    Some(self.write(f))
+  }
+}
+
+/// The data is shared with `Rc`, callbacks can capture anything.
+pub enum SingleThreaded {}
+
+/// The data is shared with `Arc`, callbacks must be `Send + Sync`.
+pub enum MultiThreaded {}
+
+/// Implemented by the callbacks that a manager with the given [`SharedData::Threading`] accepts.
+///
+/// ```compile_fail
+/// # use std::{rc::Rc, sync::{Arc, RwLock}};
+/// # use appconfig::AtomicAppConfigManager;
+/// let loads = Rc::new(());
+/// let manager = AtomicAppConfigManager::new(Arc::new(RwLock::new(0_u32)), "app", "org")
+///   .with_on_load(move |_| drop(loads.clone()));
+/// ```
+pub trait ThreadingBound<M> {}
+
+impl<F: ?Sized> ThreadingBound<SingleThreaded> for F {}
+
+impl<F: ?Sized + Send + Sync> ThreadingBound<MultiThreaded> for F {}
+
+impl<T> SharedData<T> for Rc<RefCell<T>> {
+  type Threading = SingleThreaded;
+
+  fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+    f(&self.borrow())
+  }
+
+  fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+    f(&mut self.borrow_mut())
+  }
+
+  fn try_read<R>(&self, f: impl FnOnce(&T) -> R) -> Option<R> {
+    Some(f(&*self.try_borrow().ok()?))
+  }
+
+  fn try_write<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+    let mut data = self.try_borrow_mut().ok()?;
+    Some(f(&mut data))
+  }
+}
+
+/// A poisoned lock is used as it is, so the data of a panicked thread is still saved.
+impl<T> SharedData<T> for Arc<RwLock<T>> {
+  type Threading = MultiThreaded;
+
+  fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+    f(&RwLock::read(self).unwrap_or_else(PoisonError::into_inner))
+  }
+
+  fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+    f(&mut RwLock::write(self).unwrap_or_else(PoisonError::into_inner))
+  }
+}
+
+/// A boxed callback or format of a manager, `Send + Sync` if the manager is [`MultiThreaded`].
+pub(crate) struct Boxed<M, F: ?Sized> {
+  f: Box<F>,
+  threading: PhantomData<M>,
+}
+
+impl<M, F: ?Sized> Boxed<M, F> {
+  /// Boxes `f` with `boxed`, which must not capture anything but `f`.
+  pub(crate) fn new<C: ThreadingBound<M>>(f: C, boxed: impl FnOnce(C) -> Box<F>) -> Self {
+    Self {
+      f: boxed(f),
+      threading: PhantomData,
+    }
+  }
+
+  /// Like `new`, for values that are `Send + Sync` whatever the threading.
+  pub(crate) fn sync<C: Send + Sync>(f: C, boxed: impl FnOnce(C) -> Box<F>) -> Self {
+    Self {
+      f: boxed(f),
+      threading: PhantomData,
+    }
+  }
+}
+
+// SAFETY: `new` only accepts `Send + Sync` values for a multi-threaded manager.
+unsafe impl<F: ?Sized> Send for Boxed<MultiThreaded, F> {}
+
+// SAFETY: See above.
+unsafe impl<F: ?Sized> Sync for Boxed<MultiThreaded, F> {}
+
+impl<M, F: ?Sized> Deref for Boxed<M, F> {
+  type Target = F;
+
+  fn deref(&self) -> &F {
+    &self.f
+  }
+}
+
+impl<M, F: ?Sized> DerefMut for Boxed<M, F> {
+  fn deref_mut(&mut self) -> &mut F {
+    &mut self.f
+  }
+}
+
+/// Locks `mutex`, ignoring poisoning since the manager state stays consistent.
+pub(crate) fn lock<X>(mutex: &Mutex<X>) -> MutexGuard<'_, X> {
+  mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::MultiThreaded;
+  use crate::{AppConfigManager, AtomicAppConfigManager};
+  use serde::{Deserialize, Serialize};
+  use std::sync::{Arc, Mutex, RwLock};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Volume {
+    level: u32,
+  }
+
+  #[test]
+  fn atomic_manager() {
+    fn assert_send_sync<X: Send + Sync>(_: &X) {}
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = Arc::new(RwLock::new(Volume { level: 1 }));
+    let manager = Arc::new(
+      AtomicAppConfigManager::new(config.clone(), "atomic", "sumibi-yakitori")
+        .with_base_dir(dir.path())
+        .with_on_save(|data: &Volume| assert!(data.level > 0)),
+    );
+    assert_send_sync(&manager);
+    let path = manager.get_user_config_path().unwrap();
+
+    // Readers keep the data locked while another thread saves it
+    std::thread::scope(|scope| {
+      for _ in 0..4 {
+        scope.spawn(|| {
+          for _ in 0..100 {
+            assert!(config.read().unwrap().level > 0);
+          }
+        });
+      }
+      scope.spawn(|| {
+        for _ in 0..20 {
+          manager.save().unwrap();
+        }
+      });
+    });
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "level = 1\n");
+
+    // A thread panicked while writing
+    std::thread::spawn({
+      let config = config.clone();
+      move || {
+        let mut data = config.write().unwrap();
+        data.level = 2;
+        panic!();
+      }
+    })
+    .join()
+    .unwrap_err();
+    assert!(config.is_poisoned());
+    drop(Arc::try_unwrap(manager).ok().unwrap());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "level = 2\n");
+  }
+
+  #[derive(Clone, Default)]
+  struct Resource(Arc<Mutex<Volume>>);
+
+  impl super::SharedData<Volume> for Resource {
+    type Threading = MultiThreaded;
+
+    fn read<R>(&self, f: impl FnOnce(&Volume) -> R) -> R {
+      f(&self.0.lock().unwrap())
+    }
+
+    fn write<R>(&self, f: impl FnOnce(&mut Volume) -> R) -> R {
+      f(&mut self.0.lock().unwrap())
+    }
+  }
+
+  #[test]
+  fn custom_shared_data() {
+    use super::SharedData;
+
+    let dir = tempfile::tempdir().unwrap();
+    let resource = Resource::default();
+    let manager = AppConfigManager::new(resource.clone(), "custom", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    std::fs::write(&path, "level = 3\n").unwrap();
+    manager.load().unwrap();
+    assert_eq!(*resource.0.lock().unwrap(), Volume { level: 3 });
+    manager.data().write(|data| data.level = 4);
+    manager.save().unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "level = 4\n");
+  }
+}
@@ -0,0 +1,118 @@
+use crate::{AppConfigError, AppConfigManager, Result, SharedData};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{fmt, marker::PhantomData, sync::atomic::Ordering};
+
+/// The data at one point in time, taken by [`AppConfigManager::snapshot`].
+///
+/// Snapshots compare equal when the data serializes to the same contents.
+pub struct ConfigSnapshot<T> {
+  contents: Vec<u8>,
+  data: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for ConfigSnapshot<T> {
+  fn clone(&self) -> Self {
+    Self {
+      contents: self.contents.clone(),
+      data: PhantomData,
+    }
+  }
+}
+
+impl<T> PartialEq for ConfigSnapshot<T> {
+  fn eq(&self, other: &Self) -> bool {
+    self.contents == other.contents
+  }
+}
+
+impl<T> Eq for ConfigSnapshot<T> {}
+
+impl<T> fmt::Debug for ConfigSnapshot<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_tuple("ConfigSnapshot")
+      .field(&String::from_utf8_lossy(&self.contents))
+      .finish()
+  }
+}
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// Takes a copy of the data in the configured format, e.g. when a settings dialog opens.
+  /// Nothing is written.
+  pub fn snapshot(&self) -> Result<ConfigSnapshot<T>> {
+    let path = self.user_config_path()?;
+    let contents = self
+      .data
+      .read(|data| self.format.serialize_with(data, &self.save_options))
+      .map_err(AppConfigError::serialize(&path))?;
+    Ok(ConfigSnapshot {
+      contents,
+      data: PhantomData,
+    })
+  }
+
+  /// Replaces the data with `snapshot`, e.g. when the settings dialog is cancelled.
+  /// Nothing is written, but the next `save_if_dirty()` writes the file.
+  pub fn restore(&self, snapshot: &ConfigSnapshot<T>) -> Result {
+    let path = self.user_config_path()?;
+    let value = self
+      .format
+      .deserialize(&snapshot.contents)
+      .map_err(AppConfigError::deserialize(&path))?;
+    self.data.write(|data| *data = value);
+    self.dirty.store(true, Ordering::Relaxed);
+    Ok(())
+  }
+
+  /// Returns `true` if the data changed since `snapshot` was taken,
+  /// e.g. to enable the Apply button of a settings dialog.
+  pub fn differs_from_current(&self, snapshot: &ConfigSnapshot<T>) -> bool {
+    self.snapshot().map_or(true, |current| current != *snapshot)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::AppConfigManager;
+  use serde::{Deserialize, Serialize};
+  use std::{cell::RefCell, rc::Rc};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Theme {
+    name: String,
+  }
+
+  #[test]
+  fn snapshot() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Theme {
+      name: "light".to_owned(),
+    }));
+    let manager = AppConfigManager::new(config.clone(), "snapshot", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    manager.save().unwrap();
+
+    let snapshot = manager.snapshot().unwrap();
+    assert!(!manager.differs_from_current(&snapshot));
+    config.borrow_mut().name = "dark".to_owned();
+    assert!(manager.differs_from_current(&snapshot));
+    assert_ne!(manager.snapshot().unwrap(), snapshot);
+    manager.save().unwrap();
+
+    // Cancelled
+    manager.restore(&snapshot).unwrap();
+    assert_eq!(config.borrow().name, "light");
+    assert!(!manager.differs_from_current(&snapshot));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "name = \"dark\"\n");
+    assert!(manager.save_if_dirty().unwrap());
+    assert_eq!(
+      std::fs::read_to_string(&path).unwrap(),
+      "name = \"light\"\n"
+    );
+  }
+}
@@ -0,0 +1,555 @@
+use crate::{fs, shared::lock, AppConfigError, AppConfigManager, Result, SharedData};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+  collections::HashMap,
+  fmt, io,
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+};
+
+/// Where a manager keeps its files instead of the file system, set with
+/// [`AppConfigManager::with_store`], e.g. a database or the virtual file system of a game.
+///
+/// Every file the manager reads or writes goes through the store: the configuration file,
+/// its backups, the system-wide file and files left behind in other formats.
+/// Errors are reported as [`AppConfigError::Io`] with [`ConfigKey::path`], except for the kinds
+/// `StorageFull` and `QuotaExceeded`, reported as [`AppConfigError::QuotaExceeded`], and
+/// `Unsupported`, reported as [`AppConfigError::StorageUnavailable`].
+///
+/// A file must be replaced as a whole by `write`: a reader never sees a partial file,
+/// even if the program crashes. The file system does this with a rename, a store has to
+/// provide the same guarantee itself, e.g. with a transaction. A store is `Send + Sync`,
+/// since `enable_autosave` and `save_async` write through it from another thread.
+///
+/// ```
+/// use appconfig::{ConfigKey, ConfigStore};
+/// use std::{io, path::PathBuf};
+///
+/// /// Keeps every file below one folder, e.g. `saves/com.org.app/app_config.toml`.
+/// struct PrefixStore(PathBuf);
+///
+/// impl ConfigStore for PrefixStore {
+///   fn read(&self, key: &ConfigKey) -> io::Result<Option<Vec<u8>>> {
+///     match std::fs::read(self.0.join(key.to_string())) {
+///       Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+///       result => result.map(Some),
+///     }
+///   }
+///
+///   fn write(&self, key: &ConfigKey, contents: &[u8]) -> io::Result<()> {
+///     let path = self.0.join(key.to_string());
+///     std::fs::create_dir_all(path.parent().unwrap())?;
+///     // Written next to the file and renamed over it, so it is replaced as a whole
+///     let temp = path.with_extension("tmp");
+///     std::fs::write(&temp, contents)?;
+///     std::fs::rename(&temp, &path)
+///   }
+///
+///   fn remove(&self, key: &ConfigKey) -> io::Result<()> {
+///     match std::fs::remove_file(self.0.join(key.to_string())) {
+///       Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+///       result => result,
+///     }
+///   }
+/// }
+///
+/// # #[derive(Default, serde::Serialize, serde::Deserialize)]
+/// # struct MyAppConfig {
+/// #   volume: u32,
+/// # }
+/// # let dir = tempfile::tempdir().unwrap();
+/// let config = std::rc::Rc::new(std::cell::RefCell::new(MyAppConfig::default()));
+/// let manager = appconfig::AppConfigManager::new(config, "app", "org")
+///   .with_store(PrefixStore(dir.path().join("saves")));
+/// manager.save().unwrap();
+/// assert!(dir.path().join("saves/com.org.app/app_config.toml").exists());
+/// ```
+pub trait ConfigStore {
+  /// The contents of the file, or `None` if there is none.
+  fn read(&self, key: &ConfigKey) -> io::Result<Option<Vec<u8>>>;
+
+  /// Replaces the file as a whole, see above.
+  fn write(&self, key: &ConfigKey, contents: &[u8]) -> io::Result<()>;
+
+  /// Does nothing if there is no file.
+  fn remove(&self, key: &ConfigKey) -> io::Result<()>;
+}
+
+impl<C: ConfigStore + ?Sized> ConfigStore for Arc<C> {
+  fn read(&self, key: &ConfigKey) -> io::Result<Option<Vec<u8>>> {
+    (**self).read(key)
+  }
+
+  fn write(&self, key: &ConfigKey, contents: &[u8]) -> io::Result<()> {
+    (**self).write(key, contents)
+  }
+
+  fn remove(&self, key: &ConfigKey) -> io::Result<()> {
+    (**self).remove(key)
+  }
+}
+
+/// Identifies a file in a [`ConfigStore`].
+///
+/// Displayed as `{folder}/{subdirectory}/{file name}`, e.g. `com.org.app/app_config.toml.bak`,
+/// with a `system/` prefix for the system-wide file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ConfigKey {
+  folder: String,
+  qualifier: String,
+  organization_name: String,
+  app_name: String,
+  subdirectory: PathBuf,
+  file_name: String,
+  system: bool,
+  path: PathBuf,
+}
+
+impl ConfigKey {
+  /// The organization the file belongs to, which differs from the manager
+  /// for the file of a legacy identity. Sanitized like the folder name with `with_lenient_names`.
+  pub fn organization_name(&self) -> &str {
+    &self.organization_name
+  }
+
+  pub fn app_name(&self) -> &str {
+    &self.app_name
+  }
+
+  /// e.g. `com`, see [`with_qualifier`](crate::AppConfigManager::with_qualifier).
+  pub fn qualifier(&self) -> &str {
+    &self.qualifier
+  }
+
+  /// The name of the application folder, e.g. `com.org.app`, even when the file system path
+  /// does not use it.
+  pub fn folder(&self) -> &str {
+    &self.folder
+  }
+
+  /// The folder set with [`with_subdirectory`](crate::AppConfigManager::with_subdirectory),
+  /// empty by default.
+  pub fn subdirectory(&self) -> &Path {
+    &self.subdirectory
+  }
+
+  /// e.g. `app_config.toml` or `app_config.toml.1`
+  pub fn file_name(&self) -> &str {
+    &self.file_name
+  }
+
+  /// Returns `true` for the system-wide file of `with_system_fallback` and `with_system_layer`.
+  pub fn is_system(&self) -> bool {
+    self.system
+  }
+
+  /// Where the file would be on the file system, which also tells apart the files of
+  /// different locations, base directories and portable mode.
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+}
+
+impl fmt::Display for ConfigKey {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.system {
+      f.write_str("system/")?;
+    }
+    write!(f, "{}/", self.folder)?;
+    for component in self.subdirectory.iter() {
+      write!(f, "{}/", component.to_string_lossy())?;
+    }
+    f.write_str(&self.file_name)
+  }
+}
+
+/// Keeps the files of a manager in memory, e.g. for tests that must not touch
+/// the configuration directory of the user. Clones share the files.
+///
+/// The files are keyed by [`ConfigKey::path`].
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+  files: Arc<Mutex<HashMap<PathBuf, Vec<u8>>>>,
+}
+
+impl MemoryStore {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// The contents of the file at `path`.
+  pub fn get(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+    lock(&self.files).get(path.as_ref()).cloned()
+  }
+
+  /// Adds or replaces the file at `path`, e.g. to prepare a test.
+  pub fn insert(&self, path: impl Into<PathBuf>, contents: impl Into<Vec<u8>>) {
+    lock(&self.files).insert(path.into(), contents.into());
+  }
+
+  /// The paths of all files, sorted.
+  pub fn paths(&self) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = lock(&self.files).keys().cloned().collect();
+    paths.sort();
+    paths
+  }
+}
+
+impl ConfigStore for MemoryStore {
+  fn read(&self, key: &ConfigKey) -> io::Result<Option<Vec<u8>>> {
+    Ok(self.get(key.path()))
+  }
+
+  fn write(&self, key: &ConfigKey, contents: &[u8]) -> io::Result<()> {
+    self.insert(key.path(), contents);
+    Ok(())
+  }
+
+  fn remove(&self, key: &ConfigKey) -> io::Result<()> {
+    lock(&self.files).remove(key.path());
+    Ok(())
+  }
+}
+
+/// Shared with the threads of `enable_autosave` and `save_async`.
+pub(crate) type SharedStore = Arc<dyn ConfigStore + Send + Sync>;
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// Keeps the file, its backups and the system file in `store` instead of the file system,
+  /// e.g. a [`MemoryStore`] in tests. No folder is created.
+  ///
+  /// File locks and permission checks are skipped. Ownership, `watch`,
+  /// `save_to` and `load_from` still use the file system.
+  pub fn set_store(&mut self, store: impl ConfigStore + Send + Sync + 'static) -> &mut Self {
+    self.store = Some(Arc::new(store));
+    self
+  }
+
+  pub fn with_store(mut self, store: impl ConfigStore + Send + Sync + 'static) -> Self {
+    self.set_store(store);
+    self
+  }
+
+  /// Returns `true` if the files are not on the file system.
+  pub(crate) fn uses_store(&self) -> bool {
+    self.store.is_some()
+  }
+
+  /// Reads the file at `path` from the store or the file system.
+  pub(crate) fn read_stored(&self, path: &Path) -> Result<Vec<u8>> {
+    read_from(
+      self.store.as_ref(),
+      &self.config_key(path),
+      self.max_file_size,
+    )
+  }
+
+  /// Writes the file at `path` to the store, or atomically to the file system with `options`.
+  pub(crate) fn write_stored(
+    &self,
+    path: &Path,
+    contents: &[u8],
+    options: fs::WriteOptions,
+  ) -> Result {
+    let key = self.config_key(path);
+    write_to(self.store.as_ref(), &key, contents, options)
+      .map_err(store_error(self.store.as_ref(), path))
+  }
+
+  pub(crate) fn remove_stored(&self, path: &Path) -> Result {
+    match &self.store {
+      Some(store) => store
+        .remove(&self.config_key(path))
+        .map_err(store_error(Some(store), path)),
+      None => std::fs::remove_file(path).map_err(AppConfigError::io(path)),
+    }
+  }
+
+  /// Returns `true` if the file at `path` exists in the store or on the file system.
+  pub(crate) fn is_stored(&self, path: &Path) -> bool {
+    exists_in(self.store.as_ref(), &self.config_key(path))
+  }
+
+  pub(crate) fn copy_stored(&self, from: &Path, to: &Path) -> Result {
+    copy_within(
+      self.store.as_ref(),
+      &self.config_key(from),
+      &self.config_key(to),
+      self.max_file_size,
+    )
+  }
+
+  /// The keys of the numbered backups of `path`, `.1` first, see `with_backup_retention`.
+  pub(crate) fn numbered_backup_keys(&self, path: &Path) -> Vec<ConfigKey> {
+    (1..=self.backup_retention)
+      .map(|i| self.config_key(&fs::with_suffix(path, &format!(".{}", i))))
+      .collect()
+  }
+
+  pub(crate) fn shared_store(&self) -> Option<SharedStore> {
+    self.store.clone()
+  }
+
+  /// The key of the file at `path`, which belongs to a legacy identity if it is in its folder.
+  pub(crate) fn config_key(&self, path: &Path) -> ConfigKey {
+    let depth = self
+      .subdirectory
+      .as_ref()
+      .map_or(0, |x| x.components().count());
+    let dir = path.ancestors().nth(depth + 1).and_then(|x| x.file_name());
+    let (folder, qualifier, organization_name, app_name) = self
+      .legacy_folder_names()
+      .find(|(folder, ..)| dir.is_some_and(|x| *x == **folder))
+      .unwrap_or_else(|| {
+        (
+          self.reverse_dns_name(),
+          &self.qualifier,
+          &self.organization_name,
+          &self.app_name,
+        )
+      });
+    ConfigKey {
+      folder,
+      qualifier: qualifier.clone(),
+      organization_name: self.folder_name_part(organization_name),
+      app_name: self.folder_name_part(app_name),
+      subdirectory: self.subdirectory.clone().unwrap_or_default(),
+      file_name: path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned(),
+      system: self.system_config_path().ok().as_deref() == Some(path),
+      path: path.to_owned(),
+    }
+  }
+}
+
+/// Converts an error of `store`, see [`ConfigStore`], or of the file system without one.
+pub(crate) fn store_error(
+  store: Option<&SharedStore>,
+  path: &Path,
+) -> impl FnOnce(io::Error) -> AppConfigError {
+  let path = path.to_owned();
+  let custom = store.is_some();
+  move |source| match source.kind() {
+    _ if !custom => AppConfigError::Io { path, source },
+    io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded => {
+      AppConfigError::QuotaExceeded { path }
+    }
+    io::ErrorKind::Unsupported => AppConfigError::StorageUnavailable { path, source },
+    _ => AppConfigError::Io { path, source },
+  }
+}
+
+/// Reads `key` from `store`, or from the file system without one.
+/// Fails with [`AppConfigError::FileTooLarge`] for more than `limit` bytes unless it is 0.
+pub(crate) fn read_from(
+  store: Option<&SharedStore>,
+  key: &ConfigKey,
+  limit: u64,
+) -> Result<Vec<u8>> {
+  let path = key.path();
+  let store = match store {
+    Some(store) => store,
+    None => return crate::read_file(path, limit),
+  };
+  match store.read(key).map_err(store_error(Some(store), path))? {
+    Some(s) if limit > 0 && s.len() as u64 > limit => Err(AppConfigError::FileTooLarge {
+      path: path.to_owned(),
+      size: s.len() as u64,
+      limit,
+    }),
+    Some(s) => Ok(s),
+    None => {
+      event!(Info, "{} does not exist", path.display());
+      Err(AppConfigError::io(path)(io::ErrorKind::NotFound.into()))
+    }
+  }
+}
+
+/// Returns `true` if `key` exists in `store` or on the file system.
+pub(crate) fn exists_in(store: Option<&SharedStore>, key: &ConfigKey) -> bool {
+  match store {
+    Some(store) => store.read(key).ok().flatten().is_some(),
+    None => key.path().exists(),
+  }
+}
+
+/// Copies `from` to `to` in `store`, or on the file system without one.
+pub(crate) fn copy_within(
+  store: Option<&SharedStore>,
+  from: &ConfigKey,
+  to: &ConfigKey,
+  limit: u64,
+) -> Result {
+  let store = match store {
+    Some(store) => store,
+    None => {
+      return std::fs::copy(from.path(), to.path())
+        .map(|_| ())
+        .map_err(AppConfigError::io(to.path()))
+    }
+  };
+  let s = read_from(Some(store), from, limit)?;
+  store
+    .write(to, &s)
+    .map_err(store_error(Some(store), to.path()))
+}
+
+/// Like `fs::rotate_backups`, also in `store`. `numbered` are the keys of `.1`, `.2` and so on.
+pub(crate) fn rotate_within(
+  store: Option<&SharedStore>,
+  key: &ConfigKey,
+  numbered: &[ConfigKey],
+  limit: u64,
+) -> Result {
+  let store = match store {
+    Some(store) => store,
+    None => {
+      return fs::rotate_backups(key.path(), numbered.len()).map_err(AppConfigError::io(key.path()))
+    }
+  };
+  if let Some(last) = numbered.last() {
+    store
+      .remove(last)
+      .map_err(store_error(Some(store), last.path()))?;
+  }
+  for pair in numbered.windows(2).rev() {
+    if exists_in(Some(store), &pair[0]) {
+      copy_within(Some(store), &pair[0], &pair[1], limit)?;
+      store
+        .remove(&pair[0])
+        .map_err(store_error(Some(store), pair[0].path()))?;
+    }
+  }
+  match numbered.first() {
+    Some(first) => copy_within(Some(store), key, first, limit),
+    None => Ok(()),
+  }
+}
+
+/// Writes to `store`, or atomically to the file system with `options` without one,
+/// creating the folder if missing.
+pub(crate) fn write_to(
+  store: Option<&SharedStore>,
+  key: &ConfigKey,
+  contents: &[u8],
+  options: fs::WriteOptions,
+) -> io::Result<()> {
+  if let Some(store) = store {
+    return store.write(key, contents);
+  }
+  match key.path().parent() {
+    Some(dir) if !dir.as_os_str().is_empty() && !dir.exists() => {
+      fs::create_dir_all(dir, options.mode)?
+    }
+    _ => {}
+  }
+  fs::write_atomic(key.path(), contents, options)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{ConfigKey, ConfigStore, MemoryStore};
+  use crate::{shared::lock, AppConfigManager, LoadOutcome};
+  use serde::{Deserialize, Serialize};
+  use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    io,
+    rc::Rc,
+    sync::{Arc, Mutex},
+  };
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Volume {
+    level: u32,
+  }
+
+  #[test]
+  fn memory_store() {
+    let store = MemoryStore::new();
+    let config = Rc::from(RefCell::from(Volume { level: 1 }));
+    let manager = AppConfigManager::new(config.clone(), "memory_store", "sumibi-yakitori")
+      .with_store(store.clone())
+      .with_backup(true)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    assert!(!path.parent().unwrap().exists());
+    assert!(manager.load().unwrap_err().is_not_found());
+
+    manager.save().unwrap();
+    config.borrow_mut().level = 2;
+    manager.save().unwrap();
+    assert_eq!(store.get(&path).unwrap(), b"level = 2\n");
+    let backup = crate::fs::with_suffix(&path, ".bak");
+    assert_eq!(store.paths(), [path.clone(), backup.clone()]);
+
+    // Falls back to the backup
+    store.insert(&path, "level = ");
+    assert!(matches!(
+      manager.load_with_outcome().unwrap(),
+      LoadOutcome::BackupLoaded(_)
+    ));
+    assert_eq!(*config.borrow(), Volume { level: 1 });
+    assert!(!path.parent().unwrap().exists());
+  }
+
+  /// Keeps the files by their displayed keys.
+  #[derive(Default)]
+  struct KeyedStore(Mutex<BTreeMap<String, Vec<u8>>>);
+
+  impl ConfigStore for KeyedStore {
+    fn read(&self, key: &ConfigKey) -> io::Result<Option<Vec<u8>>> {
+      Ok(lock(&self.0).get(&key.to_string()).cloned())
+    }
+
+    fn write(&self, key: &ConfigKey, contents: &[u8]) -> io::Result<()> {
+      lock(&self.0).insert(key.to_string(), contents.to_owned());
+      Ok(())
+    }
+
+    fn remove(&self, key: &ConfigKey) -> io::Result<()> {
+      lock(&self.0).remove(&key.to_string());
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn config_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(KeyedStore::default());
+    let config = Rc::from(RefCell::from(Volume { level: 1 }));
+    let manager = AppConfigManager::new(config.clone(), "keyed", "sumibi-yakitori")
+      .with_store(store.clone())
+      .with_base_dir(dir.path())
+      .with_system_dir(dir.path().join("system"))
+      .with_system_fallback(true)
+      .with_backup_retention(2)
+      .with_auto_saving(false);
+    let system = manager.config_key(&manager.system_config_path().unwrap());
+    assert!(system.is_system());
+    lock(&store.0).insert(system.to_string(), b"level = 5\n".to_vec());
+    manager.load().unwrap();
+    assert_eq!(*config.borrow(), Volume { level: 5 });
+
+    manager.save().unwrap();
+    config.borrow_mut().level = 2;
+    manager.save().unwrap();
+    let keys: Vec<String> = lock(&store.0).keys().cloned().collect();
+    assert_eq!(
+      keys,
+      [
+        "com.sumibi-yakitori.keyed/app_config.toml",
+        "com.sumibi-yakitori.keyed/app_config.toml.1",
+        "system/com.sumibi-yakitori.keyed/app_config.toml",
+      ]
+    );
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+  }
+}
@@ -0,0 +1,83 @@
+use crate::{AppConfigError, AppConfigManager, Result, SharedData};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+  path::{Path, PathBuf},
+  sync::atomic::{AtomicUsize, Ordering},
+  time::SystemTime,
+};
+
+/// A new, empty folder in the temporary directory for tests, deleted with its contents on drop.
+///
+/// Managers created with [`manager`](Self::manager) use it as their base directory, so tests
+/// can run in parallel without touching each other's files or the profile of the user.
+///
+/// This is a small `tempfile::TempDir`, written here because `tempfile` is only
+/// a dev-dependency and applications should not need it to use this in their tests.
+///
+/// ```
+/// use appconfig::TestConfigDir;
+/// use std::{cell::RefCell, rc::Rc};
+///
+/// # #[derive(Default, serde::Serialize, serde::Deserialize)]
+/// # struct MyAppConfig {
+/// #   volume: u32,
+/// # }
+/// // Declared first so that it outlives the manager, which saves on drop
+/// let dir = TestConfigDir::new().unwrap();
+/// let config = Rc::new(RefCell::new(MyAppConfig::default()));
+/// let manager = dir.manager(config, "my_app", "sumibi-yakitori");
+/// manager.save().unwrap();
+/// assert!(dir.path().join("com.sumibi-yakitori.my_app/app_config.toml").exists());
+/// ```
+#[derive(Debug)]
+pub struct TestConfigDir {
+  path: PathBuf,
+}
+
+impl TestConfigDir {
+  pub fn new() -> Result<Self> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let nanos = SystemTime::now()
+      .duration_since(SystemTime::UNIX_EPOCH)
+      .map_or(0, |x| x.subsec_nanos());
+    loop {
+      let name = format!(
+        "appconfig-test-{}-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        nanos
+      );
+      let path = std::env::temp_dir().join(name);
+      // Tries the next name rather than sharing a folder left behind by another process
+      match std::fs::create_dir(&path) {
+        Ok(()) => return Ok(Self { path }),
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(err) => return Err(AppConfigError::io(&path)(err)),
+      }
+    }
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// A manager with this folder as its base directory.
+  pub fn manager<T, S>(
+    &self,
+    data: S,
+    app_name: impl Into<String>,
+    organization_name: impl Into<String>,
+  ) -> AppConfigManager<T, S>
+  where
+    T: Sized + Serialize + DeserializeOwned,
+    S: SharedData<T>,
+  {
+    AppConfigManager::new(data, app_name, organization_name).with_base_dir(&self.path)
+  }
+}
+
+impl Drop for TestConfigDir {
+  fn drop(&mut self) {
+    std::fs::remove_dir_all(&self.path).ok();
+  }
+}
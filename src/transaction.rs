@@ -0,0 +1,145 @@
+use crate::{AppConfigError, AppConfigManager, SharedData};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+
+/// The error returned by [`AppConfigManager::transaction`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum TransactionError<E> {
+  /// The closure failed. The data was restored and nothing was written.
+  Aborted(E),
+  /// The data is borrowed, the closure was not called.
+  AlreadyBorrowed,
+  /// A validator added with [`with_validator`](AppConfigManager::with_validator) rejected the changes
+  /// for the given reason. The data was restored and nothing was written.
+  Invalid(String),
+  /// The changes were kept, but saving them failed.
+  Save(AppConfigError),
+}
+
+impl<E: fmt::Display> fmt::Display for TransactionError<E> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TransactionError::Aborted(err) => write!(f, "The changes were rolled back: {}", err),
+      TransactionError::AlreadyBorrowed => write!(f, "The configuration data is already borrowed"),
+      TransactionError::Invalid(reason) => write!(f, "The changes were rolled back: {}", reason),
+      TransactionError::Save(err) => write!(f, "The changes could not be saved: {}", err),
+    }
+  }
+}
+
+impl<E> std::error::Error for TransactionError<E>
+where
+  E: std::error::Error + 'static,
+{
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      TransactionError::Aborted(err) => Some(err),
+      TransactionError::AlreadyBorrowed | TransactionError::Invalid(_) => None,
+      TransactionError::Save(err) => Some(err),
+    }
+  }
+}
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// Changes the data with `f` and saves it if `f` succeeds.
+  /// When `f` fails or a validator rejects the changes, the data is restored to what it was before
+  /// and nothing is written, e.g. for the Apply button of a settings dialog.
+  pub fn transaction<E>(
+    &self,
+    f: impl FnOnce(&mut T) -> std::result::Result<(), E>,
+  ) -> std::result::Result<(), TransactionError<E>>
+  where
+    T: Clone,
+  {
+    self
+      .data
+      .try_write(|data| {
+        let previous = data.clone();
+        let result = f(data).map_err(TransactionError::Aborted).and_then(|_| {
+          self
+            .validators
+            .iter()
+            .try_for_each(|validator| validator(data))
+            .map_err(TransactionError::Invalid)
+        });
+        if result.is_err() {
+          *data = previous;
+        }
+        result
+      })
+      .ok_or(TransactionError::AlreadyBorrowed)??;
+    self.save().map_err(TransactionError::Save)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::TransactionError;
+  use crate::AppConfigManager;
+  use serde::{Deserialize, Serialize};
+  use std::{cell::RefCell, rc::Rc};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default, Clone)]
+  struct Audio {
+    volume: u32,
+    muted: bool,
+  }
+
+  #[test]
+  fn transaction() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Audio::default()));
+    let manager = AppConfigManager::new(config.clone(), "transaction", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+
+    manager
+      .transaction(|audio| {
+        audio.volume = 50;
+        audio.muted = true;
+        Ok::<_, String>(())
+      })
+      .unwrap();
+    assert_eq!(
+      std::fs::read_to_string(&path).unwrap(),
+      "volume = 50\nmuted = true\n"
+    );
+
+    let result = manager.transaction(|audio| {
+      audio.muted = false;
+      if audio.volume > 10 {
+        return Err("too loud");
+      }
+      Ok(())
+    });
+    assert!(matches!(result, Err(TransactionError::Aborted("too loud"))));
+    assert_eq!(
+      *config.borrow(),
+      Audio {
+        volume: 50,
+        muted: true
+      }
+    );
+
+    let borrowed = config.borrow();
+    let result = manager.transaction(|_| Ok::<_, String>(()));
+    assert!(matches!(result, Err(TransactionError::AlreadyBorrowed)));
+    drop(borrowed);
+
+    // Kept in memory when the save fails
+    std::fs::remove_file(&path).unwrap();
+    std::fs::create_dir(&path).unwrap();
+    let result = manager.transaction(|audio| {
+      audio.volume = 20;
+      Ok::<_, String>(())
+    });
+    assert!(matches!(result, Err(TransactionError::Save(_))));
+    assert_eq!(config.borrow().volume, 20);
+  }
+}
@@ -0,0 +1,193 @@
+use crate::{AppConfigError, AppConfigManager, Result, SharedData};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::Path;
+
+/// What `load()` does when a validator added with
+/// [`with_validator`](AppConfigManager::with_validator) rejects the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum ValidationPolicy {
+  /// Keeps the data and handles [`AppConfigError::Invalid`] like a parse error,
+  /// so it is skipped when `skip_parsing_error_when_loading` is enabled.
+  #[default]
+  Reject,
+  /// Replaces the data with `T::default()` and reports
+  /// [`LoadOutcome::DefaultsUsed`](crate::LoadOutcome::DefaultsUsed).
+  UseDefaults,
+}
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// Runs the validators on `value`, read from or to be written to `path`.
+  pub(crate) fn validate(&self, path: &Path, value: &T) -> Result {
+    for validator in &self.validators {
+      validator(value).map_err(|reason| AppConfigError::Invalid {
+        path: path.to_owned(),
+        reason,
+      })?;
+    }
+    Ok(())
+  }
+
+  /// Runs the fixups on `value`. Returns whether any of them changed it.
+  pub(crate) fn fix_up(&self, value: &mut T) -> bool {
+    let mut fixed = false;
+    for fixup in &self.fixups {
+      fixed |= fixup(value);
+    }
+    fixed
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::ValidationPolicy;
+  use crate::{AppConfigError, AppConfigManager, LoadOutcome, TransactionError};
+  use serde::{Deserialize, Serialize};
+  use std::{cell::RefCell, rc::Rc};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+  struct Window {
+    width: i32,
+  }
+
+  impl Default for Window {
+    fn default() -> Self {
+      Self { width: 640 }
+    }
+  }
+
+  fn positive(window: &Window) -> Result<(), String> {
+    if window.width > 0 {
+      Ok(())
+    }
+    else {
+      Err(format!("width {} is not positive", window.width))
+    }
+  }
+
+  #[test]
+  fn reject() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Window { width: 100 }));
+    let manager = AppConfigManager::new(config.clone(), "reject", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_validator(positive)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    std::fs::write(&path, "width = -5\n").unwrap();
+
+    // Skipped like a parse error
+    match manager.load_with_outcome().unwrap() {
+      LoadOutcome::ParseErrorSkipped(AppConfigError::Invalid { reason, .. }) => {
+        assert_eq!(reason, "width -5 is not positive")
+      }
+      outcome => panic!("{:?}", outcome),
+    }
+    assert_eq!(*config.borrow(), Window { width: 100 });
+
+    let mut manager = manager;
+    manager.set_skip_parsing_error_when_loading(false);
+    assert!(matches!(
+      manager.load(),
+      Err(AppConfigError::Invalid { .. })
+    ));
+    assert_eq!(*config.borrow(), Window { width: 100 });
+
+    // Not checked before saving by default
+    config.borrow_mut().width = 0;
+    manager.save().unwrap();
+    manager.set_validate_on_save(true);
+    assert!(matches!(
+      manager.save(),
+      Err(AppConfigError::Invalid { .. })
+    ));
+
+    // Rolled back by a transaction
+    config.borrow_mut().width = 1;
+    let result = manager.transaction(|window| {
+      window.width = -1;
+      Ok::<_, String>(())
+    });
+    assert!(matches!(result, Err(TransactionError::Invalid(_))));
+    assert_eq!(*config.borrow(), Window { width: 1 });
+  }
+
+  #[test]
+  fn use_defaults() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Window { width: 100 }));
+    let manager = AppConfigManager::new(config.clone(), "use_defaults", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_validator(positive)
+      .with_validation_policy(ValidationPolicy::UseDefaults)
+      .with_skip_parsing_error_when_loading(false)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    std::fs::write(&path, "width = -5\n").unwrap();
+    assert!(matches!(
+      manager.load_with_outcome().unwrap(),
+      LoadOutcome::DefaultsUsed(AppConfigError::Invalid { .. })
+    ));
+    assert_eq!(*config.borrow(), Window::default());
+
+    // Parse errors are not affected
+    std::fs::write(&path, "width = ").unwrap();
+    assert!(matches!(
+      manager.load(),
+      Err(AppConfigError::Deserialize { .. })
+    ));
+  }
+
+  #[test]
+  fn fixup() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Window { width: 100 }));
+    let manager = AppConfigManager::new(config.clone(), "fixup", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_fixup(|window: &mut Window| {
+        let fixed = window.width.clamp(320, 3840);
+        std::mem::replace(&mut window.width, fixed) != fixed
+      })
+      .with_validator(positive)
+      .with_merge_defaults(true)
+      .with_backup(true)
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+
+    // Repaired before it is validated, the file is rewritten on the next save
+    std::fs::write(&path, "width = -5\n").unwrap();
+    assert!(matches!(
+      manager.load_with_outcome().unwrap(),
+      LoadOutcome::FixedUp
+    ));
+    assert_eq!(*config.borrow(), Window { width: 320 });
+    assert!(manager.is_dirty());
+    manager.save().unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "width = 320\n");
+
+    std::fs::write(&path, "width = 800\n").unwrap();
+    assert!(matches!(
+      manager.load_with_outcome().unwrap(),
+      LoadOutcome::Loaded
+    ));
+    assert!(!manager.is_dirty());
+
+    // Defaults merged into an empty file
+    std::fs::write(&path, "").unwrap();
+    manager.load().unwrap();
+    assert_eq!(*config.borrow(), Window::default());
+
+    // A backup loaded in place of a broken file
+    std::fs::write(crate::fs::with_suffix(&path, ".bak"), "width = 9000\n").unwrap();
+    std::fs::write(&path, "width = ").unwrap();
+    assert!(matches!(
+      manager.load_with_outcome().unwrap(),
+      LoadOutcome::BackupLoaded(_)
+    ));
+    assert_eq!(*config.borrow(), Window { width: 3840 });
+  }
+}
@@ -0,0 +1,630 @@
+use crate::{
+  format, shared::lock, AppConfigError, AppConfigManager, FormatError, Result, SharedData,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// Whether the file is loaded through a [`toml::Value`] so that other layers can be merged into it.
+  pub(crate) fn loads_layers(&self) -> bool {
+    self.system_layer
+      || self.env_prefix.is_some()
+      || !self.key_overrides.is_empty()
+      || self.defaults.is_some()
+      || self.preserve_unknown_keys
+      || !self.migrations.is_empty()
+      || self.has_secret_store()
+  }
+
+  fn has_secret_store(&self) -> bool {
+    #[cfg(feature = "secrets")]
+    return self.secret_store.is_some();
+    #[cfg(not(feature = "secrets"))]
+    false
+  }
+
+  /// Parses the file at `path` and merges it over the defaults and the system layer,
+  /// then applies the overrides. Also returns whether the file was upgraded by a migration.
+  pub(crate) fn parse_layered(&self, path: &Path, s: &[u8]) -> Result<(T, bool)> {
+    let mut value = match self.defaults {
+      Some(defaults) => Value::try_from(defaults()).map_err(|err| AppConfigError::Serialize {
+        path: path.to_owned(),
+        source: err.into(),
+      })?,
+      None => Value::Table(Default::default()),
+    };
+    if self.system_layer {
+      if let Some((system_path, system)) = self.read_system_layer()? {
+        // The system file itself is loaded when there is no user file
+        if system_path != path {
+          merge(&mut value, system);
+        }
+      }
+    }
+    let user = self
+      .parse_value(path, s)
+      .map_err(AppConfigError::deserialize(path))?;
+    #[cfg(feature = "secrets")]
+    let user = self.load_secrets(path, user)?;
+    let (user, migrated) = self.migrate(path, user)?;
+    let document = if self.preserve_unknown_keys {
+      Some(user.clone())
+    }
+    else {
+      None
+    };
+    merge(&mut value, user);
+
+    let mut shape = self
+      .data
+      .read(|data| Value::try_from(data))
+      .unwrap_or_else(|_| Value::Table(Default::default()));
+    merge(&mut shape, value.clone());
+    let mut overrides = self.env_overrides(&value, &shape);
+    apply(&mut value, &overrides);
+    let key_overrides = self.key_overrides(&value, &shape)?;
+    apply(&mut value, &key_overrides);
+    overrides.extend(key_overrides);
+    let data = T::deserialize(value).map_err(|err| AppConfigError::Deserialize {
+      path: path.to_owned(),
+      source: err.into(),
+    })?;
+    *lock(&self.session_overrides) = overrides;
+    *lock(&self.document) = document.map(|document| LoadedDocument {
+      document,
+      known: Value::try_from(&data).unwrap_or_else(|_| Value::Table(Default::default())),
+    });
+    Ok((data, migrated))
+  }
+
+  /// The values of the environment variables starting with the configured prefix.
+  ///
+  /// `PREFIX_WINDOW_POS` is matched against the keys in `value` and in the current data,
+  /// so that it becomes `window.pos` or `window_pos`, whichever exists.
+  fn env_overrides(&self, value: &Value, shape: &Value) -> Vec<SessionOverride> {
+    let prefix = match &self.env_prefix {
+      Some(prefix) => format!("{}{}", prefix, self.env_separator),
+      None => return Vec::new(),
+    };
+
+    let mut vars: Vec<(String, String)> = std::env::vars_os()
+      .filter_map(|(name, raw)| Some((name.into_string().ok()?, raw.into_string().ok()?)))
+      .filter(|(name, _)| name.starts_with(&prefix))
+      .collect();
+    vars.sort();
+    vars
+      .into_iter()
+      .map(|(name, raw)| {
+        let tokens: Vec<&str> = name[prefix.len()..].split(&self.env_separator).collect();
+        let path = resolve_env_key(shape, &tokens, &self.env_separator);
+        SessionOverride {
+          value: parse_override(&raw, get(shape, &path)),
+          underlying: get(value, &path).cloned(),
+          path,
+        }
+      })
+      .collect()
+  }
+
+  /// The overrides set with `with_overrides`, checked against `shape`.
+  fn key_overrides(&self, value: &Value, shape: &Value) -> Result<Vec<SessionOverride>> {
+    self
+      .key_overrides
+      .iter()
+      .map(|(key, raw)| {
+        let path =
+          resolve_dotted_key(shape, key).map_err(|reason| AppConfigError::InvalidOverride {
+            key: key.clone(),
+            reason,
+          })?;
+        Ok(SessionOverride {
+          value: parse_override(raw, get(shape, &path)),
+          underlying: get(value, &path).cloned(),
+          path,
+        })
+      })
+      .collect()
+  }
+
+  /// Puts back the values replaced by session overrides, unless the application changed them since.
+  fn restore_overridden(&self, value: &mut Value) {
+    for o in lock(&self.session_overrides).iter().rev() {
+      if get(value, &o.path) == Some(&o.value) {
+        set(value, &o.path, o.underlying.clone());
+      }
+    }
+  }
+
+  /// The file to write for `value`, containing only what differs from the system layer
+  /// when `save_diff_from_system` is enabled.
+  pub(crate) fn serialize_layered(&self, path: &Path, value: &T) -> Result<Option<Vec<u8>>> {
+    let save_diff = self.system_layer && self.save_diff_from_system;
+    let document = lock(&self.document);
+    #[cfg(feature = "preserve-formatting")]
+    let patch = self.preserve_formatting
+      && self.format.extension() == "toml"
+      && !self.encrypts()
+      && !self.compresses()
+      && self.integrity_policy == crate::IntegrityPolicy::Disabled
+      && self.is_stored(path);
+    #[cfg(not(feature = "preserve-formatting"))]
+    let patch = false;
+    if !(save_diff || patch)
+      && document.is_none()
+      && lock(&self.session_overrides).is_empty()
+      && self.migrations.is_empty()
+      && !self.has_secret_store()
+    {
+      return Ok(None);
+    }
+    let format = match format::builtin::<Value>(self.format.extension()) {
+      Some(format) => format,
+      None => return Ok(None),
+    };
+    #[cfg(feature = "secrets")]
+    let value = self.secret_value(value);
+    #[cfg(not(feature = "secrets"))]
+    let value = Value::try_from(value);
+    let mut value = value.map_err(|err| AppConfigError::Serialize {
+      path: path.to_owned(),
+      source: err.into(),
+    })?;
+    self.restore_overridden(&mut value);
+    if let Some(loaded) = &*document {
+      value = keep_unknown_keys(&loaded.document, &loaded.known, value);
+    }
+    #[cfg(feature = "secrets")]
+    self.store_secrets(path, &mut value)?;
+    if save_diff {
+      // Without a readable system layer, everything is written
+      if let Ok(Some((_, system))) = self.read_system_layer() {
+        value = diff(&system, value).unwrap_or_else(|| Value::Table(Default::default()));
+      }
+    }
+    if let (false, Value::Table(table)) = (self.migrations.is_empty(), &mut value) {
+      table.insert(
+        VERSION_KEY.to_owned(),
+        Value::Integer(self.schema_version().into()),
+      );
+    }
+    #[cfg(feature = "preserve-formatting")]
+    if let (true, Value::Table(table)) = (patch, &value) {
+      let original = self.read_stored(path)?;
+      let original = String::from_utf8(original).map_err(|err| AppConfigError::Io {
+        path: path.to_owned(),
+        source: std::io::Error::new(std::io::ErrorKind::InvalidData, err),
+      })?;
+      // The header is written again by the caller
+      let header = self.rendered_header().unwrap_or_default();
+      let original = original.strip_prefix(&header).unwrap_or(&original);
+      return crate::edit::patch_document(original, table)
+        .map(|s| Some(s.into_bytes()))
+        .map_err(AppConfigError::serialize(path));
+    }
+    format
+      .serialize_with(&value, &self.save_options)
+      .map(Some)
+      .map_err(AppConfigError::serialize(path))
+  }
+
+  /// The version written to the file, i.e. the highest registered migration.
+  fn schema_version(&self) -> u32 {
+    self
+      .migrations
+      .last()
+      .map_or(1, |(version, _)| *version)
+      .max(1)
+  }
+
+  /// Removes the version key from `value` and runs the migrations newer than it.
+  /// Also returns whether the file was older than the current version.
+  fn migrate(&self, path: &Path, mut value: Value) -> Result<(Value, bool)> {
+    if self.migrations.is_empty() {
+      return Ok((value, false));
+    }
+    let found = match value.as_table_mut().and_then(|x| x.remove(VERSION_KEY)) {
+      None => 1,
+      Some(Value::Integer(version)) if (0..=i64::from(u32::MAX)).contains(&version) => {
+        version as u32
+      }
+      Some(version) => {
+        return Err(AppConfigError::Deserialize {
+          path: path.to_owned(),
+          source: format!("Invalid {}: {}", VERSION_KEY, version).into(),
+        })
+      }
+    };
+    let supported = self.schema_version();
+    if found > supported {
+      return Err(AppConfigError::NewerVersion {
+        path: path.to_owned(),
+        found,
+        supported,
+      });
+    }
+    for (version, migration) in &self.migrations {
+      if *version > found {
+        migration(&mut value);
+      }
+    }
+    if found < supported {
+      event!(
+        Info,
+        "Migrated {} from version {} to {}",
+        path.display(),
+        found,
+        supported
+      );
+    }
+    Ok((value, found < supported))
+  }
+
+  fn read_system_layer(&self) -> Result<Option<(PathBuf, Value)>> {
+    let path = self.system_config_path()?;
+    if !self.is_stored(&path) {
+      return Ok(None);
+    }
+    let s = self.read_stored(&path)?;
+    let value = self
+      .parse_value(&path, &s)
+      .map_err(AppConfigError::deserialize(&path))?;
+    Ok(Some((path, value)))
+  }
+
+  /// Parses a file in one of the built-in formats into a [`toml::Value`].
+  fn parse_value(&self, path: &Path, s: &[u8]) -> std::result::Result<Value, FormatError> {
+    match format::builtin::<Value>(self.format_extension(path)) {
+      Some(format) => self.parse(&*format, s),
+      None => Err("Layered loading requires one of the built-in formats".into()),
+    }
+  }
+}
+
+/// The reserved key holding the schema version of the file.
+const VERSION_KEY: &str = "__appconfig_version";
+
+/// A value that replaces the one from the file for this session only and is not saved.
+pub(crate) struct SessionOverride {
+  path: Vec<String>,
+  /// The value from the file, written instead of `value` when saving.
+  underlying: Option<Value>,
+  value: Value,
+}
+
+/// The file as it was loaded, kept so that keys unknown to `T` can be written back.
+pub(crate) struct LoadedDocument {
+  document: Value,
+  /// The keys that belonged to `T` when the file was loaded.
+  known: Value,
+}
+
+/// Updates `document` with `value`, keeping the keys that do not belong to `T`.
+///
+/// A key missing from `value` is only removed if it is in `known`, i.e. `T` had it when loading.
+fn keep_unknown_keys(document: &Value, known: &Value, value: Value) -> Value {
+  match (document, value) {
+    (Value::Table(document), Value::Table(mut value)) => {
+      let empty = Value::Table(Default::default());
+      let mut table = toml::Table::new();
+      for (key, old) in document {
+        let known = known.get(key);
+        match value.remove(key) {
+          Some(new) => {
+            let new = keep_unknown_keys(old, known.unwrap_or(&empty), new);
+            table.insert(key.clone(), new);
+          }
+          // Removed from `T`
+          None if known.is_some() => {}
+          None => {
+            table.insert(key.clone(), old.clone());
+          }
+        }
+      }
+      table.extend(value);
+      Value::Table(table)
+    }
+    (_, value) => value,
+  }
+}
+
+fn get<'a>(value: &'a Value, path: &[String]) -> Option<&'a Value> {
+  path.iter().try_fold(value, |value, key| match value {
+    Value::Table(table) => table.get(key),
+    Value::Array(array) => array.get(key.parse::<usize>().ok()?),
+    _ => None,
+  })
+}
+
+/// Sets or, with `None`, removes the value at `path`, creating missing tables on the way.
+fn set(value: &mut Value, path: &[String], new: Option<Value>) {
+  let (key, parents) = match path.split_last() {
+    Some(split) => split,
+    None => return,
+  };
+  let mut value = value;
+  for parent in parents {
+    value = match value {
+      Value::Table(table) => table
+        .entry(parent.as_str())
+        .or_insert_with(|| Value::Table(Default::default())),
+      Value::Array(array) => match parent
+        .parse::<usize>()
+        .ok()
+        .and_then(move |i| array.get_mut(i))
+      {
+        Some(value) => value,
+        None => return,
+      },
+      _ => return,
+    };
+  }
+  match (value, new) {
+    (Value::Table(table), Some(new)) => {
+      table.insert(key.clone(), new);
+    }
+    (Value::Table(table), None) => {
+      table.remove(key);
+    }
+    (Value::Array(array), Some(new)) => {
+      if let Some(value) = key.parse::<usize>().ok().and_then(|i| array.get_mut(i)) {
+        *value = new;
+      }
+    }
+    _ => {}
+  }
+}
+
+fn apply(value: &mut Value, overrides: &[SessionOverride]) {
+  for o in overrides {
+    set(value, &o.path, Some(o.value.clone()));
+  }
+}
+
+/// Splits `window.size.0` into its parts, checking that each part can be set in `shape`.
+/// Missing keys in tables are allowed, they are created when the override is applied.
+fn resolve_dotted_key(shape: &Value, key: &str) -> std::result::Result<Vec<String>, String> {
+  let path: Vec<String> = key.split('.').map(str::to_owned).collect();
+  if path.iter().any(|part| part.is_empty()) {
+    return Err("the key or one of its parts is empty".to_owned());
+  }
+  let mut node = Some(shape);
+  for (i, part) in path.iter().enumerate() {
+    node = match node {
+      // Inside a table that does not exist yet
+      None => None,
+      Some(Value::Table(table)) => table.get(part),
+      Some(Value::Array(array)) => match part.parse::<usize>() {
+        Ok(index) if index < array.len() => array.get(index),
+        Ok(index) => {
+          return Err(format!(
+            "index {} is out of range for `{}` with {} elements",
+            index,
+            path[..i].join("."),
+            array.len()
+          ))
+        }
+        Err(_) => {
+          return Err(format!(
+            "`{}` is an array, not a table",
+            path[..i].join(".")
+          ))
+        }
+      },
+      Some(_) => return Err(format!("`{}` is not a table", path[..i].join("."))),
+    };
+  }
+  Ok(path)
+}
+
+/// Maps the parts of an environment variable name to the keys in `shape`, trying the longest
+/// match first. The remaining parts become a single key when nothing matches.
+fn resolve_env_key(shape: &Value, tokens: &[&str], separator: &str) -> Vec<String> {
+  let mut path = Vec::new();
+  let mut node = Some(shape);
+  let mut rest = tokens;
+  while !rest.is_empty() {
+    let found = (1..=rest.len()).rev().find_map(|len| {
+      let name = rest[..len].join(separator);
+      match node? {
+        Value::Table(table) => table
+          .iter()
+          .find(|(key, _)| key.eq_ignore_ascii_case(&name))
+          .map(|(key, value)| (key.clone(), value, len)),
+        Value::Array(array) => array
+          .get(name.parse::<usize>().ok()?)
+          .map(|value| (name, value, len)),
+        _ => None,
+      }
+    });
+    match found {
+      Some((key, value, len)) => {
+        path.push(key);
+        node = Some(value);
+        rest = &rest[len..];
+      }
+      None => {
+        path.push(rest.join(separator).to_lowercase());
+        break;
+      }
+    }
+  }
+  path
+}
+
+/// Parses `raw` as a TOML value, falling back to a string.
+/// A comma separated list such as `100,200` becomes an array where `shape` has one.
+fn parse_override(raw: &str, shape: Option<&Value>) -> Value {
+  let parse = |s: &str| {
+    toml::from_str::<toml::Table>(&format!("value = {}", s))
+      .ok()
+      .filter(|table| table.len() == 1)
+      .and_then(|mut table| table.remove("value"))
+  };
+  parse(raw)
+    .or_else(|| match shape {
+      Some(Value::Array(_)) => parse(&format!("[{}]", raw)),
+      _ => None,
+    })
+    .unwrap_or_else(|| Value::String(raw.to_owned()))
+}
+
+/// Merges `overlay` into `base` key by key. Values other than tables, including arrays,
+/// replace the value in `base`, even when `base` has a table at the same key.
+pub(crate) fn merge(base: &mut Value, overlay: Value) {
+  match (base, overlay) {
+    (Value::Table(base), Value::Table(overlay)) => {
+      for (key, value) in overlay {
+        match base.get_mut(&key) {
+          Some(existing) => merge(existing, value),
+          None => {
+            base.insert(key, value);
+          }
+        }
+      }
+    }
+    (base, overlay) => *base = overlay,
+  }
+}
+
+/// The parts of `value` that differ from `base`, so that `merge(base, diff)` gives `value` again.
+///
+/// Returns `None` if there is no difference. Keys removed from `value` cannot be represented.
+pub(crate) fn diff(base: &Value, value: Value) -> Option<Value> {
+  match (base, value) {
+    (Value::Table(base), Value::Table(value)) => {
+      let table: toml::Table = value
+        .into_iter()
+        .filter_map(|(key, value)| match base.get(&key) {
+          Some(base) => diff(base, value).map(|value| (key, value)),
+          None => Some((key, value)),
+        })
+        .collect();
+      if table.is_empty() {
+        None
+      }
+      else {
+        Some(Value::Table(table))
+      }
+    }
+    (base, value) if *base == value => None,
+    (_, value) => Some(value),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    diff, get, keep_unknown_keys, merge, parse_override, resolve_dotted_key, resolve_env_key, set,
+  };
+  use toml::Value;
+
+  fn value(s: &str) -> Value {
+    Value::Table(toml::from_str(s).unwrap())
+  }
+
+  #[test]
+  fn merge_tables() {
+    let mut base = value("a = 1\nb = [1, 2]\n[window]\nwidth = 800\nheight = 600\n");
+    merge(
+      &mut base,
+      value("b = [3]\nc = true\n[window]\nwidth = 1024\n"),
+    );
+    assert_eq!(
+      base,
+      value("a = 1\nb = [3]\nc = true\n[window]\nwidth = 1024\nheight = 600\n")
+    );
+  }
+
+  #[test]
+  fn merge_conflicts_prefer_overlay() {
+    let mut base = value("[window]\nwidth = 800\n");
+    merge(&mut base, value("window = \"maximized\"\n"));
+    assert_eq!(base, value("window = \"maximized\"\n"));
+
+    let mut base = value("window = \"maximized\"\n");
+    merge(&mut base, value("[window]\nwidth = 800\n"));
+    assert_eq!(base, value("[window]\nwidth = 800\n"));
+  }
+
+  #[test]
+  fn diff_tables() {
+    let base = value("a = 1\nb = 2\n[window]\nwidth = 800\nheight = 600\n");
+    let changed = value("a = 1\nb = 3\nc = 4\n[window]\nwidth = 800\nheight = 700\n");
+    let d = diff(&base, changed.clone()).unwrap();
+    assert_eq!(d, value("b = 3\nc = 4\n[window]\nheight = 700\n"));
+
+    let mut merged = base.clone();
+    merge(&mut merged, d);
+    assert_eq!(merged, changed);
+    assert_eq!(diff(&base, base.clone()), None);
+  }
+
+  #[test]
+  fn env_keys() {
+    let shape = value("window_pos = [1, 2]\n[window]\nsize = 1\n[proxy_settings]\nhost = \"\"\n");
+    let resolve = |name: &str| resolve_env_key(&shape, &name.split('_').collect::<Vec<_>>(), "_");
+    assert_eq!(resolve("WINDOW_POS"), ["window_pos"]);
+    assert_eq!(resolve("WINDOW_SIZE"), ["window", "size"]);
+    assert_eq!(resolve("WINDOW_POS_1"), ["window_pos", "1"]);
+    assert_eq!(resolve("PROXY_SETTINGS_HOST"), ["proxy_settings", "host"]);
+    assert_eq!(resolve("NEW_KEY"), ["new_key"]);
+    assert_eq!(resolve("WINDOW_NEW_KEY"), ["window", "new_key"]);
+  }
+
+  #[test]
+  fn override_values() {
+    let array = Value::Array(vec![]);
+    assert_eq!(parse_override("8080", None), Value::Integer(8080));
+    assert_eq!(parse_override("true", None), Value::Boolean(true));
+    assert_eq!(parse_override("dark", None), Value::String("dark".into()));
+    assert_eq!(parse_override("1, 2", None), Value::String("1, 2".into()));
+    assert_eq!(
+      parse_override("1, 2", Some(&array)),
+      Value::Array(vec![Value::Integer(1), Value::Integer(2)])
+    );
+    assert_eq!(
+      parse_override("1\nother = 2", None),
+      Value::String("1\nother = 2".into())
+    );
+  }
+
+  #[test]
+  fn get_and_set() {
+    let mut v = value("pos = [1, 2]\n[window]\nsize = 1\n");
+    let path = |s: &str| s.split('.').map(String::from).collect::<Vec<_>>();
+    assert_eq!(get(&v, &path("pos.1")), Some(&Value::Integer(2)));
+    set(&mut v, &path("pos.1"), Some(Value::Integer(5)));
+    set(&mut v, &path("a.b"), Some(Value::Integer(3)));
+    set(&mut v, &path("window.size"), None);
+    assert_eq!(v, value("pos = [1, 5]\n[window]\n[a]\nb = 3\n"));
+  }
+
+  #[test]
+  fn dotted_keys() {
+    let shape = value("pos = [1, 2]\n[window]\nsize = 1\n");
+    assert_eq!(resolve_dotted_key(&shape, "pos.1").unwrap(), ["pos", "1"]);
+    assert_eq!(
+      resolve_dotted_key(&shape, "new.nested.key").unwrap(),
+      ["new", "nested", "key"]
+    );
+    assert!(resolve_dotted_key(&shape, "window.size.x").is_err());
+    assert!(resolve_dotted_key(&shape, "pos.5").is_err());
+    assert!(resolve_dotted_key(&shape, "window..size").is_err());
+  }
+
+  #[test]
+  fn unknown_keys() {
+    let document = value("a = 1\nold = 2\n[plugin.foo]\nx = 1\n[window]\nwidth = 1\nextra = 2\n");
+    let known = value("a = 1\nold = 2\n[window]\nwidth = 1\n");
+    let current = value("a = 5\nnew = 3\n[window]\nwidth = 2\n");
+    assert_eq!(
+      keep_unknown_keys(&document, &known, current),
+      value("a = 5\nnew = 3\n[plugin.foo]\nx = 1\n[window]\nwidth = 2\nextra = 2\n")
+    );
+  }
+}
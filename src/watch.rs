@@ -0,0 +1,169 @@
+use crate::{fs, AppConfigManager, Result, SharedData};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+  path::Path,
+  sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+  thread::JoinHandle,
+  time::{Duration, SystemTime},
+};
+
+/// Watches the configuration file on a background thread, returned by
+/// [`AppConfigManager::watch`]. The thread stops when this is dropped.
+///
+/// The data is not thread-safe, so the watcher only reports changes. The application applies them
+/// on its own thread with [`reload_if_changed`](AppConfigManager::reload_if_changed),
+/// which also ignores the writes of the manager itself.
+///
+/// ```no_run
+/// # use std::{cell::RefCell, rc::Rc, time::Duration};
+/// # use appconfig::AppConfigManager;
+/// # let manager = AppConfigManager::new(Rc::new(RefCell::new(0_u32)), "app", "org");
+/// let watcher = manager.watch(Duration::from_millis(250)).unwrap();
+/// // In the event loop
+/// if watcher.changed() && manager.reload_if_changed().unwrap() {
+///   // Refresh the UI
+/// }
+/// ```
+pub struct ConfigWatcher {
+  receiver: Receiver<()>,
+  /// Dropped to wake up and stop the thread.
+  stop: Option<Sender<()>>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl ConfigWatcher {
+  /// Returns `true` if the file changed since the last call.
+  pub fn changed(&self) -> bool {
+    self.receiver.try_iter().count() > 0
+  }
+
+  /// Receives a message for every change, e.g. to wake up an event loop.
+  pub fn receiver(&self) -> &Receiver<()> {
+    &self.receiver
+  }
+}
+
+impl Drop for ConfigWatcher {
+  fn drop(&mut self) {
+    self.stop.take();
+    if let Some(thread) = self.thread.take() {
+      thread.join().ok();
+    }
+  }
+}
+
+impl<T, S> AppConfigManager<T, S>
+where
+  T: Sized + Serialize + DeserializeOwned,
+  S: SharedData<T>,
+{
+  /// Checks the configuration file for changes every `interval` on a background thread.
+  ///
+  /// A change is reported once the file stayed the same for another `interval`,
+  /// so that an editor writing the file twice is reported once.
+  /// The contents are compared too, since the modification time may be coarse.
+  pub fn watch(&self, interval: Duration) -> Result<ConfigWatcher> {
+    let path = self.user_config_path()?;
+    let (sender, receiver) = mpsc::channel();
+    let (stop, stopped) = mpsc::channel::<()>();
+    let mut last = stamp(&path);
+    let thread = std::thread::spawn(move || {
+      let mut pending = false;
+      while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+        let current = stamp(&path);
+        if current != last {
+          last = current;
+          pending = true;
+        }
+        else if pending {
+          pending = false;
+          if sender.send(()).is_err() {
+            break;
+          }
+        }
+      }
+    });
+    Ok(ConfigWatcher {
+      receiver,
+      stop: Some(stop),
+      thread: Some(thread),
+    })
+  }
+}
+
+/// The modification time, size and hash of the contents of the file at `path`.
+fn stamp(path: &Path) -> Option<(Option<SystemTime>, u64, u64)> {
+  let metadata = std::fs::metadata(path).ok()?;
+  let contents = std::fs::read(path).ok()?;
+  Some((
+    metadata.modified().ok(),
+    metadata.len(),
+    fs::hash_bytes(&contents),
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::AppConfigManager;
+  use serde::{Deserialize, Serialize};
+  use std::{cell::RefCell, rc::Rc, time::Duration};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Volume {
+    level: u32,
+  }
+
+  #[test]
+  fn watch() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Rc::from(RefCell::from(Volume { level: 1 }));
+    let manager = AppConfigManager::new(config.clone(), "watch", "sumibi-yakitori")
+      .with_base_dir(dir.path())
+      .with_auto_saving(false);
+    let path = manager.get_user_config_path().unwrap();
+    let interval = Duration::from_millis(50);
+    let watcher = manager.watch(interval).unwrap();
+    let wait = || {
+      watcher
+        .receiver()
+        .recv_timeout(Duration::from_secs(10))
+        .unwrap();
+      // Nothing else is reported
+      std::thread::sleep(interval * 4);
+      assert!(!watcher.changed());
+    };
+
+    // The own writes are reported but not reloaded
+    manager.save().unwrap();
+    wait();
+    assert!(!manager.reload_if_changed().unwrap());
+
+    // Written twice in a row, reported once
+    std::fs::write(&path, "level = 2\n").unwrap();
+    std::fs::write(&path, "level = 3\n").unwrap();
+    wait();
+    assert!(manager.reload_if_changed().unwrap());
+    assert_eq!(*config.borrow(), Volume { level: 3 });
+
+    // Replaced with a file of the same size and modification time
+    let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+    let temp = dir.path().join("level.toml");
+    std::fs::write(&temp, "level = 4\n").unwrap();
+    std::fs::File::options()
+      .write(true)
+      .open(&temp)
+      .unwrap()
+      .set_modified(modified)
+      .unwrap();
+    std::fs::rename(&temp, &path).unwrap();
+    wait();
+    assert!(manager.reload_if_changed().unwrap());
+    assert_eq!(*config.borrow(), Volume { level: 4 });
+
+    // Stops without waiting for the interval
+    let watcher = manager.watch(Duration::from_secs(60)).unwrap();
+    let started = std::time::Instant::now();
+    drop(watcher);
+    assert!(started.elapsed() < Duration::from_secs(10));
+  }
+}
@@ -0,0 +1,272 @@
+use crate::{ConfigKey, ConfigStore};
+use std::{fmt, io};
+
+/// The `Storage` interface of the Web Storage API, e.g. `window.localStorage`.
+///
+/// The crate does not depend on `web-sys` yet, so the application implements this with it.
+/// Implement it for a type that looks the storage up on each call, since a `web_sys::Storage`
+/// cannot be kept in a [`ConfigStore`] that must be `Send + Sync`:
+///
+/// ```ignore
+/// struct LocalStorage;
+///
+/// impl LocalStorage {
+///   fn storage() -> Result<web_sys::Storage, WebStorageError> {
+///     // `Err` in some private browsing windows, `None` when disabled
+///     match web_sys::window().map(|x| x.local_storage()) {
+///       Some(Ok(Some(storage))) => Ok(storage),
+///       _ => Err(WebStorageError::Unavailable),
+///     }
+///   }
+/// }
+///
+/// fn convert(err: wasm_bindgen::JsValue) -> WebStorageError {
+///   match err.dyn_into::<js_sys::Error>() {
+///     Ok(err) if err.name() == "QuotaExceededError" => WebStorageError::QuotaExceeded,
+///     Ok(err) => WebStorageError::Other(err.message().into()),
+///     Err(err) => WebStorageError::Other(format!("{:?}", err)),
+///   }
+/// }
+///
+/// impl WebStorage for LocalStorage {
+///   fn get_item(&self, key: &str) -> Result<Option<String>, WebStorageError> {
+///     Self::storage()?.get_item(key).map_err(convert)
+///   }
+///
+///   fn set_item(&self, key: &str, value: &str) -> Result<(), WebStorageError> {
+///     Self::storage()?.set_item(key, value).map_err(convert)
+///   }
+///
+///   fn remove_item(&self, key: &str) -> Result<(), WebStorageError> {
+///     Self::storage()?.remove_item(key).map_err(convert)
+///   }
+/// }
+///
+/// let manager = AppConfigManager::new(config, "app", "org").with_store(WebStore::new(LocalStorage));
+/// ```
+pub trait WebStorage {
+  fn get_item(&self, key: &str) -> Result<Option<String>, WebStorageError>;
+
+  fn set_item(&self, key: &str, value: &str) -> Result<(), WebStorageError>;
+
+  fn remove_item(&self, key: &str) -> Result<(), WebStorageError>;
+}
+
+/// The error of a [`WebStorage`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WebStorageError {
+  /// The `QuotaExceededError` of `setItem`, reported as [`AppConfigError::QuotaExceeded`](crate::AppConfigError::QuotaExceeded).
+  QuotaExceeded,
+  /// The storage cannot be used, e.g. in a private browsing window,
+  /// reported as [`AppConfigError::StorageUnavailable`](crate::AppConfigError::StorageUnavailable).
+  Unavailable,
+  Other(String),
+}
+
+impl fmt::Display for WebStorageError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      WebStorageError::QuotaExceeded => write!(f, "The storage quota was exceeded"),
+      WebStorageError::Unavailable => write!(f, "The storage is not available"),
+      WebStorageError::Other(message) => f.write_str(message),
+    }
+  }
+}
+
+impl std::error::Error for WebStorageError {}
+
+impl From<WebStorageError> for io::Error {
+  fn from(err: WebStorageError) -> Self {
+    let kind = match err {
+      WebStorageError::QuotaExceeded => io::ErrorKind::QuotaExceeded,
+      WebStorageError::Unavailable => io::ErrorKind::Unsupported,
+      WebStorageError::Other(_) => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, err)
+  }
+}
+
+/// Keeps the files in a [`WebStorage`] under their [`ConfigKey`],
+/// e.g. `com.{organization}.{app}/app_config.toml`. Set it with
+/// [`with_store`](crate::AppConfigManager::with_store) when compiling to `wasm32-unknown-unknown`,
+/// where there is no file system.
+///
+/// Loading, saving and saving on drop work as usual, but a page may be closed without
+/// dropping the manager, so saving on drop is only a best effort. Call `save()` when the
+/// `visibilitychange` event reports that the page was hidden instead.
+///
+/// The storage holds strings, so a file that is not UTF-8, e.g. an encrypted one,
+/// is stored in hex after an `appconfig-hex:` prefix.
+#[derive(Debug, Clone, Default)]
+pub struct WebStore<W> {
+  storage: W,
+}
+
+const HEX_PREFIX: &str = "appconfig-hex:";
+
+impl<W: WebStorage> WebStore<W> {
+  pub fn new(storage: W) -> Self {
+    Self { storage }
+  }
+
+  pub fn storage(&self) -> &W {
+    &self.storage
+  }
+}
+
+impl<W: WebStorage> ConfigStore for WebStore<W> {
+  fn read(&self, key: &ConfigKey) -> io::Result<Option<Vec<u8>>> {
+    let value = match self.storage.get_item(&key.to_string())? {
+      Some(value) => value,
+      None => return Ok(None),
+    };
+    match value.strip_prefix(HEX_PREFIX) {
+      Some(hex) => unhex(hex)
+        .map(Some)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid hex in the storage")),
+      None => Ok(Some(value.into_bytes())),
+    }
+  }
+
+  fn write(&self, key: &ConfigKey, contents: &[u8]) -> io::Result<()> {
+    let value = match std::str::from_utf8(contents) {
+      Ok(s) if !s.starts_with(HEX_PREFIX) => s.to_owned(),
+      _ => contents
+        .iter()
+        .fold(HEX_PREFIX.to_owned(), |s, x| s + &format!("{:02x}", x)),
+    };
+    // `setItem` replaces the value as a whole
+    Ok(self.storage.set_item(&key.to_string(), &value)?)
+  }
+
+  fn remove(&self, key: &ConfigKey) -> io::Result<()> {
+    Ok(self.storage.remove_item(&key.to_string())?)
+  }
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+  let chunks = s.as_bytes().chunks_exact(2);
+  if !chunks.remainder().is_empty() {
+    return None;
+  }
+  chunks
+    .map(|x| u8::from_str_radix(std::str::from_utf8(x).ok()?, 16).ok())
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{WebStorage, WebStorageError, WebStore};
+  use crate::{shared::lock, AppConfigError, AppConfigManager};
+  use serde::{Deserialize, Serialize};
+  use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
+    sync::{Arc, Mutex},
+  };
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+  struct Theme {
+    dark: bool,
+  }
+
+  /// Stands in for `localStorage`, which holds at most `quota` bytes.
+  #[derive(Clone)]
+  struct FakeStorage {
+    items: Arc<Mutex<HashMap<String, String>>>,
+    quota: usize,
+    available: bool,
+  }
+
+  impl WebStorage for FakeStorage {
+    fn get_item(&self, key: &str) -> Result<Option<String>, WebStorageError> {
+      if !self.available {
+        return Err(WebStorageError::Unavailable);
+      }
+      Ok(lock(&self.items).get(key).cloned())
+    }
+
+    fn set_item(&self, key: &str, value: &str) -> Result<(), WebStorageError> {
+      if !self.available {
+        return Err(WebStorageError::Unavailable);
+      }
+      if value.len() > self.quota {
+        return Err(WebStorageError::QuotaExceeded);
+      }
+      lock(&self.items).insert(key.to_owned(), value.to_owned());
+      Ok(())
+    }
+
+    fn remove_item(&self, key: &str) -> Result<(), WebStorageError> {
+      lock(&self.items).remove(key);
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn web_store() {
+    let storage = FakeStorage {
+      items: Default::default(),
+      quota: 100,
+      available: true,
+    };
+    let config = Rc::from(RefCell::from(Theme { dark: true }));
+    let manager = AppConfigManager::new(config.clone(), "web", "sumibi-yakitori")
+      .with_store(WebStore::new(storage.clone()))
+      .with_auto_saving(false);
+    manager.save().unwrap();
+    assert_eq!(
+      lock(&storage.items)["com.sumibi-yakitori.web/app_config.toml"],
+      "dark = true\n"
+    );
+    *config.borrow_mut() = Theme::default();
+    manager.load().unwrap();
+    assert_eq!(*config.borrow(), Theme { dark: true });
+
+    let store = WebStore::new(FakeStorage {
+      quota: 0,
+      ..storage.clone()
+    });
+    let manager = AppConfigManager::new(config.clone(), "web", "sumibi-yakitori")
+      .with_store(store)
+      .with_auto_saving(false);
+    assert!(matches!(
+      manager.save(),
+      Err(AppConfigError::QuotaExceeded { .. })
+    ));
+
+    let store = WebStore::new(FakeStorage {
+      available: false,
+      ..storage
+    });
+    let manager = AppConfigManager::new(config, "web", "sumibi-yakitori")
+      .with_store(store)
+      .with_auto_saving(false);
+    assert!(matches!(
+      manager.load(),
+      Err(AppConfigError::StorageUnavailable { .. })
+    ));
+  }
+
+  #[test]
+  fn binary_contents() {
+    let storage = FakeStorage {
+      items: Default::default(),
+      quota: 100,
+      available: true,
+    };
+    let store = WebStore::new(storage);
+    let config = Rc::from(RefCell::from(Theme::default()));
+    let manager = AppConfigManager::new(config, "web", "sumibi-yakitori").with_auto_saving(false);
+    let key = manager.config_key(std::path::Path::new("app_config.toml"));
+    for contents in [&b"\xff\x00"[..], b"appconfig-hex:", b"dark = false\n"] {
+      crate::ConfigStore::write(&store, &key, contents).unwrap();
+      assert_eq!(
+        crate::ConfigStore::read(&store, &key).unwrap().unwrap(),
+        contents
+      );
+    }
+  }
+}